@@ -1,31 +1,88 @@
+use std::fmt::Write;
+
 use crate::{mc::*, mc_inst::*};
 
-pub fn print(module: &mut AsmModule) {
-    let mut printer = Printer { module };
+/// 将机器码模块渲染为 ARM 汇编文本
+pub fn print(module: &mut AsmModule) -> String {
+    let mut printer = Printer {
+        module,
+        out: String::new(),
+    };
     printer.print_module();
+    printer.out
 }
 
 struct Printer<'a> {
     module: &'a mut AsmModule,
+    out: String,
 }
 
 impl<'a> Printer<'a> {
     pub fn print_module(&mut self) {
+        writeln!(self.out, "\t.arch armv7ve").unwrap();
+        writeln!(self.out, "\t.fpu vfpv4").unwrap();
+        writeln!(self.out, "\t.arm").unwrap();
+        writeln!(self.out, "\t.text").unwrap();
         for func_id in self.module.funcs.clone() {
             self.print_func(func_id);
         }
+        if !self.module.globals.is_empty() {
+            writeln!(self.out, "\n\t.data").unwrap();
+            for global_id in self.module.globals.clone() {
+                self.print_global(global_id);
+            }
+        }
+        if !self.module.bss_globals.is_empty() {
+            writeln!(self.out, "\n\t.bss").unwrap();
+            for global_id in self.module.bss_globals.clone() {
+                self.print_global(global_id);
+            }
+        }
     }
 
     pub fn print_func(&mut self, func_id: AsmValueId) {
         let func = self.module.get_func(func_id);
+        let name = func.name.clone();
+        writeln!(self.out, "\n\t.global\t{}", name).unwrap();
+        writeln!(self.out, "\t.p2align\t2").unwrap();
+        writeln!(self.out, "\t.type\t{}, %function", name).unwrap();
+        writeln!(self.out, "{}:", name).unwrap();
         for bb_id in func.bbs.clone() {
             self.print_bb(bb_id);
         }
+        writeln!(self.out, "\t.size\t{}, .-{}", name, name).unwrap();
+    }
+
+    pub fn print_global(&mut self, global_id: AsmValueId) {
+        let global = self.module.get_global_variable(global_id);
+        let name = &global.imm.label;
+        writeln!(self.out, "\t.global\t{}", name).unwrap();
+        writeln!(self.out, "\t.p2align\t2").unwrap();
+        writeln!(self.out, "\t.type\t{}, %object", name).unwrap();
+        writeln!(self.out, "\t.size\t{}, {}", name, global.size).unwrap();
+        writeln!(self.out, "{}:", name).unwrap();
+        // 连续的零合并为 .zero
+        let mut zeros = 0;
+        for word in &global.init {
+            if *word == 0 {
+                zeros += 4;
+                continue;
+            }
+            if zeros > 0 {
+                writeln!(self.out, "\t.zero\t{}", zeros).unwrap();
+                zeros = 0;
+            }
+            writeln!(self.out, "\t.word\t0x{:x}", word).unwrap();
+        }
+        zeros += global.size - 4 * global.init.len();
+        if zeros > 0 {
+            writeln!(self.out, "\t.zero\t{}", zeros).unwrap();
+        }
     }
 
     pub fn print_bb(&mut self, bb_id: AsmValueId) {
         let bb = self.module.get_bb(bb_id);
-        println!("{}:", bb.name);
+        writeln!(self.out, "{}:", bb.name).unwrap();
         for inst_id in bb.insts.clone() {
             self.print_inst(inst_id);
         }
@@ -33,7 +90,7 @@ impl<'a> Printer<'a> {
 
     pub fn print_inst(&mut self, inst_id: AsmValueId) {
         let inst = self.module.get_inst(inst_id).clone();
-        println!("    {}", inst.to_arm(self.module));
+        writeln!(self.out, "    {}", inst.to_arm(self.module)).unwrap();
     }
 }
 
@@ -52,6 +109,8 @@ impl ToArm for AsmInst {
             AsmInst::FBinOp(i) => i.to_arm(module),
             AsmInst::FCMP(i) => i.to_arm(module),
             AsmInst::LDR(i) => i.to_arm(module),
+            AsmInst::MLA(i) => i.to_arm(module),
+            AsmInst::MLS(i) => i.to_arm(module),
             AsmInst::Mov(i) => i.to_arm(module),
             AsmInst::STR(i) => i.to_arm(module),
             AsmInst::VCVT(i) => i.to_arm(module),
@@ -61,19 +120,27 @@ impl ToArm for AsmInst {
             AsmInst::VSTR(i) => i.to_arm(module),
             AsmInst::Prologue(i) => i.to_arm(module),
             AsmInst::Ret(i) => i.to_arm(module),
+            AsmInst::Push(i) => i.to_arm(module),
+            AsmInst::Pop(i) => i.to_arm(module),
         }
     }
 }
 
 impl ToArm for BinOpInst {
     fn to_arm(&self, module: &mut AsmModule) -> String {
-        format!(
+        let inst = format!(
             "{}\t{}, {}, {}",
-            self.op.to_arm(module),
+            with_cond(&self.op.to_arm(module), &self.cond),
             self.get_defs()[0].to_arm(module),
             self.get_uses()[0].to_arm(module),
             self.get_uses()[1].to_arm(module),
-        )
+        );
+        match &self.shift {
+            Some((op, amount)) => {
+                format!("{}, {} #0x{:x}", inst, op.to_arm(module).trim_end(), amount)
+            }
+            None => inst,
+        }
     }
 }
 
@@ -83,16 +150,22 @@ impl ToArm for BinaryOp {
             BinaryOp::Add => "ADD ".to_string(),
             BinaryOp::Sub => "SUB ".to_string(),
             BinaryOp::Mul => "MUL ".to_string(),
+            BinaryOp::MulHigh => "SMMUL".to_string(),
             BinaryOp::Div => "SDIV".to_string(),
-            BinaryOp::Mod => "ERR".to_string(),
-            BinaryOp::LogAnd => "ERR".to_string(),
-            BinaryOp::LogOr => "ERR".to_string(),
-            BinaryOp::LogEq => "ERR".to_string(),
-            BinaryOp::LogNeq => "ERR".to_string(),
-            BinaryOp::LogLt => "ERR".to_string(),
-            BinaryOp::LogGt => "ERR".to_string(),
-            BinaryOp::LogLe => "ERR".to_string(),
-            BinaryOp::LogGe => "ERR".to_string(),
+            BinaryOp::And => "AND ".to_string(),
+            BinaryOp::Or => "ORR ".to_string(),
+            BinaryOp::Xor => "EOR ".to_string(),
+            BinaryOp::Shl => "LSL ".to_string(),
+            BinaryOp::Shr => "ASR ".to_string(),
+            BinaryOp::Mod => unreachable!("mod should be lowered to SDIV + MLS"),
+            BinaryOp::LogAnd
+            | BinaryOp::LogOr
+            | BinaryOp::LogEq
+            | BinaryOp::LogNeq
+            | BinaryOp::LogLt
+            | BinaryOp::LogGt
+            | BinaryOp::LogLe
+            | BinaryOp::LogGe => unreachable!("logical op should be lowered to CMP + movCC"),
         }
     }
 }
@@ -136,34 +209,17 @@ impl ToArm for LabelImm {
 }
 impl ToArm for StackOperand {
     fn to_arm(&self, module: &mut AsmModule) -> String {
-        let mut offset = self.offset;
-        let base;
-        match self.ty {
-            StackOperandType::Spill | StackOperandType::Local => {
-                offset = -offset;
-                base = RegType::Fp;
-            }
-            StackOperandType::CallParam => {
-                base = RegType::Sp;
-            }
-            StackOperandType::SelfArg => {
-                base = RegType::Fp;
-            }
-        }
+        let (base, offset) = self.base_and_offset();
         format!("{}, #{}", base.to_arm(module), to_signed_hex_string(offset))
     }
 }
 
 pub fn to_signed_hex_string(offset: i64) -> String {
-    let mut offset = offset;
-    let mut result = String::new();
     if offset < 0 {
-        result.push('-');
-        offset = -offset;
+        format!("-0x{:x}", -offset)
+    } else {
+        format!("0x{:x}", offset)
     }
-    result.push_str("0x");
-    result.push_str(&offset.to_string());
-    result
 }
 
 impl ToArm for RegType {
@@ -220,7 +276,12 @@ impl ToArm for BXInst {
 }
 impl ToArm for CallInst {
     fn to_arm(&self, _module: &mut AsmModule) -> String {
-        format!("BL\t{}", self.label.label)
+        // 尾调用之前已经释放了栈帧，直接跳转
+        if self.tail_call {
+            format!("B\t{}", self.label.label)
+        } else {
+            format!("BL\t{}", self.label.label)
+        }
     }
 }
 impl ToArm for CMPInst {
@@ -235,8 +296,9 @@ impl ToArm for CMPInst {
 impl ToArm for FBinOpInst {
     fn to_arm(&self, module: &mut AsmModule) -> String {
         format!(
-            "{}\t{},{}",
-            self.op.to_arm(module),
+            "{}\t{}, {}, {}",
+            with_cond(&self.op.to_arm(module), &self.cond),
+            self.get_defs()[0].to_arm(module),
             self.get_uses()[0].to_arm(module),
             self.get_uses()[1].to_arm(module),
         )
@@ -250,15 +312,19 @@ impl ToArm for FBinaryOp {
             BinaryOp::Div => "VDIV.F32".to_string(),
             BinaryOp::Mul => "VMUL.F32".to_string(),
             BinaryOp::Sub => "VSUB.F32".to_string(),
+            BinaryOp::And | BinaryOp::Or | BinaryOp::Xor | BinaryOp::Shl | BinaryOp::Shr => {
+                unreachable!("bitwise op on float")
+            }
+            BinaryOp::MulHigh => unreachable!("mul high on float"),
             BinaryOp::Mod => todo!(),
-            BinaryOp::LogAnd => todo!(),
-            BinaryOp::LogOr => todo!(),
-            BinaryOp::LogEq => todo!(),
-            BinaryOp::LogNeq => todo!(),
-            BinaryOp::LogLt => todo!(),
-            BinaryOp::LogGt => todo!(),
-            BinaryOp::LogLe => todo!(),
-            BinaryOp::LogGe => todo!(),
+            BinaryOp::LogAnd
+            | BinaryOp::LogOr
+            | BinaryOp::LogEq
+            | BinaryOp::LogNeq
+            | BinaryOp::LogLt
+            | BinaryOp::LogGt
+            | BinaryOp::LogLe
+            | BinaryOp::LogGe => unreachable!("logical op should be lowered to VCMP + movCC"),
         }
     }
 }
@@ -275,17 +341,43 @@ impl ToArm for FCMPInst {
 impl ToArm for LDRInst {
     fn to_arm(&self, module: &mut AsmModule) -> String {
         format!(
-            "LDR \t{}, [{}]",
+            "{}\t{}, [{}]",
+            with_cond("LDR ", &self.cond),
+            self.get_defs()[0].to_arm(module),
+            self.get_uses()[0].to_arm(module),
+        )
+    }
+}
+impl ToArm for MLAInst {
+    fn to_arm(&self, module: &mut AsmModule) -> String {
+        format!(
+            "{}\t{}, {}, {}, {}",
+            with_cond("MLA ", &self.cond),
+            self.get_defs()[0].to_arm(module),
+            self.get_uses()[0].to_arm(module),
+            self.get_uses()[1].to_arm(module),
+            self.get_uses()[2].to_arm(module),
+        )
+    }
+}
+impl ToArm for MLSInst {
+    fn to_arm(&self, module: &mut AsmModule) -> String {
+        format!(
+            "{}\t{}, {}, {}, {}",
+            with_cond("MLS ", &self.cond),
             self.get_defs()[0].to_arm(module),
             self.get_uses()[0].to_arm(module),
+            self.get_uses()[1].to_arm(module),
+            self.get_uses()[2].to_arm(module),
         )
     }
 }
 impl ToArm for MovInst {
     fn to_arm(&self, module: &mut AsmModule) -> String {
         format!(
-            "{}\t{},{}",
+            "{}{}\t{},{}",
             self.ty.to_string(),
+            self.cond,
             self.get_defs()[0].to_arm(module),
             self.get_uses()[0].to_arm(module),
         )
@@ -294,7 +386,8 @@ impl ToArm for MovInst {
 impl ToArm for STRInst {
     fn to_arm(&self, module: &mut AsmModule) -> String {
         format!(
-            "STR\t{}, [{}]",
+            "{}\t{}, [{}]",
+            with_cond("STR", &self.cond),
             self.get_uses()[0].to_arm(module),
             self.get_uses()[1].to_arm(module),
         )
@@ -313,7 +406,8 @@ impl ToArm for VCVTInst {
 impl ToArm for VLDRInst {
     fn to_arm(&self, module: &mut AsmModule) -> String {
         format!(
-            "VLDR\t{}, [{}]",
+            "{}\t{}, [{}]",
+            with_cond("VLDR", &self.cond),
             self.get_defs()[0].to_arm(module),
             self.get_uses()[0].to_arm(module),
         )
@@ -322,7 +416,8 @@ impl ToArm for VLDRInst {
 impl ToArm for VMovInst {
     fn to_arm(&self, module: &mut AsmModule) -> String {
         format!(
-            "VMOV\t{},{}",
+            "VMOV{}\t{},{}",
+            self.cond,
             self.get_defs()[0].to_arm(module),
             self.get_uses()[0].to_arm(module),
         )
@@ -336,42 +431,149 @@ impl ToArm for VMRSInst {
 impl ToArm for VSTRInst {
     fn to_arm(&self, module: &mut AsmModule) -> String {
         format!(
-            "VSTR\t{}, [{}]",
+            "{}\t{}, [{}]",
+            with_cond("VSTR", &self.cond),
             self.get_uses()[0].to_arm(module),
             self.get_uses()[1].to_arm(module),
         )
     }
 }
-impl PrologueInst {
+// 栈帧展开之前只在 MIR 中出现
+impl ToArm for PrologueInst {
     fn to_arm(&self, module: &mut AsmModule) -> String {
-        static FORMAT: &str = "push\t{fp, lr}\n\tmov\tfp, sp\n";
-        let mut sb = String::from(FORMAT);
-        let func_id = self.func;
-        let func = module.get_func(func_id);
-
-        let stack_size = func.stack_state.total_stack_size() as i32;
-        let binop = BinOpInst::new(
-            BinaryOp::Sub,
-            IntReg::new(RegType::Sp).into(),
-            IntReg::new(RegType::Sp).into(),
-            IntImm::new(stack_size as u32).into(),
-        );
-        let binop_id = module.alloc_value(AsmValue::Inst(AsmInst::BinOp(binop)));
-        let subs = module.expand_bin_op_ip(binop_id);
-        subs.iter().for_each(|inst_id| {
-            let inst = module.get_inst(*inst_id).clone();
-            sb.push_str(&format!("\t{}\n", inst.to_arm(module)));
-        });
-        sb
+        let params: Vec<String> = self.get_defs().iter().map(|op| op.to_arm(module)).collect();
+        format!("@ prologue {}", params.join(", "))
     }
 }
 impl ToArm for RetInst {
     fn to_arm(&self, module: &mut AsmModule) -> String {
-        let comment = if !self.get_uses().is_empty() {
-            format!("ret {}", self.get_uses()[0].to_arm(module)) // assuming YourType implements ToString
+        if !self.get_uses().is_empty() {
+            format!("@ ret {}", self.get_uses()[0].to_arm(module))
         } else {
-            String::from("ret void")
-        };
-        format!("MOV\tsp, fp\t@ {}\n\tPOP\t{{fp, lr}}\n\tBX\tlr", comment)
+            String::from("@ ret void")
+        }
+    }
+}
+impl ToArm for PushInst {
+    fn to_arm(&self, module: &mut AsmModule) -> String {
+        let regs = self.get_uses();
+        let op = if regs[0].is_float() { "vpush" } else { "push" };
+        format!("{}\t{{{}}}", op, reg_list(&regs, module))
+    }
+}
+impl ToArm for PopInst {
+    fn to_arm(&self, module: &mut AsmModule) -> String {
+        let regs = self.get_defs();
+        let op = if regs[0].is_float() { "vpop" } else { "pop" };
+        format!("{}\t{{{}}}", op, reg_list(&regs, module))
+    }
+}
+
+/// 条件执行的指令在助记符后加上条件后缀，VFP 指令的后缀在 .F32 之前
+fn with_cond(op: &str, cond: &Cond) -> String {
+    if *cond == Cond::AL {
+        return op.to_string();
+    }
+    match op.trim_end().split_once('.') {
+        Some((op, ty)) => format!("{}{}.{}", op, cond, ty),
+        None => format!("{}{}", op.trim_end(), cond),
+    }
+}
+
+fn reg_list(regs: &[AsmOperand], module: &mut AsmModule) -> String {
+    let regs: Vec<String> = regs.iter().map(|reg| reg.to_arm(module)).collect();
+    regs.join(", ")
+}
+
+#[test]
+fn test_print_module() {
+    use crate::mc_pass::{frame_lowering, reg_alloc};
+
+    let mut module = reg_alloc::build_asm(
+        "int a[4] = {1, 0, 0, 7}; float f = 1.5; int z[8];
+        int g() { return a[3]; }
+        int main() { return g() + z[0]; }",
+    );
+    reg_alloc::run(&mut module);
+    frame_lowering::run(&mut module);
+    let asm = print(&mut module);
+
+    assert!(asm.starts_with("\t.arch armv7ve\n\t.fpu vfpv4\n"));
+    for func in ["g", "main"] {
+        assert!(asm.contains(&format!("\t.global\t{}\n", func)));
+        assert!(asm.contains(&format!("\t.type\t{}, %function\n{}:\n", func, func)));
+        assert!(asm.contains(&format!("\t.size\t{}, .-{}\n", func, func)));
+    }
+    // 块标号带函数名前缀，不会重复
+    assert_eq!(asm.matches(".LBB_main_entry:").count(), 1);
+
+    let data = &asm[asm.find("\t.data").unwrap()..asm.find("\t.bss").unwrap()];
+    assert!(data.contains("a:\n\t.word\t0x1\n\t.zero\t8\n\t.word\t0x7\n"));
+    assert!(data.contains("f:\n\t.word\t0x3fc00000\n"));
+    let bss = &asm[asm.find("\t.bss").unwrap()..];
+    assert!(bss.contains("\t.size\tz, 32\nz:\n\t.zero\t32\n"));
+}
+
+#[test]
+fn test_print_bitwise_and_mod() {
+    use crate::mc_pass::{frame_lowering, reg_alloc};
+
+    let mut module = reg_alloc::build_asm(
+        "int getint();
+        int main() { int a = getint(); int b = getint();
+            return a % b + (a & 12) + (a | b) + (a ^ 3) + (a << 2) + (a >> b) + (a & 4097); }",
+    );
+    reg_alloc::run(&mut module);
+    frame_lowering::run(&mut module);
+    let asm = print(&mut module);
+
+    for op in [
+        "SDIV\t", "MLS \t", "AND \t", "ORR \t", "EOR \t", "LSL \t", "ASR \t",
+    ] {
+        assert!(asm.contains(op), "missing {}", op);
     }
+    let ands: Vec<&str> = asm.lines().filter(|l| l.contains("AND \t")).collect();
+    assert!(ands.iter().any(|l| l.ends_with(", #0xc")));
+    // 4097 不是合法的 Operand2，需要先装入寄存器
+    assert!(!ands.iter().any(|l| l.ends_with(", #0x1001")));
+}
+
+#[test]
+fn test_print_logical_values() {
+    use crate::mc_pass::{frame_lowering, reg_alloc};
+
+    let mut module = reg_alloc::build_asm(
+        "int getint();
+        int main() { int a = getint(); int b = getint();
+            int x = a && b; int y = a || b;
+            return x + y + !a + ~b + (a < b); }",
+    );
+    reg_alloc::run(&mut module);
+    frame_lowering::run(&mut module);
+    let asm = print(&mut module);
+
+    // 比较结果通过 movCC 物化为 0/1
+    for op in ["MOVWNE\t", "MOVWEQ\t", "MOVWLT\t", "EOR \t"] {
+        assert!(asm.contains(op), "missing {}", op);
+    }
+    // 短路求值的 phi 落在关键边上，需要拆边
+    assert!(asm.contains("BEQ\t.LBB_main_bb_1_"));
+    assert!(asm.contains("BNE\t.LBB_main_bb_3_"));
+}
+
+#[test]
+fn test_print_int_float_conversions() {
+    use crate::mc_pass::{frame_lowering, reg_alloc};
+
+    let mut module = reg_alloc::build_asm(
+        "int getint();
+        int main() { float f = getint(); f = f * 1.5; return f; }",
+    );
+    reg_alloc::run(&mut module);
+    frame_lowering::run(&mut module);
+    let asm = print(&mut module);
+
+    assert!(asm.contains("VCVT.F32.S32\t"));
+    assert!(asm.contains("VCVT.S32.F32\t"));
+    assert!(asm.contains("VMUL.F32\ts"));
 }
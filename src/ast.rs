@@ -1,4 +1,4 @@
-use crate::{ir::ValueId, sema::SemaRef};
+use crate::{diagnostic::Span, ir::ValueId, sema::SemaRef};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct TransUnit {
@@ -100,6 +100,7 @@ pub struct FuncDecl {
     pub body: Option<Block>, // if none, this is only a function declaration, not a definition
 
     pub sema_ref: Option<SemaRef>,
+    pub span: Span,
 }
 
 impl FuncDecl {
@@ -117,6 +118,7 @@ pub struct VarDecl {
     pub init: Option<InitVal>,
 
     pub sema_ref: Option<SemaRef>,
+    pub span: Span,
 }
 
 impl From<Param> for VarDecl {
@@ -127,6 +129,7 @@ impl From<Param> for VarDecl {
             is_const: false,
             init: None,
             sema_ref: param.sema_ref,
+            span: param.span,
         }
     }
 }
@@ -137,14 +140,16 @@ pub struct Param {
     pub type_: Type,
 
     pub sema_ref: Option<SemaRef>,
+    pub span: Span,
 }
 
 impl Param {
-    pub fn new(name: String, type_: Type) -> Self {
+    pub fn new(name: String, type_: Type, span: Span) -> Self {
         Self {
             name,
             type_,
             sema_ref: None,
+            span,
         }
     }
 }
@@ -161,15 +166,16 @@ pub enum Stmt {
     IfElse(IfElseStmt),
     While(WhileStmt),
     For(ForStmt),
-    Break,
+    Break(BreakStmt),
     DoWhile(DoWhileStmt),
-    Continue,
+    Continue(ContinueStmt),
     Return(ReturnStmt),
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ReturnStmt {
     pub expr: Option<Box<Expr>>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -197,11 +203,13 @@ pub struct WhileStmt {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct BreakStmt {
     pub target: Option<ValueId>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ContinueStmt {
     pub target: Option<ValueId>,
+    pub span: Span,
 }
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ForStmt {
@@ -252,6 +260,7 @@ pub struct InfixExpr {
 
     pub infer_ty: Option<Type>,
     pub infer_val: Option<Literal>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -261,6 +270,7 @@ pub struct PrefixExpr {
 
     pub infer_ty: Option<Type>,
     pub infer_val: Option<Literal>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -270,6 +280,7 @@ pub struct PostfixExpr {
 
     pub infer_ty: Option<Type>,
     pub infer_val: Option<Literal>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -281,6 +292,7 @@ pub struct CallExpr {
 
     pub infer_ty: Option<Type>,
     pub infer_val: Option<Literal>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -288,6 +300,7 @@ pub struct IdentExpr {
     pub id: String,
 
     pub sema_ref: Option<SemaRef>,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -430,14 +443,26 @@ impl Type {
             Type::Pointer(_) | Type::Array(_) | Type::Record(_) | Type::Function(_) => false,
         }
     }
+
+    pub fn is_float(&self) -> bool {
+        matches!(
+            self,
+            Type::Builtin(BuiltinType::Float | BuiltinType::Double)
+        )
+    }
+
+    /// 整数提升：bool 参与运算时视为 int
+    pub fn promote(self) -> Type {
+        match self {
+            Type::Builtin(BuiltinType::Bool) => Type::Builtin(BuiltinType::Int),
+            _ => self,
+        }
+    }
 }
 
 impl BuiltinType {
     pub fn is_arithmetic(&self) -> bool {
-        !matches!(
-            self,
-            BuiltinType::Void | BuiltinType::Bool | BuiltinType::Float | BuiltinType::Double
-        )
+        !matches!(self, BuiltinType::Void | BuiltinType::Bool)
     }
 
     pub fn is_integer(&self) -> bool {
@@ -587,9 +612,11 @@ impl Type {
 
     pub fn can_assign_from(&self, other_type: &Type) -> bool {
         match (self, other_type) {
-            // Builtin types can be assigned from compatible Builtin types
+            // Builtin types can be assigned from compatible Builtin types,
+            // arithmetic types convert implicitly
             (Type::Builtin(builtin_self), Type::Builtin(builtin_other)) => {
                 builtin_self == builtin_other
+                    || (*builtin_self != BuiltinType::Void && *builtin_other != BuiltinType::Void)
             }
             // Pointer types can be assigned from compatible Pointer types
             (Type::Pointer(pointer_self), Type::Pointer(pointer_other)) => {
@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 #[derive(Parser, Debug)] // requires `derive` feature
 #[command(name = "rockc")]
@@ -13,9 +13,65 @@ pub struct Args {
     #[arg(short = 'O', long, default_value_t = 0)]
     pub optimize_level: u8,
 
+    /// Emit ARM assembly instead of LLVM IR
     #[arg(short = 'S', long, default_value_t = false)]
     pub assembly: bool,
 
-    #[arg(short = 'o', long)]
-    pub output: std::path::PathBuf
+    /// Place the output into <FILE>
+    #[arg(short = 'o', long, value_name = "FILE")]
+    pub output: std::path::PathBuf,
+
+    /// Dump intermediate stages next to the output, e.g. --emit=ast,ir
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "STAGE")]
+    pub emit: Vec<EmitStage>,
+
+    /// Run exactly these IR passes in order instead of the -O pipeline, e.g. --passes=mem2reg
+    #[arg(long, value_delimiter = ',', value_name = "PASS")]
+    pub passes: Vec<String>,
+
+    /// Print the IR to stderr after each run of <PASS>
+    #[arg(long, value_delimiter = ',', value_name = "PASS")]
+    pub print_after: Vec<String>,
+
+    /// Print the IR to stderr after every pass
+    #[arg(long, default_value_t = false)]
+    pub print_after_all: bool,
+
+    /// Report the time spent in each IR pass on stderr
+    #[arg(long, default_value_t = false)]
+    pub time_passes: bool,
+}
+
+/// Compiler stages that can be dumped with `--emit`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EmitStage {
+    /// Token stream produced by the parser
+    Tokens,
+    /// Abstract syntax tree after semantic analysis
+    Ast,
+    /// Symbol table
+    Symtab,
+    /// LLVM IR before mem2reg
+    PreSsaIr,
+    /// LLVM IR after the optimisation pipeline
+    Ir,
+    /// Machine IR with virtual registers
+    Mir,
+    /// ARM assembly
+    Asm,
+}
+
+impl EmitStage {
+    /// 该阶段输出文件的扩展名
+    pub fn extension(&self) -> &'static str {
+        match self {
+            EmitStage::Tokens => "tokens",
+            EmitStage::Ast => "ast",
+            EmitStage::Symtab => "symtab",
+            EmitStage::PreSsaIr => "pre.ll",
+            EmitStage::Ir => "ll",
+            EmitStage::Mir => "mir",
+            EmitStage::Asm => "s",
+        }
+    }
 }
@@ -0,0 +1,264 @@
+use std::fmt::{self, Write};
+
+/// 源码中的字节区间 [start, end)，由 pest 的 Span 转换而来
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    // 覆盖两个区间的最小区间
+    pub fn to(&self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Self {
+        Span::new(span.start(), span.end())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub primary: Option<Label>,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            primary: None,
+            secondary: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    pub fn with_primary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.primary = Some(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl From<pest::error::Error<crate::parser::Rule>> for Diagnostic {
+    fn from(err: pest::error::Error<crate::parser::Rule>) -> Self {
+        let span = match err.location {
+            pest::error::InputLocation::Pos(pos) => Span::new(pos, pos),
+            pest::error::InputLocation::Span((start, end)) => Span::new(start, end),
+        };
+        Diagnostic::error(err.variant.message().to_string()).with_primary(span, "")
+    }
+}
+
+/// 待渲染诊断信息的源文件
+///
+/// `line_offset` 为源码前拼接的行数（如 prelude），渲染时从行号中减去
+pub struct SourceFile<'a> {
+    pub name: &'a str,
+    pub src: &'a str,
+    pub line_offset: usize,
+}
+
+impl<'a> SourceFile<'a> {
+    pub fn new(name: &'a str, src: &'a str, line_offset: usize) -> Self {
+        Self {
+            name,
+            src,
+            line_offset,
+        }
+    }
+
+    // 返回 (行号, 列号, 行内容)，行号列号从 1 开始
+    fn locate(&self, pos: usize) -> (usize, usize, &'a str) {
+        let pos = pos.min(self.src.len());
+        let line_start = self.src[..pos].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.src[pos..]
+            .find('\n')
+            .map_or(self.src.len(), |i| pos + i);
+        let line = self.src[..pos].matches('\n').count() + 1;
+        let col = self.src[line_start..pos].chars().count() + 1;
+        (line, col, &self.src[line_start..line_end])
+    }
+
+    fn display_line(&self, line: usize) -> usize {
+        line.saturating_sub(self.line_offset)
+    }
+
+    /// 以 rustc 的风格渲染诊断信息
+    ///
+    /// ```text
+    /// error: undefined identifier `x`
+    ///  --> a.sy:3:5
+    ///   |
+    /// 3 |     x = 1;
+    ///   |     ^ not found in this scope
+    /// ```
+    pub fn render(&self, diag: &Diagnostic) -> String {
+        let mut out = String::new();
+        writeln!(out, "{}: {}", diag.severity, diag.message).unwrap();
+
+        let mut labels = vec![];
+        if let Some(primary) = &diag.primary {
+            labels.push((primary, '^'));
+        }
+        labels.extend(diag.secondary.iter().map(|label| (label, '-')));
+
+        let width = labels
+            .iter()
+            .map(|(label, _)| self.display_line(self.locate(label.span.start).0))
+            .max()
+            .unwrap_or(0)
+            .to_string()
+            .len();
+        let pad = " ".repeat(width);
+
+        if let Some(primary) = &diag.primary {
+            let (line, col, _) = self.locate(primary.span.start);
+            writeln!(
+                out,
+                "{}--> {}:{}:{}",
+                pad,
+                self.name,
+                self.display_line(line),
+                col
+            )
+            .unwrap();
+        }
+        if !labels.is_empty() {
+            writeln!(out, "{} |", pad).unwrap();
+        }
+        labels.sort_by_key(|(label, _)| label.span.start);
+        for (label, marker) in labels {
+            let (line, col, text) = self.locate(label.span.start);
+            writeln!(
+                out,
+                "{:>width$} | {}",
+                self.display_line(line),
+                text,
+                width = width
+            )
+            .unwrap();
+            // 与源码行对齐，保留 tab
+            let indent: String = text
+                .chars()
+                .take(col - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let rest = text.chars().count() - (col - 1);
+            let len = self.src[label.span.start..label.span.end.max(label.span.start)]
+                .chars()
+                .count()
+                .min(rest)
+                .max(1);
+            let underline = marker.to_string().repeat(len);
+            let underline = if label.message.is_empty() {
+                underline
+            } else {
+                format!("{} {}", underline, label.message)
+            };
+            writeln!(out, "{} | {}{}", pad, indent, underline).unwrap();
+        }
+        for note in &diag.notes {
+            writeln!(out, "{} = note: {}", pad, note).unwrap();
+        }
+        out
+    }
+}
+
+#[test]
+fn test_render_diagnostic() {
+    let src = "int main() {\n    x = 1;\n}\n";
+    let file = SourceFile::new("a.sy", src, 0);
+    let diag = Diagnostic::error("undefined identifier `x`")
+        .with_primary(Span::new(17, 18), "not found in this scope")
+        .with_note("declare it first");
+    assert_eq!(
+        file.render(&diag),
+        "error: undefined identifier `x`\n \
+         --> a.sy:2:5\n  \
+         |\n\
+         2 |     x = 1;\n  \
+         |     ^ not found in this scope\n  \
+         = note: declare it first\n"
+    );
+}
+
+#[test]
+fn test_render_diagnostic_with_offset() {
+    let src = "int getint();\nint main() {\n  break;\n}";
+    let file = SourceFile::new("b.sy", src, 1);
+    let diag = Diagnostic::error("`break` outside of a loop")
+        .with_primary(Span::new(29, 35), "cannot `break` outside of a loop")
+        .with_secondary(Span::new(14, 24), "in this function");
+    assert_eq!(
+        file.render(&diag),
+        "error: `break` outside of a loop\n \
+         --> b.sy:2:3\n  \
+         |\n\
+         1 | int main() {\n  \
+         | ---------- in this function\n\
+         2 |   break;\n  \
+         |   ^^^^^^ cannot `break` outside of a loop\n"
+    );
+}
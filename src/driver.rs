@@ -1,49 +1,266 @@
-use log::{debug, trace};
+use std::{collections::HashSet, fs::OpenOptions, io::Write, path::Path, process::ExitCode};
+
+use log::{debug, log_enabled, trace, Level};
 
 use crate::{
     arm_printer,
-    cli::Args,
+    ast::TransUnit,
+    cli::{Args, EmitStage},
+    diagnostic::{Diagnostic, SourceFile},
     ir_builder,
-    ir_pass::{inst_namer, mem2reg},
+    ir_pass::{inst_namer, pass_manager::PassManager},
     ir_printer, mc_builder,
+    mc_pass::{frame_lowering, graph_coloring, if_conversion, peephole, reg_alloc},
     scope::SymbolTable,
     sema::ToSemaTrait,
 };
 
-pub fn drive(args: Args) {
+const PRELUDE: &str = include_str!("prelude.c");
+
+pub fn drive(args: Args) -> ExitCode {
     assert!(!args.inputs.is_empty());
-    let prelude = include_str!("prelude.c").to_string();
-    for f_input in args.inputs {
+    let mut passes = match PassManager::from_args(&args) {
+        Ok(passes) => passes,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut emitter = Emitter::new(&args);
+    let mut output = String::new();
+    let mut n_errors = 0;
+    for f_input in &args.inputs {
         trace!("compiling {:?}", f_input);
-        let mut src = std::fs::read_to_string(f_input).expect("unable to read file");
-        src = format!("{}\n{}", prelude, src);
-        trace!("================== SRC => AST ==================");
-        let ast = crate::parser::parse(&src);
-        trace!("ast: {:#?}", ast);
-        if ast.as_ref().err().is_some() {
-            panic!("unable to parse file");
+        let src = match std::fs::read_to_string(f_input) {
+            Ok(src) => format!("{}\n{}", PRELUDE, src),
+            Err(err) => {
+                eprintln!("error: unable to read `{}`: {}", f_input.display(), err);
+                n_errors += 1;
+                continue;
+            }
+        };
+        match compile(&src, &args, &mut passes, &mut emitter) {
+            Ok(text) => output.push_str(&text),
+            Err(diags) => {
+                // prelude 与源文件之间有一个换行
+                let line_offset = PRELUDE.matches('\n').count() + 1;
+                let name = f_input.display().to_string();
+                let file = SourceFile::new(&name, &src, line_offset);
+                for diag in &diags {
+                    eprintln!("{}", file.render(diag));
+                }
+                n_errors += diags.iter().filter(|d| d.is_error()).count();
+            }
         }
-        trace!("================== AST => SEMA+AST ==================");
-        let mut syms = SymbolTable::new();
-        let mut ast = ast.unwrap();
-        ast.to_sema(&mut syms);
-        trace!("syms: \n{}", syms.print_table());
-        trace!("ast: {:#?}", ast);
-        trace!("================== SEMA+AST => Pre-SSA IR ==================");
-        debug!(";{}", args.output.display());
-        let mut module = ir_builder::build(&mut ast, syms);
-        inst_namer::run(&mut module);
+    }
+    if args.time_passes {
+        eprint!("{}", passes.report_timings());
+    }
+    if n_errors > 0 {
+        eprintln!(
+            "error: aborting due to {} previous error{}",
+            n_errors,
+            if n_errors == 1 { "" } else { "s" }
+        );
+        return ExitCode::FAILURE;
+    }
+    if emitter.failed {
+        return ExitCode::FAILURE;
+    }
+    debug!("writing output to {}", args.output.display());
+    if let Err(err) = std::fs::write(&args.output, output) {
+        eprintln!(
+            "error: unable to write `{}`: {}",
+            args.output.display(),
+            err
+        );
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+// 编译单个源文件，返回写入 -o 的内容
+fn compile(
+    src: &str,
+    args: &Args,
+    passes: &mut PassManager,
+    emitter: &mut Emitter,
+) -> Result<String, Vec<Diagnostic>> {
+    trace!("================== SRC => AST ==================");
+    // 词法单元只输出用户源文件的，行号与源文件一致
+    emitter.emit(EmitStage::Tokens, || {
+        crate::parser::dump_tokens(&src[PRELUDE.len() + 1..]).unwrap_or_default()
+    });
+    let mut ast = crate::parser::parse(src).map_err(|err| vec![Diagnostic::from(*err)])?;
+    trace!("================== AST => SEMA+AST ==================");
+    let mut syms = SymbolTable::new();
+    ast.to_sema(&mut syms);
+    let diags = syms.take_diagnostics();
+    if diags.iter().any(Diagnostic::is_error) {
+        return Err(diags);
+    }
+    trace!("syms: \n{}", syms.print_table());
+    trace!("ast: {:#?}", ast);
+    // prelude 中的函数声明排在最前面，输出时去掉
+    let n_prelude = crate::parser::parse(PRELUDE).unwrap().func_decls.len();
+    emitter.emit(EmitStage::Ast, || {
+        let user_ast = TransUnit {
+            func_decls: ast.func_decls[n_prelude..].to_vec(),
+            var_decls: ast.var_decls.clone(),
+        };
+        format!("{:#?}\n", user_ast)
+    });
+    emitter.emit(EmitStage::Symtab, || {
+        let prelude = ast.func_decls[..n_prelude]
+            .iter()
+            .map(|func_decl| func_decl.sema_ref.as_ref().unwrap().symbol_id)
+            .collect::<Vec<_>>();
+        syms.print_table_without(&prelude)
+    });
+    trace!("================== SEMA+AST => Pre-SSA IR ==================");
+    let mut module = ir_builder::build(&mut ast, syms)?;
+    inst_namer::run(&mut module);
+    if log_enabled!(Level::Trace) {
         trace!("================== Pre-SSA Module as LLVM IR ==================");
-        debug!(";{}", args.output.display());
-        ir_printer::print(&mut module);
-        mem2reg::run(&mut module);
-        inst_namer::run(&mut module);
-
-        trace!("================== SSA Module as LLVM IR ==================");
-        debug!(";{}", args.output.display());
-        ir_printer::print(&mut module);
-        trace!("================== Arm Assembly Module ==================");
-        let mut arm_module = mc_builder::build(&mut module);
-        arm_printer::print(&mut arm_module);
+        trace!("\n{}", ir_printer::print(&mut module));
+    }
+    emitter.emit(EmitStage::PreSsaIr, || ir_printer::print(&mut module));
+    passes.run(&mut module);
+    inst_namer::run(&mut module);
+
+    trace!("================== SSA Module as LLVM IR ==================");
+    let need_backend =
+        args.assembly || args.emit.contains(&EmitStage::Mir) || args.emit.contains(&EmitStage::Asm);
+    if !need_backend {
+        let ir = ir_printer::print(&mut module);
+        emitter.emit(EmitStage::Ir, || ir.clone());
+        return Ok(ir);
+    }
+    emitter.emit(EmitStage::Ir, || ir_printer::print(&mut module));
+    trace!("================== Arm Assembly Module ==================");
+    let mut arm_module = mc_builder::build(&mut module);
+    emitter.emit(EmitStage::Mir, || arm_printer::print(&mut arm_module));
+    // -O2 及以上使用图着色分配，编译更慢但拷贝和溢出更少
+    let stats = if args.optimize_level >= 2 {
+        graph_coloring::run(&mut arm_module)
+    } else {
+        reg_alloc::run(&mut arm_module)
+    };
+    debug!("register allocation: {}", stats);
+    if args.optimize_level >= 1 {
+        let rewrites = peephole::run(&mut arm_module);
+        debug!("peephole: {} rewrites", rewrites);
+        // 合并后的块中还有可以改写的跳转和相邻指令
+        let converted = if_conversion::run(&mut arm_module);
+        debug!("if-conversion: {} branches", converted);
+        if converted > 0 {
+            peephole::run(&mut arm_module);
+        }
+    }
+    frame_lowering::run(&mut arm_module);
+    let asm = arm_printer::print(&mut arm_module);
+    emitter.emit(EmitStage::Asm, || asm.clone());
+    if args.assembly {
+        Ok(asm)
+    } else {
+        Ok(ir_printer::print(&mut module))
+    }
+}
+
+/// 将 `--emit` 指定的阶段写入 -o 旁边的文件
+///
+/// 各阶段产出后立即写入，后续阶段崩溃时也能保留
+struct Emitter<'a> {
+    stages: &'a [EmitStage],
+    output: &'a Path,
+    written: HashSet<EmitStage>,
+    /// 有阶段写入失败
+    failed: bool,
+}
+
+impl<'a> Emitter<'a> {
+    fn new(args: &'a Args) -> Self {
+        Self {
+            stages: &args.emit,
+            output: &args.output,
+            written: HashSet::new(),
+            failed: false,
+        }
+    }
+
+    fn emit(&mut self, stage: EmitStage, render: impl FnOnce() -> String) {
+        if !self.stages.contains(&stage) {
+            return;
+        }
+        let path = self.output.with_extension(stage.extension());
+        debug!("writing {:?} to {}", stage, path.display());
+        // 多个输入文件时追加到同一个文件
+        let first = self.written.insert(stage);
+        let result = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(!first)
+            .truncate(first)
+            .open(&path)
+            .and_then(|mut file| file.write_all(render().as_bytes()));
+        if let Err(err) = result {
+            // 同一阶段只报告一次
+            if first {
+                eprintln!("error: unable to write `{}`: {}", path.display(), err);
+            }
+            self.failed = true;
+        }
+    }
+}
+
+#[test]
+fn test_emit_user_source() {
+    use clap::Parser;
+
+    let dir = std::env::temp_dir().join(format!("rockc-emit-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("main.sy");
+    std::fs::write(
+        &input,
+        "int g;\nint main() {\n  putint(g);\n  return 0;\n}\n",
+    )
+    .unwrap();
+    let output = dir.join("main.ll");
+    let args = Args::parse_from([
+        "rockc".as_ref(),
+        input.as_os_str(),
+        "-o".as_ref(),
+        output.as_os_str(),
+        "--emit=tokens,ast,symtab".as_ref(),
+    ]);
+    drive(args);
+
+    // 行号从用户源文件的第一行开始，prelude 中的声明不出现在输出中
+    let tokens = std::fs::read_to_string(dir.join("main.tokens")).unwrap();
+    assert!(tokens.starts_with("1:1\t"));
+    assert!(tokens.contains("3:3\tid\t\"putint\""));
+    let symtab = std::fs::read_to_string(dir.join("main.symtab")).unwrap();
+    assert!(symtab.starts_with("g: ") && !symtab.contains("putint: "));
+    let ast = std::fs::read_to_string(dir.join("main.ast")).unwrap();
+    assert_eq!(ast.matches("FuncDecl {").count(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_compile_folded_compare() {
+    use clap::Parser;
+
+    // x == x 折叠为常数后，其上的 zext 也要能生成汇编
+    let src = format!(
+        "{}\n{}",
+        PRELUDE, "int getint(); int main(){ int x = getint(); putint(x == x); return 0; }"
+    );
+    for level in ["-O1", "-O2"] {
+        let args = Args::parse_from(["rockc", "main.sy", level, "-S", "-o", "main.s"]);
+        let mut passes = PassManager::from_args(&args).unwrap();
+        let mut emitter = Emitter::new(&args);
+        let asm = compile(&src, &args, &mut passes, &mut emitter).unwrap();
+        assert!(asm.contains("main:"));
+        assert!(asm.contains("BL\tputint"));
     }
 }
@@ -56,7 +56,8 @@ impl Literal {
         match self {
             Literal::Int(_) => Type::Builtin(BuiltinType::Int),
             Literal::Char(_) => Type::Builtin(BuiltinType::Char),
-            Literal::Float(_) => Type::Builtin(BuiltinType::Double),
+            // SysY 中只有单精度浮点
+            Literal::Float(_) => Type::Builtin(BuiltinType::Float),
             Literal::Bool(_) => Type::Builtin(BuiltinType::Bool),
             Literal::String(_) => Type::Pointer(PointerType::new(Type::Builtin(BuiltinType::Char))),
             Literal::ArrayInitVal(_) => todo!(),
@@ -66,14 +67,23 @@ impl Literal {
 
 impl InferEvaluator for InfixExpr {
     fn infer_type(&self, syms: &SymbolTable) -> Option<Type> {
-        let lhs_type = self.lhs.infer_type(syms)?;
-        let rhs_type = self.rhs.infer_type(syms)?;
+        let lhs_type = self.lhs.infer_type(syms)?.promote();
+        let rhs_type = self.rhs.infer_type(syms)?.promote();
 
         // 对于不同类型的运算，C 语言规定需要进行类型转换
         match self.op {
-            InfixOp::Add | InfixOp::Sub | InfixOp::Mul | InfixOp::Div | InfixOp::Mod => {
-                // 如果左右都是算数类型，则返回右侧值的类型
+            InfixOp::Add | InfixOp::Sub | InfixOp::Mul | InfixOp::Div => {
+                // 一般算术转换：有一侧是浮点则结果为浮点
                 if lhs_type.is_arithmetic() && rhs_type.is_arithmetic() {
+                    if lhs_type.is_float() {
+                        return Some(lhs_type);
+                    }
+                    return Some(rhs_type);
+                }
+            }
+            InfixOp::Mod => {
+                // 取模只能用于整数
+                if lhs_type.is_integer() && rhs_type.is_integer() {
                     return Some(rhs_type);
                 }
             }
@@ -97,7 +107,13 @@ impl InferEvaluator for InfixExpr {
                 // 返回 bool 类型
                 return Some(Type::Builtin(BuiltinType::Bool));
             }
-            InfixOp::Assign | InfixOp::Rem => {
+            InfixOp::Assign => {
+                // 赋值时右侧隐式转换为左侧的类型
+                if lhs_type.is_arithmetic() && rhs_type.is_arithmetic() {
+                    return Some(lhs_type);
+                }
+            }
+            InfixOp::Rem => {
                 // 如果左右类型一致，则返回该类型
                 if lhs_type == rhs_type {
                     return Some(lhs_type);
@@ -156,20 +172,29 @@ impl InferEvaluator for PrefixExpr {
                 }
             }
             PrefixOp::Pos | PrefixOp::Neg => {
+                let rhs_type = rhs_type.promote();
                 if rhs_type.is_arithmetic() {
                     Some(rhs_type)
                 } else {
                     None
                 }
             }
-            PrefixOp::Not | PrefixOp::BitNot => {
-                // 取反运算符只能应用于整数或布尔类型，返回 bool 类型
-                if rhs_type.is_integer() || rhs_type == Type::Builtin(BuiltinType::Bool) {
+            PrefixOp::Not => {
+                // 逻辑非可以应用于整数、布尔或浮点类型，返回 bool 类型
+                if rhs_type.is_integer() || rhs_type == Type::Builtin(BuiltinType::Float) {
                     Some(Type::Builtin(BuiltinType::Bool))
                 } else {
                     None
                 }
             }
+            PrefixOp::BitNot => {
+                // 按位取反只能应用于整数类型，bool 提升为 int
+                if rhs_type.is_integer() {
+                    Some(rhs_type.promote())
+                } else {
+                    None
+                }
+            }
         }
     }
 
@@ -243,10 +268,11 @@ impl PostfixExpr {
 
     fn infer_type_index_access(&self, _lhs_type: &Type, _index: &Box<Expr>) -> Option<Type> {
         // 如果对一个类型进行索引访问，则返回该类型的元素类型
+        // 非数组类型由语义分析报告错误
         if let Type::Array(arr_ty) = _lhs_type {
             Some(arr_ty.element_type().clone())
         } else {
-            panic!("index access on non-array type")
+            None
         }
     }
 
@@ -565,6 +591,8 @@ impl Literal {
     pub fn not(&self) -> Option<Literal> {
         match self {
             Literal::Bool(b) => Some(Literal::Bool(!b)),
+            Literal::Int(n) => Some(Literal::Bool(*n == 0)),
+            Literal::Float(n) => Some(Literal::Bool(*n == 0.0)),
             _ => None,
         }
     }
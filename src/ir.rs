@@ -110,6 +110,8 @@ impl Module {
                         let jmp_bb = self.value_parent[&user_id];
                         preds.push(jmp_bb);
                     }
+                    // phi 的 incoming 也会登记对基本块的使用
+                    InstValue::Phi(_) => {}
                     _ => panic!("expect a branch or jump instruction"),
                 },
                 _ => panic!("expect an instruction"),
@@ -178,6 +180,13 @@ impl Module {
         }
     }
 
+    pub fn get_global_var_mut(&mut self, var_id: ValueId) -> &mut GlobalVariableValue {
+        match &mut self.values[var_id] {
+            Value::GlobalVariable(gv) => gv,
+            _ => panic!("expect a global variable"),
+        }
+    }
+
     pub fn get_value(&self, val_id: ValueId) -> &Value {
         &self.values[val_id]
     }
@@ -214,6 +223,14 @@ impl Module {
         self.get_bb(self.cur_bb.unwrap())
     }
 
+    /// 当前基本块是否已经以终结指令结束
+    pub fn cur_bb_terminated(&self) -> bool {
+        self.cur_bb()
+            .insts
+            .last()
+            .is_some_and(|inst_id| self.get_inst(*inst_id).is_term())
+    }
+
     pub fn cur_func(&self) -> &FunctionValue {
         self.get_func(self.cur_func.unwrap())
     }
@@ -222,6 +239,10 @@ impl Module {
         self.cur_bb = Some(bb);
     }
 
+    pub fn insert_point(&self) -> ValueId {
+        self.cur_bb.unwrap()
+    }
+
     pub fn set_cur_func(&mut self, func: ValueId) {
         self.cur_func = Some(func);
     }
@@ -351,16 +372,12 @@ impl Module {
             self.inspect_value(value_id),
             self.inspect_value(new_value_id)
         );
-        let tmp = self.value_user.get(&value_id);
-        if tmp.is_none() {
+        // 没有登记 user 时仍然替换操作数，user 记录可能不完整
+        let all_users = self.value_user.remove(&value_id).unwrap_or_default();
+        if all_users.is_empty() {
             warn!("value {} is not used", self.inspect_value(value_id));
-            self.value_using.remove(&value_id);
-            return;
         }
 
-        let all_users = tmp.unwrap().clone();
-        self.value_user.remove(&value_id);
-
         // 将所有使用 value_id 的用户，替换为使用 new_value_id
         // 相当于重新进行 mark_using 操作
         for cur_user in all_users {
@@ -368,6 +385,10 @@ impl Module {
                 used_by_users.retain(|&x| x != value_id);
                 used_by_users.push(new_value_id);
             }
+            let users = self.value_user.entry(new_value_id).or_default();
+            if !users.contains(&cur_user) {
+                users.push(cur_user);
+            }
         }
 
         // 将所有 value 对 value_id 的使用替换为 new_value_id
@@ -387,7 +408,129 @@ impl Module {
         //     }
         // });
 
-        self.value_using.remove(&value_id);
+        // 旧的 value 不再使用任何 value
+        for used in self.value_using.remove(&value_id).unwrap_or_default() {
+            if let Some(users) = self.value_user.get_mut(&used) {
+                users.retain(|&x| x != value_id);
+            }
+        }
+    }
+
+    /// 把指令从所在基本块中删除，并清除它对其他 value 的使用记录
+    ///
+    /// 调用前确保没有指令再使用它，通常先 replace_value
+    pub fn remove_inst(&mut self, inst_id: ValueId) {
+        self.mark_nolonger_using_any(inst_id);
+        if let Some(bb_id) = self.value_parent.remove(&inst_id) {
+            self.get_bb_mut(bb_id).insts.retain(|&x| x != inst_id);
+        }
+    }
+
+    /// 删除 phi 中来自 bb_id 的 incoming
+    pub fn remove_phi_incoming(&mut self, phi_id: ValueId, bb_id: ValueId) {
+        let phi = match self.get_inst_mut(phi_id) {
+            InstValue::Phi(phi) => phi,
+            _ => panic!("expect a phi instruction"),
+        };
+        let removed = phi
+            .incomings
+            .iter()
+            .filter(|(_, bb)| *bb == bb_id)
+            .copied()
+            .collect::<Vec<_>>();
+        phi.incomings.retain(|(_, bb)| *bb != bb_id);
+        let operands = self.get_inst(phi_id).operands();
+        for (value, bb) in removed {
+            for used in [value, bb] {
+                if !operands.contains(&used) {
+                    self.mark_nolonger_using(phi_id, used);
+                }
+            }
+        }
+    }
+
+    /// 把终结指令中跳转到 old_bb 的目标改为 new_bb
+    pub fn replace_successor(&mut self, term_id: ValueId, old_bb: ValueId, new_bb: ValueId) {
+        self.get_inst_mut(term_id).replace_operands(old_bb, new_bb);
+        self.mark_nolonger_using(term_id, old_bb);
+        if !self.value_using[&term_id].contains(&new_bb) {
+            self.mark_using(term_id, new_bb);
+        }
+    }
+
+    /// 把 phi 中来自 old_bb 的 incoming 改为来自 new_bb
+    pub fn replace_phi_incoming_bb(&mut self, phi_id: ValueId, old_bb: ValueId, new_bb: ValueId) {
+        match self.get_inst_mut(phi_id) {
+            InstValue::Phi(phi) => phi.incomings.iter_mut().for_each(|(_, bb)| {
+                if *bb == old_bb {
+                    *bb = new_bb;
+                }
+            }),
+            _ => panic!("expect a phi instruction"),
+        }
+        self.mark_nolonger_using(phi_id, old_bb);
+        if !self.value_using[&phi_id].contains(&new_bb) {
+            self.mark_using(phi_id, new_bb);
+        }
+    }
+
+    /// 从函数中删除基本块及其中的所有指令
+    ///
+    /// 调用前确保没有其他基本块跳转到它，也没有 phi 以它为来源
+    pub fn remove_bb(&mut self, func_id: ValueId, bb_id: ValueId) {
+        for inst_id in self.get_bb(bb_id).insts.clone() {
+            self.remove_inst(inst_id);
+        }
+        self.get_func_mut(func_id).bbs.remove(bb_id);
+    }
+
+    /// 基本块的终结指令
+    pub fn get_terminator(&self, bb_id: ValueId) -> ValueId {
+        *self.get_bb(bb_id).insts.last().unwrap()
+    }
+
+    /// 新建一条指令插入到基本块的 pos 处，并登记它使用的 value
+    pub fn insert_inst(&mut self, bb_id: ValueId, pos: usize, inst: InstValue) -> ValueId {
+        let operands = inst.operands();
+        let inst_id = self.alloc_value(Value::Instruction(inst));
+        for opr in operands {
+            self.mark_using(inst_id, opr);
+        }
+        self.get_bb_mut(bb_id).insts.insert(pos, inst_id);
+        self.mark_parent(inst_id, bb_id);
+        inst_id
+    }
+
+    /// 复制一条指令插入到基本块的 pos 处，操作数按 map 替换，包括跳转目标和 phi 的来源基本块
+    pub fn clone_inst(
+        &mut self,
+        inst_id: ValueId,
+        bb_id: ValueId,
+        pos: usize,
+        map: &HashMap<ValueId, ValueId>,
+    ) -> ValueId {
+        let mut inst = self.get_inst(inst_id).clone();
+        if let InstValue::Phi(phi) = &mut inst {
+            for (value, bb) in &mut phi.incomings {
+                *value = *map.get(value).unwrap_or(value);
+                *bb = *map.get(bb).unwrap_or(bb);
+            }
+        } else {
+            for opr in inst.operands() {
+                if let Some(new) = map.get(&opr) {
+                    inst.replace_operands(opr, *new);
+                }
+            }
+        }
+        self.insert_inst(bb_id, pos, inst)
+    }
+
+    /// 把已有的指令移动到另一个基本块的 pos 处
+    pub fn move_inst(&mut self, inst_id: ValueId, bb_id: ValueId, pos: usize) {
+        let old_bb = self.get_parent_id(inst_id);
+        self.get_bb_mut(old_bb).insts.retain(|&x| x != inst_id);
+        self.get_bb_mut(bb_id).insts.insert(pos, inst_id);
+        self.mark_parent(inst_id, bb_id);
     }
 
     pub fn get_parent_id(&self, value_id: ValueId) -> ValueId {
@@ -432,23 +575,16 @@ impl Module {
         let phi_value = self.values.get_mut(phi_id).unwrap();
         match phi_value {
             Value::Instruction(InstValue::Phi(phi_inst)) => {
+                // replace_value 之后部分记录可能已经不存在
                 phi_inst.incomings.iter().for_each(|(value_id, bb_id)| {
-                    self.value_user
-                        .get_mut(value_id)
-                        .unwrap()
-                        .retain(|&x| x != phi_id);
-                    self.value_user
-                        .get_mut(bb_id)
-                        .unwrap()
-                        .retain(|&x| x != phi_id);
-                    self.value_using
-                        .get_mut(&phi_id)
-                        .unwrap()
-                        .retain(|&x| x != *value_id);
-                    self.value_using
-                        .get_mut(&phi_id)
-                        .unwrap()
-                        .retain(|&x| x != *bb_id);
+                    for used in [value_id, bb_id] {
+                        if let Some(users) = self.value_user.get_mut(used) {
+                            users.retain(|&x| x != phi_id);
+                        }
+                        if let Some(using) = self.value_using.get_mut(&phi_id) {
+                            using.retain(|x| x != used);
+                        }
+                    }
                 });
             }
             _ => panic!("expect a phi instruction"),
@@ -486,12 +622,15 @@ impl Module {
         let call_inst = CallInst {
             ty: func_value.ty(),
             func,
-            args,
+            args: args.clone(),
             must_tail: false,
         };
         let val_id = self.alloc_value(call_inst.into());
 
         self.mark_using(val_id, func);
+        for arg in args {
+            self.mark_using(val_id, arg);
+        }
 
         self.cur_bb_mut().insts.push(val_id);
         self.mark_parent(val_id, self.cur_bb.unwrap());
@@ -646,6 +785,15 @@ impl Module {
         br_id
     }
 
+    pub fn spawn_cast_inst(&mut self, op: CastOp, value: ValueId, new_ty: Type) -> ValueId {
+        let cast = CastInst { op, value, new_ty };
+        let cast_id = self.alloc_value(cast.into());
+        self.mark_using(cast_id, value);
+        self.cur_bb_mut().insts.push(cast_id);
+        self.mark_parent(cast_id, self.cur_bb.unwrap());
+        cast_id
+    }
+
     pub fn alloc_basic_block(&mut self) -> ValueId {
         let bb = BasicBlockValue::default();
 
@@ -660,14 +808,14 @@ impl Module {
     pub fn spawn_alloca_inst(&mut self, name: String, ty: Type) -> ValueId {
         let alloca = AllocaInst { name, ty };
         let alloca_id = self.alloc_value(alloca.into());
-        let entry_bb_id = self.cur_func().bbs.entry_bb();
+        let entry_bb_id = *self.cur_func().bbs.entry_bb();
 
         // 用于确定插入位置的临时变量
         let ins_pos;
 
         // 使用一个新的作用域来查找插入位置
         {
-            let entry_bb = self.get_bb(*entry_bb_id);
+            let entry_bb = self.get_bb(entry_bb_id);
             ins_pos = entry_bb
                 .insts
                 .iter()
@@ -676,8 +824,9 @@ impl Module {
         }
 
         // 在找到的位置插入新的alloca指令
-        let entry_bb_mut = self.get_bb_mut(*entry_bb_id);
+        let entry_bb_mut = self.get_bb_mut(entry_bb_id);
         entry_bb_mut.insts.insert(ins_pos, alloca_id);
+        self.mark_parent(alloca_id, entry_bb_id);
 
         alloca_id
     }
@@ -800,6 +949,17 @@ impl BasicBlockList {
         self.bbs.insert(name, bb_id);
     }
 
+    pub fn remove(&mut self, bb_id: ValueId) {
+        let name = self
+            .bbs
+            .iter()
+            .find(|(_, id)| **id == bb_id)
+            .map(|(name, _)| name.clone());
+        if let Some(name) = name {
+            self.bbs.remove(&name);
+        }
+    }
+
     pub fn entry_bb(&self) -> &ValueId {
         self.bbs.get("entry").unwrap()
     }
@@ -1074,6 +1234,44 @@ impl InstValue {
             InstValue::Cast(inst) => inst.replace_operands(old_value_id, new_value_id),
         }
     }
+
+    /// 指令使用的所有 Value，包括跳转目标和 phi 的来源基本块
+    pub fn operands(&self) -> Vec<ValueId> {
+        match self {
+            InstValue::InfixOp(op) => vec![op.lhs, op.rhs],
+            InstValue::Load(inst) => vec![inst.ptr],
+            InstValue::Store(inst) => vec![inst.value, inst.ptr],
+            InstValue::Alloca(_) => vec![],
+            InstValue::Branch(inst) => vec![inst.cond, inst.then_bb, inst.else_bb],
+            InstValue::Jump(inst) => vec![inst.bb],
+            InstValue::Gep(inst) => {
+                let mut ops = vec![inst.ptr];
+                ops.extend(&inst.indices);
+                ops
+            }
+            InstValue::Return(inst) => inst.value.into_iter().collect(),
+            InstValue::Call(inst) => {
+                let mut ops = vec![inst.func];
+                ops.extend(&inst.args);
+                ops
+            }
+            InstValue::Phi(inst) => inst
+                .incomings
+                .iter()
+                .flat_map(|(val, bb)| [*val, *bb])
+                .collect(),
+            InstValue::Cast(inst) => vec![inst.value],
+        }
+    }
+
+    /// 基本块的后继，非终结指令返回空
+    pub fn successors(&self) -> Vec<ValueId> {
+        match self {
+            InstValue::Branch(inst) => vec![inst.then_bb, inst.else_bb],
+            InstValue::Jump(inst) => vec![inst.bb],
+            _ => vec![],
+        }
+    }
 }
 
 macro_rules! impl_replace_operands {
@@ -1146,7 +1344,10 @@ impl ConstValue {
                     value: 0.0,
                 }),
                 BuiltinType::Void => todo!(),
-                BuiltinType::Bool => todo!(),
+                BuiltinType::Bool => ConstValue::Int(ConstInt {
+                    ty: BuiltinType::Bool.into(),
+                    value: 0,
+                }),
                 BuiltinType::UChar => todo!(),
                 BuiltinType::Char => todo!(),
                 BuiltinType::UShort => todo!(),
@@ -1480,3 +1681,68 @@ impl From<PhiInst> for Value {
         Value::Instruction(InstValue::Phi(val))
     }
 }
+
+#[test]
+fn test_replace_value() {
+    use crate::ir_pass::build_ir;
+
+    let mut module = build_ir("int main() { int i = 0; while (i < 10) { i = i + 1; } return i; }");
+
+    let func = module.get_func(module.functions["main"]);
+    let phi_id = func
+        .bbs
+        .bbs
+        .values()
+        .flat_map(|bb_id| module.get_phis(*bb_id))
+        .next()
+        .unwrap();
+    let (add_id, _) = *module
+        .get_inst(phi_id)
+        .as_phi()
+        .incomings
+        .iter()
+        .find(|(value, _)| module.try_get_inst(*value).is_some())
+        .unwrap();
+
+    // 替换后的 Value 继承原来的 user，删除 phi 的来源时记录一并清除
+    let two = module.alloc_value(
+        ConstInt {
+            ty: BuiltinType::Int.into(),
+            value: 2,
+        }
+        .into(),
+    );
+    module.replace_value(add_id, two);
+    assert!(module.get_users_of(two).contains(&phi_id));
+    module.remove_phi_all_operands(phi_id);
+    assert!(!module.get_users_of(two).contains(&phi_id));
+}
+
+#[test]
+fn test_use_lists() {
+    use crate::ir_pass::build_ir;
+
+    let module = build_ir(
+        "int g(int x) { return x; }
+        int f(int a) { int b = a + 1; return g(b * 2); }",
+    );
+
+    // 实参记录了 call 这个 user；mem2reg 删除的 store 不再是任何值的 user
+    let func = module.get_func(module.functions["f"]);
+    let insts = func
+        .bbs
+        .bbs
+        .values()
+        .flat_map(|bb_id| module.get_bb(*bb_id).insts.clone())
+        .collect::<Vec<_>>();
+    for inst_id in &insts {
+        if let InstValue::Call(call) = module.get_inst(*inst_id) {
+            for arg in &call.args {
+                assert!(module.get_users_of(*arg).contains(inst_id));
+            }
+        }
+        for user in module.get_users_of(*inst_id) {
+            assert!(insts.contains(&user));
+        }
+    }
+}
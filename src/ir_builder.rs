@@ -2,17 +2,23 @@ use std::collections::VecDeque;
 
 use log::{debug, trace};
 
-use crate::{ast::*, infer_eval::InferEvaluator, ir::*, scope::*};
+use crate::{
+    ast::*, diagnostic::Diagnostic, infer_eval::InferEvaluator, ir::*, scope::*,
+};
 
-pub fn build(ast: &mut TransUnit, syms: SymbolTable) -> Module {
+pub fn build(ast: &mut TransUnit, syms: SymbolTable) -> Result<Module, Vec<Diagnostic>> {
     let mut builder = Builder::new(syms);
     builder.build_module(ast);
-    builder.module
+    if builder.diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(builder.diagnostics);
+    }
+    Ok(builder.module)
 }
 
 struct Builder {
     module: Module,
     loop_stack: Vec<(ValueId, ValueId)>, // (break target bb, continue target bb)
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Builder {
@@ -20,6 +26,7 @@ impl Builder {
         Self {
             module: Module::new(syms),
             loop_stack: Vec::new(),
+            diagnostics: Vec::new(),
         }
     }
 
@@ -36,6 +43,11 @@ impl Builder {
     pub fn build_function(&mut self, func_decl: &FuncDecl) {
         let name = func_decl.name.clone();
         let is_external = func_decl.is_external();
+        // 原型和定义共用一个函数，重复的原型不再生成
+        let proto_id = self.module.functions.get(&name).copied();
+        if proto_id.is_some() && is_external {
+            return;
+        }
         let ret_ty = self.build_type(&func_decl.ret_ty);
 
         let mut params = Vec::new();
@@ -66,10 +78,20 @@ impl Builder {
             self.module.set_insert_point(entry_bb_id);
         }
 
-        let function_id = self.module.alloc_value(Value::Function(cur_func));
-        self.module
-            .functions
-            .insert(func_decl.name.clone(), function_id);
+        // 先前的调用引用的是原型的 ValueId，定义直接替换原型
+        let function_id = match proto_id {
+            Some(proto_id) => {
+                self.module.values[proto_id] = Value::Function(cur_func);
+                proto_id
+            }
+            None => {
+                let function_id = self.module.alloc_value(Value::Function(cur_func));
+                self.module
+                    .functions
+                    .insert(func_decl.name.clone(), function_id);
+                function_id
+            }
+        };
 
         self.module.set_cur_func(function_id);
         if !is_external {
@@ -90,6 +112,15 @@ impl Builder {
             if let Some(body) = &func_decl.body {
                 self.build_block_statement(body);
             }
+            // 控制流到达函数末尾时补上默认的返回
+            if !self.module.cur_bb_terminated() {
+                let ret_ty = self.module.cur_func().ret_ty.clone();
+                let value = match ret_ty {
+                    Type::Builtin(BuiltinType::Void) => None,
+                    ty => Some(self.module.spawn_zero_value(ty)),
+                };
+                self.module.spawn_return_inst(value);
+            }
         }
     }
 
@@ -254,9 +285,9 @@ impl Builder {
             ConstValue::Int(_int) => InitVal::Expr(Box::new(Expr::Primary(PrimaryExpr::Literal(
                 Literal::Int(_int.value),
             )))),
-            _ => {
-                todo!()
-            }
+            ConstValue::Float(float) => InitVal::Expr(Box::new(Expr::Primary(
+                PrimaryExpr::Literal(Literal::Float(float.value)),
+            ))),
         }
     }
 
@@ -265,11 +296,25 @@ impl Builder {
             InitVal::Expr(expr) => {
                 // debug!("expr: {:?}", expr);
                 let literal = expr.eval_literal(&self.module.syms).unwrap();
+                // 初始值按声明的类型转换
+                let is_float = *type_.base_type() == BuiltinType::Float.into();
                 match literal {
+                    Literal::Int(val) if is_float => ConstValue::Float(ConstFloat {
+                        ty: type_.clone(),
+                        value: val as f64,
+                    }),
                     Literal::Int(val) => ConstValue::Int(ConstInt {
                         ty: type_.clone(),
                         value: val,
                     }),
+                    Literal::Float(val) if is_float => ConstValue::Float(ConstFloat {
+                        ty: type_.clone(),
+                        value: val,
+                    }),
+                    Literal::Float(val) => ConstValue::Int(ConstInt {
+                        ty: type_.clone(),
+                        value: val as i64,
+                    }),
                     _ => todo!(),
                 }
             }
@@ -296,9 +341,12 @@ impl Builder {
         }
     }
 
-    pub fn build_init_val(&mut self, init_val: &InitVal, _type_: &Type) -> ValueId {
+    pub fn build_init_val(&mut self, init_val: &InitVal, type_: &Type) -> ValueId {
         match init_val {
-            InitVal::Expr(expr) => self.build_expr(expr, false),
+            InitVal::Expr(expr) => {
+                let val_id = self.build_expr(expr, false);
+                self.build_conversion(val_id, type_)
+            }
             InitVal::Array(_array_init_val) => {
                 unimplemented!()
                 // let ty = Type::Array(ArrayType::Constant(ConstantArrayType {
@@ -344,9 +392,9 @@ impl Builder {
             Stmt::Block(block_stmt) => {
                 self.build_block_statement(block_stmt);
             }
-            Stmt::Break => self.build_break_statement(),
+            Stmt::Break(break_stmt) => self.build_break_statement(break_stmt),
             Stmt::DoWhile(do_while_stmt) => self.build_do_while_statement(do_while_stmt),
-            Stmt::Continue => self.build_continue_statement(),
+            Stmt::Continue(continue_stmt) => self.build_continue_statement(continue_stmt),
         }
     }
 
@@ -392,11 +440,10 @@ impl Builder {
 
             self.module.set_insert_point(body_bb);
             self.build_statement(&do_while_stmt.stmt);
-            self.module.spawn_jump_inst(cond_bb);
+            self.build_fallthrough(cond_bb);
 
             self.module.set_insert_point(cond_bb);
-            let cond_value = self.build_expr(&do_while_stmt.cond, false);
-            self.module.spawn_br_inst(cond_value, body_bb, end_bb);
+            self.visit_cond_expr(&do_while_stmt.cond, body_bb, end_bb);
 
             self.module.cur_func_mut().bbs.append(end_bb);
 
@@ -405,18 +452,26 @@ impl Builder {
         self.loop_stack.pop();
     }
 
-    pub fn build_break_statement(&mut self) {
+    pub fn build_break_statement(&mut self, break_stmt: &BreakStmt) {
         if self.loop_stack.is_empty() {
-            panic!("break statement not in loop");
+            self.diagnostics.push(
+                Diagnostic::error("break statement not in loop")
+                    .with_primary(break_stmt.span, "cannot `break` outside of a loop"),
+            );
+            return;
         }
 
         let (break_bb, _) = *self.loop_stack.last().unwrap();
         self.module.spawn_jump_inst(break_bb);
     }
 
-    pub fn build_continue_statement(&mut self) {
+    pub fn build_continue_statement(&mut self, continue_stmt: &ContinueStmt) {
         if self.loop_stack.is_empty() {
-            panic!("continue statement not in loop");
+            self.diagnostics.push(
+                Diagnostic::error("continue statement not in loop")
+                    .with_primary(continue_stmt.span, "cannot `continue` outside of a loop"),
+            );
+            return;
         }
 
         let (_, cont_bb) = *self.loop_stack.last().unwrap();
@@ -425,10 +480,21 @@ impl Builder {
 
     pub fn build_block_statement(&mut self, block_stmt: &Block) {
         for stmt in &block_stmt.stmts {
+            // return、break、continue 之后的语句不可达
+            if self.module.cur_bb_terminated() {
+                break;
+            }
             self.build_statement(stmt);
         }
     }
 
+    /// 语句体执行完后跳到 bb，语句体已经以终结指令结束时不再跳转
+    fn build_fallthrough(&mut self, bb_id: ValueId) {
+        if !self.module.cur_bb_terminated() {
+            self.module.spawn_jump_inst(bb_id);
+        }
+    }
+
     pub fn build_var_decls_statement(&mut self, var_decls_stmt: &VarDecls) {
         for decl in &var_decls_stmt.decls {
             let alloca_id = self
@@ -498,21 +564,133 @@ impl Builder {
             self.module.set_insert_point(next_bb);
             self.visit_cond_expr(&infix_expr.rhs, true_bb, false_bb);
         } else {
-            let cond_val_id = self.build_expr(cond_expr, false);
-            println!("cond_val_id: {}", self.module.inspect_value(cond_val_id));
-            // 如果不是 bool(i1) 类型，则生成一个比较指令
-            let ty = self.module.get_value(cond_val_id).ty();
-            let cond_val_id = if ty != BuiltinType::Bool.into() {
-                let zero_val_id = self.module.spawn_zero_value(ty.clone());
-                self.module
-                    .spawn_binop_inst(ty, InfixOp::Ne, cond_val_id, zero_val_id)
-            } else {
-                cond_val_id
-            };
+            let cond_val_id = self.build_bool_val(cond_expr);
             self.module.spawn_br_inst(cond_val_id, true_bb, false_bb);
         }
     }
 
+    /// 求值为 i1，不是 bool 类型时与 0 比较
+    pub fn build_bool_val(&mut self, expr: &Expr) -> ValueId {
+        let val_id = self.build_expr(expr, false);
+        let ty = self.module.get_value(val_id).ty();
+        if ty == BuiltinType::Bool.into() {
+            return val_id;
+        }
+        let zero_val_id = self.module.spawn_zero_value(ty);
+        self.module
+            .spawn_binop_inst(BuiltinType::Bool.into(), InfixOp::Ne, val_id, zero_val_id)
+    }
+
+    /// 作为右值使用的表达式，i1 零扩展为 i32
+    pub fn build_rvalue(&mut self, expr: &Expr) -> ValueId {
+        let val_id = self.build_expr(expr, false);
+        self.zext_bool(val_id)
+    }
+
+    pub fn zext_bool(&mut self, val_id: ValueId) -> ValueId {
+        if self.module.get_value(val_id).ty() != BuiltinType::Bool.into() {
+            return val_id;
+        }
+        self.module
+            .spawn_cast_inst(CastOp::ZExt, val_id, BuiltinType::Int.into())
+    }
+
+    /// 按 SysY 的隐式类型转换规则将值转换为 ty，常量直接折叠
+    pub fn build_conversion(&mut self, val_id: ValueId, ty: &Type) -> ValueId {
+        let from = self.module.get_value(val_id).ty();
+        if from == *ty {
+            return val_id;
+        }
+        let (Type::Builtin(from_bt), Type::Builtin(to_bt)) = (&from, ty) else {
+            return val_id;
+        };
+        match (from_bt, to_bt) {
+            (_, BuiltinType::Bool) => {
+                let zero_val_id = self.module.spawn_zero_value(from);
+                self.module.spawn_binop_inst(
+                    BuiltinType::Bool.into(),
+                    InfixOp::Ne,
+                    val_id,
+                    zero_val_id,
+                )
+            }
+            (BuiltinType::Bool, _) => {
+                let val_id = self.zext_bool(val_id);
+                self.build_conversion(val_id, ty)
+            }
+            (BuiltinType::Int, BuiltinType::Float) => {
+                if let Value::Const(ConstValue::Int(c)) = self.module.get_value(val_id) {
+                    let folded = ConstFloat {
+                        ty: ty.clone(),
+                        value: c.value as f64,
+                    };
+                    return self.module.alloc_value(folded.into());
+                }
+                self.module
+                    .spawn_cast_inst(CastOp::SIToFP, val_id, ty.clone())
+            }
+            (BuiltinType::Float, BuiltinType::Int) => {
+                if let Value::Const(ConstValue::Float(c)) = self.module.get_value(val_id) {
+                    let folded = ConstInt {
+                        ty: ty.clone(),
+                        value: c.value as i32 as i64,
+                    };
+                    return self.module.alloc_value(folded.into());
+                }
+                self.module
+                    .spawn_cast_inst(CastOp::FPToSI, val_id, ty.clone())
+            }
+            _ => val_id,
+        }
+    }
+
+    /*
+    int x = a && b;
+
+    ; LLVM IR 代码
+      %0 = icmp ne i32 %a, 0
+      br i1 %0, label %land.rhs, label %land.end
+
+    land.rhs:
+      %1 = icmp ne i32 %b, 0
+      br label %land.end
+
+    land.end:
+      %2 = phi i1 [ false, %entry ], [ %1, %land.rhs ]
+      %3 = zext i1 %2 to i32
+     */
+    pub fn build_logic_expr(&mut self, infix_expr: &InfixExpr) -> ValueId {
+        let is_and = infix_expr.op == InfixOp::LogicAnd;
+        let rhs_bb = self.module.spawn_basic_block();
+        let end_bb = self.module.alloc_basic_block();
+
+        let lhs = self.build_bool_val(&infix_expr.lhs);
+        let lhs_bb = self.module.insert_point();
+        if is_and {
+            self.module.spawn_br_inst(lhs, rhs_bb, end_bb);
+        } else {
+            self.module.spawn_br_inst(lhs, end_bb, rhs_bb);
+        }
+
+        self.module.set_insert_point(rhs_bb);
+        let rhs = self.build_bool_val(&infix_expr.rhs);
+        let rhs_bb = self.module.insert_point();
+        self.module.spawn_jump_inst(end_bb);
+
+        self.module.cur_func_mut().bbs.append(end_bb);
+        self.module.set_insert_point(end_bb);
+        // 短路时结果即为 lhs 的值：&& 为 false，|| 为 true
+        let short = ConstInt {
+            ty: BuiltinType::Bool.into(),
+            value: !is_and as i64,
+        };
+        let short_id = self.module.alloc_value(short.into());
+        self.module.spawn_phi_inst(
+            BuiltinType::Bool.into(),
+            vec![(short_id, lhs_bb), (rhs, rhs_bb)],
+        )
+    }
+
     pub fn build_if_statement(&mut self, if_stmt: &IfElseStmt) {
         let true_bb = self.module.spawn_basic_block();
         let exit_bb = self.module.alloc_basic_block();
@@ -526,12 +704,12 @@ impl Builder {
 
         self.module.set_insert_point(true_bb);
         self.build_statement(&if_stmt.then_stmt);
-        self.module.spawn_jump_inst(exit_bb);
+        self.build_fallthrough(exit_bb);
 
         if let Some(else_stmt) = &if_stmt.else_stmt {
             self.module.set_insert_point(false_bb);
             self.build_statement(else_stmt);
-            self.module.spawn_jump_inst(exit_bb);
+            self.build_fallthrough(exit_bb);
         }
         self.module.cur_func_mut().bbs.append(exit_bb);
         self.module.set_insert_point(exit_bb);
@@ -574,13 +752,13 @@ impl Builder {
 
         self.loop_stack.push((end_bb, cond_bb));
         {
+            self.module.spawn_jump_inst(cond_bb);
             self.module.set_insert_point(cond_bb);
-            let cond_value = self.build_expr(&while_stmt.cond, false);
-            self.module.spawn_br_inst(cond_value, body_bb, end_bb);
+            self.visit_cond_expr(&while_stmt.cond, body_bb, end_bb);
 
             self.module.set_insert_point(body_bb);
             self.build_statement(&while_stmt.body);
-            self.module.spawn_jump_inst(cond_bb);
+            self.build_fallthrough(cond_bb);
 
             self.module.cur_func_mut().bbs.append(end_bb);
 
@@ -631,13 +809,10 @@ impl Builder {
         let body_bb = self.module.spawn_basic_block();
         let end_bb = self.module.spawn_basic_block();
 
+        self.module.spawn_jump_inst(cond_bb);
         self.module.set_insert_point(cond_bb);
-        let cond_value = for_stmt
-            .cond
-            .as_ref()
-            .map(|cond| self.build_expr(cond, false));
-        if let Some(cond_value) = cond_value {
-            self.module.spawn_br_inst(cond_value, body_bb, end_bb);
+        if let Some(cond) = &for_stmt.cond {
+            self.visit_cond_expr(cond, body_bb, end_bb);
         } else {
             self.module.spawn_jump_inst(body_bb);
         }
@@ -645,19 +820,23 @@ impl Builder {
         self.module.set_insert_point(body_bb);
         self.build_statement(&for_stmt.body);
 
-        let _ = for_stmt
-            .update
-            .as_ref()
-            .map(|update| self.build_expr(update, false));
+        if !self.module.cur_bb_terminated() {
+            let _ = for_stmt
+                .update
+                .as_ref()
+                .map(|update| self.build_expr(update, false));
+        }
 
-        self.module.spawn_jump_inst(cond_bb);
+        self.build_fallthrough(cond_bb);
+        self.module.set_insert_point(end_bb);
     }
 
     pub fn build_return_statement(&mut self, return_stmt: &ReturnStmt) {
-        let value = return_stmt
-            .expr
-            .as_ref()
-            .map(|expr| self.build_expr(expr, false));
+        let ret_ty = self.module.cur_func().ret_ty.clone();
+        let value = return_stmt.expr.as_ref().map(|expr| {
+            let val_id = self.build_expr(expr, false);
+            self.build_conversion(val_id, &ret_ty)
+        });
         self.module.spawn_return_inst(value);
     }
 
@@ -668,24 +847,43 @@ impl Builder {
     }
 
     // is_lval 表示是否是左值表达式，如果是，则不需要生成 LoadInst
-    pub fn build_expr(&mut self, expr: &Box<Expr>, is_lval: bool) -> ValueId {
-        let expr = &**expr;
+    pub fn build_expr(&mut self, expr: &Expr, is_lval: bool) -> ValueId {
         match expr {
             Expr::Infix(infix_expr) => {
+                if matches!(infix_expr.op, InfixOp::LogicAnd | InfixOp::LogicOr) {
+                    return self.build_logic_expr(infix_expr);
+                }
                 let is_assign = infix_expr.op == InfixOp::Assign;
                 let lhs = self.build_expr(&infix_expr.lhs, is_assign);
                 let rhs = self.build_expr(&infix_expr.rhs, false);
 
                 if is_assign {
+                    // alloca、gep 和全局变量的类型即为所指向元素的类型
+                    let elem_ty = self.module.get_value(lhs).ty();
+                    let rhs = self.build_conversion(rhs, &elem_ty);
                     return self.module.spawn_store_inst(lhs, rhs);
                 }
                 let ty = infix_expr.infer_ty.as_ref().unwrap().clone();
                 let op = infix_expr.op.clone();
+                // 比较运算按两侧的公共类型进行，其余运算按结果类型进行
+                let opnd_ty = if op.is_boolean() {
+                    let lhs_ty = self.module.get_value(lhs).ty();
+                    let rhs_ty = self.module.get_value(rhs).ty();
+                    if lhs_ty.is_float() || rhs_ty.is_float() {
+                        BuiltinType::Float.into()
+                    } else {
+                        BuiltinType::Int.into()
+                    }
+                } else {
+                    ty.clone()
+                };
+                let lhs = self.build_conversion(lhs, &opnd_ty);
+                let rhs = self.build_conversion(rhs, &opnd_ty);
                 self.module.spawn_binop_inst(ty, op, lhs, rhs)
             }
             Expr::Prefix(prefix_expr) => {
-                let rhs = self.build_expr(&prefix_expr.rhs, false);
-                let ty = prefix_expr.infer_ty.as_ref().unwrap().clone();
+                let rhs = self.build_rvalue(&prefix_expr.rhs);
+                let ty = self.module.get_value(rhs).ty();
                 match prefix_expr.op {
                     PrefixOp::Incr => {
                         let converted_infix_op = InfixOp::Add;
@@ -707,8 +905,24 @@ impl Builder {
                         self.module
                             .spawn_binop_inst(ty, converted_infix_op, one_id, rhs)
                     }
-                    PrefixOp::Not => todo!(),
-                    PrefixOp::BitNot => todo!(),
+                    PrefixOp::Not => {
+                        let zero_id = self.module.spawn_zero_value(ty);
+                        self.module.spawn_binop_inst(
+                            BuiltinType::Bool.into(),
+                            InfixOp::Eq,
+                            rhs,
+                            zero_id,
+                        )
+                    }
+                    PrefixOp::BitNot => {
+                        let ones = ConstInt {
+                            ty: ty.clone(),
+                            value: -1,
+                        };
+                        let ones_id = self.module.alloc_value(ones.into());
+                        self.module
+                            .spawn_binop_inst(ty, InfixOp::BitXor, rhs, ones_id)
+                    }
                     PrefixOp::Pos => {
                         let converted_infix_op = InfixOp::Add;
                         let zero = ConstValue::zero_of(ty.clone());
@@ -751,7 +965,7 @@ impl Builder {
                         _lhs
                     }
                     PostfixOp::IndexAccess(ia) => {
-                        let index = self.build_expr(&ia.index, false);
+                        let index = self.build_rvalue(&ia.index);
                         let i32_zero_id = self.build_i32_val(0);
                         let lhs = self.get_value(_lhs);
                         // trace!("lhs: {:?}", lhs);
@@ -785,7 +999,18 @@ impl Builder {
                     let func_id = *self.module.functions.get(&call_expr.id).unwrap();
                     let args = call_expr.args.to_vec(); // 将结果收集到一个临时的 Vec 中
 
-                    let args = args.into_iter().map(|arg| self.build_expr(&arg, false)); // 使用临时 Vec 构建表达式，避免多次借用 self
+                    let params = self.module.get_func(func_id).params.clone();
+                    let args = args.into_iter().enumerate().map(|(i, arg)| {
+                        let arg_id = self.build_expr(&arg, false);
+                        // 实参按形参类型转换
+                        match params.get(i) {
+                            Some(param_id) => {
+                                let param_ty = self.module.get_value(*param_id).ty();
+                                self.build_conversion(arg_id, &param_ty)
+                            }
+                            None => self.zext_bool(arg_id),
+                        }
+                    }); // 使用临时 Vec 构建表达式，避免多次借用 self
                     let args = args.collect::<Vec<_>>(); // 将结果收集到一个临时的 Vec 中
                     self.module.spawn_call_inst(func_id, args)
                 }
@@ -850,3 +1075,97 @@ impl Builder {
         }
     }
 }
+
+#[cfg(test)]
+fn build_src(src: &str) -> Module {
+    use crate::sema::ToSemaTrait;
+
+    let mut ast = crate::parser::parse(src).unwrap();
+    let mut syms = SymbolTable::new();
+    ast.to_sema(&mut syms);
+    build(&mut ast, syms).unwrap()
+}
+
+/// 每个基本块以唯一的终结指令结束
+#[cfg(test)]
+fn assert_terminated(module: &Module, func: &str) {
+    let func = module.get_func(module.functions[func]);
+    for bb_id in func.bbs.bbs.values() {
+        let insts = &module.get_bb(*bb_id).insts;
+        let terms = insts
+            .iter()
+            .filter(|inst_id| module.get_inst(**inst_id).is_term())
+            .count();
+        assert_eq!(terms, 1, "{}", func.name);
+        assert!(module.get_inst(*insts.last().unwrap()).is_term());
+    }
+}
+
+#[test]
+fn test_loop_blocks() {
+    let module = build_src(
+        "int main() {
+            int i = 0;
+            while (i < 3) { i = i + 1; }
+            for (i = 0; i < 3; i = i + 1) { }
+            return i;
+        }",
+    );
+    // 循环前的块跳入条件块，循环之后的语句在出口块中
+    assert_terminated(&module, "main");
+}
+
+#[test]
+fn test_default_return() {
+    let module = build_src(
+        "int f(int x) { x = x + 1; }
+        void g(int x) { if (x) { x = 2; } }",
+    );
+    // 控制流到达函数末尾时返回 0 或直接返回
+    assert_terminated(&module, "f");
+    assert_terminated(&module, "g");
+}
+
+#[test]
+fn test_unreachable_stmts() {
+    let module = build_src(
+        "int f(int x) {
+            if (x) { return 1; x = 2; } else { return 2; }
+            while (x) { if (x > 5) { break; x = 1; } x = x - 1; continue; x = 3; }
+            for (x = 0; x < 3; x = x + 1) { return x; }
+            return x;
+            x = 4;
+        }",
+    );
+    // return、break、continue 之后的语句不再生成，块中没有多余的跳转
+    assert_terminated(&module, "f");
+}
+
+#[test]
+fn test_prototype() {
+    let module = build_src(
+        "int isodd(int n);
+        int iseven(int n) { if (n == 0) return 1; return isodd(n - 1); }
+        int isodd(int n) { if (n == 0) return 0; return iseven(n - 1); }
+        int isodd(int n);
+        int main() { return isodd(7); }",
+    );
+
+    // 原型之前和之后的调用都指向同一个已定义的函数
+    let isodd = module.functions["isodd"];
+    assert!(!module.get_func(isodd).is_external);
+    for func in ["iseven", "main"] {
+        let func = module.get_func(module.functions[func]);
+        let callees = func
+            .bbs
+            .bbs
+            .values()
+            .flat_map(|bb_id| module.get_bb(*bb_id).insts.iter())
+            .filter_map(|inst_id| match module.get_inst(*inst_id) {
+                InstValue::Call(call) => Some(call.func),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(callees, vec![isodd]);
+    }
+}
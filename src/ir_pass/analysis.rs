@@ -0,0 +1,736 @@
+//! 函数级的控制流分析：CFG、支配树、支配边界、后支配树和循环森林，以及模块的调用图
+
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::ir::{InstValue, Module, ValueId};
+
+/// 函数的控制流图，只按终结指令计算，不依赖 value_user
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub entry: ValueId,
+    /// 函数中的所有基本块，按 BasicBlockList 的顺序
+    pub bbs: Vec<ValueId>,
+    pub preds: HashMap<ValueId, Vec<ValueId>>,
+    pub succs: HashMap<ValueId, Vec<ValueId>>,
+    /// 从入口可达的基本块的逆后序
+    pub rpo: Vec<ValueId>,
+    rpo_index: HashMap<ValueId, usize>,
+}
+
+impl Cfg {
+    pub fn new(module: &Module, func_id: ValueId) -> Self {
+        let func = module.get_func(func_id);
+        let entry = *func.bbs.entry_bb();
+        let bbs = func.bbs.bbs.values().copied().collect::<Vec<_>>();
+
+        let mut preds: HashMap<ValueId, Vec<ValueId>> =
+            bbs.iter().map(|bb| (*bb, vec![])).collect();
+        let mut succs = HashMap::new();
+        for bb_id in &bbs {
+            let mut bb_succs = module
+                .get_bb(*bb_id)
+                .insts
+                .last()
+                .map(|inst_id| module.get_inst(*inst_id).successors())
+                .unwrap_or_default();
+            // 两个目标相同的 br 只算一条边
+            bb_succs.dedup();
+            for succ in &bb_succs {
+                preds.entry(*succ).or_default().push(*bb_id);
+            }
+            succs.insert(*bb_id, bb_succs);
+        }
+
+        let mut rpo = vec![];
+        post_order(entry, |bb| &succs[&bb], &mut HashSet::new(), &mut rpo);
+        rpo.reverse();
+        let rpo_index = rpo.iter().enumerate().map(|(i, bb)| (*bb, i)).collect();
+        Cfg {
+            entry,
+            bbs,
+            preds,
+            succs,
+            rpo,
+            rpo_index,
+        }
+    }
+
+    pub fn preds(&self, bb_id: ValueId) -> &[ValueId] {
+        &self.preds[&bb_id]
+    }
+
+    pub fn succs(&self, bb_id: ValueId) -> &[ValueId] {
+        &self.succs[&bb_id]
+    }
+
+    pub fn is_reachable(&self, bb_id: ValueId) -> bool {
+        self.rpo_index.contains_key(&bb_id)
+    }
+
+    /// 基本块在逆后序中的位置，不可达时为 None
+    pub fn rpo_index(&self, bb_id: ValueId) -> Option<usize> {
+        self.rpo_index.get(&bb_id).copied()
+    }
+}
+
+/// 从 entry 出发深度优先遍历，把后序追加到 out，已访问的节点会被跳过
+fn post_order<'a, F>(
+    entry: ValueId,
+    succs: F,
+    visited: &mut HashSet<ValueId>,
+    out: &mut Vec<ValueId>,
+) where
+    F: Fn(ValueId) -> &'a [ValueId],
+{
+    if !visited.insert(entry) {
+        return;
+    }
+    // 显式栈，避免长函数递归过深
+    let mut stack = vec![(entry, 0)];
+    while let Some((bb, next)) = stack.last_mut() {
+        let bb_succs = succs(*bb);
+        if let Some(succ) = bb_succs.get(*next) {
+            *next += 1;
+            if visited.insert(*succ) {
+                stack.push((*succ, 0));
+            }
+        } else {
+            out.push(*bb);
+            stack.pop();
+        }
+    }
+}
+
+/// Cooper–Harvey–Kennedy 迭代算法，节点用逆后序编号，0 为根
+fn compute_idoms(preds: &[Vec<usize>]) -> Vec<Option<usize>> {
+    let mut idoms = vec![None; preds.len()];
+    if preds.is_empty() {
+        return idoms;
+    }
+    idoms[0] = Some(0);
+    let intersect = |idoms: &[Option<usize>], mut a: usize, mut b: usize| {
+        while a != b {
+            while a > b {
+                a = idoms[a].unwrap();
+            }
+            while b > a {
+                b = idoms[b].unwrap();
+            }
+        }
+        a
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for node in 1..preds.len() {
+            let mut new_idom = None;
+            for pred in &preds[node] {
+                if idoms[*pred].is_none() {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => *pred,
+                    Some(cur) => intersect(&idoms, *pred, cur),
+                });
+            }
+            if new_idom.is_some() && new_idom != idoms[node] {
+                idoms[node] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    idoms
+}
+
+/// 支配树，只包含可达的基本块
+#[derive(Debug, Clone)]
+pub struct DomTree {
+    pub root: ValueId,
+    idom: HashMap<ValueId, ValueId>,
+    children: HashMap<ValueId, Vec<ValueId>>,
+    /// 支配树先序遍历的进入和离开时间，用于 O(1) 判断支配关系
+    dfs_in: HashMap<ValueId, usize>,
+    dfs_out: HashMap<ValueId, usize>,
+    frontiers: HashMap<ValueId, Vec<ValueId>>,
+}
+
+impl DomTree {
+    pub fn new(cfg: &Cfg) -> Self {
+        let preds = cfg
+            .rpo
+            .iter()
+            .map(|bb| {
+                cfg.preds(*bb)
+                    .iter()
+                    .filter_map(|pred| cfg.rpo_index(*pred))
+                    .collect()
+            })
+            .collect::<Vec<_>>();
+        let idoms = compute_idoms(&preds);
+        let idom = cfg
+            .rpo
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, bb)| (*bb, cfg.rpo[idoms[i].unwrap()]))
+            .collect::<HashMap<_, _>>();
+
+        let mut frontiers: HashMap<ValueId, Vec<ValueId>> = HashMap::new();
+        for bb in &cfg.rpo {
+            let bb_preds = cfg
+                .preds(*bb)
+                .iter()
+                .filter(|pred| cfg.is_reachable(**pred))
+                .collect::<Vec<_>>();
+            if bb_preds.len() < 2 {
+                continue;
+            }
+            for pred in bb_preds {
+                let mut runner = *pred;
+                while Some(&runner) != idom.get(bb) {
+                    let frontier = frontiers.entry(runner).or_default();
+                    if !frontier.contains(bb) {
+                        frontier.push(*bb);
+                    }
+                    match idom.get(&runner) {
+                        Some(up) => runner = *up,
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        Self::from_idoms(cfg.entry, &cfg.rpo, idom, frontiers)
+    }
+
+    fn from_idoms(
+        root: ValueId,
+        order: &[ValueId],
+        idom: HashMap<ValueId, ValueId>,
+        frontiers: HashMap<ValueId, Vec<ValueId>>,
+    ) -> Self {
+        let mut children: HashMap<ValueId, Vec<ValueId>> = HashMap::new();
+        for bb in order {
+            if let Some(parent) = idom.get(bb) {
+                children.entry(*parent).or_default().push(*bb);
+            }
+        }
+        let mut tree = DomTree {
+            root,
+            idom,
+            children,
+            dfs_in: HashMap::new(),
+            dfs_out: HashMap::new(),
+            frontiers,
+        };
+        let mut clock = 0;
+        let mut stack = vec![(root, false)];
+        while let Some((bb, leaving)) = stack.pop() {
+            clock += 1;
+            if leaving {
+                tree.dfs_out.insert(bb, clock);
+                continue;
+            }
+            tree.dfs_in.insert(bb, clock);
+            stack.push((bb, true));
+            for child in tree.children(bb).iter().rev() {
+                stack.push((*child, false));
+            }
+        }
+        tree
+    }
+
+    /// 直接支配者，根和不可达的基本块没有
+    pub fn idom(&self, bb_id: ValueId) -> Option<ValueId> {
+        self.idom.get(&bb_id).copied()
+    }
+
+    pub fn children(&self, bb_id: ValueId) -> &[ValueId] {
+        self.children.get(&bb_id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn contains(&self, bb_id: ValueId) -> bool {
+        self.dfs_in.contains_key(&bb_id)
+    }
+
+    /// a 是否支配 b（自身支配自身）
+    pub fn dominates(&self, a: ValueId, b: ValueId) -> bool {
+        match (self.dfs_in.get(&a), self.dfs_in.get(&b)) {
+            (Some(a_in), Some(b_in)) => a_in <= b_in && self.dfs_out[&b] <= self.dfs_out[&a],
+            _ => false,
+        }
+    }
+
+    pub fn strictly_dominates(&self, a: ValueId, b: ValueId) -> bool {
+        a != b && self.dominates(a, b)
+    }
+
+    pub fn frontier(&self, bb_id: ValueId) -> &[ValueId] {
+        self.frontiers.get(&bb_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// 支配树的先序遍历，父节点总在子节点之前
+    pub fn pre_order(&self) -> Vec<ValueId> {
+        let mut order = vec![];
+        let mut stack = vec![self.root];
+        while let Some(bb) = stack.pop() {
+            order.push(bb);
+            stack.extend(self.children(bb).iter().rev());
+        }
+        order
+    }
+}
+
+/// 后支配树。可能有多个 ret，因此在所有出口之后加一个虚拟出口，
+/// 虚拟出口直接后支配的基本块其 ipdom 为 None；到不了出口的基本块（死循环）不在树中
+#[derive(Debug, Clone)]
+pub struct PostDomTree {
+    ipdom: HashMap<ValueId, Option<ValueId>>,
+}
+
+impl PostDomTree {
+    pub fn new(cfg: &Cfg) -> Self {
+        // 反向图：虚拟出口为 0，其余节点按反向图的逆后序编号
+        let exits = cfg
+            .rpo
+            .iter()
+            .filter(|bb| cfg.succs(**bb).is_empty())
+            .copied()
+            .collect::<Vec<_>>();
+        let mut order = vec![];
+        let mut visited = HashSet::new();
+        for exit in &exits {
+            post_order(*exit, |bb| cfg.preds(bb), &mut visited, &mut order);
+        }
+        // 不可达的基本块只会沿反向边走到不可达的基本块
+        order.retain(|bb| cfg.is_reachable(*bb));
+        order.reverse();
+        let index = order
+            .iter()
+            .enumerate()
+            .map(|(i, bb)| (*bb, i + 1))
+            .collect::<HashMap<_, _>>();
+
+        let mut preds = vec![vec![]];
+        for bb in &order {
+            let mut bb_preds = cfg
+                .succs(*bb)
+                .iter()
+                .filter_map(|succ| index.get(succ).copied())
+                .collect::<Vec<_>>();
+            if cfg.succs(*bb).is_empty() {
+                bb_preds.push(0);
+            }
+            preds.push(bb_preds);
+        }
+        let idoms = compute_idoms(&preds);
+        let ipdom = order
+            .iter()
+            .enumerate()
+            .map(|(i, bb)| {
+                let ipdom = idoms[i + 1].unwrap();
+                (*bb, (ipdom != 0).then(|| order[ipdom - 1]))
+            })
+            .collect();
+        PostDomTree { ipdom }
+    }
+
+    /// 直接后支配者，None 表示虚拟出口
+    pub fn ipdom(&self, bb_id: ValueId) -> Option<ValueId> {
+        self.ipdom.get(&bb_id).copied().flatten()
+    }
+
+    pub fn contains(&self, bb_id: ValueId) -> bool {
+        self.ipdom.contains_key(&bb_id)
+    }
+
+    /// a 是否后支配 b
+    pub fn post_dominates(&self, a: ValueId, b: ValueId) -> bool {
+        if !self.contains(a) || !self.contains(b) {
+            return false;
+        }
+        let mut runner = Some(b);
+        while let Some(bb) = runner {
+            if bb == a {
+                return true;
+            }
+            runner = self.ipdom(bb);
+        }
+        false
+    }
+}
+
+/// 自然循环
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub header: ValueId,
+    /// 跳回 header 的基本块
+    pub latches: Vec<ValueId>,
+    /// 循环体，header 在最前，其余按逆后序
+    pub blocks: Vec<ValueId>,
+    /// 循环外、有来自循环内的边的基本块
+    pub exits: Vec<ValueId>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// 最外层循环为 1
+    pub depth: usize,
+    block_set: HashSet<ValueId>,
+}
+
+impl Loop {
+    pub fn contains(&self, bb_id: ValueId) -> bool {
+        self.block_set.contains(&bb_id)
+    }
+
+    /// 循环外跳入 header 的前驱
+    pub fn outside_preds(&self, cfg: &Cfg) -> Vec<ValueId> {
+        cfg.preds(self.header)
+            .iter()
+            .filter(|pred| !self.contains(**pred) && cfg.is_reachable(**pred))
+            .copied()
+            .collect()
+    }
+
+    /// 循环外唯一的、只跳转到 header 的前驱
+    pub fn preheader(&self, cfg: &Cfg) -> Option<ValueId> {
+        match self.outside_preds(cfg).as_slice() {
+            [pred] if cfg.succs(*pred) == [self.header] => Some(*pred),
+            _ => None,
+        }
+    }
+
+    /// 有边跳出循环的循环内基本块
+    pub fn exiting_blocks(&self, cfg: &Cfg) -> Vec<ValueId> {
+        self.blocks
+            .iter()
+            .filter(|bb| cfg.succs(**bb).iter().any(|succ| !self.contains(*succ)))
+            .copied()
+            .collect()
+    }
+}
+
+/// 循环森林，loops 中外层循环总在内层循环之前
+#[derive(Debug, Clone, Default)]
+pub struct LoopInfo {
+    pub loops: Vec<Loop>,
+    /// 基本块 -> 包含它的最内层循环
+    innermost: HashMap<ValueId, usize>,
+}
+
+impl LoopInfo {
+    pub fn new(cfg: &Cfg, dom: &DomTree) -> Self {
+        // 回边的目标支配源，按逆后序找到所有 header
+        let mut loops = vec![];
+        for header in &cfg.rpo {
+            let latches = cfg
+                .preds(*header)
+                .iter()
+                .filter(|pred| dom.dominates(*header, **pred))
+                .copied()
+                .collect::<Vec<_>>();
+            if latches.is_empty() {
+                continue;
+            }
+            let mut block_set = HashSet::from([*header]);
+            let mut worklist = latches.clone();
+            while let Some(bb) = worklist.pop() {
+                if !block_set.insert(bb) {
+                    continue;
+                }
+                for pred in cfg.preds(bb) {
+                    if cfg.is_reachable(*pred) && !block_set.contains(pred) {
+                        worklist.push(*pred);
+                    }
+                }
+            }
+            let blocks = cfg
+                .rpo
+                .iter()
+                .filter(|bb| block_set.contains(bb))
+                .copied()
+                .collect::<Vec<_>>();
+            let mut exits = vec![];
+            for bb in &blocks {
+                for succ in cfg.succs(*bb) {
+                    if !block_set.contains(succ) && !exits.contains(succ) {
+                        exits.push(*succ);
+                    }
+                }
+            }
+            loops.push(Loop {
+                header: *header,
+                latches,
+                blocks,
+                exits,
+                parent: None,
+                children: vec![],
+                depth: 1,
+                block_set,
+            });
+        }
+
+        // header 按逆后序排列，外层循环的 header 先出现；父循环为包含 header 的最小循环
+        for i in 0..loops.len() {
+            let parent = (0..i)
+                .filter(|j| loops[*j].contains(loops[i].header))
+                .min_by_key(|j| loops[*j].blocks.len());
+            if let Some(parent) = parent {
+                loops[i].parent = Some(parent);
+                loops[i].depth = loops[parent].depth + 1;
+                loops[parent].children.push(i);
+            }
+        }
+        let mut innermost = HashMap::new();
+        for (i, l) in loops.iter().enumerate() {
+            for bb in &l.blocks {
+                innermost.insert(*bb, i);
+            }
+        }
+        LoopInfo { loops, innermost }
+    }
+
+    /// 包含该基本块的最内层循环
+    pub fn loop_of(&self, bb_id: ValueId) -> Option<usize> {
+        self.innermost.get(&bb_id).copied()
+    }
+
+    /// 循环嵌套深度，不在循环中为 0
+    pub fn depth(&self, bb_id: ValueId) -> usize {
+        self.loop_of(bb_id).map_or(0, |l| self.loops[l].depth)
+    }
+
+    pub fn is_header(&self, bb_id: ValueId) -> bool {
+        self.loop_of(bb_id)
+            .is_some_and(|l| self.loops[l].header == bb_id)
+    }
+
+    /// 内层循环在前的顺序，适合由内向外处理
+    pub fn inner_to_outer(&self) -> Vec<usize> {
+        (0..self.loops.len()).rev().collect()
+    }
+}
+
+/// 模块的调用图，只包含有函数体的函数
+#[derive(Debug, Clone, Default)]
+pub struct CallGraph {
+    /// 被调用者在调用者之前的顺序，同一个调用环中的函数相邻
+    pub bottom_up: Vec<ValueId>,
+    /// 函数 -> 模块中调用它的指令数
+    pub call_counts: HashMap<ValueId, usize>,
+    /// 位于调用环上的函数，包括直接调用自身的函数
+    recursive: HashSet<ValueId>,
+}
+
+impl CallGraph {
+    pub fn new(module: &Module) -> Self {
+        let funcs = module
+            .functions
+            .values()
+            .filter(|func_id| !module.get_func(**func_id).is_external)
+            .copied()
+            .collect::<Vec<_>>();
+        let mut callees: HashMap<ValueId, Vec<ValueId>> = HashMap::new();
+        let mut call_counts = HashMap::new();
+        for func_id in &funcs {
+            for bb_id in module.get_func(*func_id).bbs.bbs.values() {
+                for inst_id in &module.get_bb(*bb_id).insts {
+                    if let InstValue::Call(call) = module.get_inst(*inst_id) {
+                        if !module.get_func(call.func).is_external {
+                            callees.entry(*func_id).or_default().push(call.func);
+                            *call_counts.entry(call.func).or_default() += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut tarjan = Tarjan {
+            callees: &callees,
+            index: HashMap::new(),
+            low: HashMap::new(),
+            stack: vec![],
+            sccs: vec![],
+        };
+        for func_id in &funcs {
+            if !tarjan.index.contains_key(func_id) {
+                tarjan.visit(*func_id);
+            }
+        }
+        let mut recursive = HashSet::new();
+        for scc in &tarjan.sccs {
+            let self_call = callees.get(&scc[0]).is_some_and(|c| c.contains(&scc[0]));
+            if scc.len() > 1 || self_call {
+                recursive.extend(scc.iter().copied());
+            }
+        }
+        CallGraph {
+            bottom_up: tarjan.sccs.concat(),
+            call_counts,
+            recursive,
+        }
+    }
+
+    pub fn is_recursive(&self, func_id: ValueId) -> bool {
+        self.recursive.contains(&func_id)
+    }
+}
+
+/// Tarjan 强连通分量算法，分量按逆拓扑序产生，即被调用者在前
+struct Tarjan<'a> {
+    callees: &'a HashMap<ValueId, Vec<ValueId>>,
+    index: HashMap<ValueId, usize>,
+    low: HashMap<ValueId, usize>,
+    stack: Vec<ValueId>,
+    sccs: Vec<Vec<ValueId>>,
+}
+
+impl Tarjan<'_> {
+    fn visit(&mut self, func_id: ValueId) {
+        let index = self.index.len();
+        self.index.insert(func_id, index);
+        self.low.insert(func_id, index);
+        self.stack.push(func_id);
+        for callee in self.callees.get(&func_id).cloned().unwrap_or_default() {
+            if !self.index.contains_key(&callee) {
+                self.visit(callee);
+                let low = self.low[&func_id].min(self.low[&callee]);
+                self.low.insert(func_id, low);
+            } else if self.stack.contains(&callee) {
+                let low = self.low[&func_id].min(self.index[&callee]);
+                self.low.insert(func_id, low);
+            }
+        }
+        if self.low[&func_id] == index {
+            let pos = self.stack.iter().position(|f| *f == func_id).unwrap();
+            self.sccs.push(self.stack.split_off(pos));
+        }
+    }
+}
+
+/// 按函数缓存分析结果。改变了 CFG 的 pass 运行后由 PassManager 清空缓存，
+/// pass 在运行中修改了某个函数的 CFG 时需要自行调用 invalidate
+#[derive(Default)]
+pub struct AnalysisManager {
+    cfgs: HashMap<ValueId, Rc<Cfg>>,
+    dom_trees: HashMap<ValueId, Rc<DomTree>>,
+    post_dom_trees: HashMap<ValueId, Rc<PostDomTree>>,
+    loop_infos: HashMap<ValueId, Rc<LoopInfo>>,
+}
+
+impl AnalysisManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cfg(&mut self, module: &Module, func_id: ValueId) -> Rc<Cfg> {
+        self.cfgs
+            .entry(func_id)
+            .or_insert_with(|| Rc::new(Cfg::new(module, func_id)))
+            .clone()
+    }
+
+    pub fn dom_tree(&mut self, module: &Module, func_id: ValueId) -> Rc<DomTree> {
+        if let Some(dom) = self.dom_trees.get(&func_id) {
+            return dom.clone();
+        }
+        let dom = Rc::new(DomTree::new(&self.cfg(module, func_id)));
+        self.dom_trees.insert(func_id, dom.clone());
+        dom
+    }
+
+    pub fn post_dom_tree(&mut self, module: &Module, func_id: ValueId) -> Rc<PostDomTree> {
+        if let Some(pdom) = self.post_dom_trees.get(&func_id) {
+            return pdom.clone();
+        }
+        let pdom = Rc::new(PostDomTree::new(&self.cfg(module, func_id)));
+        self.post_dom_trees.insert(func_id, pdom.clone());
+        pdom
+    }
+
+    pub fn loop_info(&mut self, module: &Module, func_id: ValueId) -> Rc<LoopInfo> {
+        if let Some(loops) = self.loop_infos.get(&func_id) {
+            return loops.clone();
+        }
+        let cfg = self.cfg(module, func_id);
+        let dom = self.dom_tree(module, func_id);
+        let loops = Rc::new(LoopInfo::new(&cfg, &dom));
+        self.loop_infos.insert(func_id, loops.clone());
+        loops
+    }
+
+    /// 丢弃某个函数的所有分析结果
+    pub fn invalidate(&mut self, func_id: ValueId) {
+        self.cfgs.remove(&func_id);
+        self.dom_trees.remove(&func_id);
+        self.post_dom_trees.remove(&func_id);
+        self.loop_infos.remove(&func_id);
+    }
+
+    pub fn invalidate_all(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[test]
+fn test_analysis() {
+    use crate::{ir_builder, scope::SymbolTable, sema::ToSemaTrait};
+
+    let mut ast = crate::parser::parse(
+        "int main() {
+            int i = 0; int s = 0;
+            while (i < 10) {
+                int j = 0;
+                while (j < i) { s = s + j; j = j + 1; }
+                if (s > 100) { s = 0; } else { s = s + 1; }
+                i = i + 1;
+            }
+            return s;
+        }",
+    )
+    .unwrap();
+    let mut syms = SymbolTable::new();
+    ast.to_sema(&mut syms);
+    let module = ir_builder::build(&mut ast, syms).unwrap();
+    let func_id = module.functions["main"];
+    let bb = |name: &str| module.get_func(func_id).bbs.bbs[name];
+
+    let mut am = AnalysisManager::new();
+    let cfg = am.cfg(&module, func_id);
+    let dom = am.dom_tree(&module, func_id);
+    let loops = am.loop_info(&module, func_id);
+    let pdom = am.post_dom_tree(&module, func_id);
+    assert_eq!(cfg.rpo[0], cfg.entry);
+    assert!(Rc::ptr_eq(&cfg, &am.cfg(&module, func_id)));
+
+    // bb_0/bb_1 为外层循环的条件和循环体，bb_2/bb_3 为内层循环
+    let (outer_cond, outer_body) = (bb("bb_0"), bb("bb_1"));
+    let (inner_cond, inner_body) = (bb("bb_2"), bb("bb_3"));
+    assert_eq!(dom.idom(outer_cond), Some(cfg.entry));
+    assert!(dom.dominates(outer_cond, inner_body));
+    assert!(!dom.dominates(inner_body, outer_cond));
+    assert_eq!(dom.idom(inner_body), Some(inner_cond));
+    assert!(dom.frontier(inner_body).contains(&inner_cond));
+    assert!(dom.frontier(outer_body).contains(&outer_cond));
+    assert!(pdom.post_dominates(outer_cond, outer_body));
+    assert!(pdom.post_dominates(inner_cond, inner_body));
+
+    assert_eq!(loops.loops.len(), 2);
+    let outer = &loops.loops[0];
+    let inner = &loops.loops[1];
+    assert_eq!(outer.header, outer_cond);
+    assert_eq!(inner.header, inner_cond);
+    assert_eq!(inner.parent, Some(0));
+    assert_eq!(outer.children, vec![1]);
+    assert_eq!(loops.depth(inner_body), 2);
+    assert_eq!(loops.depth(outer_body), 1);
+    assert_eq!(loops.depth(cfg.entry), 0);
+    assert_eq!(inner.latches, vec![inner_body]);
+    assert_eq!(outer.exits.len(), 1);
+    assert!(!outer.contains(outer.exits[0]));
+
+    am.invalidate(func_id);
+    assert!(!Rc::ptr_eq(&cfg, &am.cfg(&module, func_id)));
+}
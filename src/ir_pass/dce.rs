@@ -0,0 +1,351 @@
+//! 死代码消除：指令级的 mark-sweep、局部数组的死存储消除，以及未使用的函数和全局变量的删除
+
+use std::collections::HashSet;
+
+use crate::ir::{ConstValue, InstValue, Module, Value, ValueId};
+
+use super::{
+    analysis::AnalysisManager,
+    pass_manager::{FunctionPass, Pass},
+};
+
+/// 从有副作用的指令出发标记活跃指令，删除其余指令
+pub struct DcePass;
+
+impl FunctionPass for DcePass {
+    fn name(&self) -> &'static str {
+        "dce"
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, _am: &mut AnalysisManager) {
+        let insts = func_insts(module, func_id);
+
+        let mut live = HashSet::new();
+        let mut worklist = insts
+            .iter()
+            .filter(|inst_id| has_side_effect(module.get_inst(**inst_id)))
+            .copied()
+            .collect::<Vec<_>>();
+        while let Some(inst_id) = worklist.pop() {
+            if !live.insert(inst_id) {
+                continue;
+            }
+            for opr in module.get_inst(inst_id).operands() {
+                if matches!(module.get_value(opr), Value::Instruction(_)) && !live.contains(&opr) {
+                    worklist.push(opr);
+                }
+            }
+        }
+
+        // 死指令之间可能互相使用，先统一清除使用记录再删除
+        let dead = insts
+            .into_iter()
+            .filter(|inst_id| !live.contains(inst_id))
+            .collect::<Vec<_>>();
+        for inst_id in &dead {
+            module.mark_nolonger_using_any(*inst_id);
+        }
+        for inst_id in dead {
+            module.remove_inst(inst_id);
+        }
+    }
+}
+
+fn func_insts(module: &Module, func_id: ValueId) -> Vec<ValueId> {
+    module
+        .get_func(func_id)
+        .bbs
+        .bbs
+        .values()
+        .flat_map(|bb_id| module.get_bb(*bb_id).insts.iter().copied())
+        .collect()
+}
+
+/// 存储、调用和控制流指令总是活跃的
+fn has_side_effect(inst: &InstValue) -> bool {
+    inst.is_term() || inst.is_store() || inst.is_call()
+}
+
+/// 删除写入不逃逸的局部变量和数组的死存储
+///
+/// - 从未被读取的 alloca 连同对它的所有存储和地址计算一起删除
+/// - 同一基本块内被后续存储覆盖、中间没有读取的存储被删除
+pub struct DsePass;
+
+impl FunctionPass for DsePass {
+    fn name(&self) -> &'static str {
+        "dse"
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, _am: &mut AnalysisManager) {
+        let allocas = func_insts(module, func_id)
+            .into_iter()
+            .filter(|inst_id| module.get_inst(*inst_id).is_alloca())
+            .collect::<Vec<_>>();
+        for alloca_id in allocas {
+            let Some(uses) = AllocaUses::collect(module, alloca_id) else {
+                continue;
+            };
+            if uses.loads.is_empty() {
+                let dead = uses
+                    .stores
+                    .iter()
+                    .chain(uses.geps.iter().rev())
+                    .chain([&alloca_id]);
+                for inst_id in dead {
+                    module.remove_inst(*inst_id);
+                }
+            }
+        }
+
+        for bb_id in module
+            .get_func(func_id)
+            .bbs
+            .bbs
+            .values()
+            .copied()
+            .collect::<Vec<_>>()
+        {
+            remove_overwritten_stores(module, bb_id);
+        }
+    }
+}
+
+/// 局部变量（或全局变量）的所有使用，其地址逃逸时无法分析
+pub struct AllocaUses {
+    pub geps: Vec<ValueId>,
+    pub loads: Vec<ValueId>,
+    pub stores: Vec<ValueId>,
+}
+
+impl AllocaUses {
+    pub fn collect(module: &Module, alloca_id: ValueId) -> Option<Self> {
+        let mut uses = AllocaUses {
+            geps: vec![],
+            loads: vec![],
+            stores: vec![],
+        };
+        // geps 按定义先后排列，删除时反过来
+        let mut ptrs = vec![alloca_id];
+        let mut i = 0;
+        while i < ptrs.len() {
+            let ptr = ptrs[i];
+            i += 1;
+            let mut users = module.get_users_of(ptr);
+            users.dedup();
+            for user in users {
+                match module.get_inst(user) {
+                    InstValue::Load(_) => uses.loads.push(user),
+                    InstValue::Store(store) if store.ptr == ptr && store.value != ptr => {
+                        uses.stores.push(user)
+                    }
+                    InstValue::Gep(gep) if gep.ptr == ptr => {
+                        uses.geps.push(user);
+                        ptrs.push(user);
+                    }
+                    // 作为参数传递、被存储或参与其他运算
+                    _ => return None,
+                }
+            }
+        }
+        Some(uses)
+    }
+}
+
+/// 同一指针的两次存储之间没有任何读取和调用时，前一次存储是死的
+fn remove_overwritten_stores(module: &mut Module, bb_id: ValueId) {
+    let mut dead = vec![];
+    // 指针 -> 尚未被读取的最近一次存储
+    let mut pending: Vec<(ValueId, ValueId)> = vec![];
+    for inst_id in module.get_bb(bb_id).insts.clone() {
+        match module.get_inst(inst_id) {
+            InstValue::Store(store) => {
+                let same = |(ptr, _): &(ValueId, ValueId)| same_address(module, *ptr, store.ptr);
+                if let Some(pos) = pending.iter().position(same) {
+                    let (_, prev) = pending.remove(pos);
+                    if is_local_ptr(module, store.ptr) {
+                        dead.push(prev);
+                    }
+                }
+                pending.push((store.ptr, inst_id));
+            }
+            // 不做别名分析，任何读取都可能读到之前的存储
+            InstValue::Load(_) | InstValue::Call(_) => pending.clear(),
+            _ => {}
+        }
+    }
+    for store_id in dead {
+        module.remove_inst(store_id);
+    }
+}
+
+/// 两个指针是否一定指向同一地址：同一个 value，或基址相同、下标相同的 gep
+fn same_address(module: &Module, a: ValueId, b: ValueId) -> bool {
+    if a == b {
+        return true;
+    }
+    match (module.try_get_inst(a), module.try_get_inst(b)) {
+        (Some(InstValue::Gep(a)), Some(InstValue::Gep(b))) => {
+            a.base == b.base
+                && a.indices.len() == b.indices.len()
+                && same_address(module, a.ptr, b.ptr)
+                && a.indices
+                    .iter()
+                    .zip(&b.indices)
+                    .all(|(x, y)| x == y || same_int_const(module, *x, *y))
+        }
+        _ => false,
+    }
+}
+
+fn same_int_const(module: &Module, a: ValueId, b: ValueId) -> bool {
+    match (module.get_value(a), module.get_value(b)) {
+        (Value::Const(ConstValue::Int(a)), Value::Const(ConstValue::Int(b))) => a.value == b.value,
+        _ => false,
+    }
+}
+
+/// 指针是否指向本函数的局部变量（alloca 或基于 alloca 的 gep）
+fn is_local_ptr(module: &Module, ptr: ValueId) -> bool {
+    match module.try_get_inst(ptr) {
+        Some(InstValue::Alloca(_)) => true,
+        Some(InstValue::Gep(gep)) => is_local_ptr(module, gep.ptr),
+        _ => false,
+    }
+}
+
+/// 删除从 main 无法调用到的内部函数，以及没有任何使用的全局变量
+pub struct GlobalDcePass;
+
+impl Pass for GlobalDcePass {
+    fn name(&self) -> &'static str {
+        "globaldce"
+    }
+
+    fn run(&mut self, module: &mut Module, am: &mut AnalysisManager) {
+        let Some(main_id) = module.functions.get("main").copied() else {
+            return;
+        };
+        let mut reachable = HashSet::new();
+        let mut worklist = vec![main_id];
+        while let Some(func_id) = worklist.pop() {
+            if !reachable.insert(func_id) {
+                continue;
+            }
+            for inst_id in func_insts(module, func_id) {
+                if let InstValue::Call(call) = module.get_inst(inst_id) {
+                    worklist.push(call.func);
+                }
+            }
+        }
+
+        let dead_funcs = module
+            .functions
+            .iter()
+            .filter(|(_, func_id)| {
+                !reachable.contains(*func_id) && !module.get_func(**func_id).is_external
+            })
+            .map(|(name, func_id)| (name.clone(), *func_id))
+            .collect::<Vec<_>>();
+        for (name, func_id) in dead_funcs {
+            for inst_id in func_insts(module, func_id) {
+                module.mark_nolonger_using_any(inst_id);
+            }
+            module.functions.remove(&name);
+            am.invalidate(func_id);
+        }
+
+        let dead_globals = module
+            .global_variables
+            .iter()
+            .filter(|(_, var_id)| module.get_users_of(**var_id).is_empty())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in dead_globals {
+            module.global_variables.remove(&name);
+        }
+    }
+}
+
+#[test]
+fn test_dce() {
+    use crate::{
+        ast::BuiltinType,
+        ir::ConstInt,
+        ir_pass::{build_ir, inst_namer, verify},
+        ir_printer,
+    };
+
+    let mut module = build_ir(
+        "int unused_global = 3; int g;
+        int helper(int x) { return x * 2; }
+        int unused(int x) { return helper(x) + unused_global; }
+        int main() {
+            int a[10] = {5, 6};
+            int b[2] = {1, 2};
+            int t = g * 7;
+            g = helper(b[0]);
+            return b[1];
+        }",
+    );
+
+    // 在 b[0] = 1 之前插入一条会被它覆盖的 b[0] = 9
+    let main_id = module.functions["main"];
+    let entry = *module.get_func(main_id).bbs.entry_bb();
+    let store_1 = module
+        .get_bb(entry)
+        .insts
+        .iter()
+        .position(|inst_id| match module.get_inst(*inst_id) {
+            InstValue::Store(store) => matches!(
+                module.get_value(store.value),
+                Value::Const(ConstValue::Int(ConstInt { value: 1, .. }))
+            ),
+            _ => false,
+        })
+        .unwrap();
+    let b0 = module
+        .get_inst(module.get_bb(entry).insts[store_1])
+        .as_store()
+        .ptr;
+    let nine = module.alloc_value(
+        ConstInt {
+            ty: BuiltinType::Int.into(),
+            value: 9,
+        }
+        .into(),
+    );
+    module.set_insert_point(entry);
+    let store_9 = module.spawn_store_inst(b0, nine);
+    let insts = &mut module.get_bb_mut(entry).insts;
+    insts.pop();
+    insts.insert(store_1, store_9);
+
+    let mut am = AnalysisManager::new();
+    DsePass.run(&mut module, &mut am);
+    DcePass.run(&mut module, &mut am);
+    GlobalDcePass.run(&mut module, &mut am);
+    assert_eq!(verify::run(&module), Ok(()));
+
+    assert!(module.functions.contains_key("helper"));
+    assert!(!module.functions.contains_key("unused"));
+    assert!(module.global_variables.contains_key("g"));
+    assert!(!module.global_variables.contains_key("unused_global"));
+
+    inst_namer::run(&mut module);
+    let ir = ir_printer::print(&mut module);
+    // a 从未被读取，t 没有被使用，b[0] = 9 被覆盖
+    assert_eq!(ir.matches("alloca").count(), 1);
+    assert!(!ir.contains("store i32 5,"));
+    assert!(!ir.contains(", 7"));
+    assert!(!ir.contains("store i32 9,"));
+    assert!(ir.contains("store i32 1,"));
+}
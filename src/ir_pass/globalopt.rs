@@ -0,0 +1,182 @@
+//! 全局变量优化
+//!
+//! - 从未被写入的全局变量标记为常量，常数下标的读取直接替换为初始值
+//! - 只在不递归的 main 中使用的标量全局变量改为 main 的局部变量，随后由 mem2reg 提升
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::Type,
+    ir::{
+        AllocaInst, ConstFloat, ConstInt, ConstValue, InstValue, Module, StoreInst, Value, ValueId,
+    },
+};
+
+use super::{
+    analysis::{AnalysisManager, CallGraph},
+    dce::AllocaUses,
+    pass_manager::Pass,
+    sroa::{element_offset, scalar_count},
+};
+
+pub struct GlobalOptPass;
+
+impl Pass for GlobalOptPass {
+    fn name(&self) -> &'static str {
+        "globalopt"
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    fn run(&mut self, module: &mut Module, _am: &mut AnalysisManager) {
+        let cg = CallGraph::new(module);
+        let main = module
+            .functions
+            .get("main")
+            .copied()
+            .filter(|main| !cg.is_recursive(*main));
+        // 基本块 -> 所在函数
+        let mut owner = HashMap::new();
+        for func_id in module.functions.values() {
+            for bb_id in module.get_func(*func_id).bbs.bbs.values() {
+                owner.insert(*bb_id, *func_id);
+            }
+        }
+
+        for gv in module
+            .global_variables
+            .values()
+            .copied()
+            .collect::<Vec<_>>()
+        {
+            // 地址被传给函数或参与其他运算时可能被任意读写
+            let Some(uses) = AllocaUses::collect(module, gv) else {
+                continue;
+            };
+            if uses.stores.is_empty() {
+                fold_loads(module, gv, &uses.loads);
+                module.get_global_var_mut(gv).is_const = true;
+                continue;
+            }
+            let only_in_main = uses
+                .loads
+                .iter()
+                .chain(&uses.stores)
+                .all(|inst_id| Some(owner[&module.get_parent_id(*inst_id)]) == main);
+            if only_in_main && !matches!(module.get_global_var(gv).ty, Type::Array(_)) {
+                localize(module, gv, main.unwrap());
+            }
+        }
+    }
+}
+
+/// 多维常量数组中按标量偏移取出元素，没有给出的元素为 None，即为 0
+fn const_element(c: &ConstValue, offset: usize) -> Option<&ConstValue> {
+    let ConstValue::Array(array) = c else {
+        return Some(c);
+    };
+    let Type::Array(ty) = &array.ty else {
+        unreachable!("expect an array type");
+    };
+    let n = scalar_count(ty.element_type());
+    const_element(array.values.get(offset / n)?, offset % n)
+}
+
+fn fold_loads(module: &mut Module, gv: ValueId, loads: &[ValueId]) {
+    let var = module.get_global_var(gv);
+    let size = scalar_count(&var.ty);
+    let init = var.initializer.map(|init| match module.get_value(init) {
+        Value::Const(c) => c.clone(),
+        _ => unreachable!("global initializer should be a constant"),
+    });
+    for load_id in loads {
+        let ty = module.get_inst(*load_id).as_load().ty.clone();
+        // 变量下标的读取保留，全局变量成为只读的常量
+        let Some((_, offset)) = element_offset(module, module.get_inst(*load_id).as_load().ptr)
+        else {
+            continue;
+        };
+        if offset >= size {
+            continue;
+        }
+        let value = match init.as_ref().and_then(|init| const_element(init, offset)) {
+            Some(ConstValue::Int(c)) => ConstValue::Int(ConstInt { ty, value: c.value }),
+            Some(ConstValue::Float(c)) => ConstValue::Float(ConstFloat { ty, value: c.value }),
+            _ => ConstValue::zero_of(ty),
+        };
+        let value = module.alloc_value(value.into());
+        module.replace_value(*load_id, value);
+        module.remove_inst(*load_id);
+    }
+}
+
+/// main 只会执行一次，全局变量可以换成入口处按初始值初始化的局部变量
+fn localize(module: &mut Module, gv: ValueId, main: ValueId) {
+    let var = module.get_global_var(gv).clone();
+    let entry = *module.get_func(main).bbs.entry_bb();
+    let alloca = AllocaInst {
+        ty: var.ty.clone(),
+        name: var.name,
+    };
+    let alloca = module.insert_inst(entry, 0, InstValue::Alloca(alloca));
+    module.replace_value(gv, alloca);
+
+    let init = match var.initializer {
+        Some(init) => module.alloc_value(module.get_value(init).clone()),
+        None => module.spawn_zero_value(var.ty),
+    };
+    let pos = module
+        .get_bb(entry)
+        .insts
+        .iter()
+        .position(|inst_id| !module.get_inst(*inst_id).is_alloca())
+        .unwrap();
+    let store = StoreInst {
+        value: init,
+        ptr: alloca,
+    };
+    module.insert_inst(entry, pos, InstValue::Store(store));
+}
+
+#[test]
+fn test_globalopt() {
+    use crate::{
+        ir_pass::{build_ir, dce::GlobalDcePass, inst_namer, mem2reg, verify},
+        ir_printer,
+    };
+
+    let mut module = build_ir(
+        "int n = 10;
+        int t[2][3] = {{1, 2}, {4, 5, 6}};
+        int cnt;
+        int total;
+        int f(int i) { total = total + t[1][i]; return t[0][2] + t[1][1]; }
+        int main() {
+            int s = f(1) + n;
+            while (cnt < n) { s = s + cnt; cnt = cnt + 1; }
+            return s + total;
+        }",
+    );
+    let mut am = AnalysisManager::new();
+    GlobalOptPass.run(&mut module, &mut am);
+    GlobalDcePass.run(&mut module, &mut am);
+    mem2reg::run(&mut module);
+    assert_eq!(verify::run(&module), Ok(()));
+
+    // n 的读取被折叠后删除，t 成为常量，cnt 成为 main 的局部变量，f 中写入的 total 保留
+    assert!(!module.global_variables.contains_key("n"));
+    assert!(!module.global_variables.contains_key("cnt"));
+    assert!(module.get_global_var(module.global_variables["t"]).is_const);
+    assert!(
+        !module
+            .get_global_var(module.global_variables["total"])
+            .is_const
+    );
+    inst_namer::run(&mut module);
+    let ir = ir_printer::print(&mut module);
+    // 只剩变量下标的 t[1][i] 和 total 的读取
+    assert_eq!(ir.matches("load i32, ptr %").count(), 1);
+    assert_eq!(ir.matches("load i32, ptr @total").count(), 2);
+}
@@ -0,0 +1,167 @@
+//! 基于支配树的全局值编号：消除重复的纯计算和冗余的 load
+//!
+//! 沿支配树先序遍历，子节点继承父节点可用的表达式。load 只在没有经过
+//! store 和 call 时可用，且只能沿唯一前驱就是支配者的边传递
+
+use std::collections::HashMap;
+
+use crate::ir::{ConstValue, InstValue, Module, Value, ValueId};
+
+use super::{analysis::AnalysisManager, pass_manager::FunctionPass};
+
+pub struct GvnPass;
+
+impl FunctionPass for GvnPass {
+    fn name(&self) -> &'static str {
+        "gvn"
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, am: &mut AnalysisManager) {
+        let cfg = am.cfg(module, func_id);
+        let dom = am.dom_tree(module, func_id);
+
+        let mut stack = vec![(dom.root, Tables::default())];
+        while let Some((bb_id, mut tables)) = stack.pop() {
+            // 从支配者到这里可能经过其他路径上的 store
+            if cfg.preds(bb_id) != [dom.idom(bb_id).unwrap_or(bb_id)] {
+                tables.loads.clear();
+            }
+            for inst_id in module.get_bb(bb_id).insts.clone() {
+                if let Some(existing) = tables.lookup(module, inst_id) {
+                    module.replace_value(inst_id, existing);
+                    module.remove_inst(inst_id);
+                }
+            }
+            for child in dom.children(bb_id) {
+                stack.push((*child, tables.clone()));
+            }
+        }
+    }
+}
+
+/// 操作数的编号，相同值的常量视为同一个操作数
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Operand {
+    Value(ValueId),
+    Int(String, i64),
+    Float(String, u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Expr {
+    opcode: String,
+    ty: String,
+    operands: Vec<Operand>,
+}
+
+#[derive(Default, Clone)]
+struct Tables {
+    exprs: HashMap<Expr, ValueId>,
+    /// 指针 -> 该地址当前的值
+    loads: HashMap<ValueId, ValueId>,
+}
+
+impl Tables {
+    /// 处理一条指令，存在可替代它的值时返回该值
+    fn lookup(&mut self, module: &Module, inst_id: ValueId) -> Option<ValueId> {
+        let inst = module.get_inst(inst_id);
+        match inst {
+            InstValue::Load(load) => {
+                if let Some(value) = self.loads.get(&load.ptr) {
+                    return Some(*value);
+                }
+                self.loads.insert(load.ptr, inst_id);
+                None
+            }
+            InstValue::Store(store) => {
+                // 不做别名分析，store 之后只知道被写入的地址的值
+                self.loads.clear();
+                self.loads.insert(store.ptr, store.value);
+                None
+            }
+            InstValue::Call(_) => {
+                self.loads.clear();
+                None
+            }
+            _ => {
+                let expr = expr_of(module, inst)?;
+                if let Some(existing) = self.exprs.get(&expr) {
+                    return Some(*existing);
+                }
+                self.exprs.insert(expr, inst_id);
+                None
+            }
+        }
+    }
+}
+
+fn operand(module: &Module, val_id: ValueId) -> Operand {
+    match module.get_value(val_id) {
+        Value::Const(ConstValue::Int(c)) => Operand::Int(format!("{:?}", c.ty), c.value),
+        Value::Const(ConstValue::Float(c)) => {
+            Operand::Float(format!("{:?}", c.ty), c.value.to_bits())
+        }
+        _ => Operand::Value(val_id),
+    }
+}
+
+/// 纯指令的表达式，其余指令返回 None
+fn expr_of(module: &Module, inst: &InstValue) -> Option<Expr> {
+    let (opcode, operands) = match inst {
+        InstValue::InfixOp(op) => {
+            let mut operands = vec![operand(module, op.lhs), operand(module, op.rhs)];
+            if op.op.is_commutative() {
+                operands.sort_by_key(|opr| format!("{:?}", opr));
+            }
+            (format!("{:?}", op.op), operands)
+        }
+        InstValue::Gep(gep) => {
+            let mut operands = vec![operand(module, gep.ptr)];
+            operands.extend(gep.indices.iter().map(|idx| operand(module, *idx)));
+            (format!("gep {:?}", gep.base), operands)
+        }
+        InstValue::Cast(cast) => (format!("{:?}", cast.op), vec![operand(module, cast.value)]),
+        _ => return None,
+    };
+    Some(Expr {
+        opcode,
+        ty: format!("{:?}", inst.ty()),
+        operands,
+    })
+}
+
+#[test]
+fn test_gvn() {
+    use crate::{
+        ir_pass::{build_ir, inst_namer, verify},
+        ir_printer,
+    };
+
+    let mut module = build_ir(
+        "int g;
+        int f(int i, int j) {
+            int a[4][4] = {};
+            int x = a[i][j] + a[i][j];
+            int y = (i * 4 + j) + (j + i * 4);
+            if (x > 0) { y = y + g + g; } else { y = i * 4 + j; }
+            g = 1;
+            return x + y + g;
+        }",
+    );
+    let func_id = module.functions["f"];
+    GvnPass.run_on_func(&mut module, func_id, &mut AnalysisManager::new());
+    assert_eq!(verify::run(&module), Ok(()));
+
+    inst_namer::run(&mut module);
+    let ir = ir_printer::print(&mut module);
+    // a[i][j] 的地址（每一维一条 gep）和值只算一次，i * 4 + j 在两个分支中都复用
+    assert_eq!(ir.matches("getelementptr").count(), 2);
+    assert_eq!(ir.matches("load i32, ptr %").count(), 1);
+    assert_eq!(ir.matches("mul i32").count(), 1);
+    // g 在 then 分支中读一次，store 之后直接使用写入的值
+    assert_eq!(ir.matches("load i32, ptr @g").count(), 1);
+}
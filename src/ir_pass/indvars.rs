@@ -0,0 +1,423 @@
+//! 归纳变量分析和强度削减
+//!
+//! 基本归纳变量（IV）是 header 中的 phi，从 preheader 进入时取初值，每次迭代加上常数步长。
+//! 循环只从 latch 以 `IV pred bound` 的条件退出时，初值和边界都是常数就能算出迭代次数
+//!
+//! 强度削减把下标为 IV 仿射函数的 gep 改为每次迭代递增的指针 phi，省去地址计算中的乘法
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::{BuiltinType, InfixOp, PointerType, Type},
+    ir::{
+        BinaryOperator, ConstInt, ConstValue, GetElementPtrInst, InstValue, Module, PhiInst, Value,
+        ValueId,
+    },
+};
+
+use super::{
+    analysis::{AnalysisManager, Cfg, Loop},
+    instcombine::swap_predicate,
+    licm::is_defined_in,
+    loop_simplify::insert_preheaders,
+    pass_manager::FunctionPass,
+};
+
+/// 基本归纳变量
+#[derive(Debug, Clone)]
+pub struct InductionVar {
+    pub phi: ValueId,
+    /// 从 preheader 进入循环时的值
+    pub init: ValueId,
+    /// 从 latch 回到 header 时的值，即 `phi + step`
+    pub next: ValueId,
+    pub step: i32,
+}
+
+/// 循环的所有基本归纳变量，循环没有 preheader 或有多个 latch 时为空
+pub fn induction_vars(module: &Module, cfg: &Cfg, l: &Loop) -> Vec<InductionVar> {
+    let (Some(preheader), [latch]) = (l.preheader(cfg), l.latches.as_slice()) else {
+        return vec![];
+    };
+    let mut ivs = vec![];
+    for phi_id in module.get_phis(l.header) {
+        let phi = module.get_inst(phi_id).as_phi();
+        if phi.ty != BuiltinType::Int.into() {
+            continue;
+        }
+        let incoming = |bb| {
+            phi.incomings
+                .iter()
+                .find(|(_, b)| *b == bb)
+                .map(|(v, _)| *v)
+        };
+        let (Some(init), Some(next)) = (incoming(preheader), incoming(*latch)) else {
+            continue;
+        };
+        let Some(InstValue::InfixOp(op)) = module.try_get_inst(next) else {
+            continue;
+        };
+        let step = match (&op.op, as_int(module, op.lhs), as_int(module, op.rhs)) {
+            (InfixOp::Add, _, Some(c)) if op.lhs == phi_id => c,
+            (InfixOp::Add, Some(c), _) if op.rhs == phi_id => c,
+            (InfixOp::Sub, _, Some(c)) if op.lhs == phi_id => c.wrapping_neg(),
+            _ => continue,
+        };
+        if step != 0 && step != i32::MIN {
+            ivs.push(InductionVar {
+                phi: phi_id,
+                init,
+                next,
+                step,
+            });
+        }
+    }
+    ivs
+}
+
+fn as_int(module: &Module, val_id: ValueId) -> Option<i32> {
+    match module.get_value(val_id) {
+        Value::Const(ConstValue::Int(c)) if c.ty == BuiltinType::Int.into() => Some(c.value as i32),
+        _ => None,
+    }
+}
+
+/// 取反后的比较，用于 latch 在条件为假时回到 header 的情况
+fn invert_predicate(op: &InfixOp) -> Option<InfixOp> {
+    Some(match op {
+        InfixOp::Lt => InfixOp::Ge,
+        InfixOp::Ge => InfixOp::Lt,
+        InfixOp::Gt => InfixOp::Le,
+        InfixOp::Le => InfixOp::Gt,
+        InfixOp::Eq => InfixOp::Ne,
+        InfixOp::Ne => InfixOp::Eq,
+        _ => return None,
+    })
+}
+
+/// 只从 latch 退出、退出条件为 IV 与循环不变量比较的循环
+#[derive(Debug, Clone)]
+pub struct CountedLoop {
+    pub preheader: ValueId,
+    pub latch: ValueId,
+    pub exit: ValueId,
+    /// 控制退出的归纳变量
+    pub iv: InductionVar,
+    /// 比较的是 `iv.next` 而不是 `iv.phi`
+    pub on_next: bool,
+    /// 规范为 `IV pred bound` 为真时继续迭代
+    pub pred: InfixOp,
+    pub bound: ValueId,
+}
+
+impl CountedLoop {
+    pub fn analyze(module: &Module, cfg: &Cfg, l: &Loop) -> Option<Self> {
+        let preheader = l.preheader(cfg)?;
+        let [latch] = l.latches.as_slice() else {
+            return None;
+        };
+        if l.exiting_blocks(cfg) != [*latch] {
+            return None;
+        }
+        let InstValue::Branch(br) = module.get_inst(module.get_terminator(*latch)) else {
+            return None;
+        };
+        let (exit, continue_on_true) = if br.then_bb == l.header {
+            (br.else_bb, true)
+        } else {
+            (br.then_bb, false)
+        };
+        let Some(InstValue::InfixOp(cond)) = module.try_get_inst(br.cond) else {
+            return None;
+        };
+        if module.get_value(cond.lhs).ty() != BuiltinType::Int.into() {
+            return None;
+        }
+        let ivs = induction_vars(module, cfg, l);
+        let find = |val_id: ValueId| {
+            ivs.iter().find_map(|iv| match val_id {
+                v if v == iv.phi => Some((iv.clone(), false)),
+                v if v == iv.next => Some((iv.clone(), true)),
+                _ => None,
+            })
+        };
+        let (iv, on_next, pred, bound) = match (find(cond.lhs), find(cond.rhs)) {
+            (Some((iv, on_next)), None) => (iv, on_next, cond.op.clone(), cond.rhs),
+            (None, Some((iv, on_next))) => (iv, on_next, swap_predicate(&cond.op), cond.lhs),
+            _ => return None,
+        };
+        if is_defined_in(module, l, bound) {
+            return None;
+        }
+        let pred = if continue_on_true {
+            pred
+        } else {
+            invert_predicate(&pred)?
+        };
+        Some(CountedLoop {
+            preheader,
+            latch: *latch,
+            exit,
+            iv,
+            on_next,
+            pred,
+            bound,
+        })
+    }
+
+    /// 循环体执行的次数，初值和边界都是常数且比较的值不会溢出时才能算出
+    pub fn trip_count(&self, module: &Module) -> Option<u32> {
+        let step = self.iv.step as i64;
+        let init = as_int(module, self.iv.init)? as i64;
+        let bound = as_int(module, self.bound)? as i64;
+        // 第 k 次迭代末尾比较的值为 first + (k-1)*step，循环体至少执行一次
+        let first = if self.on_next { init + step } else { init };
+        let ceil_div = |a: i64, b: i64| if a <= 0 { 0 } else { (a + b - 1) / b };
+        let count = match (&self.pred, step > 0) {
+            (InfixOp::Lt, true) => ceil_div(bound - first, step),
+            (InfixOp::Le, true) => ceil_div(bound + 1 - first, step),
+            (InfixOp::Gt, false) => ceil_div(first - bound, -step),
+            (InfixOp::Ge, false) => ceil_div(first - bound + 1, -step),
+            (InfixOp::Ne, _) if (bound - first) % step == 0 && (bound - first) / step >= 0 => {
+                (bound - first) / step
+            }
+            _ => return None,
+        };
+        i32::try_from(first + count * step).ok()?;
+        u32::try_from(count + 1).ok()
+    }
+
+    /// IV 单调地趋向边界，条件一旦为假就不会再为真
+    pub fn is_monotonic(&self) -> bool {
+        matches!(
+            (&self.pred, self.iv.step > 0),
+            (InfixOp::Lt | InfixOp::Le, true) | (InfixOp::Gt | InfixOp::Ge, false)
+        )
+    }
+}
+
+pub struct IndVarsPass;
+
+impl FunctionPass for IndVarsPass {
+    fn name(&self) -> &'static str {
+        "indvars"
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, am: &mut AnalysisManager) {
+        if insert_preheaders(module, func_id) {
+            am.invalidate(func_id);
+        }
+        let cfg = am.cfg(module, func_id);
+        let loops = am.loop_info(module, func_id);
+        for l in loops.inner_to_outer() {
+            let l = &loops.loops[l];
+            let ivs = induction_vars(module, &cfg, l);
+            if ivs.is_empty() {
+                continue;
+            }
+            let preheader = l.preheader(&cfg).unwrap();
+            for bb_id in &l.blocks {
+                for inst_id in module.get_bb(*bb_id).insts.clone() {
+                    if module.get_inst(inst_id).is_gep() {
+                        reduce_gep(module, l, preheader, &ivs, inst_id);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 值是否为 `scale * iv + 循环不变量`，返回 scale
+fn affine_scale(module: &Module, l: &Loop, phi: ValueId, val_id: ValueId) -> Option<i32> {
+    if val_id == phi {
+        return Some(1);
+    }
+    let Some(InstValue::InfixOp(op)) = module.try_get_inst(val_id) else {
+        return None;
+    };
+    if op.ty != BuiltinType::Int.into() {
+        return None;
+    }
+    let invariant = |val_id| !is_defined_in(module, l, val_id);
+    match op.op {
+        InfixOp::Add if invariant(op.rhs) => affine_scale(module, l, phi, op.lhs),
+        InfixOp::Add if invariant(op.lhs) => affine_scale(module, l, phi, op.rhs),
+        InfixOp::Sub if invariant(op.rhs) => affine_scale(module, l, phi, op.lhs),
+        InfixOp::Mul => match (as_int(module, op.lhs), as_int(module, op.rhs)) {
+            (_, Some(c)) => affine_scale(module, l, phi, op.lhs)?.checked_mul(c),
+            (Some(c), _) => affine_scale(module, l, phi, op.rhs)?.checked_mul(c),
+            _ => None,
+        },
+        InfixOp::BitShl => match as_int(module, op.rhs) {
+            Some(c @ 0..=30) => affine_scale(module, l, phi, op.lhs)?.checked_mul(1 << c),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// 在 preheader 末尾重新计算循环中定义的值，map 给出已知的替换，如 IV 的 phi 替换为初值
+fn materialize(
+    module: &mut Module,
+    l: &Loop,
+    preheader: ValueId,
+    map: &mut HashMap<ValueId, ValueId>,
+    val_id: ValueId,
+) -> ValueId {
+    if !is_defined_in(module, l, val_id) {
+        return val_id;
+    }
+    if let Some(new_id) = map.get(&val_id) {
+        return *new_id;
+    }
+    let op = module.get_inst(val_id).as_infix_op().clone();
+    let lhs = materialize(module, l, preheader, map, op.lhs);
+    let rhs = materialize(module, l, preheader, map, op.rhs);
+    let pos = module.get_bb(preheader).insts.len() - 1;
+    let new_id = module.insert_inst(
+        preheader,
+        pos,
+        InstValue::InfixOp(BinaryOperator { lhs, rhs, ..op }),
+    );
+    map.insert(val_id, new_id);
+    new_id
+}
+
+/// 只有一个下标随 IV 变化、其余操作数都是循环不变量，且只用于读写内存的 gep 改为指针 phi
+fn reduce_gep(
+    module: &mut Module,
+    l: &Loop,
+    preheader: ValueId,
+    ivs: &[InductionVar],
+    gep_id: ValueId,
+) {
+    let gep = module.get_inst(gep_id).as_gep().clone();
+    if is_defined_in(module, l, gep.ptr) {
+        return;
+    }
+    let only_memory = module
+        .get_users_of(gep_id)
+        .iter()
+        .all(|user| match module.get_inst(*user) {
+            InstValue::Load(_) => true,
+            InstValue::Store(store) => store.value != gep_id,
+            _ => false,
+        });
+    if !only_memory {
+        return;
+    }
+    let mut varying = None;
+    for (i, index) in gep.indices.iter().enumerate() {
+        if !is_defined_in(module, l, *index) {
+            continue;
+        }
+        let found = ivs.iter().find_map(|iv| {
+            let scale = affine_scale(module, l, iv.phi, *index)?;
+            Some((iv, iv.step.checked_mul(scale)?))
+        });
+        match (varying, found) {
+            (None, Some((iv, stride))) if stride != 0 => varying = Some((i, iv, stride)),
+            _ => return,
+        }
+    }
+    let Some((i, iv, stride)) = varying else {
+        return;
+    };
+
+    // 第一次迭代的地址在 preheader 中计算，之后每次迭代在 latch 中加上步长
+    let mut map = HashMap::from([(iv.phi, iv.init)]);
+    let index = materialize(module, l, preheader, &mut map, gep.indices[i]);
+    let mut indices = gep.indices.clone();
+    indices[i] = index;
+    let pos = module.get_bb(preheader).insts.len() - 1;
+    let init = module.insert_inst(
+        preheader,
+        pos,
+        InstValue::Gep(GetElementPtrInst {
+            indices,
+            ..gep.clone()
+        }),
+    );
+    let phi = PhiInst {
+        ty: Type::Pointer(PointerType::new(gep.base.clone())),
+        incomings: vec![],
+    };
+    let phi_id = module.insert_inst(l.header, 0, InstValue::Phi(phi));
+    module.add_phi_incoming(phi_id, preheader, init);
+
+    let int = |value| {
+        ConstValue::Int(ConstInt {
+            ty: BuiltinType::Int.into(),
+            value,
+        })
+    };
+    let zero = module.alloc_value(int(0).into());
+    let mut indices = vec![zero; gep.indices.len()];
+    indices[i] = module.alloc_value(int(stride as i64).into());
+    let latch = l.latches[0];
+    let pos = module.get_bb(latch).insts.len() - 1;
+    let next = module.insert_inst(
+        latch,
+        pos,
+        InstValue::Gep(GetElementPtrInst {
+            ptr: phi_id,
+            indices,
+            ..gep
+        }),
+    );
+    module.add_phi_incoming(phi_id, latch, next);
+
+    module.replace_value(gep_id, phi_id);
+    module.remove_inst(gep_id);
+}
+
+#[test]
+fn test_indvars() {
+    use crate::{
+        ir_pass::{build_ir, inst_namer, loop_rotate::LoopRotatePass, verify},
+        ir_printer,
+    };
+
+    let mut module = build_ir(
+        "int a[100];
+        int f(int n) {
+            int i = 2; int s = 0;
+            while (i < 50) { s = s + a[i * 2 + 1]; i = i + 3; }
+            int j = 10;
+            while (j >= n) { j = j - 1; }
+            return s + j;
+        }",
+    );
+    let func_id = module.functions["f"];
+    let mut am = AnalysisManager::new();
+    LoopRotatePass.run_on_func(&mut module, func_id, &mut am);
+
+    // i = 2, 5, ..., 47 共 16 次；j 的边界不是常数
+    let cfg = Cfg::new(&module, func_id);
+    let loops = am.loop_info(&module, func_id);
+    let counted = loops
+        .loops
+        .iter()
+        .map(|l| CountedLoop::analyze(&module, &cfg, l).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(counted[0].iv.step, 3);
+    assert!(counted[0].on_next && counted[0].is_monotonic());
+    assert_eq!(counted[0].trip_count(&module), Some(16));
+    assert_eq!(counted[1].iv.step, -1);
+    assert_eq!(counted[1].pred, InfixOp::Ge);
+    assert_eq!(counted[1].trip_count(&module), None);
+
+    IndVarsPass.run_on_func(&mut module, func_id, &mut am);
+    assert_eq!(verify::run(&module), Ok(()));
+    inst_namer::run(&mut module);
+    let ir = ir_printer::print(&mut module);
+    // a[i * 2 + 1] 的地址每次迭代增加 6 个元素
+    assert!(ir.contains("phi ptr"));
+    assert!(ir
+        .lines()
+        .any(|line| line.contains("getelementptr") && line.ends_with("i32 0, i32 6")));
+}
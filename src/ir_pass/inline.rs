@@ -0,0 +1,263 @@
+//! 函数内联：把被调用者的基本块复制到调用点，形参替换为实参，ret 改为跳转到调用点之后的基本块
+//!
+//! 调用图中位于环上的递归函数不内联，其余按被调用者的规模和 -O 对应的预算决定
+
+use std::collections::HashMap;
+
+use crate::ir::{InstValue, Module, PhiInst, Value, ValueId};
+
+use super::{
+    analysis::{AnalysisManager, CallGraph, Cfg},
+    pass_manager::Pass,
+};
+
+/// 内联后调用者最多的指令数，避免编译时间和代码体积失控
+const MAX_CALLER_SIZE: usize = 2000;
+
+pub struct InlinePass {
+    /// 有多个调用点的被调用者，内联代价不超过该值时内联
+    threshold: usize,
+}
+
+impl InlinePass {
+    pub fn new(optimize_level: u8) -> Self {
+        let threshold = match optimize_level {
+            0 | 1 => 20,
+            2 => 80,
+            _ => 200,
+        };
+        InlinePass { threshold }
+    }
+
+    fn should_inline(
+        &self,
+        module: &Module,
+        cg: &CallGraph,
+        call_counts: &HashMap<ValueId, usize>,
+        caller: ValueId,
+        call_id: ValueId,
+    ) -> bool {
+        let call = module.get_inst(call_id).as_call();
+        let callee = call.func;
+        if callee == caller || module.get_func(callee).is_external || cg.is_recursive(callee) {
+            return false;
+        }
+        let callee_size = func_size(module, callee);
+        if func_size(module, caller) + callee_size > MAX_CALLER_SIZE {
+            return false;
+        }
+        // 唯一的调用点内联后被调用者会被 globaldce 删除，代码不会变多
+        if call_counts.get(&callee) == Some(&1) {
+            return true;
+        }
+        // 省去的调用开销，常量实参在内联后还可以继续折叠
+        let saved = 1
+            + call.args.len()
+            + 2 * call
+                .args
+                .iter()
+                .filter(|arg| matches!(module.get_value(**arg), Value::Const(_)))
+                .count();
+        callee_size.saturating_sub(saved) <= self.threshold
+    }
+}
+
+impl Pass for InlinePass {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+
+    fn run(&mut self, module: &mut Module, am: &mut AnalysisManager) {
+        let cg = CallGraph::new(module);
+        let mut call_counts = cg.call_counts.clone();
+        // 被调用者先处理，内联到调用者中的是已经内联过的函数体
+        for caller in &cg.bottom_up {
+            for call_id in func_calls(module, *caller) {
+                if !self.should_inline(module, &cg, &call_counts, *caller, call_id) {
+                    continue;
+                }
+                let callee = module.get_inst(call_id).as_call().func;
+                *call_counts.get_mut(&callee).unwrap() -= 1;
+                for inner in func_calls(module, callee) {
+                    let inner_callee = module.get_inst(inner).as_call().func;
+                    *call_counts.entry(inner_callee).or_default() += 1;
+                }
+                inline_call(module, *caller, call_id);
+                am.invalidate(*caller);
+            }
+        }
+    }
+}
+
+fn func_size(module: &Module, func_id: ValueId) -> usize {
+    module
+        .get_func(func_id)
+        .bbs
+        .bbs
+        .values()
+        .map(|bb_id| module.get_bb(*bb_id).insts.len())
+        .sum()
+}
+
+fn func_calls(module: &Module, func_id: ValueId) -> Vec<ValueId> {
+    module
+        .get_func(func_id)
+        .bbs
+        .bbs
+        .values()
+        .flat_map(|bb_id| module.get_bb(*bb_id).insts.iter().copied())
+        .filter(|inst_id| module.get_inst(*inst_id).is_call())
+        .collect()
+}
+
+/// 在调用点展开被调用者
+pub fn inline_call(module: &mut Module, caller: ValueId, call_id: ValueId) {
+    let call = module.get_inst(call_id).as_call().clone();
+    let callee = call.func;
+    let call_bb = module.get_parent_id(call_id);
+    let pos = module
+        .get_bb(call_bb)
+        .insts
+        .iter()
+        .position(|inst_id| *inst_id == call_id)
+        .unwrap();
+
+    // 调用点之后的指令移到新的基本块中，作为被调用者返回后的去处
+    module.set_cur_func(caller);
+    let cont = module.spawn_basic_block();
+    let tail = module.get_bb_mut(call_bb).insts.split_off(pos + 1);
+    for inst_id in &tail {
+        module.mark_parent(*inst_id, cont);
+    }
+    module.get_bb_mut(cont).insts = tail;
+    let mut succs = module.get_inst(module.get_terminator(cont)).successors();
+    succs.dedup();
+    for succ in succs {
+        for phi_id in module.get_phis(succ) {
+            module.replace_phi_incoming_bb(phi_id, call_bb, cont);
+        }
+    }
+
+    // 按逆后序复制，非 phi 指令的操作数总在它之前复制；phi 的来源最后再填
+    let mut map = module
+        .get_func(callee)
+        .params
+        .iter()
+        .copied()
+        .zip(call.args.iter().copied())
+        .collect::<HashMap<_, _>>();
+    let cfg = Cfg::new(module, callee);
+    for bb_id in &cfg.rpo {
+        let new_bb = module.spawn_basic_block();
+        map.insert(*bb_id, new_bb);
+    }
+    let mut phis = vec![];
+    let mut allocas = vec![];
+    let mut rets = vec![];
+    for bb_id in &cfg.rpo {
+        let new_bb = map[bb_id];
+        for inst_id in module.get_bb(*bb_id).insts.clone() {
+            let pos = module.get_bb(new_bb).insts.len();
+            match module.get_inst(inst_id).clone() {
+                InstValue::Phi(phi) => {
+                    let empty = PhiInst {
+                        ty: phi.ty.clone(),
+                        incomings: vec![],
+                    };
+                    let new_id = module.insert_inst(new_bb, pos, InstValue::Phi(empty));
+                    phis.push((phi, new_id));
+                    map.insert(inst_id, new_id);
+                }
+                InstValue::Return(ret) => {
+                    let value = ret.value.map(|value| *map.get(&value).unwrap_or(&value));
+                    rets.push((value, new_bb));
+                    module.set_insert_point(new_bb);
+                    module.spawn_jump_inst(cont);
+                }
+                inst => {
+                    let new_id = module.clone_inst(inst_id, new_bb, pos, &map);
+                    map.insert(inst_id, new_id);
+                    if inst.is_alloca() {
+                        allocas.push(new_id);
+                    }
+                    // ret 变为跳转，被调用者中的尾调用不再处于尾部
+                    if let InstValue::Call(call) = module.get_inst_mut(new_id) {
+                        call.must_tail = false;
+                    }
+                }
+            }
+        }
+    }
+    for (phi, new_id) in phis {
+        for (value, bb) in phi.incomings {
+            if let Some(bb) = map.get(&bb) {
+                module.add_phi_incoming(new_id, *bb, *map.get(&value).unwrap_or(&value));
+            }
+        }
+    }
+    // 局部数组留在入口块中，避免在循环中反复分配栈空间
+    let entry = *module.get_func(caller).bbs.entry_bb();
+    for alloca_id in allocas.into_iter().rev() {
+        module.move_inst(alloca_id, entry, 0);
+    }
+
+    // 返回值经过 phi 汇合后替换调用
+    if !module.get_users_of(call_id).is_empty() {
+        let value = match rets.as_slice() {
+            [(Some(value), _)] => *value,
+            // 被调用者不会返回
+            [] => module.spawn_zero_value(call.ty.clone()),
+            _ => {
+                let phi = PhiInst {
+                    ty: call.ty.clone(),
+                    incomings: rets
+                        .iter()
+                        .map(|(value, bb)| (value.unwrap(), *bb))
+                        .collect(),
+                };
+                module.insert_inst(cont, 0, InstValue::Phi(phi))
+            }
+        };
+        module.replace_value(call_id, value);
+    }
+    module.remove_inst(call_id);
+    module.set_insert_point(call_bb);
+    module.spawn_jump_inst(map[&cfg.entry]);
+}
+
+#[test]
+fn test_inline() {
+    use crate::{
+        ir_pass::{build_ir, dce::GlobalDcePass, inst_namer, verify},
+        ir_printer,
+    };
+
+    let mut module = build_ir(
+        "int sq(int x) { return x * x; }
+        int abs(int x) { if (x < 0) { return 0 - x; } return x; }
+        int fib(int n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); }
+        int big(int x) {
+            int s = 0;
+            while (x > 0) { s = s + x * x * x + x / 3 + x % 7 + abs(x - 10); x = x - 1; }
+            return s;
+        }
+        int main() {
+            int a = sq(3) + sq(4) + abs(0 - 5);
+            return a + fib(a) + big(a) + big(a + 1);
+        }",
+    );
+    let mut am = AnalysisManager::new();
+    InlinePass::new(1).run(&mut module, &mut am);
+    GlobalDcePass.run(&mut module, &mut am);
+    assert_eq!(verify::run(&module), Ok(()));
+
+    // 递归的 fib 和多处调用的较大的 big 保留，sq 和 abs 被内联后删除
+    assert!(module.functions.contains_key("fib"));
+    assert!(module.functions.contains_key("big"));
+    assert!(!module.functions.contains_key("sq"));
+    assert!(!module.functions.contains_key("abs"));
+    inst_namer::run(&mut module);
+    let ir = ir_printer::print(&mut module);
+    assert_eq!(ir.matches("call i32 @big").count(), 2);
+    assert_eq!(ir.matches("call i32 @fib").count(), 3);
+}
@@ -42,7 +42,15 @@ impl InstNamer<'_> {
 
         self.visit_params(func_val_id);
 
-        self.next_id += 1;
+        // 入口块占用一个编号，即 LLVM 中隐式的 %N，phi 中需要引用
+        let func = self.module.get_func(func_val_id);
+        if let Some((_, entry_id)) = func.bbs.bbs.front() {
+            let entry_id = *entry_id;
+            let name = self.generate_bb_name();
+            self.assign(entry_id, name);
+        } else {
+            self.next_id += 1;
+        }
 
         let func = self.module.get_func(func_val_id);
 
@@ -107,3 +115,15 @@ impl InstNamer<'_> {
         name
     }
 }
+
+#[test]
+fn test_entry_block_name() {
+    use crate::{ir_pass::build_ir, ir_printer};
+
+    let mut module = build_ir("int f(int n) { int i = 0; while (i < n) { i = i + 1; } return i; }");
+    run(&mut module);
+    let ir = ir_printer::print(&mut module);
+
+    // 形参是 %0，入口块隐式编号为 %1，phi 的来源按该编号引用入口块
+    assert!(ir.contains("[0, %1]"), "{}", ir);
+}
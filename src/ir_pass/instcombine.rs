@@ -0,0 +1,454 @@
+//! 指令合并：根据操作数化简整数二元运算和比较
+//!
+//! - 恒等式：`x+0`、`x*1`、`x-x`、`x&x` 等直接替换为已有的值或常量
+//! - 强度削减：乘以 2 的幂改为左移，除以和模常数改为乘高位加移位，不再生成 sdiv
+//! - 常数链重结合：`(x+c1)+c2` 改为 `x+(c1+c2)`
+//! - 比较规范化：常数放到右边，`x+c1 == c2` 改为 `x == c2-c1`
+//!
+//! 乘高位在 IR 中表示为 `trunc(ashr(mul(sext x, M), 32))`，mc_builder 把它整体选择为 SMMUL
+
+use crate::{
+    ast::{BuiltinType, InfixOp, Type},
+    ir::{
+        BinaryOperator, CastInst, CastOp, ConstInt, ConstValue, InstValue, Module, Value, ValueId,
+    },
+};
+
+use super::{analysis::AnalysisManager, pass_manager::FunctionPass, sccp::{fold_cast, fold_infix}};
+
+pub struct InstCombinePass;
+
+impl FunctionPass for InstCombinePass {
+    fn name(&self) -> &'static str {
+        "instcombine"
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, _am: &mut AnalysisManager) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            let bbs = module
+                .get_func(func_id)
+                .bbs
+                .bbs
+                .values()
+                .copied()
+                .collect::<Vec<_>>();
+            for bb_id in bbs {
+                for inst_id in module.get_bb(bb_id).insts.clone() {
+                    let new_id = match module.get_inst(inst_id).clone() {
+                        InstValue::InfixOp(op) => {
+                            let mut b = Builder {
+                                module,
+                                bb: bb_id,
+                                before: inst_id,
+                            };
+                            combine(&mut b, op)
+                        }
+                        // 操作数化简为常数后，其上的类型转换也要折叠
+                        InstValue::Cast(cast) => match module.get_value(cast.value) {
+                            Value::Const(c) => fold_cast(&cast.op, c, &cast.new_ty)
+                                .map(|c| module.alloc_value(c.into())),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if let Some(new_id) = new_id {
+                        module.replace_value(inst_id, new_id);
+                        module.remove_inst(inst_id);
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 在指定指令之前插入新指令
+struct Builder<'a> {
+    module: &'a mut Module,
+    bb: ValueId,
+    before: ValueId,
+}
+
+impl Builder<'_> {
+    fn insert(&mut self, inst: InstValue) -> ValueId {
+        let pos = self
+            .module
+            .get_bb(self.bb)
+            .insts
+            .iter()
+            .position(|inst_id| *inst_id == self.before)
+            .unwrap();
+        self.module.insert_inst(self.bb, pos, inst)
+    }
+
+    fn binop(&mut self, op: InfixOp, lhs: ValueId, rhs: ValueId) -> ValueId {
+        let ty = self.module.get_value(lhs).ty();
+        self.insert(InstValue::InfixOp(BinaryOperator { ty, op, lhs, rhs }))
+    }
+
+    fn cast(&mut self, op: CastOp, value: ValueId, new_ty: Type) -> ValueId {
+        self.insert(InstValue::Cast(CastInst { op, value, new_ty }))
+    }
+
+    fn constant(&mut self, value: i64, ty: BuiltinType) -> ValueId {
+        self.module.alloc_value(
+            ConstInt {
+                ty: ty.into(),
+                value,
+            }
+            .into(),
+        )
+    }
+
+    fn int(&mut self, value: i32) -> ValueId {
+        self.constant(value as i64, BuiltinType::Int)
+    }
+
+    fn fold(&mut self, op: &InfixOp, lhs: i32, rhs: i32, ty: &Type) -> Option<ValueId> {
+        let c = fold_infix(op, &int_const(lhs), &int_const(rhs), ty)?;
+        Some(self.module.alloc_value(c.into()))
+    }
+
+    /// 有符号 32 位乘法结果的高 32 位
+    fn mul_high(&mut self, x: ValueId, m: i32) -> ValueId {
+        let wide = self.cast(CastOp::SExt, x, BuiltinType::Int64.into());
+        let m = self.constant(m as i64, BuiltinType::Int64);
+        let prod = self.binop(InfixOp::Mul, wide, m);
+        let shift = self.constant(32, BuiltinType::Int64);
+        let high = self.binop(InfixOp::BitShr, prod, shift);
+        self.cast(CastOp::Trunc, high, BuiltinType::Int.into())
+    }
+}
+
+fn int_const(value: i32) -> ConstValue {
+    ConstValue::Int(ConstInt {
+        ty: BuiltinType::Int.into(),
+        value: value as i64,
+    })
+}
+
+fn as_int(module: &Module, val_id: ValueId) -> Option<i32> {
+    match module.get_value(val_id) {
+        Value::Const(ConstValue::Int(c)) if c.ty == BuiltinType::Int.into() => Some(c.value as i32),
+        _ => None,
+    }
+}
+
+/// 返回替换该指令的值，新的指令插入在它之前
+fn combine(b: &mut Builder, mut inst: BinaryOperator) -> Option<ValueId> {
+    let int_ty: Type = BuiltinType::Int.into();
+    if inst.op.is_boolean() {
+        return combine_cmp(b, inst);
+    }
+    if inst.ty != int_ty {
+        return None;
+    }
+    // 常数放到可交换运算的右边
+    if inst.op.is_commutative()
+        && as_int(b.module, inst.lhs).is_some()
+        && as_int(b.module, inst.rhs).is_none()
+    {
+        std::mem::swap(&mut inst.lhs, &mut inst.rhs);
+        return Some(b.binop(inst.op, inst.lhs, inst.rhs));
+    }
+    let x = inst.lhs;
+    if inst.lhs == inst.rhs {
+        match inst.op {
+            InfixOp::Sub | InfixOp::BitXor => return Some(b.int(0)),
+            InfixOp::BitAnd | InfixOp::BitOr => return Some(x),
+            _ => {}
+        }
+    }
+    let c = as_int(b.module, inst.rhs)?;
+    if let Some(l) = as_int(b.module, inst.lhs) {
+        return b.fold(&inst.op, l, c, &inst.ty);
+    }
+
+    match (&inst.op, c) {
+        (InfixOp::Add | InfixOp::Sub | InfixOp::BitOr | InfixOp::BitXor, 0)
+        | (InfixOp::BitShl | InfixOp::BitShr, 0)
+        | (InfixOp::Mul | InfixOp::Div, 1)
+        | (InfixOp::BitAnd, -1) => return Some(x),
+        (InfixOp::Mul | InfixOp::BitAnd, 0) | (InfixOp::Rem | InfixOp::Mod, 1 | -1) => {
+            return Some(b.int(0))
+        }
+        (InfixOp::BitOr, -1) => return Some(b.int(-1)),
+        (InfixOp::Mul | InfixOp::Div, -1) => {
+            let zero = b.int(0);
+            return Some(b.binop(InfixOp::Sub, zero, x));
+        }
+        _ => {}
+    }
+
+    if let Some(new_id) = reassociate(b, &inst, c) {
+        return Some(new_id);
+    }
+
+    match inst.op {
+        InfixOp::Mul if c > 0 && (c as u32).is_power_of_two() => {
+            let k = b.int(c.trailing_zeros() as i32);
+            Some(b.binop(InfixOp::BitShl, x, k))
+        }
+        // 除数为 0 时保留原来的未定义行为
+        InfixOp::Div if c != 0 => Some(build_div(b, x, c)),
+        InfixOp::Rem | InfixOp::Mod if c != 0 => Some(build_rem(b, x, c)),
+        _ => None,
+    }
+}
+
+/// `(x op c1) op c2` 改为 `x op (c1 op c2)`，加减法统一按加上一个常数处理
+fn reassociate(b: &mut Builder, inst: &BinaryOperator, c2: i32) -> Option<ValueId> {
+    let Some(InstValue::InfixOp(inner)) = b.module.try_get_inst(inst.lhs).cloned() else {
+        return None;
+    };
+    let c1 = as_int(b.module, inner.rhs)?;
+    if inner.ty != inst.ty {
+        return None;
+    }
+    match (&inner.op, &inst.op) {
+        (InfixOp::Add | InfixOp::Sub, InfixOp::Add | InfixOp::Sub) => {
+            let offset = |op: &InfixOp, c: i32| match op {
+                InfixOp::Add => c,
+                _ => c.wrapping_neg(),
+            };
+            let sum = offset(&inner.op, c1).wrapping_add(offset(&inst.op, c2));
+            Some(add_const(b, inner.lhs, sum))
+        }
+        (InfixOp::Mul, InfixOp::Mul)
+        | (InfixOp::BitAnd, InfixOp::BitAnd)
+        | (InfixOp::BitOr, InfixOp::BitOr)
+        | (InfixOp::BitXor, InfixOp::BitXor) => {
+            let c = b.fold(&inst.op, c1, c2, &inst.ty)?;
+            Some(b.binop(inst.op.clone(), inner.lhs, c))
+        }
+        _ => None,
+    }
+}
+
+/// 负的常数改为减法，ARM 的立即数不能直接表示大多数负数
+fn add_const(b: &mut Builder, x: ValueId, c: i32) -> ValueId {
+    if c < 0 && c != i32::MIN {
+        let c = b.int(-c);
+        b.binop(InfixOp::Sub, x, c)
+    } else {
+        let c = b.int(c);
+        b.binop(InfixOp::Add, x, c)
+    }
+}
+
+/// x 为负数时需要加上的偏置，使算术右移向零取整
+fn round_bias(b: &mut Builder, x: ValueId, k: u32) -> ValueId {
+    let k31 = b.int(31);
+    let sign = b.binop(InfixOp::BitShr, x, k31);
+    let mask = b.int(((1u64 << k) - 1) as i32);
+    let bias = b.binop(InfixOp::BitAnd, sign, mask);
+    b.binop(InfixOp::Add, x, bias)
+}
+
+/// 有符号除以常数 d，d 不为 0 和 ±1
+fn build_div(b: &mut Builder, x: ValueId, d: i32) -> ValueId {
+    let ad = d.unsigned_abs();
+    let q = if ad.is_power_of_two() {
+        let k = ad.trailing_zeros();
+        let t = round_bias(b, x, k);
+        let k = b.int(k as i32);
+        b.binop(InfixOp::BitShr, t, k)
+    } else {
+        let (m, s) = magic(ad);
+        let mut q = b.mul_high(x, m);
+        // 乘数超过 i32 范围时被当作负数，补上多减的 x
+        if m < 0 {
+            q = b.binop(InfixOp::Add, q, x);
+        }
+        if s > 0 {
+            let s = b.int(s as i32);
+            q = b.binop(InfixOp::BitShr, q, s);
+        }
+        // x 为负数时商加一，向零取整
+        let k31 = b.int(31);
+        let sign = b.binop(InfixOp::BitShr, x, k31);
+        b.binop(InfixOp::Sub, q, sign)
+    };
+    if d < 0 {
+        let zero = b.int(0);
+        b.binop(InfixOp::Sub, zero, q)
+    } else {
+        q
+    }
+}
+
+/// 有符号取模，余数的符号与被除数相同，与除数的符号无关
+fn build_rem(b: &mut Builder, x: ValueId, d: i32) -> ValueId {
+    let ad = d.unsigned_abs();
+    let multiple = if ad.is_power_of_two() {
+        let k = ad.trailing_zeros();
+        let t = round_bias(b, x, k);
+        let mask = b.int((ad as i32).wrapping_neg());
+        b.binop(InfixOp::BitAnd, t, mask)
+    } else {
+        let q = build_div(b, x, ad as i32);
+        let ad = b.int(ad as i32);
+        b.binop(InfixOp::Mul, q, ad)
+    };
+    b.binop(InfixOp::Sub, x, multiple)
+}
+
+/// 除以 d 的魔数 M 和移位 s：`x / d == (mulhs(x, M) >> s) + (x < 0)`
+///
+/// 见 Hacker's Delight 10-1，d 为大于 1 且不是 2 的幂的正数
+fn magic(d: u32) -> (i32, u32) {
+    const TWO31: u32 = 1 << 31;
+    let anc = TWO31 - 1 - TWO31 % d;
+    let mut p = 31;
+    let (mut q1, mut r1) = (TWO31 / anc, TWO31 % anc);
+    let (mut q2, mut r2) = (TWO31 / d, TWO31 % d);
+    loop {
+        p += 1;
+        q1 = q1.wrapping_mul(2);
+        r1 *= 2;
+        if r1 >= anc {
+            q1 = q1.wrapping_add(1);
+            r1 -= anc;
+        }
+        q2 = q2.wrapping_mul(2);
+        r2 *= 2;
+        if r2 >= d {
+            q2 = q2.wrapping_add(1);
+            r2 -= d;
+        }
+        let delta = d - r2;
+        if q1 > delta || (q1 == delta && r1 != 0) {
+            break;
+        }
+    }
+    (q2.wrapping_add(1) as i32, p - 32)
+}
+
+/// 交换比较两边的操作数后等价的比较
+pub fn swap_predicate(op: &InfixOp) -> InfixOp {
+    match op {
+        InfixOp::Lt => InfixOp::Gt,
+        InfixOp::Gt => InfixOp::Lt,
+        InfixOp::Le => InfixOp::Ge,
+        InfixOp::Ge => InfixOp::Le,
+        op => op.clone(),
+    }
+}
+
+fn combine_cmp(b: &mut Builder, inst: BinaryOperator) -> Option<ValueId> {
+    if matches!(inst.op, InfixOp::LogicAnd | InfixOp::LogicOr) {
+        return None;
+    }
+    let is_int = b.module.get_value(inst.lhs).ty() == BuiltinType::Int.into();
+    let is_const = |b: &Builder, val_id| matches!(b.module.get_value(val_id), Value::Const(_));
+    // 常数放到右边
+    if is_const(b, inst.lhs) && !is_const(b, inst.rhs) {
+        let op = swap_predicate(&inst.op);
+        return Some(b.insert(InstValue::InfixOp(BinaryOperator {
+            op,
+            lhs: inst.rhs,
+            rhs: inst.lhs,
+            ..inst
+        })));
+    }
+    if !is_int {
+        return None;
+    }
+    // 浮点数可能是 NaN，只对整数化简 x op x
+    if inst.lhs == inst.rhs {
+        let value = matches!(inst.op, InfixOp::Eq | InfixOp::Le | InfixOp::Ge);
+        return Some(b.constant(value as i64, BuiltinType::Bool));
+    }
+    // 等式两边同时减去常数不会改变结果，不等关系在溢出时会变化
+    let c2 = as_int(b.module, inst.rhs)?;
+    if !matches!(inst.op, InfixOp::Eq | InfixOp::Ne) {
+        return None;
+    }
+    let Some(InstValue::InfixOp(inner)) = b.module.try_get_inst(inst.lhs).cloned() else {
+        return None;
+    };
+    let c1 = as_int(b.module, inner.rhs)?;
+    let c = match inner.op {
+        InfixOp::Add => c2.wrapping_sub(c1),
+        InfixOp::Sub => c2.wrapping_add(c1),
+        InfixOp::BitXor => c2 ^ c1,
+        _ => return None,
+    };
+    let rhs = b.int(c);
+    Some(b.insert(InstValue::InfixOp(BinaryOperator {
+        lhs: inner.lhs,
+        rhs,
+        ..inst
+    })))
+}
+
+#[test]
+fn test_instcombine() {
+    use crate::{
+        ir_pass::{build_ir, dce::DcePass, inst_namer, verify},
+        ir_printer,
+    };
+
+    let mut module = build_ir(
+        "int f(int x, int y) {
+            int a = (x + 0) * 1 + (y - y);
+            int b = a * 8 + a / 4 + a % 16;
+            int c = b / 7 + b % 10 + b / -3;
+            int d = ((c + 1) + 2) - 5;
+            if (3 < d) { return d; }
+            if (d + 4 == 10) { return 1; }
+            return 0;
+        }",
+    );
+    let func_id = module.functions["f"];
+    let mut am = AnalysisManager::new();
+    InstCombinePass.run_on_func(&mut module, func_id, &mut am);
+    DcePass.run_on_func(&mut module, func_id, &mut am);
+    assert_eq!(verify::run(&module), Ok(()));
+
+    inst_namer::run(&mut module);
+    let ir = ir_printer::print(&mut module);
+    // 不再有除法和取模，乘以 8 改为左移，三个不是 2 的幂的除数各用一次乘高位
+    assert!(!ir.contains("sdiv") && !ir.contains("srem"));
+    assert!(ir.contains("shl i32"));
+    assert_eq!(ir.matches("mul i64").count(), 3);
+    assert_eq!(ir.matches("mul i32").count(), 1);
+    // 常数链合并为一次减法，比较的常数在右边，d + 4 == 10 改为 c == 8
+    assert!(ir
+        .lines()
+        .any(|line| line.contains("sub i32") && line.contains(", 2 ")));
+    assert!(ir.contains("icmp sgt i32"));
+    let eq = ir.lines().find(|line| line.contains("icmp eq")).unwrap();
+    assert!(eq.contains(", 8 "));
+
+    // 魔数展开与除法的结果一致
+    for d in [3u32, 5, 6, 7, 10, 12, 25, 125, 641, 1000, 0x7fff_ffff] {
+        let (m, s) = magic(d);
+        for x in [
+            0,
+            1,
+            -1,
+            7,
+            -7,
+            100,
+            -100,
+            12345,
+            -98765,
+            i32::MAX,
+            i32::MIN,
+        ] {
+            let mut q = ((x as i64 * m as i64) >> 32) as i32;
+            if m < 0 {
+                q = q.wrapping_add(x);
+            }
+            q = (q >> s) - (x >> 31);
+            assert_eq!(q, x / d as i32, "{} / {}", x, d);
+        }
+    }
+}
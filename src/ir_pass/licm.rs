@@ -0,0 +1,204 @@
+//! 循环不变量外提：把操作数都在循环外定义的纯计算，以及读取循环中不会被写入的内存的 load
+//! 移到 preheader 中
+
+use crate::{
+    ast::InfixOp,
+    ir::{BinaryOperator, ConstValue, InstValue, Module, Value, ValueId},
+};
+
+use super::{
+    analysis::{AnalysisManager, Cfg, DomTree, Loop},
+    loop_simplify::insert_preheaders,
+    pass_manager::FunctionPass,
+};
+
+pub struct LicmPass;
+
+impl FunctionPass for LicmPass {
+    fn name(&self) -> &'static str {
+        "licm"
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, am: &mut AnalysisManager) {
+        if insert_preheaders(module, func_id) {
+            am.invalidate(func_id);
+        }
+        let cfg = am.cfg(module, func_id);
+        let dom = am.dom_tree(module, func_id);
+        let loops = am.loop_info(module, func_id);
+        // 先处理内层循环，外提到内层 preheader 的指令还可能继续外提
+        for l in loops.inner_to_outer() {
+            hoist_loop(module, &cfg, &dom, &loops.loops[l]);
+        }
+    }
+}
+
+fn hoist_loop(module: &mut Module, cfg: &Cfg, dom: &DomTree, l: &Loop) {
+    let preheader = l.preheader(cfg).unwrap();
+    let memory = LoopMemory::collect(module, l);
+    let exiting = l.exiting_blocks(cfg);
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for bb_id in &l.blocks {
+            // 支配所有出口的基本块在进入循环后一定会执行
+            let always_executed = exiting.iter().all(|exit| dom.dominates(*bb_id, *exit));
+            for inst_id in module.get_bb(*bb_id).insts.clone() {
+                let inst = module.get_inst(inst_id);
+                let invariant = inst
+                    .operands()
+                    .iter()
+                    .all(|opr| !is_defined_in(module, l, *opr));
+                let hoistable = match inst {
+                    InstValue::InfixOp(op) => always_executed || !may_trap(module, op),
+                    InstValue::Gep(_) | InstValue::Cast(_) => true,
+                    InstValue::Load(load) => always_executed && !memory.may_write(module, load.ptr),
+                    _ => false,
+                };
+                if invariant && hoistable {
+                    let pos = module.get_bb(preheader).insts.len() - 1;
+                    module.move_inst(inst_id, preheader, pos);
+                    changed = true;
+                }
+            }
+        }
+    }
+}
+
+/// 值由循环中的指令定义，常量、参数和循环外的指令都是循环不变量
+pub fn is_defined_in(module: &Module, l: &Loop, val_id: ValueId) -> bool {
+    module
+        .value_parent
+        .get(&val_id)
+        .is_some_and(|bb_id| l.contains(*bb_id))
+}
+
+/// 整数除法和取模的除数可能为 0 或 -1 时不能提前执行
+fn may_trap(module: &Module, op: &BinaryOperator) -> bool {
+    if !matches!(op.op, InfixOp::Div | InfixOp::Rem | InfixOp::Mod) || op.ty.is_float() {
+        return false;
+    }
+    !matches!(
+        module.get_value(op.rhs),
+        Value::Const(ConstValue::Int(c)) if c.value != 0 && c.value != -1
+    )
+}
+
+/// 指针指向的内存对象
+#[derive(PartialEq)]
+enum Base {
+    Local(ValueId),
+    Global(ValueId),
+    /// 参数传入的数组，可能是任何非局部的对象
+    Unknown,
+}
+
+fn base_of(module: &Module, ptr: ValueId) -> Base {
+    match module.get_value(ptr) {
+        Value::Instruction(InstValue::Alloca(_)) => Base::Local(ptr),
+        Value::Instruction(InstValue::Gep(gep)) => base_of(module, gep.ptr),
+        Value::GlobalVariable(_) => Base::Global(ptr),
+        _ => Base::Unknown,
+    }
+}
+
+fn may_alias(a: &Base, b: &Base) -> bool {
+    match (a, b) {
+        (Base::Local(_), _) | (_, Base::Local(_)) | (Base::Global(_), Base::Global(_)) => a == b,
+        _ => true,
+    }
+}
+
+/// 循环中写入的内存
+struct LoopMemory {
+    stores: Vec<Base>,
+    /// 循环中调用了可能写内存的函数
+    has_unknown_write: bool,
+}
+
+impl LoopMemory {
+    fn collect(module: &Module, l: &Loop) -> Self {
+        let mut memory = LoopMemory {
+            stores: vec![],
+            has_unknown_write: false,
+        };
+        for bb_id in &l.blocks {
+            for inst_id in &module.get_bb(*bb_id).insts {
+                match module.get_inst(*inst_id) {
+                    InstValue::Store(store) => memory.stores.push(base_of(module, store.ptr)),
+                    InstValue::Call(call) => {
+                        // 运行时库函数只会写入作为参数传入的数组
+                        let func = module.get_func(call.func);
+                        if !func.is_external
+                            || call
+                                .args
+                                .iter()
+                                .any(|arg| module.get_value(*arg).ty().is_pointer(false))
+                        {
+                            memory.has_unknown_write = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        memory
+    }
+
+    fn may_write(&self, module: &Module, ptr: ValueId) -> bool {
+        let base = base_of(module, ptr);
+        self.has_unknown_write || self.stores.iter().any(|store| may_alias(store, &base))
+    }
+}
+
+#[test]
+fn test_licm() {
+    use crate::{
+        ir_pass::{analysis::LoopInfo, build_ir, inst_namer, loop_rotate::LoopRotatePass, verify},
+        ir_printer,
+    };
+
+    let mut module = build_ir(
+        "int g; int h;
+        int f(int n, int k) {
+            int a[8][8] = {};
+            int i = 0; int s = 0;
+            while (i < n) {
+                s = s + a[k][i] + g * k + n / k + h;
+                h = s;
+                i = i + 1;
+            }
+            return s;
+        }",
+    );
+    let func_id = module.functions["f"];
+    let mut am = AnalysisManager::new();
+    LoopRotatePass.run_on_func(&mut module, func_id, &mut am);
+    LicmPass.run_on_func(&mut module, func_id, &mut am);
+    assert_eq!(verify::run(&module), Ok(()));
+
+    // 循环中只剩 a[k][i] 最后一维的地址计算和读取，以及被循环写入的 h 的读取
+    let cfg = Cfg::new(&module, func_id);
+    let loops = LoopInfo::new(&cfg, &DomTree::new(&cfg));
+    let [l] = loops.loops.as_slice() else {
+        panic!("expect one loop");
+    };
+    let in_loop = |pred: fn(&InstValue) -> bool| {
+        l.blocks
+            .iter()
+            .flat_map(|bb_id| module.get_bb(*bb_id).insts.iter())
+            .filter(|inst_id| pred(module.get_inst(**inst_id)))
+            .count()
+    };
+    assert_eq!(in_loop(InstValue::is_load), 2);
+    assert_eq!(in_loop(InstValue::is_gep), 1);
+    assert_eq!(
+        in_loop(|inst| matches!(inst, InstValue::InfixOp(op) if op.op == InfixOp::Mul)),
+        0
+    );
+
+    inst_namer::run(&mut module);
+    let ir = ir_printer::print(&mut module);
+    assert!(ir.contains("load i32, ptr @g"));
+}
@@ -0,0 +1,234 @@
+//! 循环旋转：把先判断条件的 while 循环改为带守卫的 do-while 循环
+//!
+//! header 复制到 preheader 中作为守卫，原 header 成为循环末尾的 latch，
+//! 循环体的第一个基本块成为新的 header。旋转后进入循环时循环体至少执行一次
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{InstValue, Module, ValueId};
+
+use super::{
+    analysis::{AnalysisManager, Cfg, DomTree, Loop, LoopInfo},
+    loop_simplify::insert_preheaders,
+    pass_manager::FunctionPass,
+    ssa_updater::SsaUpdater,
+};
+
+/// header 中最多复制的指令数
+const MAX_HEADER_SIZE: usize = 16;
+
+pub struct LoopRotatePass;
+
+impl FunctionPass for LoopRotatePass {
+    fn name(&self) -> &'static str {
+        "loop-rotate"
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, am: &mut AnalysisManager) {
+        let mut changed = insert_preheaders(module, func_id);
+        // 旋转过的循环 latch 已经是出口，不会被再次选中
+        loop {
+            let mut cfg = Cfg::new(module, func_id);
+            let loops = LoopInfo::new(&cfg, &DomTree::new(&cfg));
+            let rotations = loops
+                .loops
+                .iter()
+                .filter_map(|l| Rotation::check(module, &cfg, l))
+                .collect::<Vec<_>>();
+            if rotations.is_empty() {
+                break;
+            }
+            // 一次分析旋转所有互不相干的循环，改动过的基本块所在的循环留到下一轮
+            let mut touched = HashSet::new();
+            for rotation in rotations {
+                let blocks = [
+                    rotation.preheader,
+                    rotation.header,
+                    rotation.latch,
+                    rotation.body,
+                    rotation.exit,
+                ];
+                if blocks.iter().any(|bb| touched.contains(bb)) {
+                    continue;
+                }
+                rotation.apply(module, &mut cfg);
+                touched.extend(blocks);
+            }
+            insert_preheaders(module, func_id);
+            changed = true;
+        }
+        if changed {
+            am.invalidate(func_id);
+        }
+    }
+}
+
+struct Rotation {
+    preheader: ValueId,
+    header: ValueId,
+    latch: ValueId,
+    /// header 跳转到的循环内和循环外的基本块
+    body: ValueId,
+    exit: ValueId,
+}
+
+impl Rotation {
+    /// header 以条件跳转退出循环，唯一的 latch 无条件跳回 header 时可以旋转
+    fn check(module: &Module, cfg: &Cfg, l: &Loop) -> Option<Self> {
+        let preheader = l.preheader(cfg)?;
+        let [latch] = l.latches.as_slice() else {
+            return None;
+        };
+        if *latch == l.header || !module.get_inst(module.get_terminator(*latch)).is_jump() {
+            return None;
+        }
+        let InstValue::Branch(br) = module.get_inst(module.get_terminator(l.header)) else {
+            return None;
+        };
+        let (body, exit) = match (l.contains(br.then_bb), l.contains(br.else_bb)) {
+            (true, false) => (br.then_bb, br.else_bb),
+            (false, true) => (br.else_bb, br.then_bb),
+            _ => return None,
+        };
+        let insts = &module.get_bb(l.header).insts;
+        let size = insts
+            .iter()
+            .filter(|inst_id| !module.get_inst(**inst_id).is_phi())
+            .count();
+        if size > MAX_HEADER_SIZE
+            || insts
+                .iter()
+                .any(|inst_id| module.get_inst(*inst_id).is_call())
+        {
+            return None;
+        }
+        Some(Rotation {
+            preheader,
+            header: l.header,
+            latch: *latch,
+            body,
+            exit,
+        })
+    }
+
+    /// 旋转后 cfg 中 preheader 的边随之更新
+    fn apply(&self, module: &mut Module, cfg: &mut Cfg) {
+        let insts = module.get_bb(self.header).insts.clone();
+
+        // header 的副本替换 preheader 的 jump，phi 取来自 preheader 的值
+        let mut map = HashMap::new();
+        for phi_id in module.get_phis(self.header) {
+            let (value, _) = *module
+                .get_inst(phi_id)
+                .as_phi()
+                .incomings
+                .iter()
+                .find(|(_, bb)| *bb == self.preheader)
+                .unwrap();
+            map.insert(phi_id, value);
+        }
+        module.remove_inst(module.get_terminator(self.preheader));
+        for inst_id in &insts {
+            if module.get_inst(*inst_id).is_phi() {
+                continue;
+            }
+            let pos = module.get_bb(self.preheader).insts.len();
+            let new_id = module.clone_inst(*inst_id, self.preheader, pos, &map);
+            map.insert(*inst_id, new_id);
+        }
+        for succ in [self.body, self.exit] {
+            for phi_id in module.get_phis(succ) {
+                let value = module
+                    .get_inst(phi_id)
+                    .as_phi()
+                    .incomings
+                    .iter()
+                    .find(|(_, bb)| *bb == self.header)
+                    .map(|(value, _)| *value)
+                    .unwrap();
+                let value = *map.get(&value).unwrap_or(&value);
+                module.add_phi_incoming(phi_id, self.preheader, value);
+            }
+        }
+        for phi_id in module.get_phis(self.header) {
+            module.remove_phi_incoming(phi_id, self.preheader);
+        }
+
+        // preheader 改为跳到 body 和 exit
+        cfg.preds
+            .get_mut(&self.header)
+            .unwrap()
+            .retain(|bb| *bb != self.preheader);
+        for succ in [self.body, self.exit] {
+            cfg.preds.get_mut(&succ).unwrap().push(self.preheader);
+        }
+        cfg.succs.insert(self.preheader, vec![self.body, self.exit]);
+
+        // header 中定义的值现在在 preheader 中也有定义，header 之外的使用需要经过 phi
+        for inst_id in &insts {
+            let Some(copy) = map.get(inst_id).copied() else {
+                continue;
+            };
+            let mut users = module.get_users_of(*inst_id);
+            users.sort();
+            users.dedup();
+            users.retain(|user| {
+                module.get_inst(*user).is_phi() || module.get_parent_id(*user) != self.header
+            });
+            if users.is_empty() {
+                continue;
+            }
+            let mut updater = SsaUpdater::new(module.get_inst(*inst_id).ty());
+            updater.add_def(self.header, *inst_id);
+            updater.add_def(self.preheader, copy);
+            for user in users {
+                updater.rewrite_use(module, cfg, user, *inst_id);
+            }
+            updater.remove_trivial_phis(module);
+        }
+
+        // 原 header 只剩 latch 一个前驱
+        for phi_id in module.get_phis(self.header) {
+            let (value, _) = module.get_inst(phi_id).as_phi().incomings[0];
+            module.replace_value(phi_id, value);
+            module.remove_inst(phi_id);
+        }
+    }
+}
+
+#[test]
+fn test_loop_rotate() {
+    use crate::{
+        ir_pass::{build_ir, inst_namer, verify},
+        ir_printer,
+    };
+
+    let mut module = build_ir(
+        "int g;
+        int f(int n) {
+            int i = 0; int s = 0;
+            while (i < n) { s = s + g * i; i = i + 1; }
+            return s + i;
+        }",
+    );
+    let func_id = module.functions["f"];
+    LoopRotatePass.run_on_func(&mut module, func_id, &mut AnalysisManager::new());
+    assert_eq!(verify::run(&module), Ok(()));
+
+    // 循环只从 latch 退出，header 之前有守卫和 preheader
+    let cfg = Cfg::new(&module, func_id);
+    let loops = LoopInfo::new(&cfg, &DomTree::new(&cfg));
+    let [l] = loops.loops.as_slice() else {
+        panic!("expect one loop");
+    };
+    assert_eq!(l.exiting_blocks(&cfg), l.latches);
+    let preheader = l.preheader(&cfg).unwrap();
+    let [guard] = cfg.preds(preheader) else {
+        panic!("expect a guard");
+    };
+    assert!(module.get_inst(module.get_terminator(*guard)).is_branch());
+
+    inst_namer::run(&mut module);
+    let ir = ir_printer::print(&mut module);
+    assert_eq!(ir.matches("icmp slt").count(), 2);
+}
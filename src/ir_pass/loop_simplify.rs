@@ -0,0 +1,116 @@
+//! 为每个循环插入 preheader：循环外唯一的前驱，且只跳转到 header
+//!
+//! LICM 把不变量外提到 preheader，循环旋转在 preheader 中复制 header
+
+use crate::ir::{InstValue, Module, PhiInst, ValueId};
+
+use super::{
+    analysis::{AnalysisManager, Cfg, DomTree, Loop, LoopInfo},
+    pass_manager::FunctionPass,
+};
+
+pub struct LoopSimplifyPass;
+
+impl FunctionPass for LoopSimplifyPass {
+    fn name(&self) -> &'static str {
+        "loop-simplify"
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, am: &mut AnalysisManager) {
+        if insert_preheaders(module, func_id) {
+            am.invalidate(func_id);
+        }
+    }
+}
+
+/// 为所有缺少 preheader 的循环插入 preheader，CFG 有改动时返回 true
+pub fn insert_preheaders(module: &mut Module, func_id: ValueId) -> bool {
+    let cfg = Cfg::new(module, func_id);
+    let loops = LoopInfo::new(&cfg, &DomTree::new(&cfg));
+    // 新的 preheader 只接管跳入 header 的边，其他循环 header 的前驱不变，
+    // 外层循环虽然多了基本块，但只用到 header 的前驱是否在循环内，一次分析就够了
+    let mut changed = false;
+    for l in &loops.loops {
+        if l.preheader(&cfg).is_none() {
+            insert_preheader(module, func_id, &cfg, l);
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn insert_preheader(module: &mut Module, func_id: ValueId, cfg: &Cfg, l: &Loop) -> ValueId {
+    let preds = l.outside_preds(cfg);
+    module.set_cur_func(func_id);
+    let preheader = module.spawn_basic_block();
+    module.set_insert_point(preheader);
+    module.spawn_jump_inst(l.header);
+
+    for pred in &preds {
+        module.replace_successor(module.get_terminator(*pred), l.header, preheader);
+    }
+    // header 的 phi 中来自循环外的 incoming 移到 preheader 中合并
+    for phi_id in module.get_phis(l.header) {
+        let incomings = module
+            .get_inst(phi_id)
+            .as_phi()
+            .incomings
+            .iter()
+            .filter(|(_, bb)| preds.contains(bb))
+            .copied()
+            .collect::<Vec<_>>();
+        let value = match incomings.as_slice() {
+            [] => continue,
+            [(value, _)] => *value,
+            _ => {
+                let phi = PhiInst {
+                    ty: module.get_inst(phi_id).ty(),
+                    incomings: incomings.clone(),
+                };
+                module.insert_inst(preheader, 0, InstValue::Phi(phi))
+            }
+        };
+        for (_, bb) in incomings {
+            module.remove_phi_incoming(phi_id, bb);
+        }
+        module.add_phi_incoming(phi_id, preheader, value);
+    }
+    preheader
+}
+
+#[test]
+fn test_loop_simplify() {
+    use crate::ir_pass::{build_ir, simplify_cfg::SimplifyCfgPass, verify};
+
+    let mut module = build_ir(
+        "int f(int n) {
+            int i = 0;
+            if (n > 5) {
+                while (i < n) {
+                    int j = 0;
+                    while (j < i) { j = j + 1; }
+                    i = i + j;
+                }
+            }
+            return i;
+        }",
+    );
+    let func_id = module.functions["f"];
+    // 化简后两个循环都直接从条件跳转进入 header
+    SimplifyCfgPass.run_on_func(&mut module, func_id, &mut AnalysisManager::new());
+    let cfg = Cfg::new(&module, func_id);
+    let loops = LoopInfo::new(&cfg, &DomTree::new(&cfg));
+    assert!(loops.loops.iter().all(|l| l.preheader(&cfg).is_none()));
+
+    LoopSimplifyPass.run_on_func(&mut module, func_id, &mut AnalysisManager::new());
+    assert_eq!(verify::run(&module), Ok(()));
+
+    let cfg = Cfg::new(&module, func_id);
+    let loops = LoopInfo::new(&cfg, &DomTree::new(&cfg));
+    assert_eq!(loops.loops.len(), 2);
+    for l in &loops.loops {
+        assert!(l.preheader(&cfg).is_some());
+    }
+    // 再次运行不再改动
+    assert!(!insert_preheaders(&mut module, func_id));
+}
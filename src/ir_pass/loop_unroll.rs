@@ -0,0 +1,426 @@
+//! 循环展开
+//!
+//! - 迭代次数为常数、展开后规模不大的最内层循环完全展开为直线代码
+//! - 其余以单调条件退出的最内层循环按 factor 部分展开，剩下不足 factor 次的迭代由原循环执行
+//!
+//! 只处理旋转后只从 latch 退出的循环，从 preheader 进入时循环体至少执行一次
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::{BuiltinType, InfixOp, PointerType, Type},
+    ir::{BinaryOperator, BranchInst, ConstInt, InstValue, Module, PhiInst, ValueId},
+};
+
+use super::{
+    analysis::{AnalysisManager, Cfg, DomTree, Loop, LoopInfo},
+    indvars::CountedLoop,
+    loop_simplify::insert_preheaders,
+    pass_manager::FunctionPass,
+    ssa_updater::SsaUpdater,
+};
+
+/// 完全展开后最多的指令数
+const MAX_FULL_UNROLL_SIZE: usize = 128;
+/// 部分展开后循环体最多的指令数
+const MAX_PARTIAL_UNROLL_SIZE: usize = 128;
+
+pub struct LoopUnrollPass {
+    /// 部分展开时每次迭代执行的原循环体份数，小于 2 时不做部分展开
+    factor: usize,
+}
+
+impl LoopUnrollPass {
+    pub fn new(factor: usize) -> Self {
+        LoopUnrollPass { factor }
+    }
+
+    fn can_partial_unroll(&self, cl: &CountedLoop, size: usize, trip_count: Option<usize>) -> bool {
+        let offset = (self.factor as i64 - 1) * cl.iv.step as i64;
+        self.factor >= 2
+            && size * self.factor <= MAX_PARTIAL_UNROLL_SIZE
+            && cl.on_next
+            && cl.is_monotonic()
+            && i32::try_from(offset).is_ok()
+            && trip_count.is_none_or(|n| n >= self.factor)
+    }
+}
+
+impl FunctionPass for LoopUnrollPass {
+    fn name(&self) -> &'static str {
+        "loop-unroll"
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, am: &mut AnalysisManager) {
+        let mut changed = insert_preheaders(module, func_id);
+        // 只处理原有的最内层循环，剩余迭代的循环沿用原来的 header，展开出的循环不会被再次选中。
+        // 最内层循环互不相交，展开一个循环不会改动其他循环的基本块和 preheader，分析结果可以共用
+        let cfg = Cfg::new(module, func_id);
+        let loops = LoopInfo::new(&cfg, &DomTree::new(&cfg));
+        for l in loops.loops.iter().rev().filter(|l| l.children.is_empty()) {
+            let Some(cl) = CountedLoop::analyze(module, &cfg, l) else {
+                continue;
+            };
+            let size = loop_size(module, l);
+            let trip_count = cl.trip_count(module).map(|n| n as usize);
+            if let Some(n) = trip_count.filter(|n| n * size <= MAX_FULL_UNROLL_SIZE) {
+                full_unroll(module, func_id, l, &cl, n);
+                changed = true;
+            } else if self.can_partial_unroll(&cl, size, trip_count) {
+                partial_unroll(module, func_id, l, &cl, self.factor);
+                changed = true;
+            }
+        }
+        if changed {
+            am.invalidate(func_id);
+        }
+    }
+}
+
+fn loop_size(module: &Module, l: &Loop) -> usize {
+    l.blocks
+        .iter()
+        .flat_map(|bb_id| module.get_bb(*bb_id).insts.iter())
+        .filter(|inst_id| !module.get_inst(**inst_id).is_phi())
+        .count()
+}
+
+fn lookup(map: &HashMap<ValueId, ValueId>, val_id: ValueId) -> ValueId {
+    *map.get(&val_id).unwrap_or(&val_id)
+}
+
+fn incoming(module: &Module, phi_id: ValueId, bb_id: ValueId) -> ValueId {
+    module
+        .get_inst(phi_id)
+        .as_phi()
+        .incomings
+        .iter()
+        .find(|(_, bb)| *bb == bb_id)
+        .map(|(value, _)| *value)
+        .unwrap()
+}
+
+fn push_inst(module: &mut Module, bb_id: ValueId, inst: InstValue) -> ValueId {
+    let pos = module.get_bb(bb_id).insts.len();
+    module.insert_inst(bb_id, pos, inst)
+}
+
+/// 把基本块的终结指令改为无条件跳转
+fn set_jump(module: &mut Module, bb_id: ValueId, target: ValueId) {
+    module.remove_inst(module.get_terminator(bb_id));
+    module.set_insert_point(bb_id);
+    module.spawn_jump_inst(target);
+}
+
+/// 复制一份循环体，返回原值 -> 副本的映射。map 中预先给出的 header phi 不复制
+fn clone_body(
+    module: &mut Module,
+    func_id: ValueId,
+    l: &Loop,
+    mut map: HashMap<ValueId, ValueId>,
+) -> HashMap<ValueId, ValueId> {
+    module.set_cur_func(func_id);
+    for bb_id in &l.blocks {
+        let new_bb = module.spawn_basic_block();
+        map.insert(*bb_id, new_bb);
+    }
+    // 先复制为空 phi，所有值都复制之后再填写来源
+    let mut phis = vec![];
+    for bb_id in &l.blocks {
+        let new_bb = map[bb_id];
+        for inst_id in module.get_bb(*bb_id).insts.clone() {
+            if map.contains_key(&inst_id) {
+                continue;
+            }
+            let pos = module.get_bb(new_bb).insts.len();
+            let new_id = match module.get_inst(inst_id) {
+                InstValue::Phi(phi) => {
+                    let empty = PhiInst {
+                        ty: phi.ty.clone(),
+                        incomings: vec![],
+                    };
+                    phis.push(inst_id);
+                    module.insert_inst(new_bb, pos, InstValue::Phi(empty))
+                }
+                _ => module.clone_inst(inst_id, new_bb, pos, &map),
+            };
+            map.insert(inst_id, new_id);
+        }
+    }
+    for phi_id in phis {
+        for (value, bb) in module.get_inst(phi_id).as_phi().incomings.clone() {
+            if let Some(bb) = map.get(&bb) {
+                module.add_phi_incoming(map[&phi_id], *bb, lookup(&map, value));
+            }
+        }
+    }
+    map
+}
+
+/// 循环中定义、在循环外使用的值及其使用者
+fn outside_uses(module: &Module, l: &Loop) -> Vec<(ValueId, Vec<ValueId>)> {
+    let mut uses = vec![];
+    for bb_id in &l.blocks {
+        for inst_id in &module.get_bb(*bb_id).insts {
+            let mut users = module
+                .get_users_of(*inst_id)
+                .into_iter()
+                .filter(|user| {
+                    module
+                        .value_parent
+                        .get(user)
+                        .is_some_and(|bb_id| !l.contains(*bb_id))
+                })
+                .collect::<Vec<_>>();
+            users.sort();
+            users.dedup();
+            if !users.is_empty() {
+                uses.push((*inst_id, users));
+            }
+        }
+    }
+    uses
+}
+
+/// 展开后循环有多个出口，每个出口处的值由对应副本的映射给出，循环外的使用经过 phi 汇合
+fn rewrite_outside_uses(
+    module: &mut Module,
+    func_id: ValueId,
+    uses: Vec<(ValueId, Vec<ValueId>)>,
+    exits: &[(ValueId, &HashMap<ValueId, ValueId>)],
+) {
+    let cfg = Cfg::new(module, func_id);
+    for (value, users) in uses {
+        let ty = match module.get_inst(value) {
+            InstValue::Gep(gep) => Type::Pointer(PointerType::new(gep.base.clone())),
+            inst => inst.ty(),
+        };
+        let mut updater = SsaUpdater::new(ty);
+        for (bb_id, map) in exits {
+            updater.add_def(*bb_id, lookup(map, value));
+        }
+        for user in users {
+            updater.rewrite_use(module, &cfg, user, value);
+        }
+        updater.remove_trivial_phis(module);
+    }
+}
+
+/// 复制 trip_count 份循环体依次连接，header 的 phi 取上一份中来自 latch 的值
+fn full_unroll(
+    module: &mut Module,
+    func_id: ValueId,
+    l: &Loop,
+    cl: &CountedLoop,
+    trip_count: usize,
+) {
+    let uses = outside_uses(module, l);
+    let phis = module.get_phis(l.header);
+    let mut copies = vec![HashMap::new()];
+    for _ in 1..trip_count {
+        let prev = copies.last().unwrap();
+        let map = phis
+            .iter()
+            .map(|phi_id| (*phi_id, lookup(prev, incoming(module, *phi_id, cl.latch))))
+            .collect();
+        copies.push(clone_body(module, func_id, l, map));
+    }
+    for (k, map) in copies.iter().enumerate() {
+        let target = match copies.get(k + 1) {
+            Some(next) => next[&l.header],
+            None => cl.exit,
+        };
+        set_jump(module, lookup(map, cl.latch), target);
+    }
+
+    let last = copies.last().unwrap();
+    let last_latch = lookup(last, cl.latch);
+    if last_latch != cl.latch {
+        for phi_id in module.get_phis(cl.exit) {
+            module.replace_phi_incoming_bb(phi_id, cl.latch, last_latch);
+        }
+    }
+    rewrite_outside_uses(module, func_id, uses, &[(last_latch, last)]);
+    for phi_id in phis {
+        let init = incoming(module, phi_id, cl.preheader);
+        module.replace_value(phi_id, init);
+        module.remove_inst(phi_id);
+    }
+}
+
+/// 展开为每次迭代执行 factor 份循环体的新循环，原循环留作剩余迭代
+///
+/// 单调的条件下，剩余至少 factor 次迭代等价于 `next pred bound - (factor-1)*step`。
+/// 调整后的边界在 preheader 中计算，溢出时直接执行原循环
+fn partial_unroll(
+    module: &mut Module,
+    func_id: ValueId,
+    l: &Loop,
+    cl: &CountedLoop,
+    factor: usize,
+) {
+    let uses = outside_uses(module, l);
+    let phis = module.get_phis(l.header);
+    let mut copies: Vec<HashMap<ValueId, ValueId>> = vec![];
+    for _ in 0..factor {
+        let map = match copies.last() {
+            Some(prev) => phis
+                .iter()
+                .map(|phi_id| (*phi_id, lookup(prev, incoming(module, *phi_id, cl.latch))))
+                .collect(),
+            None => HashMap::new(),
+        };
+        copies.push(clone_body(module, func_id, l, map));
+    }
+    for k in 1..factor {
+        set_jump(module, copies[k - 1][&cl.latch], copies[k][&l.header]);
+    }
+    let first = &copies[0];
+    let last = copies.last().unwrap();
+    let header = first[&l.header];
+    let last_latch = last[&cl.latch];
+
+    module.set_cur_func(func_id);
+    let guard = module.spawn_basic_block();
+    let unrolled_exit = module.spawn_basic_block();
+    let remainder = module.spawn_basic_block();
+
+    let int = |module: &mut Module, value: i64| {
+        module.alloc_value(
+            ConstInt {
+                ty: BuiltinType::Int.into(),
+                value,
+            }
+            .into(),
+        )
+    };
+    let cmp = |op, lhs, rhs| {
+        InstValue::InfixOp(BinaryOperator {
+            ty: BuiltinType::Bool.into(),
+            op,
+            lhs,
+            rhs,
+        })
+    };
+    let offset = int(module, (factor as i64 - 1) * cl.iv.step as i64);
+    module.remove_inst(module.get_terminator(cl.preheader));
+    let bound = push_inst(
+        module,
+        cl.preheader,
+        InstValue::InfixOp(BinaryOperator {
+            ty: BuiltinType::Int.into(),
+            op: InfixOp::Sub,
+            lhs: cl.bound,
+            rhs: offset,
+        }),
+    );
+    let strict = if cl.iv.step > 0 {
+        InfixOp::Lt
+    } else {
+        InfixOp::Gt
+    };
+    let no_overflow = push_inst(module, cl.preheader, cmp(strict, bound, cl.bound));
+    module.set_insert_point(cl.preheader);
+    module.spawn_br_inst(no_overflow, guard, remainder);
+
+    let enter = push_inst(module, guard, cmp(cl.pred.clone(), cl.iv.init, bound));
+    module.set_insert_point(guard);
+    module.spawn_br_inst(enter, header, remainder);
+
+    module.remove_inst(module.get_terminator(last_latch));
+    let next = lookup(last, cl.iv.next);
+    let again = push_inst(module, last_latch, cmp(cl.pred.clone(), next, bound));
+    module.set_insert_point(last_latch);
+    module.spawn_br_inst(again, header, unrolled_exit);
+
+    // 不足 factor 次时按原来的条件决定是否进入剩余迭代的循环
+    let br = module.get_inst(module.get_terminator(cl.latch)).as_branch();
+    let [then_bb, else_bb] =
+        [br.then_bb, br.else_bb].map(|bb| if bb == l.header { remainder } else { bb });
+    let br = BranchInst {
+        cond: lookup(last, br.cond),
+        then_bb,
+        else_bb,
+    };
+    push_inst(module, unrolled_exit, InstValue::Branch(br));
+
+    for phi_id in &phis {
+        let init = incoming(module, *phi_id, cl.preheader);
+        let next = lookup(last, incoming(module, *phi_id, cl.latch));
+        let phi = PhiInst {
+            ty: module.get_inst(*phi_id).ty(),
+            incomings: vec![(init, cl.preheader), (init, guard), (next, unrolled_exit)],
+        };
+        let value = push_inst(module, remainder, InstValue::Phi(phi));
+        module.remove_phi_incoming(*phi_id, cl.preheader);
+        module.add_phi_incoming(*phi_id, remainder, value);
+
+        let copy = first[phi_id];
+        module.remove_phi_incoming(copy, first[&cl.latch]);
+        module.add_phi_incoming(copy, guard, init);
+        module.add_phi_incoming(copy, last_latch, next);
+    }
+    module.set_insert_point(remainder);
+    module.spawn_jump_inst(l.header);
+
+    for phi_id in module.get_phis(cl.exit) {
+        let value = incoming(module, phi_id, cl.latch);
+        module.add_phi_incoming(phi_id, unrolled_exit, value);
+    }
+    let identity = HashMap::new();
+    rewrite_outside_uses(
+        module,
+        func_id,
+        uses,
+        &[(cl.latch, &identity), (last_latch, last)],
+    );
+}
+
+#[test]
+fn test_loop_unroll() {
+    use crate::ir_pass::{build_ir, loop_rotate::LoopRotatePass, verify};
+
+    let mut module = build_ir(
+        "int a[16];
+        int f(int n) {
+            int i = 0; int s = 0;
+            while (i < 4) { s = s + a[i]; i = i + 1; }
+            int j = 0;
+            while (j < n) {
+                if (a[j] > 0) { s = s + a[j]; } else { s = s - 1; }
+                j = j + 1;
+            }
+            return s + j;
+        }",
+    );
+    let func_id = module.functions["f"];
+    let mut am = AnalysisManager::new();
+    LoopRotatePass.run_on_func(&mut module, func_id, &mut am);
+    LoopUnrollPass::new(4).run_on_func(&mut module, func_id, &mut am);
+    assert_eq!(verify::run(&module), Ok(()));
+
+    // 第一个循环完全展开；第二个循环展开为 4 份循环体的循环和剩余迭代的原循环
+    let cfg = Cfg::new(&module, func_id);
+    let loops = LoopInfo::new(&cfg, &DomTree::new(&cfg));
+    assert_eq!(loops.loops.len(), 2);
+    let mut sizes = loops
+        .loops
+        .iter()
+        .map(|l| {
+            l.blocks
+                .iter()
+                .flat_map(|bb_id| module.get_bb(*bb_id).insts.iter())
+                .filter(|inst_id| module.get_inst(**inst_id).is_load())
+                .count()
+        })
+        .collect::<Vec<_>>();
+    sizes.sort();
+    assert_eq!(sizes, vec![2, 8]);
+    let loads = cfg
+        .rpo
+        .iter()
+        .flat_map(|bb_id| module.get_bb(*bb_id).insts.iter())
+        .filter(|inst_id| module.get_inst(**inst_id).is_load())
+        .count();
+    assert_eq!(loads, 4 + 2 + 8);
+}
@@ -5,11 +5,31 @@ use crate::{
     ir::{ConstValue, InstValue, Module, Value, ValueId},
 };
 
+use super::{analysis::AnalysisManager, pass_manager::FunctionPass};
+
 pub fn run(module: &mut Module) {
     let mut pass = Mem2Reg::new(module);
     pass.run();
 }
 
+pub struct Mem2RegPass;
+
+impl FunctionPass for Mem2RegPass {
+    fn name(&self) -> &'static str {
+        "mem2reg"
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, _am: &mut AnalysisManager) {
+        let mut pass = Mem2Reg::new(module);
+        pass.cur_func = Some(func_id);
+        pass.run_on_func(func_id);
+    }
+}
+
 pub struct Mem2Reg<'a> {
     module: &'a mut Module,
 
@@ -92,8 +112,7 @@ impl Mem2Reg<'_> {
                     let store_target = store_inst.ptr;
                     let store_val = store_inst.value;
                     self.write_var(bb_id, store_target, store_val);
-                    self.module.mark_nolonger_using(inst_id, store_target);
-                    // inst.removeAllOperandUseFromValue();
+                    self.module.mark_nolonger_using_any(inst_id);
                     false
                 } else {
                     true
@@ -111,8 +130,8 @@ impl Mem2Reg<'_> {
             self.add_phi_operands(incomplete_phi.phi, incomplete_phi.bb_id, incomplete_phi.ptr);
         }
 
-        // 把所有phi指令加入基本块
-        for phi in self.pending_phis.clone() {
+        // 把所有phi指令加入基本块，run 会用同一个实例处理多个函数，加入后清空
+        for phi in std::mem::take(&mut self.pending_phis) {
             let bb = self.module.get_parent_mut(phi);
             bb.insts.insert(0, phi);
         }
@@ -127,12 +146,11 @@ impl Mem2Reg<'_> {
     /// var 表示的是任何一个变量，phi、alloca、const int
     fn read_var(&mut self, bb_id: ValueId, alloca_id: ValueId) -> ValueId {
         let ptr = alloca_id;
-        let defs = self.var_defs.get(&ptr);
-        if defs.is_none() {
-            unreachable!("alloca_id should be in var_defs");
-        }
+        // 还没有遇到过任何存储，例如没有初始化的数组元素被 sroa 拆开后
+        let Some(defs) = self.var_defs.get(&ptr) else {
+            return self.read_var_recursive(bb_id, ptr);
+        };
 
-        let defs = defs.unwrap();
         let def_in_cur_bb = defs.get(&bb_id);
         if let Some(def) = def_in_cur_bb {
             return self.find_in_dead_phis(*def);
@@ -211,10 +229,10 @@ impl Mem2Reg<'_> {
 
         // path compression
         for phi in compress_todo {
-            self.dead_phis.insert(cur, phi);
+            self.dead_phis.insert(phi, cur);
         }
 
-        def
+        cur
     }
 
     pub fn add_phi_operands(
@@ -229,7 +247,7 @@ impl Mem2Reg<'_> {
                 self.module.get_value(val).ty(),
                 self.module.get_inst(phi_id).ty()
             );
-            self.module.add_phi_incoming(phi_id, bb_id, val)
+            self.module.add_phi_incoming(phi_id, pred, val)
         }
         self.try_remove_trivial_phi(phi_id, alloca_id)
     }
@@ -239,7 +257,6 @@ impl Mem2Reg<'_> {
     pub fn try_remove_trivial_phi(&mut self, phi_id: ValueId, alloca_id: ValueId) -> ValueId {
         let phi = self.module.get_inst(phi_id).as_phi();
         let mut same_val = None;
-        let mut undef = false;
         for (opr_id, _bb_id) in phi.incomings.clone() {
             if (same_val.is_some() && opr_id == same_val.unwrap()) || opr_id == phi_id {
                 continue;
@@ -254,7 +271,6 @@ impl Mem2Reg<'_> {
         if same_val.is_none() {
             // 所有operand都是phi自己
             // assert self.module.value_user[phi_id].is_empty()
-            undef = true;
             same_val = Some(self.get_undef_value(self.module.get_inst(phi_id).ty()));
         }
 
@@ -270,9 +286,7 @@ impl Mem2Reg<'_> {
         self.module.remove_phi_all_operands(phi_id);
         self.pending_phis.remove(&phi_id);
 
-        if !undef {
-            self.dead_phis.insert(phi_id, same_val.unwrap());
-        }
+        self.dead_phis.insert(phi_id, same_val.unwrap());
 
         for user_id in to_recursive {
             self.try_remove_trivial_phi(user_id, alloca_id);
@@ -369,3 +383,76 @@ impl Mem2Reg<'_> {
         promotables
     }
 }
+
+#[test]
+fn test_phi_incomings() {
+    use crate::ir_pass::build_ir;
+
+    let module = build_ir(
+        "int main() {
+            int i = 0;
+            int s = 0;
+            while (i < 10) { s = s + i; i = i + 1; }
+            return s;
+        }",
+    );
+
+    // phi 的每个来源都是所在块的前驱
+    let func = module.get_func(module.functions["main"]);
+    let mut n_phis = 0;
+    for bb_id in func.bbs.bbs.values() {
+        let mut preds = module.get_bb_preds(*bb_id);
+        preds.sort();
+        for phi_id in module.get_phis(*bb_id) {
+            let mut bbs = module
+                .get_inst(phi_id)
+                .as_phi()
+                .incomings
+                .iter()
+                .map(|(_, bb)| *bb)
+                .collect::<Vec<_>>();
+            bbs.sort();
+            assert_eq!(bbs, preds);
+            n_phis += 1;
+        }
+    }
+    assert_eq!(n_phis, 2);
+}
+
+#[test]
+fn test_dead_phis() {
+    use crate::ir_pass::build_ir;
+
+    let module = build_ir(
+        "int f() {
+            int a = 5;
+            int b = 10;
+            if (a == 6 || b == 11) { return a; }
+            if (b == 10 && a == 1) a = 25;
+            else if (b == 10 && a == -5) a = a + 15;
+            else a = -a;
+            return a;
+        }",
+    );
+
+    // 被删除的 phi 沿替换链找到最终的值，不再被任何指令引用
+    let func = module.get_func(module.functions["f"]);
+    let insts = func
+        .bbs
+        .bbs
+        .values()
+        .flat_map(|bb_id| module.get_bb(*bb_id).insts.clone())
+        .collect::<HashSet<_>>();
+    for inst_id in &insts {
+        if let InstValue::Phi(phi) = module.get_inst(*inst_id) {
+            for (value, _) in &phi.incomings {
+                if module
+                    .try_get_inst(*value)
+                    .is_some_and(|inst| inst.is_phi())
+                {
+                    assert!(insts.contains(value));
+                }
+            }
+        }
+    }
+}
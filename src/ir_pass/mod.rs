@@ -1,2 +1,32 @@
+pub mod analysis;
+pub mod dce;
+pub mod globalopt;
+pub mod gvn;
+pub mod indvars;
+pub mod inline;
+pub mod instcombine;
 pub mod inst_namer;
+pub mod licm;
+pub mod loop_rotate;
+pub mod loop_simplify;
+pub mod loop_unroll;
 pub mod mem2reg;
+pub mod pass_manager;
+pub mod sccp;
+pub mod simplify_cfg;
+pub mod sroa;
+pub mod ssa_updater;
+pub mod tail_call;
+pub mod verify;
+
+#[cfg(test)]
+pub(crate) fn build_ir(src: &str) -> crate::ir::Module {
+    use crate::{ir_builder, scope::SymbolTable, sema::ToSemaTrait};
+
+    let mut ast = crate::parser::parse(src).unwrap();
+    let mut syms = SymbolTable::new();
+    ast.to_sema(&mut syms);
+    let mut module = ir_builder::build(&mut ast, syms).unwrap();
+    mem2reg::run(&mut module);
+    module
+}
@@ -0,0 +1,271 @@
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::{
+    cli::Args,
+    ir::{Module, ValueId},
+    ir_printer,
+};
+
+use super::{
+    analysis::AnalysisManager,
+    dce::{DcePass, DsePass, GlobalDcePass},
+    globalopt::GlobalOptPass,
+    gvn::GvnPass,
+    indvars::IndVarsPass,
+    inline::InlinePass,
+    inst_namer,
+    instcombine::InstCombinePass,
+    licm::LicmPass,
+    loop_rotate::LoopRotatePass,
+    loop_simplify::LoopSimplifyPass,
+    loop_unroll::LoopUnrollPass,
+    mem2reg::Mem2RegPass,
+    sccp::SccpPass,
+    simplify_cfg::SimplifyCfgPass,
+    sroa::SroaPass,
+    tail_call::{TailCallElimPass, TailCallPass},
+    verify,
+};
+
+/// 作用于整个模块的 IR pass
+pub trait Pass {
+    /// `--passes` 和 `--print-after` 中使用的名字
+    fn name(&self) -> &'static str;
+
+    /// 不改变任何函数的 CFG 时返回 true，PassManager 据此保留缓存的分析结果
+    fn preserves_cfg(&self) -> bool {
+        false
+    }
+
+    fn run(&mut self, module: &mut Module, am: &mut AnalysisManager);
+}
+
+/// 逐函数运行的 IR pass，外部声明的函数会被跳过
+pub trait FunctionPass {
+    fn name(&self) -> &'static str;
+
+    fn preserves_cfg(&self) -> bool {
+        false
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, am: &mut AnalysisManager);
+}
+
+impl<P: FunctionPass> Pass for P {
+    fn name(&self) -> &'static str {
+        FunctionPass::name(self)
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        FunctionPass::preserves_cfg(self)
+    }
+
+    fn run(&mut self, module: &mut Module, am: &mut AnalysisManager) {
+        for (_, func_id) in module.functions.clone() {
+            if module.get_func(func_id).is_external {
+                continue;
+            }
+            self.run_on_func(module, func_id, am);
+        }
+    }
+}
+
+// 各优化级别的默认流水线
+const O0_PIPELINE: &[&str] = &["mem2reg"];
+const O1_PIPELINE: &[&str] = &[
+    "sroa",
+    "mem2reg",
+    "inline",
+    "tailcallelim",
+    "sccp",
+    "instcombine",
+    "dce",
+    "simplifycfg",
+    "tailcall",
+];
+const O2_PIPELINE: &[&str] = &[
+    "sroa",
+    "mem2reg",
+    "inline",
+    "tailcallelim",
+    // 删除已经全部内联的函数，之后只在 main 中使用的全局变量才能局部化
+    "globaldce",
+    "globalopt",
+    "sroa",
+    "mem2reg",
+    "sccp",
+    "instcombine",
+    "simplifycfg",
+    "loop-rotate",
+    "licm",
+    "gvn",
+    "dse",
+    "indvars",
+    "loop-unroll",
+    "instcombine",
+    "dce",
+    "simplifycfg",
+    "globaldce",
+    // 放在最后，标记之后调用和 ret 之间不会再插入指令
+    "tailcall",
+];
+
+/// 按名字创建 pass，名字未知时返回 None。optimize_level 决定内联等启发式的预算
+pub fn create_pass(name: &str, optimize_level: u8) -> Option<Box<dyn Pass>> {
+    match name {
+        "mem2reg" => Some(Box::new(Mem2RegPass)),
+        // constfold 是常量折叠的通用叫法
+        "sccp" | "constfold" => Some(Box::new(SccpPass)),
+        "dce" => Some(Box::new(DcePass)),
+        "gvn" => Some(Box::new(GvnPass)),
+        "inline" => Some(Box::new(InlinePass::new(optimize_level))),
+        "instcombine" => Some(Box::new(InstCombinePass)),
+        "dse" => Some(Box::new(DsePass)),
+        "globaldce" => Some(Box::new(GlobalDcePass)),
+        "globalopt" => Some(Box::new(GlobalOptPass)),
+        "sroa" => Some(Box::new(SroaPass)),
+        "simplifycfg" => Some(Box::new(SimplifyCfgPass)),
+        "loop-simplify" => Some(Box::new(LoopSimplifyPass)),
+        "loop-rotate" => Some(Box::new(LoopRotatePass)),
+        "licm" => Some(Box::new(LicmPass)),
+        "indvars" => Some(Box::new(IndVarsPass)),
+        // 部分展开的份数
+        "loop-unroll" => Some(Box::new(LoopUnrollPass::new(match optimize_level {
+            0..=2 => 4,
+            _ => 8,
+        }))),
+        "tailcallelim" => Some(Box::new(TailCallElimPass)),
+        "tailcall" => Some(Box::new(TailCallPass)),
+        "verify" => Some(Box::new(verify::VerifyPass)),
+        _ => None,
+    }
+}
+
+pub fn default_pipeline(optimize_level: u8) -> &'static [&'static str] {
+    match optimize_level {
+        0 => O0_PIPELINE,
+        1 => O1_PIPELINE,
+        _ => O2_PIPELINE,
+    }
+}
+
+/// 按顺序运行一组 pass，并负责 `--print-after` 和计时
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    print_after: Vec<String>,
+    print_after_all: bool,
+    analyses: AnalysisManager,
+    /// 每个 pass 的累计耗时，按首次运行的顺序排列
+    pub timings: Vec<(&'static str, Duration)>,
+}
+
+impl PassManager {
+    pub fn new(names: &[&str], optimize_level: u8) -> Result<Self, String> {
+        let passes = names
+            .iter()
+            .map(|name| {
+                create_pass(name, optimize_level).ok_or_else(|| format!("unknown pass `{}`", name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            passes,
+            print_after: vec![],
+            print_after_all: false,
+            analyses: AnalysisManager::new(),
+            timings: vec![],
+        })
+    }
+
+    /// 显式给出 `--passes` 时使用该列表，否则使用 `-O` 对应的流水线
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        let mut pm = if args.passes.is_empty() {
+            Self::new(default_pipeline(args.optimize_level), args.optimize_level)?
+        } else {
+            let names = args.passes.iter().map(String::as_str).collect::<Vec<_>>();
+            Self::new(&names, args.optimize_level)?
+        };
+        // 别名换成 pass 的实际名字
+        for name in &args.print_after {
+            let pass = create_pass(name, args.optimize_level)
+                .ok_or_else(|| format!("unknown pass `{}` in --print-after", name))?;
+            pm.print_after.push(pass.name().to_string());
+        }
+        pm.print_after_all = args.print_after_all;
+        Ok(pm)
+    }
+
+    pub fn run(&mut self, module: &mut Module) {
+        for pass in &mut self.passes {
+            let name = pass.name();
+            let start = Instant::now();
+            pass.run(module, &mut self.analyses);
+            if !pass.preserves_cfg() {
+                self.analyses.invalidate_all();
+            }
+            let elapsed = start.elapsed();
+            debug!("pass {} took {:?}", name, elapsed);
+            match self.timings.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, total)) => *total += elapsed,
+                None => self.timings.push((name, elapsed)),
+            }
+
+            // debug 构建下每个 pass 之后都校验 IR
+            if cfg!(debug_assertions) {
+                if let Err(errors) = verify::run(module) {
+                    panic!("after pass {}: {}", name, verify::format_errors(&errors));
+                }
+            }
+
+            if self.print_after_all || self.print_after.iter().any(|n| n == name) {
+                inst_namer::run(module);
+                eprintln!("; *** IR Dump After {} ***", name);
+                eprint!("{}", ir_printer::print(module));
+            }
+        }
+    }
+
+    /// `--time-passes` 的输出
+    pub fn report_timings(&self) -> String {
+        let total: Duration = self.timings.iter().map(|(_, d)| *d).sum();
+        let mut out = String::from("===-- Pass execution timing report --===\n");
+        for (name, elapsed) in &self.timings {
+            out.push_str(&format!(
+                "{:>10.3}ms  {}\n",
+                elapsed.as_secs_f64() * 1000.0,
+                name
+            ));
+        }
+        out.push_str(&format!(
+            "{:>10.3}ms  Total\n",
+            total.as_secs_f64() * 1000.0
+        ));
+        out
+    }
+}
+
+#[test]
+fn test_pass_manager() {
+    use crate::ir_pass::build_ir;
+    use clap::Parser;
+
+    let args = Args::parse_from(["rockc", "a.sy", "-o", "a.ll", "--passes=mem2reg,bogus"]);
+    let err = PassManager::from_args(&args).err().unwrap();
+    assert_eq!(err, "unknown pass `bogus`");
+    let args = Args::parse_from(["rockc", "a.sy", "-o", "a.ll", "--passes=constfold"]);
+    let pm = PassManager::from_args(&args).unwrap();
+    assert_eq!(pm.passes[0].name(), "sccp");
+
+    let mut module = build_ir("int main() { int a = 1; return a + 2; }");
+
+    let args = Args::parse_from(["rockc", "a.sy", "-o", "a.ll", "-O2"]);
+    let mut pm = PassManager::from_args(&args).unwrap();
+    pm.run(&mut module);
+    assert_eq!(pm.timings[0].0, "sroa");
+    inst_namer::run(&mut module);
+    let ir = ir_printer::print(&mut module);
+    // 返回值折叠为常数
+    assert!(ir.contains("ret i32 3"));
+    assert!(pm.report_timings().ends_with("Total\n"));
+}
@@ -0,0 +1,416 @@
+//! 稀疏条件常量传播（Wegman–Zadeck）
+//!
+//! 只在可执行的边上传播常量，常量条件的 br 改写为 jump，不可达的基本块被删除
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{
+    ast::{BuiltinType, InfixOp, Type},
+    ir::{CastOp, ConstFloat, ConstInt, ConstValue, InstValue, Module, Value, ValueId},
+};
+
+use super::{analysis::AnalysisManager, pass_manager::FunctionPass};
+
+pub struct SccpPass;
+
+impl FunctionPass for SccpPass {
+    fn name(&self) -> &'static str {
+        "sccp"
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, am: &mut AnalysisManager) {
+        let mut sccp = Sccp::new(module);
+        sccp.solve(func_id);
+        if sccp.rewrite(func_id) {
+            am.invalidate(func_id);
+        }
+    }
+}
+
+/// 格：未定义 < 常量 < 非常量
+#[derive(Debug, Clone)]
+enum Lattice {
+    Undef,
+    Const(ConstValue),
+    Overdefined,
+}
+
+impl PartialEq for Lattice {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Lattice::Undef, Lattice::Undef) => true,
+            (Lattice::Overdefined, Lattice::Overdefined) => true,
+            (Lattice::Const(a), Lattice::Const(b)) => same_const(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// 浮点按位比较，区分 0.0 和 -0.0
+fn same_const(a: &ConstValue, b: &ConstValue) -> bool {
+    match (a, b) {
+        (ConstValue::Int(a), ConstValue::Int(b)) => a == b,
+        (ConstValue::Float(a), ConstValue::Float(b)) => {
+            a.ty == b.ty && a.value.to_bits() == b.value.to_bits()
+        }
+        _ => false,
+    }
+}
+
+impl Lattice {
+    fn meet(&self, other: &Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Undef, x) | (x, Lattice::Undef) => x.clone(),
+            (Lattice::Const(a), Lattice::Const(b)) if same_const(a, b) => self.clone(),
+            _ => Lattice::Overdefined,
+        }
+    }
+}
+
+struct Sccp<'a> {
+    module: &'a mut Module,
+    lattice: HashMap<ValueId, Lattice>,
+    exec_edges: HashSet<(ValueId, ValueId)>,
+    exec_bbs: HashSet<ValueId>,
+    ssa_worklist: VecDeque<ValueId>,
+}
+
+impl<'a> Sccp<'a> {
+    fn new(module: &'a mut Module) -> Self {
+        Sccp {
+            module,
+            lattice: HashMap::new(),
+            exec_edges: HashSet::new(),
+            exec_bbs: HashSet::new(),
+            ssa_worklist: VecDeque::new(),
+        }
+    }
+
+    fn value_of(&self, val_id: ValueId) -> Lattice {
+        match self.module.get_value(val_id) {
+            Value::Const(c) if !c.is_array() => Lattice::Const(c.clone()),
+            Value::Instruction(_) => self.lattice.get(&val_id).cloned().unwrap_or(Lattice::Undef),
+            // 参数、全局变量等
+            _ => Lattice::Overdefined,
+        }
+    }
+
+    fn solve(&mut self, func_id: ValueId) {
+        let entry = *self.module.get_func(func_id).bbs.entry_bb();
+        self.exec_bbs.insert(entry);
+        self.ssa_worklist
+            .extend(self.module.get_bb(entry).insts.iter().copied());
+
+        while let Some(inst_id) = self.ssa_worklist.pop_front() {
+            self.visit(inst_id);
+        }
+    }
+
+    fn mark_edge(&mut self, from: ValueId, to: ValueId) {
+        if !self.exec_edges.insert((from, to)) {
+            return;
+        }
+        if self.exec_bbs.insert(to) {
+            self.ssa_worklist
+                .extend(self.module.get_bb(to).insts.iter().copied());
+        } else {
+            // 新的可执行边只影响 phi
+            self.ssa_worklist.extend(self.module.get_phis(to));
+        }
+    }
+
+    fn visit(&mut self, inst_id: ValueId) {
+        let Some(bb_id) = self.module.value_parent.get(&inst_id).copied() else {
+            return;
+        };
+        if !self.exec_bbs.contains(&bb_id) {
+            return;
+        }
+        let new = match self.module.get_inst(inst_id).clone() {
+            InstValue::Phi(phi) => phi
+                .incomings
+                .iter()
+                .filter(|(_, pred)| self.exec_edges.contains(&(*pred, bb_id)))
+                .fold(Lattice::Undef, |acc, (val, _)| {
+                    acc.meet(&self.value_of(*val))
+                }),
+            InstValue::InfixOp(inst) => match (self.value_of(inst.lhs), self.value_of(inst.rhs)) {
+                (Lattice::Const(lhs), Lattice::Const(rhs)) => {
+                    fold_infix(&inst.op, &lhs, &rhs, &inst.ty)
+                        .map_or(Lattice::Overdefined, Lattice::Const)
+                }
+                (Lattice::Overdefined, _) | (_, Lattice::Overdefined) => Lattice::Overdefined,
+                _ => Lattice::Undef,
+            },
+            InstValue::Cast(inst) => match self.value_of(inst.value) {
+                Lattice::Const(c) => fold_cast(&inst.op, &c, &inst.new_ty)
+                    .map_or(Lattice::Overdefined, Lattice::Const),
+                other => other,
+            },
+            InstValue::Branch(br) => {
+                match self.value_of(br.cond) {
+                    Lattice::Const(c) => {
+                        let target = if const_as_i32(&c) != 0 {
+                            br.then_bb
+                        } else {
+                            br.else_bb
+                        };
+                        self.mark_edge(bb_id, target);
+                    }
+                    Lattice::Overdefined => {
+                        self.mark_edge(bb_id, br.then_bb);
+                        self.mark_edge(bb_id, br.else_bb);
+                    }
+                    Lattice::Undef => {}
+                }
+                return;
+            }
+            InstValue::Jump(jump) => {
+                self.mark_edge(bb_id, jump.bb);
+                return;
+            }
+            InstValue::Store(_) | InstValue::Return(_) => return,
+            InstValue::Load(_) | InstValue::Alloca(_) | InstValue::Gep(_) | InstValue::Call(_) => {
+                Lattice::Overdefined
+            }
+        };
+
+        let old = self.value_of(inst_id);
+        if new != old {
+            // 单调性：只会从 Undef 往 Overdefined 方向变化
+            let new = old.meet(&new);
+            self.lattice.insert(inst_id, new);
+            self.ssa_worklist.extend(self.module.get_users_of(inst_id));
+        }
+    }
+
+    /// 根据求解结果改写函数，返回是否修改了 CFG
+    fn rewrite(&mut self, func_id: ValueId) -> bool {
+        let bbs = self
+            .module
+            .get_func(func_id)
+            .bbs
+            .bbs
+            .values()
+            .copied()
+            .collect::<Vec<_>>();
+
+        // 1. 常量替换
+        for bb_id in &bbs {
+            if !self.exec_bbs.contains(bb_id) {
+                continue;
+            }
+            for inst_id in self.module.get_bb(*bb_id).insts.clone() {
+                if let Some(Lattice::Const(c)) = self.lattice.get(&inst_id) {
+                    let const_id = self.module.alloc_value(c.clone().into());
+                    self.module.replace_value(inst_id, const_id);
+                    self.module.remove_inst(inst_id);
+                }
+            }
+        }
+
+        // 2. 删除不可执行边对应的 phi incoming
+        for bb_id in &bbs {
+            if !self.exec_bbs.contains(bb_id) {
+                continue;
+            }
+            for phi_id in self.module.get_phis(*bb_id) {
+                let dead_preds = self
+                    .module
+                    .get_inst(phi_id)
+                    .as_phi()
+                    .incomings
+                    .iter()
+                    .map(|(_, pred)| *pred)
+                    .filter(|pred| !self.exec_edges.contains(&(*pred, *bb_id)))
+                    .collect::<Vec<_>>();
+                for pred in dead_preds {
+                    self.module.remove_phi_incoming(phi_id, pred);
+                }
+            }
+        }
+
+        // 3. 只有一条可执行出边的 br 改为 jump
+        let mut cfg_changed = false;
+        for bb_id in &bbs {
+            if !self.exec_bbs.contains(bb_id) {
+                continue;
+            }
+            let Some(term_id) = self.module.get_bb(*bb_id).insts.last().copied() else {
+                continue;
+            };
+            let InstValue::Branch(br) = self.module.get_inst(term_id).clone() else {
+                continue;
+            };
+            let targets = [br.then_bb, br.else_bb]
+                .into_iter()
+                .filter(|succ| self.exec_edges.contains(&(*bb_id, *succ)))
+                .collect::<HashSet<_>>();
+            if targets.len() == 1 {
+                let target = targets.into_iter().next().unwrap();
+                self.module.remove_inst(term_id);
+                self.module.set_insert_point(*bb_id);
+                self.module.spawn_jump_inst(target);
+                cfg_changed = true;
+            }
+        }
+
+        // 4. 删除不可达的基本块
+        for bb_id in &bbs {
+            if !self.exec_bbs.contains(bb_id) {
+                self.module.remove_bb(func_id, *bb_id);
+                cfg_changed = true;
+            }
+        }
+        cfg_changed
+    }
+}
+
+fn is_bool(ty: &Type) -> bool {
+    *ty == BuiltinType::Bool.into()
+}
+
+/// i1 按有符号解释，true 为 -1
+fn const_as_i32(c: &ConstValue) -> i32 {
+    match c {
+        ConstValue::Int(c) if is_bool(&c.ty) => -((c.value & 1) as i32),
+        ConstValue::Int(c) => c.value as i32,
+        _ => unreachable!("expect an integer constant"),
+    }
+}
+
+fn make_int(value: i32, ty: &Type) -> ConstValue {
+    let value = if is_bool(ty) {
+        (value & 1) as i64
+    } else {
+        value as i64
+    };
+    ConstValue::Int(ConstInt {
+        ty: ty.clone(),
+        value,
+    })
+}
+
+fn make_float(value: f32, ty: &Type) -> ConstValue {
+    ConstValue::Float(ConstFloat {
+        ty: ty.clone(),
+        value: value as f64,
+    })
+}
+
+/// 按 32 位整数和单精度浮点的语义折叠二元运算，结果未定义（除零、超宽移位）时返回 None
+pub fn fold_infix(
+    op: &InfixOp,
+    lhs: &ConstValue,
+    rhs: &ConstValue,
+    ty: &Type,
+) -> Option<ConstValue> {
+    if let (ConstValue::Float(l), ConstValue::Float(r)) = (lhs, rhs) {
+        let (l, r) = (l.value as f32, r.value as f32);
+        let cmp = |b: bool| Some(make_int(b as i32, ty));
+        return match op {
+            InfixOp::Add => Some(make_float(l + r, ty)),
+            InfixOp::Sub => Some(make_float(l - r, ty)),
+            InfixOp::Mul => Some(make_float(l * r, ty)),
+            InfixOp::Div => Some(make_float(l / r, ty)),
+            InfixOp::Rem | InfixOp::Mod => Some(make_float(l % r, ty)),
+            InfixOp::Eq => cmp(l == r),
+            InfixOp::Ne => cmp(l != r),
+            InfixOp::Lt => cmp(l < r),
+            InfixOp::Le => cmp(l <= r),
+            InfixOp::Gt => cmp(l > r),
+            InfixOp::Ge => cmp(l >= r),
+            _ => None,
+        };
+    }
+    let (ConstValue::Int(l), ConstValue::Int(r)) = (lhs, rhs) else {
+        return None;
+    };
+    // instcombine 展开除法时生成的 64 位乘法和移位
+    if l.ty == BuiltinType::Int64.into() {
+        let value = match op {
+            InfixOp::Mul => l.value.wrapping_mul(r.value),
+            InfixOp::BitShr if (0..64).contains(&r.value) => l.value >> r.value,
+            _ => return None,
+        };
+        return Some(ConstValue::Int(ConstInt {
+            ty: ty.clone(),
+            value,
+        }));
+    }
+    let (l, r) = (const_as_i32(lhs), const_as_i32(rhs));
+    let value = match op {
+        InfixOp::Add => l.wrapping_add(r),
+        InfixOp::Sub => l.wrapping_sub(r),
+        InfixOp::Mul => l.wrapping_mul(r),
+        InfixOp::Div if r != 0 => l.wrapping_div(r),
+        InfixOp::Rem | InfixOp::Mod if r != 0 => l.wrapping_rem(r),
+        InfixOp::BitAnd => l & r,
+        InfixOp::BitOr => l | r,
+        InfixOp::BitXor => l ^ r,
+        InfixOp::BitShl if (0..32).contains(&r) => l << r,
+        InfixOp::BitShr if (0..32).contains(&r) => l >> r,
+        InfixOp::Eq => (l == r) as i32,
+        InfixOp::Ne => (l != r) as i32,
+        InfixOp::Lt => (l < r) as i32,
+        InfixOp::Le => (l <= r) as i32,
+        InfixOp::Gt => (l > r) as i32,
+        InfixOp::Ge => (l >= r) as i32,
+        _ => return None,
+    };
+    Some(make_int(value, ty))
+}
+
+pub fn fold_cast(op: &CastOp, value: &ConstValue, ty: &Type) -> Option<ConstValue> {
+    match (op, value) {
+        (CastOp::ZExt, ConstValue::Int(c)) if is_bool(&c.ty) => {
+            Some(make_int((c.value & 1) as i32, ty))
+        }
+        (CastOp::ZExt | CastOp::SExt | CastOp::Trunc, ConstValue::Int(_)) => {
+            Some(make_int(const_as_i32(value), ty))
+        }
+        (CastOp::SIToFP, ConstValue::Int(_)) => Some(make_float(const_as_i32(value) as f32, ty)),
+        (CastOp::UIToFP, ConstValue::Int(c)) => Some(make_float(c.value as u32 as f32, ty)),
+        (CastOp::FPToSI, ConstValue::Float(c)) => Some(make_int(c.value as f32 as i32, ty)),
+        (CastOp::FPExt | CastOp::FPTrunc, ConstValue::Float(c)) => {
+            Some(make_float(c.value as f32, ty))
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn test_sccp() {
+    use crate::{
+        ir_pass::{build_ir, inst_namer, verify},
+        ir_printer,
+    };
+
+    let mut module = build_ir(
+        "int main() {
+            int flag = 1; int i = 0; int s = 0;
+            while (i < 10) {
+                if (flag == 1) { s = s + 2 * 3; } else { s = s - 1; }
+                i = i + 1;
+            }
+            int k = 7 / 2 + (1 << 4);
+            if (k > 100) { return 0; }
+            return s + k;
+        }",
+    );
+    let func_id = module.functions["main"];
+    let n_bbs = module.get_func(func_id).bbs.bbs.len();
+    SccpPass.run_on_func(&mut module, func_id, &mut AnalysisManager::new());
+    assert_eq!(verify::run(&module), Ok(()));
+
+    // else 分支和 k > 100 的分支不可达
+    assert_eq!(module.get_func(func_id).bbs.bbs.len(), n_bbs - 2);
+    inst_namer::run(&mut module);
+    let ir = ir_printer::print(&mut module);
+    assert!(!ir.contains("sub"));
+    assert!(!ir.contains("sdiv"));
+    assert!(ir.contains("add i32 %") && ir.contains(", 6"));
+    assert!(ir.contains(", 19"));
+    // 循环条件依赖 phi，仍然保留
+    assert_eq!(ir.matches("br i1").count(), 1);
+}
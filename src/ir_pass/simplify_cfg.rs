@@ -0,0 +1,286 @@
+//! CFG 化简：删除不可达基本块、折叠 br、穿过空基本块跳转、合并只有唯一前驱的基本块
+
+use crate::ir::{ConstValue, InstValue, Module, Value, ValueId};
+
+use super::{
+    analysis::{AnalysisManager, Cfg},
+    pass_manager::FunctionPass,
+};
+
+pub struct SimplifyCfgPass;
+
+impl FunctionPass for SimplifyCfgPass {
+    fn name(&self) -> &'static str {
+        "simplifycfg"
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, am: &mut AnalysisManager) {
+        let mut changed = false;
+        // 删除不可达基本块之后用工作表化简，只有化简又产生了不可达基本块时才重新开始
+        loop {
+            let cfg = Cfg::new(module, func_id);
+            if remove_unreachable(module, func_id, &cfg) {
+                changed = true;
+                continue;
+            }
+            let mut simplifier = Simplifier {
+                module,
+                func_id,
+                worklist: cfg.rpo.iter().rev().copied().collect(),
+                cfg,
+                changed: false,
+                dead: false,
+            };
+            simplifier.run();
+            changed |= simplifier.changed;
+            if !simplifier.dead {
+                break;
+            }
+        }
+        if changed {
+            am.invalidate(func_id);
+        }
+    }
+}
+
+fn terminator(module: &Module, bb_id: ValueId) -> ValueId {
+    *module.get_bb(bb_id).insts.last().unwrap()
+}
+
+fn remove_unreachable(module: &mut Module, func_id: ValueId, cfg: &Cfg) -> bool {
+    let dead = cfg
+        .bbs
+        .iter()
+        .filter(|bb| !cfg.is_reachable(**bb))
+        .copied()
+        .collect::<Vec<_>>();
+    for bb_id in &dead {
+        for succ in cfg.succs(*bb_id) {
+            if !cfg.is_reachable(*succ) {
+                continue;
+            }
+            for phi_id in module.get_phis(*succ) {
+                module.remove_phi_incoming(phi_id, *bb_id);
+            }
+        }
+    }
+    for bb_id in &dead {
+        module.remove_bb(func_id, *bb_id);
+    }
+    !dead.is_empty()
+}
+
+/// 按工作表化简，改动时同步更新 cfg 的前驱和后继，并把受影响的基本块放回工作表
+struct Simplifier<'a> {
+    module: &'a mut Module,
+    func_id: ValueId,
+    cfg: Cfg,
+    worklist: Vec<ValueId>,
+    changed: bool,
+    /// 有基本块失去了所有前驱，需要删除后再来一轮
+    dead: bool,
+}
+
+impl Simplifier<'_> {
+    fn run(&mut self) {
+        while let Some(bb_id) = self.worklist.pop() {
+            // 已经并入前驱的基本块
+            if !self.cfg.succs.contains_key(&bb_id) {
+                continue;
+            }
+            let step =
+                self.fold_branch(bb_id) || self.merge_into_pred(bb_id) || self.thread_jump(bb_id);
+            self.changed |= step;
+        }
+    }
+
+    fn remove_edge(&mut self, from: ValueId, to: ValueId) {
+        self.cfg
+            .succs
+            .get_mut(&from)
+            .unwrap()
+            .retain(|bb| *bb != to);
+        let preds = self.cfg.preds.get_mut(&to).unwrap();
+        preds.retain(|bb| *bb != from);
+        if preds.is_empty() && to != self.cfg.entry {
+            self.dead = true;
+        }
+    }
+
+    fn add_edge(&mut self, from: ValueId, to: ValueId) {
+        let succs = self.cfg.succs.get_mut(&from).unwrap();
+        if !succs.contains(&to) {
+            succs.push(to);
+            self.cfg.preds.get_mut(&to).unwrap().push(from);
+        }
+    }
+
+    /// 两个目标相同或条件为常量的 br 改为 jump
+    fn fold_branch(&mut self, bb_id: ValueId) -> bool {
+        let module = &mut *self.module;
+        let term_id = terminator(module, bb_id);
+        let InstValue::Branch(br) = module.get_inst(term_id).clone() else {
+            return false;
+        };
+        let (target, other) = match module.get_value(br.cond) {
+            _ if br.then_bb == br.else_bb => (br.then_bb, None),
+            Value::Const(ConstValue::Int(c)) if c.value & 1 != 0 => (br.then_bb, Some(br.else_bb)),
+            Value::Const(ConstValue::Int(_)) => (br.else_bb, Some(br.then_bb)),
+            _ => return false,
+        };
+        if let Some(other) = other {
+            for phi_id in module.get_phis(other) {
+                module.remove_phi_incoming(phi_id, bb_id);
+            }
+        }
+        module.remove_inst(term_id);
+        module.set_insert_point(bb_id);
+        module.spawn_jump_inst(target);
+        if let Some(other) = other {
+            self.remove_edge(bb_id, other);
+            self.worklist.push(other);
+        }
+        self.worklist.push(bb_id);
+        self.worklist.push(target);
+        true
+    }
+
+    /// 基本块只有一个前驱，且前驱只跳转到它时，把它并入前驱
+    fn merge_into_pred(&mut self, bb_id: ValueId) -> bool {
+        let [pred] = self.cfg.preds(bb_id) else {
+            return false;
+        };
+        let pred = *pred;
+        if bb_id == self.cfg.entry || pred == bb_id || self.cfg.succs(pred).len() != 1 {
+            return false;
+        }
+
+        let module = &mut *self.module;
+        // 唯一前驱时 phi 只有一个 incoming
+        for phi_id in module.get_phis(bb_id) {
+            let (value, _) = module.get_inst(phi_id).as_phi().incomings[0];
+            module.replace_value(phi_id, value);
+            module.remove_inst(phi_id);
+        }
+        module.remove_inst(terminator(module, pred));
+        for inst_id in module.get_bb(bb_id).insts.clone() {
+            module.get_bb_mut(pred).insts.push(inst_id);
+            module.mark_parent(inst_id, pred);
+        }
+        module.get_bb_mut(bb_id).insts.clear();
+        let succs = self.cfg.succs.remove(&bb_id).unwrap();
+        for succ in &succs {
+            for phi_id in module.get_phis(*succ) {
+                module.replace_phi_incoming_bb(phi_id, bb_id, pred);
+            }
+            for from in self.cfg.preds.get_mut(succ).unwrap() {
+                if *from == bb_id {
+                    *from = pred;
+                }
+            }
+        }
+        module.get_func_mut(self.func_id).bbs.remove(bb_id);
+        self.cfg.preds.remove(&bb_id);
+        self.cfg.succs.insert(pred, succs.clone());
+        self.worklist.extend(succs);
+        self.worklist.push(pred);
+        true
+    }
+
+    /// 只含一条 jump 的基本块，让它的前驱直接跳到目标
+    fn thread_jump(&mut self, bb_id: ValueId) -> bool {
+        let module = &mut *self.module;
+        let insts = &module.get_bb(bb_id).insts;
+        if bb_id == self.cfg.entry || insts.len() != 1 {
+            return false;
+        }
+        let InstValue::Jump(jump) = module.get_inst(insts[0]) else {
+            return false;
+        };
+        let target = jump.bb;
+        if target == bb_id {
+            return false;
+        }
+
+        let phis = module.get_phis(target);
+        let incoming_from = |module: &Module, phi_id: ValueId, bb: ValueId| {
+            module
+                .get_inst(phi_id)
+                .as_phi()
+                .incomings
+                .iter()
+                .find(|(_, from)| *from == bb)
+                .map(|(value, _)| *value)
+        };
+        let mut changed = false;
+        for pred in self.cfg.preds(bb_id).to_vec() {
+            let module = &mut *self.module;
+            // 前驱已经能直接到达目标时，phi 中两条边的值必须相同
+            let already_pred = self.cfg.preds(target).contains(&pred);
+            if already_pred
+                && phis.iter().any(|phi_id| {
+                    incoming_from(module, *phi_id, pred) != incoming_from(module, *phi_id, bb_id)
+                })
+            {
+                continue;
+            }
+            module.replace_successor(terminator(module, pred), bb_id, target);
+            if !already_pred {
+                for phi_id in &phis {
+                    let value = incoming_from(module, *phi_id, bb_id).unwrap();
+                    module.add_phi_incoming(*phi_id, pred, value);
+                }
+            }
+            self.remove_edge(pred, bb_id);
+            self.add_edge(pred, target);
+            self.worklist.push(pred);
+            changed = true;
+        }
+        if changed {
+            self.worklist.push(target);
+        }
+        changed
+    }
+}
+
+#[test]
+fn test_simplify_cfg() {
+    use crate::{
+        ir_pass::{build_ir, inst_namer, verify},
+        ir_printer,
+    };
+
+    let mut module = build_ir(
+        "int f(int a) {
+            int s = 0;
+            if (a > 1) { s = 1; } else { if (a < 0) { s = 2; } }
+            while (a > 0) { if (a == 5) { } a = a - 1; }
+            if (1) { s = s + 1; }
+            return s;
+        }",
+    );
+    let func_id = module.functions["f"];
+    let n_bbs = module.get_func(func_id).bbs.bbs.len();
+    SimplifyCfgPass.run_on_func(&mut module, func_id, &mut AnalysisManager::new());
+    assert_eq!(verify::run(&module), Ok(()));
+
+    let bbs = module.get_func(func_id).bbs.bbs.clone();
+    assert!(bbs.len() < n_bbs);
+    let cfg = Cfg::new(&module, func_id);
+    for bb_id in bbs.values() {
+        let insts = &module.get_bb(*bb_id).insts;
+        // 只含一条 jump 的基本块只会因为目标的 phi 冲突而保留
+        if let [jump_id] = insts.as_slice() {
+            if let InstValue::Jump(jump) = module.get_inst(*jump_id) {
+                assert!(module.bb_has_phi(jump.bb));
+            }
+        }
+        // 没有可以并入前驱的基本块
+        if let [pred] = cfg.preds(*bb_id) {
+            assert!(cfg.succs(*pred).len() > 1);
+        }
+    }
+    inst_namer::run(&mut module);
+    let ir = ir_printer::print(&mut module);
+    assert!(!ir.contains("br i1 1"));
+}
@@ -0,0 +1,193 @@
+//! 标量替换：只用常数下标访问的局部数组拆成每个元素一个标量 alloca，随后由 mem2reg 提升到寄存器
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::{ArrayType, Type},
+    ir::{AllocaInst, ConstValue, InstValue, LoadInst, Module, StoreInst, Value, ValueId},
+};
+
+use super::{analysis::AnalysisManager, dce::AllocaUses, pass_manager::FunctionPass};
+
+/// 元素过多的数组拆开后 mem2reg 的代价太大
+const MAX_ELEMENTS: usize = 64;
+
+pub struct SroaPass;
+
+impl FunctionPass for SroaPass {
+    fn name(&self) -> &'static str {
+        "sroa"
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, _am: &mut AnalysisManager) {
+        let entry = *module.get_func(func_id).bbs.entry_bb();
+        for alloca_id in module.get_bb(entry).insts.clone() {
+            let InstValue::Alloca(alloca) = module.get_inst(alloca_id) else {
+                continue;
+            };
+            if !matches!(alloca.ty, Type::Array(_)) || scalar_count(&alloca.ty) > MAX_ELEMENTS {
+                continue;
+            }
+            let Some(uses) = AllocaUses::collect(module, alloca_id) else {
+                continue;
+            };
+            if let Some(accesses) = element_accesses(module, alloca_id, &uses) {
+                split(module, entry, alloca_id, &uses.geps, accesses);
+            }
+        }
+    }
+}
+
+/// 类型中标量元素的个数
+pub fn scalar_count(ty: &Type) -> usize {
+    ty.size() / ty.base_type().size()
+}
+
+/// 指针相对于根对象（alloca、全局变量等不是 gep 的值）的偏移，以标量元素为单位
+///
+/// 下标不全是常数或越界时返回 None
+pub fn element_offset(module: &Module, ptr: ValueId) -> Option<(ValueId, usize)> {
+    let Some(InstValue::Gep(gep)) = module.try_get_inst(ptr) else {
+        return Some((ptr, 0));
+    };
+    let (root, offset) = element_offset(module, gep.ptr)?;
+    let mut offset = offset as i64;
+    let mut ty = &gep.ty;
+    for (i, index) in gep.indices.iter().enumerate() {
+        let Value::Const(ConstValue::Int(index)) = module.get_value(*index) else {
+            return None;
+        };
+        // 第一个下标按整个 ty 移动指针，之后的下标逐层进入数组
+        if i > 0 {
+            let Type::Array(ArrayType::Constant(array)) = ty else {
+                return None;
+            };
+            if !(0..array.size as i64).contains(&index.value) {
+                return None;
+            }
+            ty = &array.element_type;
+        }
+        offset += index.value * scalar_count(ty) as i64;
+    }
+    Some((root, usize::try_from(offset).ok()?))
+}
+
+/// 所有 load/store 都以常数下标读写单个元素时，返回每个访问及其元素偏移
+fn element_accesses(
+    module: &Module,
+    alloca_id: ValueId,
+    uses: &AllocaUses,
+) -> Option<Vec<(ValueId, usize)>> {
+    let ty = module.get_inst(alloca_id).ty();
+    let elem_ty = ty.base_type();
+    let mut accesses = vec![];
+    for inst_id in uses.loads.iter().chain(&uses.stores) {
+        let (ptr, access_ty) = match module.get_inst(*inst_id) {
+            InstValue::Load(load) => (load.ptr, load.ty.clone()),
+            InstValue::Store(store) => (store.ptr, module.get_value(store.value).ty()),
+            _ => unreachable!(),
+        };
+        let (_, offset) = element_offset(module, ptr)?;
+        if access_ty != *elem_ty || offset >= scalar_count(&ty) {
+            return None;
+        }
+        accesses.push((*inst_id, offset));
+    }
+    Some(accesses)
+}
+
+fn split(
+    module: &mut Module,
+    entry: ValueId,
+    alloca_id: ValueId,
+    geps: &[ValueId],
+    accesses: Vec<(ValueId, usize)>,
+) {
+    let InstValue::Alloca(alloca) = module.get_inst(alloca_id).clone() else {
+        unreachable!()
+    };
+    let elem_ty = alloca.ty.base_type().clone();
+    let mut elems = HashMap::new();
+    for (inst_id, offset) in accesses {
+        let elem = *elems.entry(offset).or_insert_with(|| {
+            let elem = AllocaInst {
+                ty: elem_ty.clone(),
+                name: format!("{}.{}", alloca.name, offset),
+            };
+            module.insert_inst(entry, 0, InstValue::Alloca(elem))
+        });
+        let bb_id = module.get_parent_id(inst_id);
+        let pos = module
+            .get_bb(bb_id)
+            .insts
+            .iter()
+            .position(|id| *id == inst_id)
+            .unwrap();
+        match module.get_inst(inst_id).clone() {
+            InstValue::Load(load) => {
+                let new_load = LoadInst {
+                    ty: load.ty,
+                    ptr: elem,
+                };
+                let new_id = module.insert_inst(bb_id, pos, InstValue::Load(new_load));
+                module.replace_value(inst_id, new_id);
+            }
+            InstValue::Store(store) => {
+                let new_store = StoreInst {
+                    value: store.value,
+                    ptr: elem,
+                };
+                module.insert_inst(bb_id, pos, InstValue::Store(new_store));
+            }
+            _ => unreachable!(),
+        }
+        module.remove_inst(inst_id);
+    }
+    for gep_id in geps.iter().rev() {
+        module.remove_inst(*gep_id);
+    }
+    module.remove_inst(alloca_id);
+}
+
+#[test]
+fn test_sroa() {
+    use crate::ir_pass::{build_ir, mem2reg::Mem2RegPass, verify};
+
+    let mut module = build_ir(
+        "int f(int n) {
+            int a[4] = {1, n};
+            int b[2][3] = {{1, 2, 3}, {a[1], 5}};
+            int c[3] = {4, 5, 6};
+            return a[0] + a[1] + b[1][0] + b[0][2] + b[1][2] + c[n];
+        }",
+    );
+    let func_id = module.functions["f"];
+    let mut am = AnalysisManager::new();
+    SroaPass.run_on_func(&mut module, func_id, &mut am);
+    Mem2RegPass.run_on_func(&mut module, func_id, &mut am);
+    assert_eq!(verify::run(&module), Ok(()));
+
+    // 只剩用变量下标访问的 c
+    let insts = module
+        .get_func(func_id)
+        .bbs
+        .bbs
+        .values()
+        .flat_map(|bb_id| module.get_bb(*bb_id).insts.iter())
+        .map(|inst_id| module.get_inst(*inst_id))
+        .collect::<Vec<_>>();
+    let allocas = insts
+        .iter()
+        .filter_map(|inst| match inst {
+            InstValue::Alloca(alloca) => Some(alloca.ty.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(allocas.len(), 1);
+    assert_eq!(scalar_count(&allocas[0]), 3);
+    assert_eq!(insts.iter().filter(|inst| inst.is_load()).count(), 1);
+}
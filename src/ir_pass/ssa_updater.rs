@@ -0,0 +1,131 @@
+//! 同一个值在多个基本块中有定义时重建 SSA
+//!
+//! 复制基本块之后，原值和副本都是定义，按需在汇合点插入 phi，再把使用改为可达的定义
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::Type,
+    ir::{InstValue, Module, PhiInst, ValueId},
+};
+
+use super::analysis::Cfg;
+
+pub struct SsaUpdater {
+    ty: Type,
+    /// 基本块 -> 块末尾可用的定义
+    defs: HashMap<ValueId, ValueId>,
+    /// 没有定义的基本块 -> 块开头可用的值
+    incoming: HashMap<ValueId, ValueId>,
+    phis: Vec<ValueId>,
+}
+
+impl SsaUpdater {
+    pub fn new(ty: Type) -> Self {
+        SsaUpdater {
+            ty,
+            defs: HashMap::new(),
+            incoming: HashMap::new(),
+            phis: vec![],
+        }
+    }
+
+    pub fn add_def(&mut self, bb_id: ValueId, value: ValueId) {
+        self.defs.insert(bb_id, value);
+    }
+
+    pub fn value_at_end(&mut self, module: &mut Module, cfg: &Cfg, bb_id: ValueId) -> ValueId {
+        match self.defs.get(&bb_id) {
+            Some(value) => *value,
+            None => self.value_at_start(module, cfg, bb_id),
+        }
+    }
+
+    fn value_at_start(&mut self, module: &mut Module, cfg: &Cfg, bb_id: ValueId) -> ValueId {
+        if let Some(value) = self.incoming.get(&bb_id) {
+            return *value;
+        }
+        let preds = cfg
+            .preds(bb_id)
+            .iter()
+            .filter(|pred| cfg.is_reachable(**pred))
+            .copied()
+            .collect::<Vec<_>>();
+        let value = match preds.as_slice() {
+            // 没有任何定义可达，说明这里的使用走不到
+            [] => module.spawn_zero_value(self.ty.clone()),
+            [pred] => self.value_at_end(module, cfg, *pred),
+            _ => {
+                // 先登记 phi 再查询前驱，打断循环
+                let phi = PhiInst {
+                    ty: self.ty.clone(),
+                    incomings: vec![],
+                };
+                let phi_id = module.insert_inst(bb_id, 0, InstValue::Phi(phi));
+                self.phis.push(phi_id);
+                self.incoming.insert(bb_id, phi_id);
+                for pred in preds {
+                    let value = self.value_at_end(module, cfg, pred);
+                    module.add_phi_incoming(phi_id, pred, value);
+                }
+                phi_id
+            }
+        };
+        self.incoming.insert(bb_id, value);
+        value
+    }
+
+    /// 把 user 中对 old 的使用改为到达该处的定义，user 不能位于定义所在的基本块中
+    pub fn rewrite_use(&mut self, module: &mut Module, cfg: &Cfg, user: ValueId, old: ValueId) {
+        let mut news = vec![];
+        if let InstValue::Phi(phi) = module.get_inst(user).clone() {
+            // phi 的使用发生在对应前驱的末尾
+            let mut incomings = phi.incomings;
+            for (value, bb) in &mut incomings {
+                if *value == old {
+                    *value = self.value_at_end(module, cfg, *bb);
+                    news.push(*value);
+                }
+            }
+            match module.get_inst_mut(user) {
+                InstValue::Phi(phi) => phi.incomings = incomings,
+                _ => unreachable!(),
+            }
+        } else {
+            let bb_id = module.get_parent_id(user);
+            let new = self.value_at_start(module, cfg, bb_id);
+            module.get_inst_mut(user).replace_operands(old, new);
+            news.push(new);
+        }
+        module.mark_nolonger_using(user, old);
+        for new in news {
+            module.mark_using(user, new);
+        }
+    }
+
+    /// 删除只有一种来源值的 phi
+    pub fn remove_trivial_phis(&mut self, module: &mut Module) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for phi_id in self.phis.clone() {
+                let mut values = module
+                    .get_inst(phi_id)
+                    .as_phi()
+                    .incomings
+                    .iter()
+                    .map(|(value, _)| *value)
+                    .filter(|value| *value != phi_id)
+                    .collect::<Vec<_>>();
+                values.sort();
+                values.dedup();
+                if let [value] = values.as_slice() {
+                    module.replace_value(phi_id, *value);
+                    module.remove_inst(phi_id);
+                    self.phis.retain(|x| *x != phi_id);
+                    changed = true;
+                }
+            }
+        }
+    }
+}
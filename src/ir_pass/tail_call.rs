@@ -0,0 +1,202 @@
+//! 尾调用
+//!
+//! - tailcallelim：自递归的尾调用改为跳回函数开头的循环，形参变为循环头的 phi
+//! - tailcall：其余处于尾部的调用标记为 must_tail，由后端释放栈帧后直接跳转到被调用者
+//!
+//! 尾调用是紧跟在 ret 之前、返回值就是调用结果的调用。调用者的栈帧在调用前释放或复用，
+//! 实参不能指向调用者的局部数组
+
+use crate::{
+    ast::{BuiltinType, Type},
+    ir::{InstValue, Module, PhiInst, ValueId},
+    mc::{AsmTypeTag, ParamInfo, VfpCallConv},
+};
+
+use super::{analysis::AnalysisManager, pass_manager::FunctionPass};
+
+pub struct TailCallElimPass;
+
+impl FunctionPass for TailCallElimPass {
+    fn name(&self) -> &'static str {
+        "tailcallelim"
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, am: &mut AnalysisManager) {
+        let calls = tail_calls(module, func_id)
+            .into_iter()
+            .filter(|call_id| module.get_inst(*call_id).as_call().func == func_id)
+            .collect::<Vec<_>>();
+        if calls.is_empty() {
+            return;
+        }
+        let header = split_entry(module, func_id);
+        let params = module.get_func(func_id).params.clone();
+        let entry = *module.get_func(func_id).bbs.entry_bb();
+        let mut phis = vec![];
+        for (i, param) in params.iter().enumerate() {
+            let phi = PhiInst {
+                ty: module.get_value(*param).ty(),
+                incomings: vec![],
+            };
+            let phi_id = module.insert_inst(header, i, InstValue::Phi(phi));
+            module.replace_value(*param, phi_id);
+            module.add_phi_incoming(phi_id, entry, *param);
+            phis.push(phi_id);
+        }
+        for call_id in calls {
+            let bb_id = module.get_parent_id(call_id);
+            let args = module.get_inst(call_id).as_call().args.clone();
+            module.remove_inst(module.get_terminator(bb_id));
+            module.remove_inst(call_id);
+            module.set_insert_point(bb_id);
+            module.spawn_jump_inst(header);
+            for (phi_id, arg) in phis.iter().zip(args) {
+                module.add_phi_incoming(*phi_id, bb_id, arg);
+            }
+        }
+        am.invalidate(func_id);
+    }
+}
+
+pub struct TailCallPass;
+
+impl FunctionPass for TailCallPass {
+    fn name(&self) -> &'static str {
+        "tailcall"
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    fn run_on_func(&mut self, module: &mut Module, func_id: ValueId, _am: &mut AnalysisManager) {
+        let arg_size = stack_arg_size(module, func_id);
+        for call_id in tail_calls(module, func_id) {
+            let callee = module.get_inst(call_id).as_call().func;
+            // 被调用者的栈上参数写入调用者传入参数的位置
+            if module.get_func(callee).is_variadic || stack_arg_size(module, callee) > arg_size {
+                continue;
+            }
+            if let InstValue::Call(call) = module.get_inst_mut(call_id) {
+                call.must_tail = true;
+            }
+        }
+    }
+}
+
+/// 函数中处于尾部、实参不引用局部数组的调用
+fn tail_calls(module: &Module, func_id: ValueId) -> Vec<ValueId> {
+    let mut calls = vec![];
+    for bb_id in module.get_func(func_id).bbs.bbs.values() {
+        let insts = &module.get_bb(*bb_id).insts;
+        let [.., call_id, ret_id] = insts.as_slice() else {
+            continue;
+        };
+        let (InstValue::Call(call), InstValue::Return(ret)) =
+            (module.get_inst(*call_id), module.get_inst(*ret_id))
+        else {
+            continue;
+        };
+        let returns_call = match ret.value {
+            Some(value) => value == *call_id,
+            None => call.ty == Type::Builtin(BuiltinType::Void),
+        };
+        if returns_call && !call.args.iter().any(|arg| points_to_local(module, *arg)) {
+            calls.push(*call_id);
+        }
+    }
+    calls
+}
+
+/// 值是由 alloca 经过若干 gep 得到的地址
+fn points_to_local(module: &Module, mut val_id: ValueId) -> bool {
+    loop {
+        match module.try_get_inst(val_id) {
+            Some(InstValue::Gep(gep)) => val_id = gep.ptr,
+            Some(InstValue::Alloca(_)) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// 按 VFP 调用约定在栈上传递的参数大小
+fn stack_arg_size(module: &Module, func_id: ValueId) -> i64 {
+    let func = module.get_func(func_id);
+    let params = func
+        .params
+        .iter()
+        .map(|param| ParamInfo::from(module.get_value(*param).ty()))
+        .collect::<Vec<_>>();
+    VfpCallConv::new()
+        .resolve(&params, AsmTypeTag::from(func.ret_ty.clone()))
+        .nsaa
+}
+
+/// 入口块中 alloca 之后的指令移到新的基本块中作为循环头，入口块只保留 alloca
+fn split_entry(module: &mut Module, func_id: ValueId) -> ValueId {
+    let entry = *module.get_func(func_id).bbs.entry_bb();
+    let pos = module
+        .get_bb(entry)
+        .insts
+        .iter()
+        .position(|inst_id| !module.get_inst(*inst_id).is_alloca())
+        .unwrap();
+    module.set_cur_func(func_id);
+    let header = module.spawn_basic_block();
+    let tail = module.get_bb_mut(entry).insts.split_off(pos);
+    for inst_id in &tail {
+        module.mark_parent(*inst_id, header);
+    }
+    module.get_bb_mut(header).insts = tail;
+    let mut succs = module.get_inst(module.get_terminator(header)).successors();
+    succs.dedup();
+    for succ in succs {
+        for phi_id in module.get_phis(succ) {
+            module.replace_phi_incoming_bb(phi_id, entry, header);
+        }
+    }
+    module.set_insert_point(entry);
+    module.spawn_jump_inst(header);
+    header
+}
+
+#[test]
+fn test_tail_call() {
+    use crate::ir_pass::{build_ir, verify};
+
+    let mut module = build_ir(
+        "int sum(int n, int acc) { if (n == 0) return acc; return sum(n - 1, acc + n); }
+        int g(int x) { if (x) return sum(x, 1) + 1; return sum(x, 0); }",
+    );
+    let mut am = AnalysisManager::new();
+    for func in ["sum", "g"] {
+        let func_id = module.functions[func];
+        TailCallElimPass.run_on_func(&mut module, func_id, &mut am);
+        TailCallPass.run_on_func(&mut module, func_id, &mut am);
+    }
+    assert_eq!(verify::run(&module), Ok(()));
+
+    let calls = |func: &str| {
+        module
+            .get_func(module.functions[func])
+            .bbs
+            .bbs
+            .values()
+            .flat_map(|bb_id| module.get_bb(*bb_id).insts.iter())
+            .filter_map(|inst_id| match module.get_inst(*inst_id) {
+                InstValue::Call(call) => {
+                    Some((module.get_func(call.func).name.clone(), call.must_tail))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    // sum 的自递归变为循环；g 中只有直接返回结果的调用是尾调用
+    assert!(calls("sum").is_empty());
+    let mut g_calls = calls("g");
+    g_calls.sort();
+    assert_eq!(
+        g_calls,
+        vec![("sum".to_string(), false), ("sum".to_string(), true)]
+    );
+}
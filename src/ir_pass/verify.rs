@@ -0,0 +1,610 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    ast::{BuiltinType, InfixOp, Type},
+    ir::{CastOp, ConstValue, InstValue, Module, Value, ValueId},
+};
+
+use super::{
+    analysis::{AnalysisManager, Cfg, DomTree},
+    pass_manager::Pass,
+};
+
+/// 校验整个模块，返回发现的所有问题
+pub fn run(module: &Module) -> Result<(), Vec<VerifyError>> {
+    let mut verifier = Verifier {
+        module,
+        errors: vec![],
+        func_name: String::new(),
+        bb_names: HashMap::new(),
+    };
+    for (_, func_id) in module.functions.iter() {
+        verifier.verify_function(*func_id);
+    }
+    if verifier.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(verifier.errors)
+    }
+}
+
+/// 把校验作为 pass 放进流水线，失败时终止编译
+pub struct VerifyPass;
+
+impl Pass for VerifyPass {
+    fn name(&self) -> &'static str {
+        "verify"
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    fn run(&mut self, module: &mut Module, _am: &mut AnalysisManager) {
+        if let Err(errors) = run(module) {
+            panic!("{}", format_errors(&errors));
+        }
+    }
+}
+
+pub fn format_errors(errors: &[VerifyError]) -> String {
+    let mut out = format!("IR verification failed with {} error(s):\n", errors.len());
+    for err in errors {
+        out.push_str(&format!("  {}\n", err));
+    }
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub func: String,
+    pub block: String,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "in function `{}`, block `{}`: {}",
+            self.func, self.block, self.message
+        )
+    }
+}
+
+struct Verifier<'a> {
+    module: &'a Module,
+    errors: Vec<VerifyError>,
+    func_name: String,
+    bb_names: HashMap<ValueId, String>,
+}
+
+impl Verifier<'_> {
+    fn report(&mut self, bb_id: ValueId, message: String) {
+        let block = self
+            .bb_names
+            .get(&bb_id)
+            .cloned()
+            .unwrap_or_else(|| format!("<detached {}>", bb_id.index()));
+        self.errors.push(VerifyError {
+            func: self.func_name.clone(),
+            block,
+            message,
+        });
+    }
+
+    fn describe(&self, val_id: ValueId) -> String {
+        match self.module.value_name.get(&val_id) {
+            Some(name) => format!("{} [{}]", name, val_id.index()),
+            None => format!("[{}]", val_id.index()),
+        }
+    }
+
+    fn verify_function(&mut self, func_id: ValueId) {
+        let func = self.module.get_func(func_id);
+        if func.is_external {
+            return;
+        }
+        self.func_name = func.name.clone();
+        let n_errors = self.errors.len();
+        self.bb_names = func
+            .bbs
+            .bbs
+            .iter()
+            .map(|(name, bb_id)| (*bb_id, name.clone()))
+            .collect();
+        let bbs = func.bbs.bbs.values().copied().collect::<Vec<_>>();
+
+        // 每条指令所在的基本块和位置
+        let mut position = HashMap::new();
+        for bb_id in &bbs {
+            for (idx, inst_id) in self.module.get_bb(*bb_id).insts.iter().enumerate() {
+                if let Some((other_bb, _)) = position.insert(*inst_id, (*bb_id, idx)) {
+                    let msg = format!(
+                        "instruction {} also appears in block `{}`",
+                        self.describe(*inst_id),
+                        self.bb_names[&other_bb]
+                    );
+                    self.report(*bb_id, msg);
+                }
+            }
+        }
+
+        let mut succs = HashMap::new();
+        for bb_id in &bbs {
+            succs.insert(*bb_id, self.verify_block_shape(*bb_id));
+        }
+        let mut preds: HashMap<ValueId, Vec<ValueId>> = HashMap::new();
+        for bb_id in &bbs {
+            for succ in &succs[bb_id] {
+                if !self.bb_names.contains_key(succ) {
+                    let msg = format!("branch to block [{}] outside the function", succ.index());
+                    self.report(*bb_id, msg);
+                    continue;
+                }
+                let entry = preds.entry(*succ).or_default();
+                if !entry.contains(bb_id) {
+                    entry.push(*bb_id);
+                }
+            }
+        }
+
+        for bb_id in &bbs {
+            let cfg_preds = preds.remove(bb_id).unwrap_or_default();
+            self.verify_preds(*bb_id, &cfg_preds);
+            for inst_id in self.module.get_bb(*bb_id).insts.clone() {
+                self.verify_def_use(*bb_id, inst_id, &position);
+                self.verify_types(*bb_id, inst_id, func_id);
+                self.verify_wide(*bb_id, inst_id);
+            }
+        }
+
+        // CFG 本身有问题时支配关系没有意义
+        if self.errors.len() > n_errors {
+            return;
+        }
+        let dom = DomTree::new(&Cfg::new(self.module, func_id));
+        for bb_id in &bbs {
+            // 不可达的基本块不做支配检查
+            if !dom.contains(*bb_id) {
+                continue;
+            }
+            for inst_id in self.module.get_bb(*bb_id).insts.clone() {
+                self.verify_dominance(*bb_id, inst_id, &position, &dom);
+            }
+        }
+    }
+
+    /// 检查终结指令和 phi 的位置，返回后继基本块
+    fn verify_block_shape(&mut self, bb_id: ValueId) -> Vec<ValueId> {
+        let insts = self.module.get_bb(bb_id).insts.clone();
+        let Some(last) = insts.last() else {
+            self.report(bb_id, "block is empty".to_string());
+            return vec![];
+        };
+        let mut seen_non_phi = false;
+        for (idx, inst_id) in insts.iter().enumerate() {
+            let inst = self.module.get_inst(*inst_id);
+            if inst.is_term() && idx + 1 != insts.len() {
+                let msg = format!(
+                    "terminator {} is not at the end of the block",
+                    self.describe(*inst_id)
+                );
+                self.report(bb_id, msg);
+            }
+            if inst.is_phi() {
+                if seen_non_phi {
+                    let msg = format!(
+                        "phi {} is not grouped at the top of the block",
+                        self.describe(*inst_id)
+                    );
+                    self.report(bb_id, msg);
+                }
+            } else {
+                seen_non_phi = true;
+            }
+            // 后端在调用处释放栈帧，之后的 ret 不再生成
+            if matches!(inst, InstValue::Call(call) if call.must_tail) {
+                let returns_call = insts.get(idx + 1).is_some_and(|next| {
+                    matches!(self.module.get_inst(*next),
+                        InstValue::Return(ret) if ret.value.is_none_or(|value| value == *inst_id))
+                });
+                if !returns_call {
+                    let msg = format!(
+                        "must_tail call {} is not followed by a return of its result",
+                        self.describe(*inst_id)
+                    );
+                    self.report(bb_id, msg);
+                }
+            }
+        }
+        let last = self.module.get_inst(*last);
+        if !last.is_term() {
+            self.report(bb_id, "block does not end with a terminator".to_string());
+            return vec![];
+        }
+        last.successors()
+    }
+
+    fn verify_preds(&mut self, bb_id: ValueId, cfg_preds: &[ValueId]) {
+        let mut recorded = self.module.get_bb_preds(bb_id);
+        recorded.sort();
+        recorded.dedup();
+        let mut expected = cfg_preds.to_vec();
+        expected.sort();
+        if recorded != expected {
+            let msg = format!(
+                "def-use maps record predecessors {:?} but the CFG has {:?}",
+                self.names_of(&recorded),
+                self.names_of(&expected)
+            );
+            self.report(bb_id, msg);
+        }
+
+        for phi_id in self.module.get_phis(bb_id) {
+            let phi = self.module.get_inst(phi_id).as_phi();
+            let mut incoming = phi.incomings.iter().map(|(_, bb)| *bb).collect::<Vec<_>>();
+            incoming.sort();
+            let n_incoming = incoming.len();
+            incoming.dedup();
+            if n_incoming != incoming.len() || incoming != expected {
+                let msg = format!(
+                    "phi {} has incoming blocks {:?} but the predecessors are {:?}",
+                    self.describe(phi_id),
+                    self.names_of(&phi.incomings.iter().map(|(_, bb)| *bb).collect::<Vec<_>>()),
+                    self.names_of(&expected)
+                );
+                self.report(bb_id, msg);
+            }
+        }
+    }
+
+    fn names_of(&self, bbs: &[ValueId]) -> Vec<String> {
+        bbs.iter()
+            .map(|bb| {
+                self.bb_names
+                    .get(bb)
+                    .cloned()
+                    .unwrap_or_else(|| format!("[{}]", bb.index()))
+            })
+            .collect()
+    }
+
+    fn verify_def_use(
+        &mut self,
+        bb_id: ValueId,
+        inst_id: ValueId,
+        position: &HashMap<ValueId, (ValueId, usize)>,
+    ) {
+        let using = self.module.value_using.get(&inst_id);
+        for opr in self.module.get_inst(inst_id).operands() {
+            if !using.is_some_and(|using| using.contains(&opr)) {
+                let msg = format!(
+                    "{} uses {} but value_using does not record it",
+                    self.describe(inst_id),
+                    self.describe(opr)
+                );
+                self.report(bb_id, msg);
+            }
+        }
+        for used in using.cloned().unwrap_or_default() {
+            let users = self.module.value_user.get(&used);
+            if !users.is_some_and(|users| users.contains(&inst_id)) {
+                let msg = format!(
+                    "value_using of {} contains {} but value_user does not",
+                    self.describe(inst_id),
+                    self.describe(used)
+                );
+                self.report(bb_id, msg);
+            }
+        }
+        for user in self.module.get_users_of(inst_id) {
+            // 已从基本块中删除的用户只要不再登记使用即可
+            let recorded = self
+                .module
+                .value_using
+                .get(&user)
+                .is_some_and(|using| using.contains(&inst_id));
+            if !recorded {
+                let msg = format!(
+                    "value_user of {} contains {} but value_using does not",
+                    self.describe(inst_id),
+                    self.describe(user)
+                );
+                self.report(bb_id, msg);
+            } else if !position.contains_key(&user) {
+                let msg = format!(
+                    "{} is used by {}, which is not in any block",
+                    self.describe(inst_id),
+                    self.describe(user)
+                );
+                self.report(bb_id, msg);
+            }
+        }
+    }
+
+    fn ty_of(&self, val_id: ValueId) -> Type {
+        self.module.get_value(val_id).ty()
+    }
+
+    /// alloca、gep 和全局变量的类型就是指向的类型，指针 phi 的类型是指针
+    fn pointee_ty(&self, ptr: ValueId) -> Type {
+        match self.ty_of(ptr) {
+            Type::Pointer(pointer) => *pointer.type_,
+            ty => ty,
+        }
+    }
+
+    fn expect_ty(&mut self, bb_id: ValueId, inst_id: ValueId, what: &str, got: Type, want: &Type) {
+        if got != *want {
+            let msg = format!(
+                "{} of {} has type {:?}, expected {:?}",
+                what,
+                self.describe(inst_id),
+                got,
+                want
+            );
+            self.report(bb_id, msg);
+        }
+    }
+
+    fn verify_types(&mut self, bb_id: ValueId, inst_id: ValueId, func_id: ValueId) {
+        let bool_ty: Type = BuiltinType::Bool.into();
+        match self.module.get_inst(inst_id).clone() {
+            InstValue::InfixOp(inst) => {
+                let lhs_ty = self.ty_of(inst.lhs);
+                self.expect_ty(bb_id, inst_id, "rhs", self.ty_of(inst.rhs), &lhs_ty);
+                let want = if inst.op.is_boolean() {
+                    bool_ty
+                } else {
+                    lhs_ty
+                };
+                self.expect_ty(bb_id, inst_id, "result", inst.ty, &want);
+            }
+            InstValue::Load(inst) => {
+                self.expect_ty(
+                    bb_id,
+                    inst_id,
+                    "result",
+                    inst.ty,
+                    &self.pointee_ty(inst.ptr),
+                );
+            }
+            InstValue::Store(inst) => {
+                let want = self.pointee_ty(inst.ptr);
+                self.expect_ty(
+                    bb_id,
+                    inst_id,
+                    "stored value",
+                    self.ty_of(inst.value),
+                    &want,
+                );
+            }
+            InstValue::Branch(inst) => {
+                self.expect_ty(bb_id, inst_id, "condition", self.ty_of(inst.cond), &bool_ty);
+            }
+            InstValue::Return(inst) => {
+                let ret_ty = self.module.get_func(func_id).ret_ty.clone();
+                match inst.value {
+                    Some(val) => {
+                        self.expect_ty(bb_id, inst_id, "return value", self.ty_of(val), &ret_ty)
+                    }
+                    None if ret_ty != BuiltinType::Void.into() => {
+                        let msg = format!(
+                            "{} returns no value from a non-void function",
+                            self.describe(inst_id)
+                        );
+                        self.report(bb_id, msg);
+                    }
+                    None => {}
+                }
+            }
+            InstValue::Call(inst) => {
+                let callee = self.module.get_func(inst.func);
+                let params = callee.params.clone();
+                if inst.args.len() < params.len()
+                    || (!callee.is_variadic && inst.args.len() != params.len())
+                {
+                    let msg = format!(
+                        "{} passes {} arguments to `{}`, which takes {}",
+                        self.describe(inst_id),
+                        inst.args.len(),
+                        callee.name,
+                        params.len()
+                    );
+                    self.report(bb_id, msg);
+                }
+                for (arg, param) in inst.args.iter().zip(params) {
+                    let want = self.ty_of(param);
+                    self.expect_ty(bb_id, inst_id, "argument", self.ty_of(*arg), &want);
+                }
+            }
+            InstValue::Phi(inst) => {
+                for (val, _) in &inst.incomings {
+                    let (got, want) = match &inst.ty {
+                        Type::Pointer(pointer) => (self.pointee_ty(*val), pointer.type_.as_ref()),
+                        ty => (self.ty_of(*val), ty),
+                    };
+                    self.expect_ty(bb_id, inst_id, "incoming value", got, want);
+                }
+            }
+            InstValue::Cast(inst) => {
+                let from = self.ty_of(inst.value);
+                let ok = match inst.op {
+                    CastOp::ZExt | CastOp::SExt | CastOp::Trunc => {
+                        from.is_integer() && inst.new_ty.is_integer()
+                    }
+                    CastOp::SIToFP | CastOp::UIToFP => from.is_integer() && inst.new_ty.is_float(),
+                    CastOp::FPToSI | CastOp::FPToUI => from.is_float() && inst.new_ty.is_integer(),
+                    CastOp::FPExt | CastOp::FPTrunc => from.is_float() && inst.new_ty.is_float(),
+                    CastOp::PtrToInt | CastOp::IntToPtr | CastOp::BitCast => true,
+                };
+                if !ok {
+                    let msg = format!(
+                        "{:?} from {:?} to {:?} in {}",
+                        inst.op,
+                        from,
+                        inst.new_ty,
+                        self.describe(inst_id)
+                    );
+                    self.report(bb_id, msg);
+                }
+            }
+            InstValue::Alloca(_) | InstValue::Jump(_) | InstValue::Gep(_) => {}
+        }
+    }
+
+    /// 后端没有 64 位寄存器，i64 只能出现在最终被 trunc 的整数运算中
+    fn verify_wide(&mut self, bb_id: ValueId, inst_id: ValueId) {
+        let int64: Type = BuiltinType::Int64.into();
+        let inst = self.module.get_inst(inst_id).clone();
+        let ok = match &inst {
+            InstValue::Cast(cast) if cast.new_ty == int64 => matches!(cast.op, CastOp::SExt),
+            InstValue::InfixOp(op) if op.ty == int64 => match op.op {
+                InfixOp::Add
+                | InfixOp::Sub
+                | InfixOp::Mul
+                | InfixOp::BitAnd
+                | InfixOp::BitOr
+                | InfixOp::BitXor => true,
+                // 右移只能取出符号扩展值或两个 32 位数乘积的高位
+                InfixOp::BitShr => {
+                    let is_high = matches!(
+                        self.module.get_value(op.rhs),
+                        Value::Const(ConstValue::Int(c)) if (32..64).contains(&c.value)
+                    );
+                    is_high && self.is_narrow_mul(op.lhs)
+                }
+                _ => false,
+            },
+            inst => inst.ty() != int64,
+        };
+        if !ok {
+            let msg = format!(
+                "{} produces a 64-bit value the backend cannot lower",
+                self.describe(inst_id)
+            );
+            self.report(bb_id, msg);
+        }
+
+        let uses_wide = match &inst {
+            InstValue::InfixOp(op) => op.ty == int64,
+            InstValue::Cast(cast) => matches!(cast.op, CastOp::Trunc),
+            _ => false,
+        };
+        if uses_wide {
+            return;
+        }
+        for opr in inst.operands() {
+            if self.ty_of(opr) == int64 {
+                let msg = format!(
+                    "{} uses 64-bit value {} outside a truncated computation",
+                    self.describe(inst_id),
+                    self.describe(opr)
+                );
+                self.report(bb_id, msg);
+            }
+        }
+    }
+
+    /// 符号扩展的 32 位值，或两个这样的值的乘积
+    fn is_narrow_mul(&self, val_id: ValueId) -> bool {
+        let is_narrow = |val_id: ValueId| match self.module.get_value(val_id) {
+            Value::Instruction(InstValue::Cast(cast)) => matches!(cast.op, CastOp::SExt),
+            Value::Const(ConstValue::Int(c)) => i32::try_from(c.value).is_ok(),
+            _ => false,
+        };
+        match self.module.get_value(val_id) {
+            Value::Instruction(InstValue::InfixOp(op)) if op.op == InfixOp::Mul => {
+                is_narrow(op.lhs) && is_narrow(op.rhs)
+            }
+            _ => is_narrow(val_id),
+        }
+    }
+
+    fn verify_dominance(
+        &mut self,
+        bb_id: ValueId,
+        inst_id: ValueId,
+        position: &HashMap<ValueId, (ValueId, usize)>,
+        dom: &DomTree,
+    ) {
+        let inst = self.module.get_inst(inst_id);
+        // phi 的操作数只需支配对应的前驱块末尾
+        let uses = match inst {
+            InstValue::Phi(phi) => phi
+                .incomings
+                .iter()
+                .map(|(val, pred)| (*val, *pred, usize::MAX))
+                .collect::<Vec<_>>(),
+            _ => {
+                let idx = position[&inst_id].1;
+                inst.operands()
+                    .into_iter()
+                    .map(|opr| (opr, bb_id, idx))
+                    .collect()
+            }
+        };
+        for (opr, use_bb, use_idx) in uses {
+            if !matches!(self.module.get_value(opr), Value::Instruction(_)) {
+                continue;
+            }
+            let Some((def_bb, def_idx)) = position.get(&opr).copied() else {
+                let msg = format!(
+                    "{} uses {}, which is not in any block of this function",
+                    self.describe(inst_id),
+                    self.describe(opr)
+                );
+                self.report(bb_id, msg);
+                continue;
+            };
+            let dominated = if def_bb == use_bb {
+                def_idx < use_idx
+            } else {
+                !dom.contains(use_bb) || dom.dominates(def_bb, use_bb)
+            };
+            if !dominated {
+                let msg = format!(
+                    "{} does not dominate its use in {}",
+                    self.describe(opr),
+                    self.describe(inst_id)
+                );
+                self.report(bb_id, msg);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_verify() {
+    use crate::{ir_builder, ir_pass::mem2reg, scope::SymbolTable, sema::ToSemaTrait};
+
+    let mut ast = crate::parser::parse(
+        "int f(int n) { int s = 0; while (n > 0) { s = s + n; n = n - 1; } return s; }
+        int main() { return f(3) && f(4); }",
+    )
+    .unwrap();
+    let mut syms = SymbolTable::new();
+    ast.to_sema(&mut syms);
+    let mut module = ir_builder::build(&mut ast, syms).unwrap();
+    assert_eq!(run(&module), Ok(()));
+    mem2reg::run(&mut module);
+    assert_eq!(run(&module), Ok(()));
+
+    // 交换循环头中 phi 与其后的指令，破坏 phi 在块首的约束
+    let func_id = module.functions["f"];
+    let bb_id = *module
+        .get_func(func_id)
+        .bbs
+        .bbs
+        .values()
+        .find(|bb| module.bb_has_phi(**bb))
+        .unwrap();
+    let insts = &mut module.get_bb_mut(bb_id).insts;
+    let n_phis = insts.len() - 2;
+    insts.swap(0, n_phis);
+    let errors = run(&module).unwrap_err();
+    assert!(errors
+        .iter()
+        .any(|e| e.func == "f" && e.message.contains("not grouped at the top")));
+}
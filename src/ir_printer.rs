@@ -1,14 +1,20 @@
-use log::debug;
+use std::fmt::Write;
 
 use crate::{ast::*, ir::*};
 
-pub fn print(module: &mut Module) {
-    let mut printer = Printer { module };
+/// 将模块渲染为 LLVM IR 文本
+pub fn print(module: &mut Module) -> String {
+    let mut printer = Printer {
+        module,
+        out: String::new(),
+    };
     printer.print_module();
+    printer.out
 }
 
 struct Printer<'a> {
     module: &'a Module,
+    out: String,
 }
 
 impl<'a> Printer<'a> {
@@ -38,13 +44,15 @@ impl<'a> Printer<'a> {
                 "global"
             }
         };
-        println!(
+        writeln!(
+            self.out,
             "@{} = {} {} {}",
             name,
             constant,
             self.format_type(&var.ty),
             literal
-        );
+        )
+        .unwrap();
     }
 
     pub fn print_function(&mut self, name: &str, func_val_id: ValueId) {
@@ -54,48 +62,64 @@ impl<'a> Printer<'a> {
             return;
         }
 
-        println!("define {} @{}(", self.format_type(&func.ret_ty), name);
+        writeln!(
+            self.out,
+            "define {} @{}(",
+            self.format_type(&func.ret_ty),
+            name
+        )
+        .unwrap();
         for (i, arg_value_id) in func.params.iter().enumerate() {
             let arg = FunctionValue::resolve_param(*arg_value_id, self.module);
             if i != 0 {
-                println!(", ");
+                writeln!(self.out, ", ").unwrap();
             }
-            print!(
+            write!(
+                self.out,
                 "{} {}",
                 self.format_type(&arg.ty),
                 self.resolve_name(arg_value_id)
-            );
+            )
+            .unwrap();
         }
-        println!(") {{");
+        writeln!(self.out, ") {{").unwrap();
         for (bb_name, bb_val_id) in &func.bbs.bbs {
             self.print_block(bb_name, *bb_val_id);
         }
-        println!("}}");
+        writeln!(self.out, "}}").unwrap();
     }
 
     pub fn print_external_function(&mut self, name: &str, func_val_id: ValueId) {
         let func = self.module.get_func(func_val_id);
-        print!("declare {} @{}", self.format_type(&func.ret_ty), name);
-        print!("(");
+        write!(
+            self.out,
+            "declare {} @{}",
+            self.format_type(&func.ret_ty),
+            name
+        )
+        .unwrap();
+        write!(self.out, "(").unwrap();
         for (i, arg_value_id) in func.params.iter().enumerate() {
             let arg = FunctionValue::resolve_param(*arg_value_id, self.module);
             if i != 0 {
-                print!(", ");
+                write!(self.out, ", ").unwrap();
             }
-            print!("{}", self.format_type(&arg.ty));
+            write!(self.out, "{}", self.format_type(&arg.ty)).unwrap();
         }
-        println!(")");
+        writeln!(self.out, ")").unwrap();
     }
 
     pub fn print_block(&mut self, name: &str, bb_val_id: ValueId) {
         let bb = self.module.get_bb(bb_val_id);
         // println!("{}:", name);
         if name != "entry" {
-            println!(
+            writeln!(
+                self.out,
                 "{}:                                        ; val_ids=[{}]",
                 self.resolve_name(&bb_val_id),
                 bb_val_id.index()
-            );
+            )
+            .unwrap();
         }
         for inst_val_id in &bb.insts {
             let inst = BasicBlockValue::resolve_inst(*inst_val_id, self.module);
@@ -115,7 +139,7 @@ impl<'a> Printer<'a> {
             InstValue::Return(inst) => self.print_ret_inst(val_id, inst),
             InstValue::Call(inst) => self.print_call_inst(val_id, inst),
             InstValue::Phi(_) => self.print_phi_inst(val_id, inst_val),
-            InstValue::Cast(_) => todo!(),
+            InstValue::Cast(inst) => self.print_cast_inst(val_id, inst),
         }
     }
 
@@ -127,32 +151,41 @@ impl<'a> Printer<'a> {
             .map(|id| Value::resolve(*id, self.module))
             .collect();
 
-        print!(
+        write!(
+            self.out,
             "{} = getelementptr {}, ptr {}",
             self.resolve_name(val_id),
             self.format_type(&inst.ty),
             self.format_value(&inst.ptr, ptr_val)
-        );
+        )
+        .unwrap();
         for i in 0..index_vals.len() {
-            print!(
+            write!(
+                self.out,
                 ", i32 {}",
                 self.format_value(&inst.indices[i], index_vals[i])
-            );
+            )
+            .unwrap();
         }
-        println!();
+        writeln!(self.out).unwrap();
     }
 
     pub fn print_branch_inst(&mut self, _val_id: &ValueId, inst: &BranchInst) {
         let cond_val = Value::resolve(inst.cond, self.module);
-        print!("br i1 {}, ", self.format_value(&inst.cond, cond_val));
-        print!("label %{}, ", self.resolve_name(&inst.then_bb));
-        print!("label %{}", self.resolve_name(&inst.else_bb));
-        println!();
+        write!(
+            self.out,
+            "br i1 {}, ",
+            self.format_value(&inst.cond, cond_val)
+        )
+        .unwrap();
+        write!(self.out, "label %{}, ", self.resolve_name(&inst.then_bb)).unwrap();
+        write!(self.out, "label %{}", self.resolve_name(&inst.else_bb)).unwrap();
+        writeln!(self.out).unwrap();
     }
 
     pub fn print_jump_inst(&mut self, _val_id: &ValueId, inst: &JumpInst) {
-        print!("br label %{}", self.resolve_name(&inst.bb));
-        println!();
+        write!(self.out, "br label %{}", self.resolve_name(&inst.bb)).unwrap();
+        writeln!(self.out).unwrap();
     }
 
     pub fn print_call_inst(&mut self, val_id: &ValueId, inst: &CallInst) {
@@ -161,24 +194,31 @@ impl<'a> Printer<'a> {
             Value::Function(func) => func.name.clone(),
             _ => panic!("{} is not a function", self.format_value(&inst.func, func)),
         };
-        print!(
-            "{} = call {} @{}(",
+        // LLVM 的 musttail 要求函数签名一致，这里只作为提示输出
+        let tail = if inst.must_tail { "tail " } else { "" };
+        write!(
+            self.out,
+            "{} = {}call {} @{}(",
             self.resolve_name(val_id),
+            tail,
             self.format_type(&Value::ty(func)),
             func_name
-        );
+        )
+        .unwrap();
         for (i, arg) in inst.args.iter().enumerate() {
             let arg_val = Value::resolve(*arg, self.module);
             if i != 0 {
-                print!(", ");
+                write!(self.out, ", ").unwrap();
             }
-            print!(
+            write!(
+                self.out,
                 "{} {}",
                 self.format_type(&arg_val.ty()),
                 self.format_value(arg, arg_val)
-            );
+            )
+            .unwrap();
         }
-        println!(")");
+        writeln!(self.out, ")").unwrap();
     }
 
     pub fn print_phi_inst(&mut self, val_id: &ValueId, inst_val: &InstValue) {
@@ -186,48 +226,54 @@ impl<'a> Printer<'a> {
             InstValue::Phi(inst) => inst,
             _ => panic!("[{}] is not a phi inst", val_id.index()),
         };
-        print!(
+        write!(
+            self.out,
             "{} = phi {}",
             self.resolve_name(val_id),
             self.format_type(&inst.ty)
-        );
+        )
+        .unwrap();
         for (i, (bb, val)) in inst.incomings.iter().enumerate() {
             let bb_val = Value::resolve(*bb, self.module);
             let val_val = Value::resolve(*val, self.module);
             if i != 0 {
-                print!(", ");
+                write!(self.out, ", ").unwrap();
             }
-            print!(
+            write!(
+                self.out,
                 "[{}, %{}]",
                 self.format_value(bb, bb_val),
                 self.format_value(val, val_val)
-            );
+            )
+            .unwrap();
         }
-        println!();
+        writeln!(self.out).unwrap();
     }
 
     pub fn print_store_inst(&mut self, inst: &StoreInst) {
         let src_val = Value::resolve(inst.value, self.module);
         let dst_val = Value::resolve(inst.ptr, self.module);
         let ty = Value::ty(src_val);
-        print!(
+        write!(
+            self.out,
             "store {} {}, ptr {}",
             self.format_type(&ty),
             self.format_value(&inst.value, src_val),
             self.format_value(&inst.ptr, dst_val)
-        );
-        println!();
+        )
+        .unwrap();
+        writeln!(self.out).unwrap();
     }
     pub fn print_load_inst(&mut self, val_id: &ValueId, inst: &LoadInst) {
-        let src_val = Value::resolve(inst.ptr, self.module);
-        let ty = Value::ty(src_val);
-        print!(
+        write!(
+            self.out,
             "{} = load {}, ptr {}",
             self.resolve_name(val_id),
-            self.format_type(&ty),
+            self.format_type(&inst.ty),
             self.resolve_name(&inst.ptr)
-        );
-        println!();
+        )
+        .unwrap();
+        writeln!(self.out).unwrap();
     }
     pub fn print_infix_op_inst(&mut self, val_id: &ValueId, inst: &InstValue) {
         let inst = match inst {
@@ -237,62 +283,126 @@ impl<'a> Printer<'a> {
         let lhs_val = Value::resolve(inst.lhs, self.module);
         let rhs_val = Value::resolve(inst.rhs, self.module);
         let ty = Value::ty(lhs_val);
-        print!(
+        write!(
+            self.out,
             "{} = {} {} {}, {}                  ; val_ids: {:?}",
             self.resolve_name(val_id),
-            self.format_infix_op(&inst.op),
+            self.format_infix_op(&inst.op, ty.is_float()),
             self.format_type(&ty),
             self.format_value(&inst.lhs, lhs_val),
             self.format_value(&inst.rhs, rhs_val),
             vec![val_id.index(), inst.lhs.index(), inst.rhs.index()]
-        );
-        println!();
+        )
+        .unwrap();
+        writeln!(self.out).unwrap();
     }
 
-    pub fn format_infix_op(&mut self, op: &InfixOp) -> String {
+    pub fn format_infix_op(&self, op: &InfixOp, is_float: bool) -> String {
+        if is_float {
+            return self.format_float_infix_op(op);
+        }
         match op {
             InfixOp::Add => "add".to_string(),
             InfixOp::Sub => "sub".to_string(),
             InfixOp::Mul => "mul".to_string(),
             InfixOp::Div => "sdiv".to_string(),
-            InfixOp::Rem => "srem".to_string(),
+            InfixOp::Mod | InfixOp::Rem => "srem".to_string(),
             InfixOp::Eq => "icmp eq".to_string(),
             InfixOp::Ne => "icmp ne".to_string(),
             InfixOp::Lt => "icmp slt".to_string(),
             InfixOp::Le => "icmp sle".to_string(),
             InfixOp::Gt => "icmp sgt".to_string(),
             InfixOp::Ge => "icmp sge".to_string(),
-            InfixOp::BitAnd => todo!(),
-            InfixOp::BitOr => todo!(),
-            InfixOp::BitXor => todo!(),
-            InfixOp::BitShl => todo!(),
-            InfixOp::BitShr => todo!(),
-            InfixOp::LogicAnd => todo!(),
-            InfixOp::LogicOr => todo!(),
-            InfixOp::Mod => todo!(),
+            InfixOp::BitAnd => "and".to_string(),
+            InfixOp::BitOr => "or".to_string(),
+            InfixOp::BitXor => "xor".to_string(),
+            InfixOp::BitShl => "shl".to_string(),
+            InfixOp::BitShr => "ashr".to_string(),
+            InfixOp::LogicAnd | InfixOp::LogicOr => {
+                unreachable!("logical op should be built as branches and a phi")
+            }
             InfixOp::Assign => unreachable!("assign should be built as a StoreInst"),
         }
     }
 
-    pub fn print_alloca_inst(&self, val_id: &ValueId, inst: &AllocaInst) {
-        print!(
+    pub fn format_float_infix_op(&self, op: &InfixOp) -> String {
+        match op {
+            InfixOp::Add => "fadd".to_string(),
+            InfixOp::Sub => "fsub".to_string(),
+            InfixOp::Mul => "fmul".to_string(),
+            InfixOp::Div => "fdiv".to_string(),
+            InfixOp::Mod | InfixOp::Rem => "frem".to_string(),
+            InfixOp::Eq => "fcmp oeq".to_string(),
+            InfixOp::Ne => "fcmp une".to_string(),
+            InfixOp::Lt => "fcmp olt".to_string(),
+            InfixOp::Le => "fcmp ole".to_string(),
+            InfixOp::Gt => "fcmp ogt".to_string(),
+            InfixOp::Ge => "fcmp oge".to_string(),
+            _ => unreachable!("{:?} on float", op),
+        }
+    }
+
+    pub fn print_cast_inst(&mut self, val_id: &ValueId, inst: &CastInst) {
+        let val = Value::resolve(inst.value, self.module);
+        write!(
+            self.out,
+            "{} = {} {} {} to {}",
+            self.resolve_name(val_id),
+            self.format_cast_op(&inst.op),
+            self.format_type(&Value::ty(val)),
+            self.format_value(&inst.value, val),
+            self.format_type(&inst.new_ty)
+        )
+        .unwrap();
+        writeln!(self.out).unwrap();
+    }
+
+    pub fn format_cast_op(&self, op: &CastOp) -> String {
+        match op {
+            CastOp::Trunc => "trunc".to_string(),
+            CastOp::ZExt => "zext".to_string(),
+            CastOp::SExt => "sext".to_string(),
+            CastOp::FPTrunc => "fptrunc".to_string(),
+            CastOp::FPExt => "fpext".to_string(),
+            CastOp::FPToUI => "fptoui".to_string(),
+            CastOp::FPToSI => "fptosi".to_string(),
+            CastOp::UIToFP => "uitofp".to_string(),
+            CastOp::SIToFP => "sitofp".to_string(),
+            CastOp::PtrToInt => "ptrtoint".to_string(),
+            CastOp::IntToPtr => "inttoptr".to_string(),
+            CastOp::BitCast => "bitcast".to_string(),
+        }
+    }
+
+    pub fn print_alloca_inst(&mut self, val_id: &ValueId, inst: &AllocaInst) {
+        write!(
+            self.out,
             "{} = alloca {}                 ; val_ids: {:?}",
             self.resolve_name(val_id),
             self.format_type(&inst.ty),
             vec![val_id.index()]
-        );
-        println!();
+        )
+        .unwrap();
+        writeln!(self.out).unwrap();
     }
 
     pub fn print_ret_inst(&mut self, _val_id: &ValueId, inst: &ReturnInst) {
         if let Some(val_id) = &inst.value {
             let val = Value::resolve(*val_id, self.module);
-            print!("ret i32 {}", self.format_value(val_id, val));
+            write!(
+                self.out,
+                "ret {} {}",
+                self.format_type(&Value::ty(val)),
+                self.format_value(val_id, val)
+            )
+            .unwrap();
+        } else {
+            write!(self.out, "ret void").unwrap();
         }
-        println!();
+        writeln!(self.out).unwrap();
     }
 
-    pub fn format_value(&mut self, val_id: &ValueId, val: &Value) -> String {
+    pub fn format_value(&self, val_id: &ValueId, val: &Value) -> String {
         match val {
             Value::GlobalVariable(_) => self.resolve_name(val_id),
             Value::Function(_) => todo!(),
@@ -321,7 +431,8 @@ impl<'a> Printer<'a> {
             ConstValue::Int(i) => {
                 format!("{}", i.value)
             }
-            ConstValue::Float(_) => todo!(),
+            // LLVM 要求 float 常量写成可精确表示的 double 十六进制
+            ConstValue::Float(f) => format!("0x{:016X}", (f.value as f32 as f64).to_bits()),
             ConstValue::Array(ca) => {
                 // debug!("const_array: {:?}", ca);
                 if let Type::Array(ArrayType::Constant(const_at)) = &ca.ty {
@@ -347,7 +458,13 @@ impl<'a> Printer<'a> {
                                 );
                             }
                             None => {
-                                ret += &format!("{} 0", self.format_type(&ty.clone().unwrap()));
+                                let ty = ty.clone().unwrap();
+                                let zero = if ty == BuiltinType::Float.into() {
+                                    "0.0"
+                                } else {
+                                    "0"
+                                };
+                                ret += &format!("{} {}", self.format_type(&ty), zero);
                             }
                         }
                         is_first = false;
@@ -364,7 +481,7 @@ impl<'a> Printer<'a> {
     pub fn format_type(&self, ty: &Type) -> String {
         match ty {
             Type::Builtin(t) => self.format_builtin_type(t),
-            Type::Pointer(_) => todo!(),
+            Type::Pointer(_) => "ptr".to_string(),
             Type::Array(t) => self.format_array_type(t),
             Type::Record(_) => todo!(),
             Type::Function(_) => todo!(),
@@ -403,3 +520,47 @@ impl<'a> Printer<'a> {
         }
     }
 }
+
+#[test]
+fn test_print_implicit_conversions() {
+    use crate::{ir_builder, ir_pass::inst_namer, scope::SymbolTable, sema::ToSemaTrait};
+
+    let mut ast = crate::parser::parse(
+        "float g(float x) { return x; }
+        int main() { int a = 3; float b = a; a = b * 2; return g(a) < a; }",
+    )
+    .unwrap();
+    let mut syms = SymbolTable::new();
+    ast.to_sema(&mut syms);
+    let mut module = ir_builder::build(&mut ast, syms).unwrap();
+    inst_namer::run(&mut module);
+    let ir = print(&mut module);
+
+    assert!(ir.contains("ret float "));
+    for inst in [
+        "sitofp i32 ",
+        "fmul float ",
+        "fptosi float ",
+        "fcmp olt float ",
+    ] {
+        assert!(ir.contains(inst), "missing {}", inst);
+    }
+    // 常量 2 直接折叠为浮点常量
+    assert!(!ir.contains("sitofp i32 2 "));
+}
+
+#[test]
+fn test_float_literal_operands() {
+    use crate::ir_pass::{build_ir, inst_namer};
+
+    let mut module =
+        build_ir("float f(int n) { float h = n + 0.5; float k = 0.5 * n; return h + k; }");
+    inst_namer::run(&mut module);
+    let ir = print(&mut module);
+
+    // 浮点字面量与 int 运算时 int 一侧先转换为 float
+    assert_eq!(ir.matches("sitofp i32 %0 to float").count(), 2);
+    assert!(ir.contains("fadd float %2, 0x3FE0000000000000"));
+    assert!(ir.contains("fmul float 0x3FE0000000000000, %4"));
+    assert!(!ir.contains("double"));
+}
@@ -5,6 +5,7 @@ pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub mod arm;
 pub mod ast;
 pub mod cli;
+pub mod diagnostic;
 pub mod driver;
 pub mod infer_eval;
 pub mod ir;
@@ -14,6 +15,7 @@ pub mod ir_printer;
 pub mod mc;
 pub mod mc_builder;
 pub mod mc_inst;
+pub mod mc_pass;
 pub mod arm_printer;
 pub mod parser;
 pub mod scope;
@@ -8,15 +8,14 @@ fn main() -> ExitCode {
     env_logger::init();
     let args = Args::parse();
     debug!("args: {:?}", args);
-    driver::drive(args);
-    ExitCode::SUCCESS
+    driver::drive(args)
 }
 
 #[test]
 fn test_all() {
-    env_logger::builder()
+    let _ = env_logger::builder()
         .filter_level(log::LevelFilter::Trace)
-        .init();
+        .try_init();
 
     use std::fs;
 
@@ -54,9 +53,9 @@ fn test_all() {
 
 #[test]
 fn test_single() {
-    env_logger::builder()
+    let _ = env_logger::builder()
         .filter_level(log::LevelFilter::Trace)
-        .init();
+        .try_init();
 
     let dir = "./tests/functional/";
     let file_stem = "05_arr_defn4_glob";
@@ -12,7 +12,7 @@ pub type RegConstraintMap = std::collections::HashMap<VirtReg, AsmOperand>;
 pub struct AsmModule {
     values: id_arena::Arena<AsmValue>,
     pub globals: Vec<AsmValueId>,
-    pub bss_globals: Vec<AsmValueId>, // uninitialized globals
+    pub bss_globals: Vec<AsmValueId>, // zero-initialized globals
     pub funcs: Vec<AsmValueId>,
 
     cur_func: Option<AsmValueId>,
@@ -143,7 +143,7 @@ impl AsmModule {
             let inst_id = self.values.alloc(AsmValue::Inst(inst));
             ret.push(inst_id);
         } else if let Imm::Label(_) | Imm::Int(_) = imm {
-            if imm.highest_one_bit() < 65535 {
+            if imm.highest_one_bit() < 16 {
                 // ret.push(MovInst::new(MovType::Movw, reg.clone(), imm.clone().into()).into());
                 let inst: AsmInst =
                     MovInst::new(MovType::Movw, reg, imm.clone().into(), None).into();
@@ -202,9 +202,7 @@ impl AsmModule {
         }
         match op2.clone() {
             AsmOperand::Imm(imm) => {
-                if !matches!(bin_inst.op, mc_inst::BinaryOp::Add | mc_inst::BinaryOp::Sub)
-                    || imm.highest_one_bit() >= 255
-                {
+                if !bin_inst.is_imm_fit(&imm) {
                     assert!(!ip_used);
                     // ip_used = true;
                     let tmp = AsmOperand::IntReg(IntReg::new(RegType::Ip));
@@ -226,6 +224,9 @@ pub struct AsmFunction {
     pub entry: Option<AsmValueId>,
     pub bbs: Vec<AsmValueId>,
     pub stack_state: StackState,
+    // 寄存器分配后用到的 callee-saved 寄存器，升序
+    pub callee_saved_regs: Vec<RegType>,
+    pub callee_saved_vfp_regs: Vec<i64>,
 }
 
 pub struct AsmGlobalVariable {
@@ -233,6 +234,8 @@ pub struct AsmGlobalVariable {
     // 用于填充导出的链接器符号的大小，和bss段时占用空间的大小。以字节为单位
     pub size: usize,
     pub imm: LabelImm,
+    // 按字展开的初始值，为空表示全零，放在 bss 段
+    pub init: Vec<u32>,
 }
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum AsmTypeTag {
@@ -269,6 +272,8 @@ pub struct StackState {
     pub spill_size: i64,
     pub local_size: i64,
     max_arg_size: i64,
+    // 入口处 push/vpush 保存的寄存器所占空间，栈帧展开时确定
+    pub saved_size: i64,
 }
 
 impl StackState {
@@ -295,10 +300,11 @@ impl StackState {
         }
     }
 
+    /// push 之后 sp 还需下移的大小
     pub fn total_stack_size(&self) -> i64 {
-        let ret = self.local_size + self.spill_size + self.max_arg_size;
-        // Round up to the nearest multiple of 8
-        (ret + 7) / 8 * 8
+        let ret = self.saved_size + self.local_size + self.spill_size + self.max_arg_size;
+        // AAPCS: 调用时 sp 需 8 字节对齐，连同保存的寄存器一起向上取整
+        (ret + 7) / 8 * 8 - self.saved_size
     }
 }
 
@@ -515,6 +521,17 @@ pub struct StackOperand {
     pub offset: i64,
 }
 
+impl StackOperand {
+    /// 寻址使用的基址寄存器和有符号偏移
+    pub fn base_and_offset(&self) -> (RegType, i64) {
+        match self.ty {
+            StackOperandType::Spill | StackOperandType::Local => (RegType::Fp, -self.offset),
+            StackOperandType::CallParam => (RegType::Sp, self.offset),
+            StackOperandType::SelfArg => (RegType::Fp, self.offset),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum StackOperandType {
     Local,
@@ -908,7 +925,7 @@ use std::{cmp, default, hash};
 
 use crate::{
     ast::Type,
-    mc_inst::{self, AsmInst, AsmInstTrait, MovInst, MovType, VMovInst, VMovType},
+    mc_inst::{AsmInst, AsmInstTrait, MovInst, MovType, VMovInst, VMovType},
 };
 
 #[derive(Debug, PartialEq, Eq, Clone)]
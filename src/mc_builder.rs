@@ -6,13 +6,14 @@ use std::{
 use log::debug;
 
 use crate::{
-    ast::{ArrayType, BuiltinType, Type},
+    ast::{ArrayType, BuiltinType, InfixOp, Type},
     ir::*,
     mc::*,
     mc_inst::{
         self, AsmInst, AsmInstTrait, BinOpInst, BinaryOp, BrInst, CMPInst, Cond, ConstraintsTrait,
-        FBinOpInst, FBinaryOp, FCMPInst, LDRInst, MovInst, MovType, PrologueInst, RetInst, STRInst,
-        StackOpInstTrait, VCVTInst, VCVTType, VLDRInst, VMovInst, VMovType, VSTRInst,
+        FBinOpInst, FBinaryOp, FCMPInst, LDRInst, MLSInst, MovInst, MovType, Operand2,
+        PrologueInst, RetInst, STRInst, StackOpInstTrait, VCVTInst, VCVTType, VLDRInst, VMRSInst,
+        VMovInst, VMovType, VSTRInst,
     },
 };
 
@@ -47,6 +48,30 @@ impl From<GlobalVariableValue> for AsmGlobalVariable {
             base: (value.ty.base_type().clone()).into(),
             size: value.ty.size(),
             imm: LabelImm::new(value.name),
+            init: vec![],
+        }
+    }
+}
+
+/// 将常量初始值按字展开，数组中省略的元素补零
+fn flatten_const(cv: &ConstValue, ty: &Type, out: &mut Vec<u32>) {
+    let is_float = *ty.base_type() == BuiltinType::Float.into();
+    match cv {
+        ConstValue::Int(i) if is_float => out.push((i.value as f32).to_bits()),
+        ConstValue::Int(i) => out.push(i.value as u32),
+        ConstValue::Float(f) if is_float => out.push((f.value as f32).to_bits()),
+        ConstValue::Float(f) => out.push(f.value as i32 as u32),
+        ConstValue::Array(ca) => {
+            let at = match &ca.ty {
+                Type::Array(ArrayType::Constant(at)) => at,
+                _ => panic!("expect a constant array type"),
+            };
+            for i in 0..at.size {
+                match ca.values.get(i) {
+                    Some(value) => flatten_const(value, &at.element_type, out),
+                    None => out.extend(std::iter::repeat_n(0, at.element_type.size() / 4)),
+                }
+            }
         }
     }
 }
@@ -58,6 +83,8 @@ impl From<FunctionValue> for AsmFunction {
             entry: None,
             bbs: vec![],
             stack_state: StackState::default(),
+            callee_saved_regs: vec![],
+            callee_saved_vfp_regs: vec![],
         }
     }
 }
@@ -124,9 +151,18 @@ impl McBuilder<'_> {
     fn build_global_variables(&mut self) {
         for (_name, id) in &self.ir_module.global_variables.clone() {
             let global = self.ir_module.get_global_var(*id);
-            let val = global.clone().into();
+            let mut val: AsmGlobalVariable = global.clone().into();
+            if let Some(init_id) = global.initializer {
+                if let Value::Const(cv) = self.ir_module.get_value(init_id) {
+                    flatten_const(cv, &global.ty, &mut val.init);
+                }
+            }
+            let is_zero = val.init.iter().all(|word| *word == 0);
+            if is_zero {
+                val.init.clear();
+            }
             let val_id = self.module.alloc_value(AsmValue::GlobalVariable(val));
-            if global.initializer.is_some() {
+            if is_zero {
                 self.module.bss_globals.push(val_id);
             } else {
                 self.module.globals.push(val_id);
@@ -158,7 +194,8 @@ impl McBuilder<'_> {
             let mut asm_block = AsmBlock {
                 prev: None,
                 next: None,
-                name: _name.clone(),
+                // 块标号在整个汇编文件内需唯一
+                name: format!(".LBB_{}_{}", ssa_func.name, _name),
                 preds: vec![],
                 succs: vec![],
                 insts: vec![],
@@ -288,14 +325,12 @@ impl McBuilder<'_> {
         } else {
             let size = preds.len();
             for pred_id in preds {
-                let pred = self.module.get_bb(pred_id);
-                if pred.succs.len() != 1 {
-                    panic!(
-                        "Unsplit critical edge: {} to {}.",
-                        pred_id.index(),
-                        asm_bb_id.index()
-                    );
-                }
+                // 关键边上的拷贝不能放在前驱中，需要插入新块
+                let move_bb_id = if self.module.get_bb(pred_id).succs.len() != 1 {
+                    self.split_critical_edge(asm_func_id, pred_id, asm_bb_id)
+                } else {
+                    pred_id
+                };
 
                 let mut parallel_movs = Vec::new();
                 for phi_id in phi_ids.clone() {
@@ -319,11 +354,68 @@ impl McBuilder<'_> {
                     }
                     assert!(found);
                 }
-                self.make_parallel_movs(pred_id, &parallel_movs);
+                self.make_parallel_movs(move_bb_id, &parallel_movs);
             }
         }
     }
 
+    /// 在 pred 到 bb 的边上插入只含跳转的新块，放在函数末尾
+    fn split_critical_edge(
+        &mut self,
+        asm_func_id: AsmValueId,
+        pred_id: AsmValueId,
+        bb_id: AsmValueId,
+    ) -> AsmValueId {
+        let last_bb_id = *self.module.get_func(asm_func_id).bbs.last().unwrap();
+        let edge_bb = AsmBlock {
+            prev: Some(last_bb_id),
+            next: None,
+            name: format!("{}_{}", self.get_label(bb_id), pred_id.index()),
+            preds: vec![pred_id],
+            succs: vec![bb_id],
+            insts: vec![],
+        };
+        let edge_bb_id = self.module.alloc_value(AsmValue::Block(edge_bb));
+        self.module.get_bb_mut(last_bb_id).next = Some(edge_bb_id);
+        self.module.get_func_mut(asm_func_id).bbs.push(edge_bb_id);
+
+        let target_label = self.get_label(bb_id);
+        let jmp = BrInst::new_with_label(Cond::AL, bb_id, target_label);
+        let jmp_id = self.module.alloc_value(AsmValue::Inst(AsmInst::Br(jmp)));
+        self.module.get_bb_mut(edge_bb_id).insts.push(jmp_id);
+
+        // 前驱中跳往 bb 的分支改为跳往新块，原本顺序落入 bb 的补一条跳转
+        let edge_label = self.get_label(edge_bb_id);
+        let mut falls_through = self.module.get_bb(pred_id).next == Some(bb_id);
+        for inst_id in self.module.get_bb(pred_id).insts.clone() {
+            if let AsmInst::Br(br) = self.module.get_inst(inst_id) {
+                if br.target != bb_id {
+                    continue;
+                }
+                if br.cond == Cond::AL {
+                    falls_through = false;
+                }
+                let br = BrInst::new_with_label(br.cond.clone(), edge_bb_id, edge_label.clone());
+                self.module.set_inst(inst_id, br.into());
+            }
+        }
+        if falls_through {
+            let jmp = BrInst::new_with_label(Cond::AL, edge_bb_id, edge_label);
+            let jmp_id = self.module.alloc_value(AsmValue::Inst(AsmInst::Br(jmp)));
+            self.module.get_bb_mut(pred_id).insts.push(jmp_id);
+        }
+
+        let pred = self.module.get_bb_mut(pred_id);
+        for succ in pred.succs.iter_mut().filter(|succ| **succ == bb_id) {
+            *succ = edge_bb_id;
+        }
+        let bb = self.module.get_bb_mut(bb_id);
+        for pred in bb.preds.iter_mut().filter(|pred| **pred == pred_id) {
+            *pred = edge_bb_id;
+        }
+        edge_bb_id
+    }
+
     fn make_parallel_movs(
         &mut self,
         asm_bb_id: AsmValueId,
@@ -463,7 +555,8 @@ impl McBuilder<'_> {
                 let then_bb = *self.bb_map.get(&br_inst.then_bb).unwrap();
                 let else_bb = *self.bb_map.get(&br_inst.else_bb).unwrap();
 
-                if then_bb == next_bb.unwrap() {
+                // 最后一个基本块没有可以直接落入的后继
+                if next_bb == Some(then_bb) {
                     //  ==0 跳转到false
                     // abb.insts.push(
                     //     BrInst::builder(fb)
@@ -479,7 +572,7 @@ impl McBuilder<'_> {
 
                     let abb = self.module.get_bb_mut(asm_bb_id);
                     abb.insts.push(br_inst_id);
-                } else if else_bb == next_bb.unwrap() {
+                } else if next_bb == Some(else_bb) {
                     // != 0跳转到true
                     // abb.insts.push(
                     //     BrInst::builder(tb)
@@ -507,11 +600,20 @@ impl McBuilder<'_> {
                     let br_inst_id = self
                         .module
                         .alloc_value(AsmValue::Inst(AsmInst::Br(br_inst)));
+                    let target_label = self.get_label(else_bb);
+                    let jmp_inst = BrInst::new_with_label(Cond::AL, else_bb, target_label);
+                    let jmp_inst_id = self
+                        .module
+                        .alloc_value(AsmValue::Inst(AsmInst::Br(jmp_inst)));
                     let abb = self.module.get_bb_mut(asm_bb_id);
                     abb.insts.push(br_inst_id);
+                    abb.insts.push(jmp_inst_id);
                 }
                 let mut abb = self.module.get_bb_mut(asm_bb_id);
                 abb.succs = vec![then_bb, else_bb];
+                // 前驱后继维护
+                self.module.get_bb_mut(then_bb).preds.push(asm_bb_id);
+                self.module.get_bb_mut(else_bb).preds.push(asm_bb_id);
             }
             _ => panic!("Unknown Terminator Inst."),
         }
@@ -544,6 +646,9 @@ impl McBuilder<'_> {
                 abb.insts.append(&mut insts);
             }
 
+            // 64 位运算在使用它的 trunc 处按 32 位重新计算
+            InstValue::InfixOp(infix_op) if infix_op.ty == BuiltinType::Int64.into() => {}
+
             InstValue::InfixOp(infix_op) => {
                 let mut op1 = self.convert_value(infix_op.lhs, asm_func_id, asm_bb_id);
                 let mut op2 = self.convert_value(infix_op.rhs, asm_func_id, asm_bb_id);
@@ -559,33 +664,40 @@ impl McBuilder<'_> {
 
                 if !infix_op.op.is_boolean() {
                     assert_eq!(op1.is_float(), to.is_float());
-                    let bin_id = if is_float {
-                        let bin = FBinOpInst::new(
-                            FBinaryOp::from(BinaryOp::from(infix_op.op.clone())),
-                            to,
-                            op1,
-                            op2,
-                        );
-                        self.module
-                            .alloc_value(AsmValue::Inst(AsmInst::FBinOp(bin)))
+                    let op = BinaryOp::from(infix_op.op.clone());
+                    let mut insts = if is_float {
+                        let bin = FBinOpInst::new(FBinaryOp::from(op), to, op1, op2);
+                        let bin_id = self
+                            .module
+                            .alloc_value(AsmValue::Inst(AsmInst::FBinOp(bin)));
+                        // VFP 运算没有立即数形式
+                        self.expand_inst_imm(bin_id)
+                    } else if op == BinaryOp::Mod {
+                        self.build_mod(to, op1, op2)
                     } else {
-                        let bin = BinOpInst::new(infix_op.op.clone().into(), to, op1, op2);
-                        self.module.alloc_value(AsmValue::Inst(AsmInst::BinOp(bin)))
+                        let bin = BinOpInst::new(op, to, op1, op2);
+                        let bin_id = self.module.alloc_value(AsmValue::Inst(AsmInst::BinOp(bin)));
+                        self.expand_bin_op(bin_id)
                     };
-
-                    let mut insts = self.expand_bin_op(bin_id);
                     let abb = self.module.get_bb_mut(asm_bb_id);
                     abb.insts.append(&mut insts);
                 } else {
                     // Generate CMP + conditional MOV
-                    let cmp = if is_float {
+                    let mut insts = if is_float {
                         let cmp = FCMPInst::new(op1, op2);
-                        self.module.alloc_value(AsmValue::Inst(AsmInst::FCMP(cmp)))
+                        let cmp = self.module.alloc_value(AsmValue::Inst(AsmInst::FCMP(cmp)));
+                        let mut insts = self.expand_inst_imm(cmp);
+                        // 浮点比较结果需要搬到 APSR
+                        let vmrs = self
+                            .module
+                            .alloc_value(AsmValue::Inst(AsmInst::VMRS(VMRSInst {})));
+                        insts.push(vmrs);
+                        insts
                     } else {
                         let cmp = CMPInst::new(op1, op2);
-                        self.module.alloc_value(AsmValue::Inst(AsmInst::CMP(cmp)))
+                        let cmp = self.module.alloc_value(AsmValue::Inst(AsmInst::CMP(cmp)));
+                        self.expand_cmp_imm(cmp)
                     };
-                    let mut insts = self.expand_bin_op(cmp);
                     {
                         let abb = self.module.get_bb_mut(asm_bb_id);
                         abb.insts.append(&mut insts);
@@ -623,25 +735,29 @@ impl McBuilder<'_> {
                     let cc = self.get_cc(&ssa_func_id);
 
                     if call.must_tail {
-                        call_inst = mc_inst::CallInst::new(
-                            LabelImm::new(format!(".LBB_{}_tail_call", ssa_func.name.clone())),
+                        call_inst = mc_inst::CallInst::new_tail_call(
+                            LabelImm::new(ssa_func.name.clone()),
                             cc.clone(),
                         );
                     } else {
-                        call_inst = mc_inst::CallInst::new_tail_call(
+                        call_inst = mc_inst::CallInst::new(
                             LabelImm::new(ssa_func.name.clone()),
                             cc.clone(),
                         );
                     }
 
-                    for i in 0..ssa_func.params.len() {
+                    // 尾调用的栈上参数写入调用者传入参数的位置，先读出所有实参，避免覆盖还未读取的传入参数
+                    let ops = call
+                        .args
+                        .iter()
+                        .map(|arg| self.convert_value(*arg, asm_func_id, asm_bb_id))
+                        .collect::<Vec<_>>();
+                    for (i, op) in ops.into_iter().enumerate() {
                         let loc = if call.must_tail {
                             &cc.as_vfp_call_conv().self_args[i]
                         } else {
                             &cc.as_vfp_call_conv().call_params[i]
                         };
-
-                        let op = self.convert_value(call.args[i], asm_func_id, asm_bb_id);
                         self.process_call_arg(&mut call_inst, op, loc.clone(), asm_bb_id, false);
                     }
                 } else {
@@ -685,7 +801,9 @@ impl McBuilder<'_> {
                     }
                 }
 
-                if let ret = call_inst.cc.get_ret_reg() {
+                // 尾调用的结果由被调用者直接返回，也不使用调用者栈帧中传出参数的区域
+                if !call_inst.tail_call {
+                    let ret = call_inst.cc.get_ret_reg();
                     let ret_val = self.convert_value(inst_id, asm_func_id, asm_bb_id);
                     call_inst.get_defs_mut().push(ret_val.clone());
                     if let AsmOperand::IntReg(reg) = ret {
@@ -695,10 +813,10 @@ impl McBuilder<'_> {
                     } else {
                         unimplemented!();
                     }
+                    let func = self.module.get_func_mut(asm_func_id);
+                    func.stack_state
+                        .preserve_arg_size(call_inst.cc.get_stack_size());
                 }
-                let func = self.module.get_func_mut(asm_func_id);
-                func.stack_state
-                    .preserve_arg_size(call_inst.cc.get_stack_size());
                 let call_inst_id = self
                     .module
                     .alloc_value(AsmValue::Inst(AsmInst::Call(call_inst)));
@@ -719,7 +837,7 @@ impl McBuilder<'_> {
                         let op = self.convert_value(cast.value, asm_func_id, asm_bb_id);
                         assert!(op.is_float());
                         let mid = self.get_vreg(true);
-                        let to = self.convert_value(cast.value, asm_func_id, asm_bb_id);
+                        let to = self.convert_value(inst_id, asm_func_id, asm_bb_id);
                         assert!(!to.is_float());
 
                         let vcvt = VCVTInst::new(VCVTType::F2I, mid.into(), op);
@@ -739,7 +857,7 @@ impl McBuilder<'_> {
                         let op = self.convert_value(cast.value, asm_func_id, asm_bb_id);
                         assert!(!op.is_float());
                         let mid = self.get_vreg(true);
-                        let to = self.convert_value(cast.value, asm_func_id, asm_bb_id);
+                        let to = self.convert_value(inst_id, asm_func_id, asm_bb_id);
                         assert!(to.is_float());
 
                         let vmov = VMovInst::new(VMovType::A2S, mid.into(), op);
@@ -761,9 +879,33 @@ impl McBuilder<'_> {
                     }
                     CastOp::ZExt => {
                         // i1 -> i32 extension, no-op
-                        let tmp = self.convert_value(cast.value, asm_func_id, asm_bb_id);
-                        let vreg = tmp.as_virt_reg().unwrap();
-                        self.vreg_map.insert(inst_id, *vreg);
+                        match self.convert_value(cast.value, asm_func_id, asm_bb_id) {
+                            AsmOperand::Imm(imm) => {
+                                let to = self.convert_value(inst_id, asm_func_id, asm_bb_id);
+                                let mut insts = self.module.load_imm(to, &imm);
+                                let abb = self.module.get_bb_mut(asm_bb_id);
+                                abb.insts.append(&mut insts);
+                            }
+                            tmp => {
+                                let vreg = tmp.as_virt_reg().unwrap();
+                                self.vreg_map.insert(inst_id, *vreg);
+                            }
+                        }
+                    }
+                    CastOp::SExt if cast.new_ty == BuiltinType::Int64.into() => {}
+                    CastOp::Trunc => {
+                        let mut insts = Vec::new();
+                        let low = self.build_wide(cast.value, false, &mut insts);
+                        let to = self.convert_value(inst_id, asm_func_id, asm_bb_id);
+                        match low {
+                            AsmOperand::Imm(imm) => insts.extend(self.module.load_imm(to, &imm)),
+                            low => {
+                                let mov = MovInst::new(MovType::Reg, to, low, None);
+                                insts.push(self.module.alloc_value(AsmValue::Inst(mov.into())));
+                            }
+                        }
+                        let abb = self.module.get_bb_mut(asm_bb_id);
+                        abb.insts.append(&mut insts);
                     }
                     _ => unimplemented!("{:?}", cast),
                 }
@@ -987,6 +1129,20 @@ impl McBuilder<'_> {
             );
         }
 
+        // 全局变量且偏移为零时 current 仍是标号
+        if let AsmOperand::Imm(imm) = &current {
+            let tmp = self.get_vreg(false);
+            let mut insts = self.module.load_imm(tmp.into(), imm);
+            self.module.get_bb_mut(asm_bb_id).insts.append(&mut insts);
+            current = tmp.into();
+        }
+        // 排在定义之前的基本块中的使用已经分配了虚拟寄存器
+        if let Some(vreg) = self.vreg_map.get(&inst_id) {
+            let mov = MovInst::new(MovType::Reg, (*vreg).into(), current, None);
+            let mov_id = self.module.alloc_value(AsmValue::Inst(AsmInst::Mov(mov)));
+            self.module.get_bb_mut(asm_bb_id).insts.push(mov_id);
+            return;
+        }
         self.vreg_map
             .insert(inst_id, *current.as_virt_reg().unwrap());
     }
@@ -1058,8 +1214,9 @@ impl McBuilder<'_> {
         _asm_bb_id: AsmValueId,
         before_jump: bool,
     ) -> AsmOperand {
-        let _asm_func_id = self.module.cur_func_value_id();
-        let ssa_func_id = self.ir_module.cur_func_value_id();
+        let asm_func_id = self.module.cur_func_value_id();
+        // ir_module 的当前函数停留在构建 IR 时的最后一个函数
+        let ssa_func_id = self.func_map_rev[&asm_func_id];
         let asm_bb_id = self.module.cur_bb_value_id();
         let _ssa_bb_id = self.ir_module.cur_bb_value_id();
         let ssa_func = self.ir_module.get_func(ssa_func_id);
@@ -1087,10 +1244,11 @@ impl McBuilder<'_> {
             return AsmOperand::Imm(Imm::Label(asmgv.imm.clone()));
         }
 
-        let ret = VirtReg {
-            index: self.vreg_idx,
-            is_float: *v.ty().base_type() == crate::ast::BuiltinType::Float.into(),
-        };
+        // gep 的类型是元素类型，指针 phi 的类型是指针，值都是地址
+        let is_float = !v.ty().is_pointer(true)
+            && !matches!(v, Value::Instruction(InstValue::Gep(_)))
+            && *v.ty().base_type() == crate::ast::BuiltinType::Float.into();
+        let ret = self.get_vreg(is_float);
         self.vreg_map.insert(valud_id, ret);
 
         // 如果是参数且在内存中，则生成load指令
@@ -1261,6 +1419,86 @@ impl McBuilder<'_> {
             new_ops.push((*op).clone());
         }
     }
+    // trunc(ashr(mul(sext a, sext b), 32)) 即 SMMUL a, b，返回 a 和 b
+    // 64 位的常数操作数转为立即数时截断为低 32 位
+    /// 计算 64 位值的低 32 位，high 为真时计算高 32 位。能出现的形式由 verify 保证
+    fn build_wide(
+        &mut self,
+        value: ValueId,
+        high: bool,
+        insts: &mut Vec<AsmValueId>,
+    ) -> AsmOperand {
+        let asm_func_id = self.module.cur_func_value_id();
+        let asm_bb_id = self.module.cur_bb_value_id();
+        match self.ir_module.get_value(value).clone() {
+            Value::Const(ConstValue::Int(c)) => {
+                let bits = if high { c.value >> 32 } else { c.value };
+                AsmOperand::Imm(Imm::Int(IntImm::from(bits as i32)))
+            }
+            Value::Instruction(InstValue::Cast(cast)) if matches!(cast.op, CastOp::SExt) => {
+                let op = self.convert_value(cast.value, asm_func_id, asm_bb_id);
+                if !high {
+                    return op;
+                }
+                let sign = AsmOperand::Imm(Imm::Int(IntImm::from(31)));
+                self.build_wide_bin_op(BinaryOp::Shr, op, sign, insts)
+            }
+            // 两个 32 位数乘积的高位用 SMMUL 计算
+            Value::Instruction(InstValue::InfixOp(mul)) if high && mul.op == InfixOp::Mul => {
+                let op1 = self.build_wide(mul.lhs, false, insts);
+                let op2 = self.build_wide(mul.rhs, false, insts);
+                self.build_wide_bin_op(BinaryOp::MulHigh, op1, op2, insts)
+            }
+            Value::Instruction(InstValue::InfixOp(shr)) if shr.op == InfixOp::BitShr => {
+                let Value::Const(ConstValue::Int(c)) = self.ir_module.get_value(shr.rhs) else {
+                    unreachable!("64-bit shift by a variable amount");
+                };
+                let amount = c.value - 32;
+                let op = self.build_wide(shr.lhs, true, insts);
+                if amount == 0 {
+                    return op;
+                }
+                let amount = AsmOperand::Imm(Imm::Int(IntImm::from(amount as i32)));
+                self.build_wide_bin_op(BinaryOp::Shr, op, amount, insts)
+            }
+            // 加减乘和位运算结果的低位只取决于操作数的低位
+            Value::Instruction(InstValue::InfixOp(infix_op)) if !high => {
+                let op1 = self.build_wide(infix_op.lhs, false, insts);
+                let op2 = self.build_wide(infix_op.rhs, false, insts);
+                self.build_wide_bin_op(infix_op.op.into(), op1, op2, insts)
+            }
+            value => unreachable!("unsupported 64-bit value {:?}", value),
+        }
+    }
+
+    fn build_wide_bin_op(
+        &mut self,
+        op: BinaryOp,
+        op1: AsmOperand,
+        op2: AsmOperand,
+        insts: &mut Vec<AsmValueId>,
+    ) -> AsmOperand {
+        let to = AsmOperand::VirtReg(self.get_vreg(false));
+        let bin = BinOpInst::new(op, to.clone(), op1, op2);
+        let bin_id = self.module.alloc_value(AsmValue::Inst(AsmInst::BinOp(bin)));
+        insts.append(&mut self.expand_bin_op(bin_id));
+        to
+    }
+
+    // a % b = a - a / b * b，展开为 SDIV + MLS
+    fn build_mod(&mut self, to: AsmOperand, op1: AsmOperand, op2: AsmOperand) -> Vec<AsmValueId> {
+        let mut ret = Vec::new();
+        let mut ops = Vec::new();
+        self.expand_imm(&op1, &mut ops, &mut ret);
+        self.expand_imm(&op2, &mut ops, &mut ret);
+        let quot = AsmOperand::VirtReg(self.get_vreg(false));
+        let div = BinOpInst::new(BinaryOp::Div, quot.clone(), ops[0].clone(), ops[1].clone());
+        ret.push(self.module.alloc_value(AsmValue::Inst(div.into())));
+        let mls = MLSInst::new(to, quot, ops[1].clone(), ops[0].clone());
+        ret.push(self.module.alloc_value(AsmValue::Inst(mls.into())));
+        ret
+    }
+
     // Flexible Operand 2 can be Imm8m
     fn expand_operand2(
         &mut self,
@@ -1270,7 +1508,7 @@ impl McBuilder<'_> {
     ) {
         match op {
             AsmOperand::Imm(immop) => {
-                if immop.highest_one_bit() < 255 {
+                if Operand2::is_imm_fit(immop) {
                     new_ops.push(AsmOperand::Imm(immop.clone()));
                     return;
                 }
@@ -1301,9 +1539,7 @@ impl McBuilder<'_> {
         }
         match op2.clone() {
             AsmOperand::Imm(imm) => {
-                if !matches!(bin_inst.op, mc_inst::BinaryOp::Add | mc_inst::BinaryOp::Sub)
-                    || imm.highest_one_bit() >= 255
-                {
+                if !bin_inst.is_imm_fit(&imm) {
                     let tmp = AsmOperand::VirtReg(self.get_vreg(op2.is_float()));
                     ret.extend(self.module.load_imm(tmp.clone(), &imm));
                     op2 = tmp;
@@ -1483,3 +1719,109 @@ impl McBuilder<'_> {
     //     todo!()
     // }
 }
+
+#[test]
+fn test_compare_value() {
+    use crate::{arm_printer, mc_pass::reg_alloc};
+
+    let mut module = reg_alloc::build_asm(
+        "int lt(int a) { int r = a < 3; return r; }
+        int flt(float a, float b) { int r = a < b; return r; }",
+    );
+    reg_alloc::run(&mut module);
+    let asm = arm_printer::print(&mut module);
+
+    // 浮点比较的结果要从 FPSCR 搬到 APSR 后才能按条件 mov
+    assert!(asm.contains("CMP\t"));
+    assert!(asm.contains("VCMP.F32\t"));
+    assert!(asm.contains("vmrs\tAPSR_nzcv, FPSCR"));
+}
+
+#[test]
+fn test_wide_trunc() {
+    use crate::{
+        arm_printer,
+        ir_pass::{build_ir, verify},
+        mc_pass::reg_alloc,
+    };
+
+    let mut module = build_ir("int f(int a, int b) { return a * b; }");
+
+    // 把 a * b 改写成 trunc((sext a * sext b) + ((sext a * sext b) >> 40))
+    let func_id = module.functions["f"];
+    let (bb_id, pos, mul_id) = module
+        .get_func(func_id)
+        .bbs
+        .bbs
+        .values()
+        .find_map(|bb_id| {
+            let insts = &module.get_bb(*bb_id).insts;
+            insts.iter().enumerate().find_map(|(pos, inst_id)| {
+                matches!(module.get_value(*inst_id),
+                    Value::Instruction(InstValue::InfixOp(op)) if op.op == InfixOp::Mul)
+                .then_some((*bb_id, pos, *inst_id))
+            })
+        })
+        .unwrap();
+    let InstValue::InfixOp(mul) = module.get_inst(mul_id).clone() else {
+        unreachable!();
+    };
+    let int64: Type = BuiltinType::Int64.into();
+    let mut insts = Vec::new();
+    let mut insert = |module: &mut Module, inst: InstValue| {
+        let inst_id = module.insert_inst(bb_id, pos + insts.len(), inst);
+        insts.push(inst_id);
+        inst_id
+    };
+    let wide = |value: ValueId| {
+        InstValue::Cast(CastInst {
+            op: CastOp::SExt,
+            value,
+            new_ty: int64.clone(),
+        })
+    };
+    let binop = |op: InfixOp, lhs: ValueId, rhs: ValueId| {
+        InstValue::InfixOp(BinaryOperator {
+            ty: int64.clone(),
+            op,
+            lhs,
+            rhs,
+        })
+    };
+    let lhs = insert(&mut module, wide(mul.lhs));
+    let rhs = insert(&mut module, wide(mul.rhs));
+    let prod = insert(&mut module, binop(InfixOp::Mul, lhs, rhs));
+    let shift = module.alloc_value(
+        ConstInt {
+            ty: int64.clone(),
+            value: 40,
+        }
+        .into(),
+    );
+    let high = insert(&mut module, binop(InfixOp::BitShr, prod, shift));
+    let sum = insert(&mut module, binop(InfixOp::Add, prod, high));
+    let trunc = CastInst {
+        op: CastOp::Trunc,
+        value: sum,
+        new_ty: BuiltinType::Int.into(),
+    };
+    let trunc = insert(&mut module, InstValue::Cast(trunc));
+    module.replace_value(mul_id, trunc);
+    module.remove_inst(mul_id);
+    assert_eq!(verify::run(&module), Ok(()));
+
+    let mut asm_module = build(&mut module);
+    reg_alloc::run(&mut asm_module);
+    let asm = arm_printer::print(&mut asm_module);
+    // 右移 40 位取乘积高位后再右移 8 位
+    assert!(asm.contains("SMMUL\t"));
+    assert!(asm.contains("ASR \t") && asm.contains("#0x8"));
+
+    // 直接返回 64 位值无法在后端表示
+    let ret_id = *module.get_bb(bb_id).insts.last().unwrap();
+    module.replace_value(trunc, sum);
+    let errors = verify::run(&module).unwrap_err();
+    assert!(errors
+        .iter()
+        .any(|e| e.message.contains(&format!("[{}]", ret_id.index()))));
+}
@@ -4,8 +4,7 @@ use std::vec;
 use crate::{
     ast::InfixOp,
     mc::{
-        AsmOperand, AsmValueId, CallConv, Imm, ImmTrait, LabelImm, RegConstraintMap, StackOperand,
-        VirtReg,
+        AsmOperand, AsmValueId, CallConv, Imm, LabelImm, RegConstraintMap, StackOperand, VirtReg,
     },
 };
 
@@ -86,12 +85,13 @@ pub trait AsmInstTrait {
 macro_rules! impl_asm_inst_trait_no_oprs {
     ($t:ty) => {
         impl AsmInstTrait for $t {
+            // 没有操作数，寄存器分配等遍历时视为空
             fn get_defs(&self) -> Vec<AsmOperand> {
-                unimplemented!()
+                vec![]
             }
 
             fn get_uses(&self) -> Vec<AsmOperand> {
-                unimplemented!()
+                vec![]
             }
 
             fn get_uses_mut(&mut self) -> &mut Vec<AsmOperand> {
@@ -153,6 +153,8 @@ pub enum AsmInst {
     FBinOp(FBinOpInst),
     FCMP(FCMPInst),
     LDR(LDRInst),
+    MLA(MLAInst),
+    MLS(MLSInst),
     Mov(MovInst),
     STR(STRInst),
     VCVT(VCVTInst),
@@ -162,6 +164,8 @@ pub enum AsmInst {
     VSTR(VSTRInst),
     Prologue(PrologueInst),
     Ret(RetInst),
+    Push(PushInst),
+    Pop(PopInst),
 }
 
 macro_rules! impl_stack_op_inst_trait {
@@ -297,6 +301,20 @@ impl AsmInst {
         }
     }
 
+    pub fn as_mla(&self) -> Option<&MLAInst> {
+        match self {
+            AsmInst::MLA(inst) => Some(inst),
+            _ => None,
+        }
+    }
+
+    pub fn as_mls(&self) -> Option<&MLSInst> {
+        match self {
+            AsmInst::MLS(inst) => Some(inst),
+            _ => None,
+        }
+    }
+
     pub fn as_ldr_mut(&mut self) -> Option<&mut LDRInst> {
         match self {
             AsmInst::LDR(inst) => Some(inst),
@@ -429,6 +447,20 @@ impl AsmInst {
             _ => None,
         }
     }
+
+    pub fn as_push(&self) -> Option<&PushInst> {
+        match self {
+            AsmInst::Push(inst) => Some(inst),
+            _ => None,
+        }
+    }
+
+    pub fn as_pop(&self) -> Option<&PopInst> {
+        match self {
+            AsmInst::Pop(inst) => Some(inst),
+            _ => None,
+        }
+    }
 }
 
 impl AsmInstTrait for AsmInst {
@@ -442,6 +474,8 @@ impl AsmInstTrait for AsmInst {
             AsmInst::FBinOp(inst) => inst.get_defs(),
             AsmInst::FCMP(inst) => inst.get_defs(),
             AsmInst::LDR(inst) => inst.get_defs(),
+            AsmInst::MLA(inst) => inst.get_defs(),
+            AsmInst::MLS(inst) => inst.get_defs(),
             AsmInst::Mov(inst) => inst.get_defs(),
             AsmInst::STR(inst) => inst.get_defs(),
             AsmInst::VCVT(inst) => inst.get_defs(),
@@ -451,6 +485,8 @@ impl AsmInstTrait for AsmInst {
             AsmInst::VSTR(inst) => inst.get_defs(),
             AsmInst::Prologue(inst) => inst.get_defs(),
             AsmInst::Ret(inst) => inst.get_defs(),
+            AsmInst::Push(inst) => inst.get_defs(),
+            AsmInst::Pop(inst) => inst.get_defs(),
         }
     }
 
@@ -464,6 +500,8 @@ impl AsmInstTrait for AsmInst {
             AsmInst::FBinOp(inst) => inst.get_uses(),
             AsmInst::FCMP(inst) => inst.get_uses(),
             AsmInst::LDR(inst) => inst.get_uses(),
+            AsmInst::MLA(inst) => inst.get_uses(),
+            AsmInst::MLS(inst) => inst.get_uses(),
             AsmInst::Mov(inst) => inst.get_uses(),
             AsmInst::STR(inst) => inst.get_uses(),
             AsmInst::VCVT(inst) => inst.get_uses(),
@@ -473,6 +511,8 @@ impl AsmInstTrait for AsmInst {
             AsmInst::VSTR(inst) => inst.get_uses(),
             AsmInst::Prologue(inst) => inst.get_uses(),
             AsmInst::Ret(inst) => inst.get_uses(),
+            AsmInst::Push(inst) => inst.get_uses(),
+            AsmInst::Pop(inst) => inst.get_uses(),
         }
     }
 
@@ -486,6 +526,8 @@ impl AsmInstTrait for AsmInst {
             AsmInst::FBinOp(inst) => inst.get_uses_mut(),
             AsmInst::FCMP(inst) => inst.get_uses_mut(),
             AsmInst::LDR(inst) => inst.get_uses_mut(),
+            AsmInst::MLA(inst) => inst.get_uses_mut(),
+            AsmInst::MLS(inst) => inst.get_uses_mut(),
             AsmInst::Mov(inst) => inst.get_uses_mut(),
             AsmInst::STR(inst) => inst.get_uses_mut(),
             AsmInst::VCVT(inst) => inst.get_uses_mut(),
@@ -495,6 +537,8 @@ impl AsmInstTrait for AsmInst {
             AsmInst::VSTR(inst) => inst.get_uses_mut(),
             AsmInst::Prologue(inst) => inst.get_uses_mut(),
             AsmInst::Ret(inst) => inst.get_uses_mut(),
+            AsmInst::Push(inst) => inst.get_uses_mut(),
+            AsmInst::Pop(inst) => inst.get_uses_mut(),
         }
     }
 
@@ -508,6 +552,8 @@ impl AsmInstTrait for AsmInst {
             AsmInst::FBinOp(inst) => inst.get_defs_mut(),
             AsmInst::FCMP(inst) => inst.get_defs_mut(),
             AsmInst::LDR(inst) => inst.get_defs_mut(),
+            AsmInst::MLA(inst) => inst.get_defs_mut(),
+            AsmInst::MLS(inst) => inst.get_defs_mut(),
             AsmInst::Mov(inst) => inst.get_defs_mut(),
             AsmInst::STR(inst) => inst.get_defs_mut(),
             AsmInst::VCVT(inst) => inst.get_defs_mut(),
@@ -517,6 +563,8 @@ impl AsmInstTrait for AsmInst {
             AsmInst::VSTR(inst) => inst.get_defs_mut(),
             AsmInst::Prologue(inst) => inst.get_defs_mut(),
             AsmInst::Ret(inst) => inst.get_defs_mut(),
+            AsmInst::Push(inst) => inst.get_defs_mut(),
+            AsmInst::Pop(inst) => inst.get_defs_mut(),
         }
     }
 
@@ -530,6 +578,8 @@ impl AsmInstTrait for AsmInst {
             AsmInst::FBinOp(inst) => inst.set_uses(uses),
             AsmInst::FCMP(inst) => inst.set_uses(uses),
             AsmInst::LDR(inst) => inst.set_uses(uses),
+            AsmInst::MLA(inst) => inst.set_uses(uses),
+            AsmInst::MLS(inst) => inst.set_uses(uses),
             AsmInst::Mov(inst) => inst.set_uses(uses),
             AsmInst::STR(inst) => inst.set_uses(uses),
             AsmInst::VCVT(inst) => inst.set_uses(uses),
@@ -539,6 +589,8 @@ impl AsmInstTrait for AsmInst {
             AsmInst::VSTR(inst) => inst.set_uses(uses),
             AsmInst::Prologue(inst) => inst.set_uses(uses),
             AsmInst::Ret(inst) => inst.set_uses(uses),
+            AsmInst::Push(inst) => inst.set_uses(uses),
+            AsmInst::Pop(inst) => inst.set_uses(uses),
         }
     }
 
@@ -552,6 +604,8 @@ impl AsmInstTrait for AsmInst {
             AsmInst::FBinOp(inst) => inst.set_defs(defs),
             AsmInst::FCMP(inst) => inst.set_defs(defs),
             AsmInst::LDR(inst) => inst.set_defs(defs),
+            AsmInst::MLA(inst) => inst.set_defs(defs),
+            AsmInst::MLS(inst) => inst.set_defs(defs),
             AsmInst::Mov(inst) => inst.set_defs(defs),
             AsmInst::STR(inst) => inst.set_defs(defs),
             AsmInst::VCVT(inst) => inst.set_defs(defs),
@@ -561,6 +615,8 @@ impl AsmInstTrait for AsmInst {
             AsmInst::VSTR(inst) => inst.set_defs(defs),
             AsmInst::Prologue(inst) => inst.set_defs(defs),
             AsmInst::Ret(inst) => inst.set_defs(defs),
+            AsmInst::Push(inst) => inst.set_defs(defs),
+            AsmInst::Pop(inst) => inst.set_defs(defs),
         }
     }
 }
@@ -583,6 +639,8 @@ impl_asm_from_trait!(CMP, CMPInst);
 impl_asm_from_trait!(FBinOp, FBinOpInst);
 impl_asm_from_trait!(FCMP, FCMPInst);
 impl_asm_from_trait!(LDR, LDRInst);
+impl_asm_from_trait!(MLA, MLAInst);
+impl_asm_from_trait!(MLS, MLSInst);
 impl_asm_from_trait!(Mov, MovInst);
 impl_asm_from_trait!(STR, STRInst);
 impl_asm_from_trait!(VCVT, VCVTInst);
@@ -592,6 +650,8 @@ impl_asm_from_trait!(VMRS, VMRSInst);
 impl_asm_from_trait!(VSTR, VSTRInst);
 impl_asm_from_trait!(Prologue, PrologueInst);
 impl_asm_from_trait!(Ret, RetInst);
+impl_asm_from_trait!(Push, PushInst);
+impl_asm_from_trait!(Pop, PopInst);
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct RetInst {
@@ -615,13 +675,16 @@ impl_constraints_trait!(RetInst);
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct PrologueInst {
     pub func: AsmValueId,
+    /// defs 为寄存器传入的参数
+    pub oprs: AsmOperandComponent,
     pub constraints: ConstraintsComponent,
 }
-impl_asm_inst_trait_no_oprs!(PrologueInst);
+impl_asm_inst_trait!(PrologueInst);
 impl PrologueInst {
     pub fn new(func: AsmValueId) -> Self {
         Self {
             func,
+            oprs: AsmOperandComponent::new(vec![], vec![]),
             constraints: ConstraintsComponent::default(),
         }
     }
@@ -629,6 +692,34 @@ impl PrologueInst {
 
 impl_constraints_trait!(PrologueInst);
 
+/// push/vpush，由栈帧展开生成，寄存器列表需同为整数或同为连续的浮点寄存器
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PushInst {
+    pub oprs: AsmOperandComponent,
+}
+impl_asm_inst_trait!(PushInst);
+impl PushInst {
+    pub fn new(regs: Vec<AsmOperand>) -> Self {
+        Self {
+            oprs: AsmOperandComponent::new(vec![], regs),
+        }
+    }
+}
+
+/// pop/vpop，列表中含 pc 时同时完成返回
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PopInst {
+    pub oprs: AsmOperandComponent,
+}
+impl_asm_inst_trait!(PopInst);
+impl PopInst {
+    pub fn new(regs: Vec<AsmOperand>) -> Self {
+        Self {
+            oprs: AsmOperandComponent::new(regs, vec![]),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct VMRSInst {}
 impl_asm_inst_trait_no_oprs!(VMRSInst);
@@ -642,6 +733,7 @@ pub enum VMovType {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct VMovInst {
     pub ty: VMovType,
+    pub cond: Cond,
     pub oprs: AsmOperandComponent,
 }
 impl_asm_inst_trait!(VMovInst);
@@ -649,6 +741,7 @@ impl VMovInst {
     pub fn new(ty: VMovType, to: AsmOperand, from: AsmOperand) -> Self {
         Self {
             ty,
+            cond: Cond::AL,
             oprs: AsmOperandComponent::new(vec![to], vec![from]),
         }
     }
@@ -668,9 +761,9 @@ pub struct VCVTInst {
 impl ToString for VCVTType {
     fn to_string(&self) -> String {
         match self {
-            VCVTType::F2I => "VCVT.F32.S32".to_string(),
-            VCVTType::I2F => "VCVT.S32.F32".to_string(),
-            VCVTType::F2D => "VCVT.F32.F64".to_string(),
+            VCVTType::F2I => "VCVT.S32.F32".to_string(),
+            VCVTType::I2F => "VCVT.F32.S32".to_string(),
+            VCVTType::F2D => "VCVT.F64.F32".to_string(),
         }
     }
 }
@@ -689,6 +782,8 @@ pub enum MovType {
     Reg,
     Movw,
     Movt,
+    /// 按位取反后传送，用于装入取反后才是 Operand2 的常量
+    Mvn,
 }
 
 impl ToString for MovType {
@@ -697,6 +792,7 @@ impl ToString for MovType {
             MovType::Reg => "MOV".to_string(),
             MovType::Movw => "MOVW".to_string(),
             MovType::Movt => "MOVT".to_string(),
+            MovType::Mvn => "MVN".to_string(),
         }
     }
 }
@@ -753,6 +849,7 @@ impl From<BinaryOp> for FBinaryOp {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct FBinOpInst {
     pub op: FBinaryOp,
+    pub cond: Cond,
     pub oprs: AsmOperandComponent,
 }
 impl_asm_inst_trait!(FBinOpInst);
@@ -760,6 +857,7 @@ impl FBinOpInst {
     pub fn new(op: FBinaryOp, to: AsmOperand, op1: AsmOperand, op2: AsmOperand) -> Self {
         Self {
             op,
+            cond: Cond::AL,
             oprs: AsmOperandComponent::new(vec![to], vec![op1, op2]),
         }
     }
@@ -818,6 +916,59 @@ impl AsmInst {
     pub fn is_stack_op(&self) -> bool {
         matches!(self, AsmInst::LDR(_) | AsmInst::VLDR(_) | AsmInst::STR(_))
     }
+
+    /// 条件执行的条件，不支持条件执行的指令总是 AL
+    pub fn cond(&self) -> Cond {
+        match self {
+            AsmInst::BinOp(inst) => inst.cond.clone(),
+            AsmInst::Br(inst) => inst.cond.clone(),
+            AsmInst::FBinOp(inst) => inst.cond.clone(),
+            AsmInst::LDR(inst) => inst.cond.clone(),
+            AsmInst::MLA(inst) => inst.cond.clone(),
+            AsmInst::MLS(inst) => inst.cond.clone(),
+            AsmInst::Mov(inst) => inst.cond.clone(),
+            AsmInst::STR(inst) => inst.cond.clone(),
+            AsmInst::VLDR(inst) => inst.cond.clone(),
+            AsmInst::VMov(inst) => inst.cond.clone(),
+            AsmInst::VSTR(inst) => inst.cond.clone(),
+            _ => Cond::AL,
+        }
+    }
+
+    /// 能否改为条件执行：不设置标志位、不改变控制流，且尚未带条件
+    pub fn is_predicable(&self) -> bool {
+        let predicable = matches!(
+            self,
+            AsmInst::BinOp(_)
+                | AsmInst::FBinOp(_)
+                | AsmInst::LDR(_)
+                | AsmInst::MLA(_)
+                | AsmInst::MLS(_)
+                | AsmInst::Mov(_)
+                | AsmInst::STR(_)
+                | AsmInst::VLDR(_)
+                | AsmInst::VMov(_)
+                | AsmInst::VSTR(_)
+        );
+        predicable && self.cond() == Cond::AL
+    }
+
+    pub fn set_cond(&mut self, cond: Cond) {
+        match self {
+            AsmInst::BinOp(inst) => inst.cond = cond,
+            AsmInst::Br(inst) => inst.cond = cond,
+            AsmInst::FBinOp(inst) => inst.cond = cond,
+            AsmInst::LDR(inst) => inst.cond = cond,
+            AsmInst::MLA(inst) => inst.cond = cond,
+            AsmInst::MLS(inst) => inst.cond = cond,
+            AsmInst::Mov(inst) => inst.cond = cond,
+            AsmInst::STR(inst) => inst.cond = cond,
+            AsmInst::VLDR(inst) => inst.cond = cond,
+            AsmInst::VMov(inst) => inst.cond = cond,
+            AsmInst::VSTR(inst) => inst.cond = cond,
+            _ => unreachable!("set_cond: {:?} is not predicable", self),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -863,8 +1014,15 @@ pub enum BinaryOp {
     Add,
     Sub,
     Mul,
+    /// 有符号乘法结果的高 32 位
+    MulHigh,
     Div,
     Mod,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
     LogAnd,
     LogOr,
     LogEq,
@@ -883,14 +1041,14 @@ impl From<InfixOp> for BinaryOp {
             InfixOp::Mul => BinaryOp::Mul,
             InfixOp::Div => BinaryOp::Div,
             InfixOp::Mod => BinaryOp::Mod,
-            InfixOp::BitAnd => todo!(),
-            InfixOp::BitOr => todo!(),
-            InfixOp::BitXor => todo!(),
-            InfixOp::BitShl => todo!(),
-            InfixOp::BitShr => todo!(),
+            InfixOp::BitAnd => BinaryOp::And,
+            InfixOp::BitOr => BinaryOp::Or,
+            InfixOp::BitXor => BinaryOp::Xor,
+            InfixOp::BitShl => BinaryOp::Shl,
+            InfixOp::BitShr => BinaryOp::Shr,
             InfixOp::LogicAnd => BinaryOp::LogAnd,
             InfixOp::LogicOr => BinaryOp::LogOr,
-            InfixOp::Rem => todo!(),
+            InfixOp::Rem => BinaryOp::Mod,
             InfixOp::Eq => BinaryOp::LogEq,
             InfixOp::Ne => BinaryOp::LogNeq,
             InfixOp::Lt => BinaryOp::LogLt,
@@ -907,13 +1065,17 @@ impl From<InfixOp> for BinaryOp {
 #[derive(Debug, PartialEq, Eq, Clone)]
 
 pub struct VLDRInst {
+    pub cond: Cond,
     pub oprs: AsmOperandComponent,
 }
 impl_asm_inst_trait!(VLDRInst);
 impl VLDRInst {
     pub fn new(dest: AsmOperand, addr: AsmOperand) -> VLDRInst {
         let oprs = AsmOperandComponent::new(vec![dest], vec![addr]);
-        VLDRInst { oprs }
+        VLDRInst {
+            cond: Cond::AL,
+            oprs,
+        }
     }
 
     pub fn is_imm_fit(so: &StackOperand) -> bool {
@@ -925,13 +1087,17 @@ impl VLDRInst {
 #[derive(Debug, PartialEq, Eq, Clone)]
 
 pub struct LDRInst {
+    pub cond: Cond,
     pub oprs: AsmOperandComponent,
 }
 impl_asm_inst_trait!(LDRInst);
 impl LDRInst {
     pub fn new(dest: AsmOperand, addr: AsmOperand) -> LDRInst {
         let oprs = AsmOperandComponent::new(vec![dest], vec![addr]);
-        LDRInst { oprs }
+        LDRInst {
+            cond: Cond::AL,
+            oprs,
+        }
     }
 
     pub fn is_imm_fit(so: &StackOperand) -> bool {
@@ -942,13 +1108,17 @@ impl LDRInst {
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct STRInst {
+    pub cond: Cond,
     pub oprs: AsmOperandComponent,
 }
 impl_asm_inst_trait!(STRInst);
 impl STRInst {
     pub fn new(val: AsmOperand, addr: AsmOperand) -> STRInst {
         let oprs = AsmOperandComponent::new(vec![], vec![val, addr]);
-        STRInst { oprs }
+        STRInst {
+            cond: Cond::AL,
+            oprs,
+        }
     }
 
     pub fn is_imm_fit(so: &StackOperand) -> bool {
@@ -959,13 +1129,17 @@ impl STRInst {
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct VSTRInst {
+    pub cond: Cond,
     pub oprs: AsmOperandComponent,
 }
 impl_asm_inst_trait!(VSTRInst);
 impl VSTRInst {
     pub fn new(val: AsmOperand, addr: AsmOperand) -> VSTRInst {
         let oprs = AsmOperandComponent::new(vec![], vec![val, addr]);
-        VSTRInst { oprs }
+        VSTRInst {
+            cond: Cond::AL,
+            oprs,
+        }
     }
 
     pub fn is_imm_fit(so: &StackOperand) -> bool {
@@ -981,12 +1155,17 @@ impl VSTRInst {
  * 2. SUB Rd, Rn, #<imm12> 同上
  * 3. MUL Rd, Rm, Rs 无法使用立即数，必须要转换了
  * 4. SDIV Rd, Rn, Rm 有符号除法，同上
- * 5. 取模：不支持，在IR那边转换为调用相关eabi函数
+ * 5. 取模：不支持，由 mc_builder 展开为 SDIV + MLS
+ * 6. AND/ORR/EOR Rd, Rn, <Operand2>
+ * 7. LSL/ASR Rd, Rn, #<imm5> 或 Rd, Rn, Rs
  */
 #[derive(Debug, PartialEq, Eq, Clone)]
 
 pub struct BinOpInst {
     pub op: BinaryOp,
+    pub cond: Cond,
+    /// 第二个操作数先按 Shl/Shr 移位立即数位，即 Operand2 的寄存器移位形式
+    pub shift: Option<(BinaryOp, u32)>,
     pub oprs: AsmOperandComponent,
 }
 
@@ -995,7 +1174,12 @@ impl_asm_inst_trait!(BinOpInst);
 impl BinOpInst {
     pub fn new(op: BinaryOp, to: AsmOperand, op1: AsmOperand, op2: AsmOperand) -> BinOpInst {
         let oprs = AsmOperandComponent::new(vec![to], vec![op1, op2]);
-        BinOpInst { op, oprs }
+        BinOpInst {
+            op,
+            cond: Cond::AL,
+            shift: None,
+            oprs,
+        }
     }
 
     pub fn op_to_string(op: &BinaryOp) -> &'static str {
@@ -1003,24 +1187,71 @@ impl BinOpInst {
             BinaryOp::Add => "ADD ",
             BinaryOp::Sub => "SUB ",
             BinaryOp::Mul => "MUL ",
+            BinaryOp::MulHigh => "SMMUL",
             BinaryOp::Div => "SDIV",
+            BinaryOp::And => "AND ",
+            BinaryOp::Or => "ORR ",
+            BinaryOp::Xor => "EOR ",
+            BinaryOp::Shl => "LSL ",
+            BinaryOp::Shr => "ASR ",
             _ => unreachable!(),
         }
     }
 
-    fn is_imm_fit(&self, m: &Imm) -> bool {
+    /// 第二个操作数能否直接使用立即数
+    pub fn is_imm_fit(&self, m: &Imm) -> bool {
         match self.op {
-            BinaryOp::Add | BinaryOp::Sub => Operand2::is_imm_fit(m),
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => {
+                Operand2::is_imm_fit(m)
+            }
+            BinaryOp::Shl | BinaryOp::Shr => matches!(m, Imm::Int(imm) if imm.value < 32),
             _ => false,
         }
     }
 }
 
+/// MLA Rd, Rn, Rm, Ra: Rd = Ra + Rn * Rm，由窥孔优化合并 mul 和 add 得到
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MLAInst {
+    pub cond: Cond,
+    pub oprs: AsmOperandComponent,
+}
+impl_asm_inst_trait!(MLAInst);
+impl MLAInst {
+    pub fn new(to: AsmOperand, rn: AsmOperand, rm: AsmOperand, ra: AsmOperand) -> MLAInst {
+        let oprs = AsmOperandComponent::new(vec![to], vec![rn, rm, ra]);
+        MLAInst {
+            cond: Cond::AL,
+            oprs,
+        }
+    }
+}
+
+/// MLS Rd, Rn, Rm, Ra: Rd = Ra - Rn * Rm，用于取模
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MLSInst {
+    pub cond: Cond,
+    pub oprs: AsmOperandComponent,
+}
+impl_asm_inst_trait!(MLSInst);
+impl MLSInst {
+    pub fn new(to: AsmOperand, rn: AsmOperand, rm: AsmOperand, ra: AsmOperand) -> MLSInst {
+        let oprs = AsmOperandComponent::new(vec![to], vec![rn, rm, ra]);
+        MLSInst {
+            cond: Cond::AL,
+            oprs,
+        }
+    }
+}
+
 pub struct Operand2;
-// Flexible Operand 2 目前仅当作8bit常量使用
+// Flexible Operand 2 的常量形式：8bit 数循环右移偶数位；寄存器移位形式见 BinOpInst::shift
 impl Operand2 {
     pub fn is_imm_fit(m: &Imm) -> bool {
-        m.highest_one_bit() < 255
+        match m {
+            Imm::Int(imm) => (0..16).any(|i| imm.value.rotate_left(2 * i) <= 0xff),
+            _ => false,
+        }
     }
 }
 
@@ -0,0 +1,216 @@
+//! 栈帧展开，在寄存器分配之后运行
+//!
+//! 栈帧布局（高地址在上）：
+//!
+//! ```text
+//!       | 栈上传入的参数  |  fp + saved_size + offset
+//!       | lr, fp, r4-r10  |  push
+//!       | s16-sN          |  vpush
+//! fp -> +-----------------+
+//!       | 局部变量、溢出槽 |  fp - offset
+//!       | 传出的参数      |  sp + offset
+//! sp -> +-----------------+
+//! ```
+//!
+//! PrologueInst 展开为 push/vpush/mov fp/sub sp，RetInst 展开为 mov sp/vpop/pop {.., pc}，
+//! 尾调用之前插入 mov sp/vpop/pop {.., lr} 释放栈帧
+use crate::{mc::*, mc_inst::*};
+
+pub fn run(module: &mut AsmModule) {
+    for func_id in module.funcs.clone() {
+        lower_function(module, func_id);
+    }
+}
+
+fn lower_function(module: &mut AsmModule, func_id: AsmValueId) {
+    let func = module.get_func_mut(func_id);
+    let mut int_regs: Vec<AsmOperand> = func
+        .callee_saved_regs
+        .iter()
+        .map(|ty| IntReg::new(*ty).into())
+        .collect();
+    int_regs.push(IntReg::new(RegType::Fp).into());
+    int_regs.push(IntReg::new(RegType::Lr).into());
+    // vpush 只接受连续的寄存器
+    let vfp_regs: Vec<AsmOperand> = match func.callee_saved_vfp_regs.iter().max() {
+        Some(max) => (16..=*max).map(|i| VfpReg::from(i).into()).collect(),
+        None => vec![],
+    };
+    func.stack_state.saved_size = 4 * (int_regs.len() + vfp_regs.len()) as i64;
+    let saved_size = func.stack_state.saved_size;
+    let frame_size = func.stack_state.total_stack_size();
+
+    for bb_id in module.get_func(func_id).bbs.clone() {
+        let mut new_insts = vec![];
+        for inst_id in module.get_bb(bb_id).insts.clone() {
+            match module.get_inst(inst_id) {
+                AsmInst::Prologue(_) => {
+                    new_insts.extend(prologue(module, &int_regs, &vfp_regs, frame_size));
+                }
+                AsmInst::Ret(_) => {
+                    new_insts.extend(epilogue(module, &int_regs, &vfp_regs, false));
+                }
+                AsmInst::Call(call) if call.tail_call => {
+                    new_insts.extend(epilogue(module, &int_regs, &vfp_regs, true));
+                    new_insts.push(inst_id);
+                }
+                AsmInst::LDR(_) | AsmInst::VLDR(_) | AsmInst::STR(_) | AsmInst::VSTR(_) => {
+                    new_insts.extend(lower_stack_operand(module, inst_id, saved_size));
+                }
+                _ => new_insts.push(inst_id),
+            }
+        }
+        module.get_bb_mut(bb_id).insts = new_insts;
+    }
+}
+
+fn prologue(
+    module: &mut AsmModule,
+    int_regs: &[AsmOperand],
+    vfp_regs: &[AsmOperand],
+    frame_size: i64,
+) -> Vec<AsmValueId> {
+    let mut ret = vec![module.alloc_value(AsmValue::Inst(PushInst::new(int_regs.to_vec()).into()))];
+    if !vfp_regs.is_empty() {
+        let vpush = PushInst::new(vfp_regs.to_vec());
+        ret.push(module.alloc_value(AsmValue::Inst(vpush.into())));
+    }
+    let mov = MovInst::new(
+        MovType::Reg,
+        IntReg::new(RegType::Fp).into(),
+        IntReg::new(RegType::Sp).into(),
+        None,
+    );
+    ret.push(module.alloc_value(AsmValue::Inst(mov.into())));
+    if frame_size > 0 {
+        let sub = BinOpInst::new(
+            BinaryOp::Sub,
+            IntReg::new(RegType::Sp).into(),
+            IntReg::new(RegType::Sp).into(),
+            IntImm::from(frame_size as i32).into(),
+        );
+        let sub_id = module.alloc_value(AsmValue::Inst(sub.into()));
+        ret.extend(module.expand_bin_op_ip(sub_id));
+    }
+    ret
+}
+
+fn epilogue(
+    module: &mut AsmModule,
+    int_regs: &[AsmOperand],
+    vfp_regs: &[AsmOperand],
+    tail_call: bool,
+) -> Vec<AsmValueId> {
+    let mov = MovInst::new(
+        MovType::Reg,
+        IntReg::new(RegType::Sp).into(),
+        IntReg::new(RegType::Fp).into(),
+        None,
+    );
+    let mut ret = vec![module.alloc_value(AsmValue::Inst(mov.into()))];
+    if !vfp_regs.is_empty() {
+        let vpop = PopInst::new(vfp_regs.to_vec());
+        ret.push(module.alloc_value(AsmValue::Inst(vpop.into())));
+    }
+    // 保存的 lr 直接弹入 pc 返回；尾调用恢复 lr，被调用者直接返回到调用者的调用者
+    let mut regs = int_regs.to_vec();
+    if !tail_call {
+        *regs.last_mut().unwrap() = IntReg::new(RegType::Pc).into();
+    }
+    ret.push(module.alloc_value(AsmValue::Inst(PopInst::new(regs).into())));
+    ret
+}
+
+/// 修正传入参数的偏移，偏移超出立即数范围时借用 ip 计算地址
+fn lower_stack_operand(
+    module: &mut AsmModule,
+    inst_id: AsmValueId,
+    saved_size: i64,
+) -> Vec<AsmValueId> {
+    let mut inst = module.get_inst(inst_id).clone();
+    let addr_index = if matches!(inst, AsmInst::STR(_) | AsmInst::VSTR(_)) {
+        1
+    } else {
+        0
+    };
+    let mut uses = inst.get_uses();
+    let mut so = match &uses[addr_index] {
+        AsmOperand::StackOperand(so) => so.clone(),
+        _ => return vec![inst_id],
+    };
+    // mc_builder 按只保存了 fp、lr 计算传入参数的偏移
+    if so.ty == StackOperandType::SelfArg {
+        so.offset += saved_size - 8;
+    }
+
+    let mut ret = vec![];
+    if inst.is_imm_fit(&so) {
+        uses[addr_index] = so.into();
+    } else {
+        let (base, offset) = so.base_and_offset();
+        let ip = AsmOperand::IntReg(IntReg::new(RegType::Ip));
+        ret.extend(module.load_imm(ip.clone(), &Imm::Int(IntImm::from(offset.abs() as i32))));
+        let op = if offset < 0 {
+            BinaryOp::Sub
+        } else {
+            BinaryOp::Add
+        };
+        let addr = BinOpInst::new(op, ip.clone(), IntReg::new(base).into(), ip.clone());
+        ret.push(module.alloc_value(AsmValue::Inst(addr.into())));
+        uses[addr_index] = ip;
+    }
+    inst.set_uses(uses);
+    module.set_inst(inst_id, inst);
+    ret.push(inst_id);
+    ret
+}
+
+#[test]
+fn test_frame_lowering() {
+    let mut module = super::reg_alloc::build_asm(
+        "int getint();
+        int f(int a, int b, int c, int d, int e, int g) { return a + e + g; }
+        int main() { int x = getint(); if (x) return 1; return f(x, 2, 3, 4, 5, 6) + x; }",
+    );
+    super::reg_alloc::run(&mut module);
+    run(&mut module);
+
+    for func_id in module.funcs.clone() {
+        let func = module.get_func(func_id);
+        let stack = &func.stack_state;
+        assert_eq!((stack.saved_size + stack.total_stack_size()) % 8, 0);
+
+        let insts: Vec<&AsmInst> = func
+            .bbs
+            .iter()
+            .flat_map(|bb_id| module.get_bb(*bb_id).insts.iter())
+            .map(|inst_id| module.get_inst(*inst_id))
+            .collect();
+        assert!(!insts
+            .iter()
+            .any(|inst| matches!(inst, AsmInst::Prologue(_) | AsmInst::Ret(_))));
+        let push = insts[0].as_push().unwrap();
+        assert_eq!(
+            push.get_uses().last(),
+            Some(&IntReg::new(RegType::Lr).into())
+        );
+        let pc = IntReg::new(RegType::Pc).into();
+        let n_ret = insts
+            .iter()
+            .filter(|inst| matches!(inst, AsmInst::Pop(pop) if pop.get_defs().contains(&pc)))
+            .count();
+        assert_eq!(n_ret, if func.name == "main" { 2 } else { 1 });
+
+        // f 的第 5、6 个参数在调用者的栈上，位于保存的寄存器之上
+        if func.name == "f" {
+            let self_args: Vec<i64> = insts
+                .iter()
+                .filter_map(|inst| inst.as_ldr())
+                .filter_map(|ldr| ldr.get_uses()[0].as_stack_operand().cloned())
+                .filter(|so| so.ty == StackOperandType::SelfArg)
+                .map(|so| so.offset)
+                .collect();
+            assert_eq!(self_args, vec![stack.saved_size, stack.saved_size + 4]);
+        }
+    }
+}
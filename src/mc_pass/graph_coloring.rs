@@ -0,0 +1,601 @@
+//! 迭代寄存器合并（George & Appel, Iterated Register Coalescing）
+//!
+//! 1. 与线性扫描相同，先把 Prologue/Call/Ret 的约束展开为 mov
+//! 2. 按指令粒度构建冲突图，拷贝指令的两端之间不连边
+//! 3. simplify / coalesce / freeze / potential spill 交替进行，直到所有结点入栈
+//! 4. 出栈着色，着色失败的结点按循环深度加权的代价溢出，插入 load/store 后重新开始
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use log::debug;
+
+use crate::mc::*;
+
+use super::reg_alloc::{
+    as_copy, assign_regs, insert_spill_code, inst_defs_uses, lower_constraints, next_vreg_index,
+    remove_identity_moves, Liveness, PhysReg, Reg, RegAllocStats,
+};
+
+pub fn run(module: &mut AsmModule) -> RegAllocStats {
+    let mut stats = RegAllocStats::default();
+    for func_id in module.funcs.clone() {
+        let mut ra = GraphColoring::new(module, func_id);
+        stats += ra.run();
+    }
+    stats
+}
+
+/// 每个基本块所在的循环层数
+///
+/// 以 DFS 中指向栈上结点的边作为回边，同一个头结点的自然循环合并计算
+pub fn loop_depths(module: &AsmModule, func_id: AsmValueId) -> HashMap<AsmValueId, u32> {
+    let func = module.get_func(func_id);
+    let mut depths: HashMap<AsmValueId, u32> = func.bbs.iter().map(|bb| (*bb, 0)).collect();
+    let entry = match func.entry {
+        Some(entry) => entry,
+        None => return depths,
+    };
+
+    let mut back_edges: Vec<(AsmValueId, AsmValueId)> = vec![];
+    let mut visited = HashSet::from([entry]);
+    let mut on_stack = HashSet::from([entry]);
+    let mut stack = vec![(entry, 0)];
+    while let Some((bb_id, i)) = stack.last_mut() {
+        let bb_id = *bb_id;
+        let succs = &module.get_bb(bb_id).succs;
+        if *i == succs.len() {
+            on_stack.remove(&bb_id);
+            stack.pop();
+            continue;
+        }
+        let succ = succs[*i];
+        *i += 1;
+        if on_stack.contains(&succ) {
+            back_edges.push((bb_id, succ));
+        } else if visited.insert(succ) {
+            on_stack.insert(succ);
+            stack.push((succ, 0));
+        }
+    }
+
+    let mut bodies: HashMap<AsmValueId, HashSet<AsmValueId>> = HashMap::new();
+    for (tail, header) in back_edges {
+        let body = bodies
+            .entry(header)
+            .or_insert_with(|| HashSet::from([header]));
+        let mut worklist = vec![tail];
+        while let Some(bb_id) = worklist.pop() {
+            if body.insert(bb_id) {
+                worklist.extend(module.get_bb(bb_id).preds.iter().copied());
+            }
+        }
+    }
+    for body in bodies.values() {
+        for bb_id in body {
+            *depths.get_mut(bb_id).unwrap() += 1;
+        }
+    }
+    depths
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum NodeState {
+    Precolored,
+    Initial,
+    Simplify,
+    Freeze,
+    Spill,
+    Coalesced,
+    OnStack,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum MoveState {
+    Worklist,
+    Active,
+    Coalesced,
+    Constrained,
+    Frozen,
+}
+
+/// 一轮分配使用的冲突图，结点以下标表示
+struct Graph {
+    nodes: Vec<Reg>,
+    index: HashMap<Reg, usize>,
+    state: Vec<NodeState>,
+    adj_set: HashSet<(usize, usize)>,
+    adj_list: Vec<Vec<usize>>,
+    degree: Vec<usize>,
+    alias: Vec<usize>,
+    spill_cost: Vec<f64>,
+    // 每个结点相关的拷贝
+    move_list: Vec<Vec<usize>>,
+    // 拷贝的 (目标, 源)
+    moves: Vec<(usize, usize)>,
+    move_state: Vec<MoveState>,
+
+    simplify_worklist: BTreeSet<usize>,
+    freeze_worklist: BTreeSet<usize>,
+    spill_worklist: BTreeSet<usize>,
+    worklist_moves: BTreeSet<usize>,
+    select_stack: Vec<usize>,
+}
+
+impl Graph {
+    fn new() -> Graph {
+        Graph {
+            nodes: vec![],
+            index: HashMap::new(),
+            state: vec![],
+            adj_set: HashSet::new(),
+            adj_list: vec![],
+            degree: vec![],
+            alias: vec![],
+            spill_cost: vec![],
+            move_list: vec![],
+            moves: vec![],
+            move_state: vec![],
+            simplify_worklist: BTreeSet::new(),
+            freeze_worklist: BTreeSet::new(),
+            spill_worklist: BTreeSet::new(),
+            worklist_moves: BTreeSet::new(),
+            select_stack: vec![],
+        }
+    }
+
+    fn node(&mut self, reg: Reg) -> usize {
+        if let Some(n) = self.index.get(&reg) {
+            return *n;
+        }
+        let n = self.nodes.len();
+        self.nodes.push(reg);
+        self.index.insert(reg, n);
+        self.state.push(match reg {
+            Reg::Phys(_) => NodeState::Precolored,
+            Reg::Virt(_) => NodeState::Initial,
+        });
+        self.adj_list.push(vec![]);
+        self.degree.push(0);
+        self.alias.push(n);
+        self.spill_cost.push(0.0);
+        self.move_list.push(vec![]);
+        n
+    }
+
+    fn is_float(&self, n: usize) -> bool {
+        match self.nodes[n] {
+            Reg::Virt(vreg) => vreg.is_float,
+            Reg::Phys(reg) => reg.is_float(),
+        }
+    }
+
+    // 可用颜色数
+    fn k(&self, n: usize) -> usize {
+        PhysReg::allocatable(self.is_float(n)).len()
+    }
+
+    fn is_precolored(&self, n: usize) -> bool {
+        self.state[n] == NodeState::Precolored
+    }
+
+    fn add_edge(&mut self, u: usize, v: usize) {
+        if u == v || self.is_float(u) != self.is_float(v) || self.adj_set.contains(&(u, v)) {
+            return;
+        }
+        self.adj_set.insert((u, v));
+        self.adj_set.insert((v, u));
+        for (a, b) in [(u, v), (v, u)] {
+            if !self.is_precolored(a) {
+                self.adj_list[a].push(b);
+                self.degree[a] += 1;
+            }
+        }
+    }
+
+    fn adjacent(&self, n: usize) -> Vec<usize> {
+        self.adj_list[n]
+            .iter()
+            .copied()
+            .filter(|m| !matches!(self.state[*m], NodeState::OnStack | NodeState::Coalesced))
+            .collect()
+    }
+
+    fn node_moves(&self, n: usize) -> Vec<usize> {
+        self.move_list[n]
+            .iter()
+            .copied()
+            .filter(|m| matches!(self.move_state[*m], MoveState::Worklist | MoveState::Active))
+            .collect()
+    }
+
+    fn move_related(&self, n: usize) -> bool {
+        !self.node_moves(n).is_empty()
+    }
+
+    fn get_alias(&self, n: usize) -> usize {
+        let mut n = n;
+        while self.state[n] == NodeState::Coalesced {
+            n = self.alias[n];
+        }
+        n
+    }
+
+    fn set_state(&mut self, n: usize, state: NodeState) {
+        match self.state[n] {
+            NodeState::Simplify => self.simplify_worklist.remove(&n),
+            NodeState::Freeze => self.freeze_worklist.remove(&n),
+            NodeState::Spill => self.spill_worklist.remove(&n),
+            _ => false,
+        };
+        match state {
+            NodeState::Simplify => self.simplify_worklist.insert(n),
+            NodeState::Freeze => self.freeze_worklist.insert(n),
+            NodeState::Spill => self.spill_worklist.insert(n),
+            _ => false,
+        };
+        self.state[n] = state;
+    }
+
+    fn make_worklist(&mut self) {
+        for n in 0..self.nodes.len() {
+            if self.state[n] != NodeState::Initial {
+                continue;
+            }
+            let state = if self.degree[n] >= self.k(n) {
+                NodeState::Spill
+            } else if self.move_related(n) {
+                NodeState::Freeze
+            } else {
+                NodeState::Simplify
+            };
+            self.set_state(n, state);
+        }
+    }
+
+    fn simplify(&mut self) {
+        let n = self.simplify_worklist.pop_first().unwrap();
+        self.state[n] = NodeState::OnStack;
+        self.select_stack.push(n);
+        for m in self.adjacent(n) {
+            self.decrement_degree(m);
+        }
+    }
+
+    fn decrement_degree(&mut self, m: usize) {
+        if self.is_precolored(m) {
+            return;
+        }
+        let d = self.degree[m];
+        self.degree[m] = d - 1;
+        if d == self.k(m) {
+            let mut nodes = self.adjacent(m);
+            nodes.push(m);
+            self.enable_moves(&nodes);
+            if self.move_related(m) {
+                self.set_state(m, NodeState::Freeze);
+            } else {
+                self.set_state(m, NodeState::Simplify);
+            }
+        }
+    }
+
+    fn enable_moves(&mut self, nodes: &[usize]) {
+        for n in nodes {
+            for m in self.node_moves(*n) {
+                if self.move_state[m] == MoveState::Active {
+                    self.move_state[m] = MoveState::Worklist;
+                    self.worklist_moves.insert(m);
+                }
+            }
+        }
+    }
+
+    fn add_worklist(&mut self, u: usize) {
+        if !self.is_precolored(u) && !self.move_related(u) && self.degree[u] < self.k(u) {
+            self.set_state(u, NodeState::Simplify);
+        }
+    }
+
+    // George 条件：t 的所有邻居要么度数低，要么已经与 r 冲突
+    fn ok(&self, t: usize, r: usize) -> bool {
+        self.degree[t] < self.k(t) || self.is_precolored(t) || self.adj_set.contains(&(t, r))
+    }
+
+    // Briggs 条件：合并后高度数邻居少于 K 个
+    fn conservative(&self, nodes: &BTreeSet<usize>, k: usize) -> bool {
+        nodes
+            .iter()
+            .filter(|n| self.is_precolored(**n) || self.degree[**n] >= self.k(**n))
+            .count()
+            < k
+    }
+
+    fn coalesce(&mut self) -> bool {
+        let m = self.worklist_moves.pop_first().unwrap();
+        let (dst, src) = self.moves[m];
+        let x = self.get_alias(dst);
+        let y = self.get_alias(src);
+        let (u, v) = if self.is_precolored(y) {
+            (y, x)
+        } else {
+            (x, y)
+        };
+
+        if u == v {
+            self.move_state[m] = MoveState::Coalesced;
+            self.add_worklist(u);
+            true
+        } else if self.is_precolored(v) || self.adj_set.contains(&(u, v)) {
+            self.move_state[m] = MoveState::Constrained;
+            self.add_worklist(u);
+            self.add_worklist(v);
+            false
+        } else if (self.is_precolored(u) && self.adjacent(v).iter().all(|t| self.ok(*t, u)))
+            || (!self.is_precolored(u) && {
+                let nodes = self
+                    .adjacent(u)
+                    .into_iter()
+                    .chain(self.adjacent(v))
+                    .collect();
+                self.conservative(&nodes, self.k(u))
+            })
+        {
+            self.move_state[m] = MoveState::Coalesced;
+            self.combine(u, v);
+            self.add_worklist(u);
+            true
+        } else {
+            self.move_state[m] = MoveState::Active;
+            false
+        }
+    }
+
+    fn combine(&mut self, u: usize, v: usize) {
+        self.set_state(v, NodeState::Coalesced);
+        self.alias[v] = u;
+        let moves = self.move_list[v].clone();
+        self.move_list[u].extend(moves);
+        self.enable_moves(&[v]);
+        for t in self.adjacent(v) {
+            self.add_edge(t, u);
+            self.decrement_degree(t);
+        }
+        if self.state[u] == NodeState::Freeze && self.degree[u] >= self.k(u) {
+            self.set_state(u, NodeState::Spill);
+        }
+    }
+
+    fn freeze(&mut self) {
+        let u = *self.freeze_worklist.first().unwrap();
+        self.set_state(u, NodeState::Simplify);
+        self.freeze_moves(u);
+    }
+
+    fn freeze_moves(&mut self, u: usize) {
+        for m in self.node_moves(u) {
+            let (x, y) = self.moves[m];
+            let v = if self.get_alias(y) == self.get_alias(u) {
+                self.get_alias(x)
+            } else {
+                self.get_alias(y)
+            };
+            self.move_state[m] = MoveState::Frozen;
+            if self.state[v] == NodeState::Freeze
+                && !self.move_related(v)
+                && self.degree[v] < self.k(v)
+            {
+                self.set_state(v, NodeState::Simplify);
+            }
+        }
+    }
+
+    // 代价 / 度数最小的结点作为潜在溢出
+    fn select_spill(&mut self) {
+        let m = *self
+            .spill_worklist
+            .iter()
+            .min_by(|a, b| {
+                let a = self.spill_cost[**a] / self.degree[**a] as f64;
+                let b = self.spill_cost[**b] / self.degree[**b] as f64;
+                a.total_cmp(&b)
+            })
+            .unwrap();
+        self.set_state(m, NodeState::Simplify);
+        self.freeze_moves(m);
+    }
+
+    /// 出栈着色，返回着色结果和需要真正溢出的虚拟寄存器
+    fn assign_colors(&mut self) -> (HashMap<VirtReg, PhysReg>, Vec<VirtReg>) {
+        let mut color: HashMap<usize, PhysReg> = HashMap::new();
+        for (n, reg) in self.nodes.iter().enumerate() {
+            if let Reg::Phys(reg) = reg {
+                color.insert(n, *reg);
+            }
+        }
+        let mut spilled = vec![];
+        while let Some(n) = self.select_stack.pop() {
+            let used: HashSet<PhysReg> = self.adj_list[n]
+                .iter()
+                .filter_map(|w| color.get(&self.get_alias(*w)).copied())
+                .collect();
+            let ok_color = PhysReg::allocatable(self.is_float(n))
+                .into_iter()
+                .find(|reg| !used.contains(reg));
+            match ok_color {
+                Some(reg) => {
+                    color.insert(n, reg);
+                }
+                None => match self.nodes[n] {
+                    Reg::Virt(vreg) => spilled.push(vreg),
+                    Reg::Phys(_) => unreachable!(),
+                },
+            }
+        }
+
+        let mut assignment = HashMap::new();
+        for (n, reg) in self.nodes.iter().enumerate() {
+            if let Reg::Virt(vreg) = reg {
+                if let Some(reg) = color.get(&self.get_alias(n)) {
+                    assignment.insert(*vreg, *reg);
+                }
+            }
+        }
+        (assignment, spilled)
+    }
+}
+
+pub struct GraphColoring<'a> {
+    module: &'a mut AsmModule,
+    func_id: AsmValueId,
+    next_vreg: i32,
+    // 溢出产生的临时寄存器区间极短，尽量不再溢出
+    no_spill: HashSet<VirtReg>,
+}
+
+impl GraphColoring<'_> {
+    pub fn new(module: &mut AsmModule, func_id: AsmValueId) -> GraphColoring<'_> {
+        GraphColoring {
+            module,
+            func_id,
+            next_vreg: 0,
+            no_spill: HashSet::new(),
+        }
+    }
+
+    pub fn run(&mut self) -> RegAllocStats {
+        self.next_vreg = next_vreg_index(self.module, self.func_id);
+        lower_constraints(self.module, self.func_id);
+        let depths = loop_depths(self.module, self.func_id);
+        let mut n_spilled = 0;
+        let assignment = loop {
+            let mut graph = self.build(&depths);
+            graph.make_worklist();
+            loop {
+                if !graph.simplify_worklist.is_empty() {
+                    graph.simplify();
+                } else if !graph.worklist_moves.is_empty() {
+                    graph.coalesce();
+                } else if !graph.freeze_worklist.is_empty() {
+                    graph.freeze();
+                } else if !graph.spill_worklist.is_empty() {
+                    graph.select_spill();
+                } else {
+                    break;
+                }
+            }
+            let (assignment, spilled) = graph.assign_colors();
+            if spilled.is_empty() {
+                break assignment;
+            }
+            n_spilled += spilled.len();
+            for vreg in spilled {
+                let temps = insert_spill_code(self.module, self.func_id, vreg, &mut self.next_vreg);
+                self.no_spill.extend(temps);
+            }
+        };
+        assign_regs(self.module, self.func_id, &assignment);
+        let stats = RegAllocStats {
+            spilled: n_spilled,
+            coalesced: remove_identity_moves(self.module, self.func_id),
+        };
+        debug!(
+            "graph coloring: {}: {}",
+            self.module.get_func(self.func_id).name,
+            stats
+        );
+        stats
+    }
+
+    fn build(&self, depths: &HashMap<AsmValueId, u32>) -> Graph {
+        let mut graph = Graph::new();
+        let liveness = Liveness::new(self.module, self.func_id);
+        for bb_id in &liveness.bbs {
+            let weight = 10f64.powi(depths[bb_id].min(8) as i32);
+            let mut live: HashSet<Reg> = liveness.live_out[bb_id].clone();
+            for inst_id in self.module.get_bb(*bb_id).insts.iter().rev() {
+                let inst = self.module.get_inst(*inst_id);
+                let (defs, uses) = inst_defs_uses(inst);
+                if let Some((to, from)) = as_copy(inst) {
+                    live.remove(&from);
+                    let m = graph.moves.len();
+                    let (to, from) = (graph.node(to), graph.node(from));
+                    graph.moves.push((to, from));
+                    graph.move_state.push(MoveState::Worklist);
+                    graph.worklist_moves.insert(m);
+                    graph.move_list[to].push(m);
+                    graph.move_list[from].push(m);
+                }
+                live.extend(defs.iter().copied());
+                for d in &defs {
+                    let d = graph.node(*d);
+                    for l in &live {
+                        let l = graph.node(*l);
+                        graph.add_edge(l, d);
+                    }
+                }
+                for reg in defs.iter().chain(uses.iter()) {
+                    let n = graph.node(*reg);
+                    graph.spill_cost[n] += weight;
+                }
+                for d in &defs {
+                    live.remove(d);
+                }
+                live.extend(uses);
+            }
+        }
+        for (n, reg) in graph.nodes.iter().enumerate() {
+            if matches!(reg, Reg::Virt(vreg) if self.no_spill.contains(vreg)) {
+                graph.spill_cost[n] = f64::INFINITY;
+            }
+        }
+        graph
+    }
+}
+
+#[cfg(test)]
+fn count_copies(module: &AsmModule, func_id: AsmValueId) -> usize {
+    module
+        .get_func(func_id)
+        .bbs
+        .iter()
+        .flat_map(|bb_id| module.get_bb(*bb_id).insts.iter())
+        .filter(|inst_id| as_copy(module.get_inst(**inst_id)).is_some())
+        .count()
+}
+
+#[test]
+fn test_graph_coloring_coalesce_phi_moves() {
+    let src = "int main() {
+        int i = 0, s = 0;
+        while (i < 10) { s = s + i; i = i + 1; }
+        return s;
+    }";
+    let mut module = super::reg_alloc::build_asm(src);
+    let stats = run(&mut module);
+    let main = module.funcs[0];
+    assert!(!super::reg_alloc::has_virt_reg(&module, main));
+    assert_eq!(stats.spilled, 0);
+    assert!(stats.coalesced > 0);
+
+    let mut linear = super::reg_alloc::build_asm(src);
+    super::reg_alloc::run(&mut linear);
+    assert!(count_copies(&module, main) <= count_copies(&linear, linear.funcs[0]));
+}
+
+#[test]
+fn test_graph_coloring_spill_outside_loop() {
+    let n = 14;
+    let decls: String = (0..n).map(|i| format!("int a{} = getint();", i)).collect();
+    let sum: Vec<String> = (0..n).map(|i| format!("a{}", i)).collect();
+    let mut module = super::reg_alloc::build_asm(&format!(
+        "int getint(); int main() {{ {} int i = 0; int s = 0;
+        while (i < 100) {{ s = s + i * i; i = i + 1; }}
+        return s + {}; }}",
+        decls,
+        sum.join(" + ")
+    ));
+    let stats = run(&mut module);
+    let main = module.funcs[0];
+    assert!(!super::reg_alloc::has_virt_reg(&module, main));
+    assert!(stats.spilled > 0);
+    assert!(module.get_func(main).stack_state.spill_size > 0);
+}
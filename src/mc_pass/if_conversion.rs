@@ -0,0 +1,181 @@
+//! 条件执行（if-conversion）：把机器 CFG 上小的三角形和菱形改为条件执行的指令，省去跳转
+//!
+//! ```text
+//! 三角形  H: bCC J; S: ...; J:       =>  H: ...(!CC); J:
+//! 菱形    H: bCC T; F: ...; b J; T: ...; J:  =>  H: ...(!CC); ...(CC); J:
+//! ```
+//!
+//! 在寄存器分配和窥孔优化之后运行，此时分支已经直接按比较的条件跳转。
+//! 被合并的块中的指令都不改变标志位，两侧的指令在运行时至多执行一侧，互不影响
+use crate::{mc::*, mc_inst::*};
+
+/// 合并进前驱的块最多的指令数，更长时跳过的代价小于执行条件不成立的指令
+const MAX_PREDICATED: usize = 4;
+
+pub fn run(module: &mut AsmModule) -> usize {
+    let mut converted = 0;
+    for func_id in module.funcs.clone() {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for bb_id in module.get_func(func_id).bbs.clone() {
+                if module.get_func(func_id).bbs.contains(&bb_id) && convert(module, func_id, bb_id)
+                {
+                    converted += 1;
+                    changed = true;
+                }
+            }
+        }
+    }
+    converted
+}
+
+/// 以 head 为入口的三角形或菱形改为条件执行
+fn convert(module: &mut AsmModule, func_id: AsmValueId, head: AsmValueId) -> bool {
+    let Some((cond, taken, not_taken, branches)) = cond_branch(module, head) else {
+        return false;
+    };
+    let side = |bb_id| side_block(module, head, bb_id);
+    // (块, 执行条件) 和汇合块
+    let (blocks, join) = match (side(not_taken), side(taken)) {
+        (Some(f), Some(t)) if f == t => (vec![(not_taken, cond.neg()), (taken, cond)], f),
+        (Some(f), _) if f == taken => (vec![(not_taken, cond.neg())], taken),
+        (_, Some(t)) if t == not_taken => (vec![(taken, cond)], not_taken),
+        _ => return false,
+    };
+    if join == head {
+        return false;
+    }
+
+    let mut insts = module.get_bb(head).insts.clone();
+    insts.truncate(insts.len() - branches);
+    for (bb_id, cond) in &blocks {
+        for inst_id in block_body(module, *bb_id) {
+            module.get_inst_mut(inst_id).set_cond(cond.clone());
+            insts.push(inst_id);
+        }
+        remove_block(module, func_id, *bb_id);
+        let preds = &mut module.get_bb_mut(join).preds;
+        preds.retain(|pred| pred != bb_id);
+    }
+    if !module.get_bb(join).preds.contains(&head) {
+        module.get_bb_mut(join).preds.push(head);
+    }
+    if module.get_bb(head).next != Some(join) {
+        let label = module.get_bb(join).name.clone();
+        let jump = BrInst::new_with_label(Cond::AL, join, label);
+        insts.push(module.alloc_value(AsmValue::Inst(jump.into())));
+    }
+    let bb = module.get_bb_mut(head);
+    bb.insts = insts;
+    bb.succs = vec![join];
+
+    // 汇合块只剩 head 一个前驱时并入 head，外层的分支可以继续转换
+    if module.get_bb(join).preds == [head] && module.get_bb(head).next == Some(join) {
+        let join_bb = module.get_bb(join);
+        let (join_insts, succs) = (join_bb.insts.clone(), join_bb.succs.clone());
+        for succ in &succs {
+            for pred in module.get_bb_mut(*succ).preds.iter_mut() {
+                if *pred == join {
+                    *pred = head;
+                }
+            }
+        }
+        let bb = module.get_bb_mut(head);
+        bb.insts.extend(join_insts);
+        bb.succs = succs;
+        remove_block(module, func_id, join);
+    }
+    true
+}
+
+/// 块末尾的条件分支：(条件, 跳转目标, 不跳转时的去处, 分支指令数)
+fn cond_branch(
+    module: &AsmModule,
+    bb_id: AsmValueId,
+) -> Option<(Cond, AsmValueId, AsmValueId, usize)> {
+    let bb = module.get_bb(bb_id);
+    let br = |inst_id: &AsmValueId| module.get_inst(*inst_id).as_br();
+    let (cond_br, not_taken, branches) = match bb.insts.as_slice() {
+        [.., cond_id, jump_id] if br(jump_id).is_some_and(|jump| jump.cond == Cond::AL) => {
+            (br(cond_id)?, br(jump_id)?.target, 2)
+        }
+        [.., cond_id] => (br(cond_id)?, bb.next?, 1),
+        [] => return None,
+    };
+    if cond_br.cond == Cond::AL || cond_br.target == not_taken {
+        return None;
+    }
+    Some((cond_br.cond.clone(), cond_br.target, not_taken, branches))
+}
+
+/// 只从 head 进入、只有一个后继、指令都可以条件执行的小块，返回它的后继
+fn side_block(module: &AsmModule, head: AsmValueId, bb_id: AsmValueId) -> Option<AsmValueId> {
+    let bb = module.get_bb(bb_id);
+    let [succ] = bb.succs[..] else {
+        return None;
+    };
+    if bb_id == head || bb.preds != [head] || succ == bb_id {
+        return None;
+    }
+    // 末尾没有跳转时顺序落入后继
+    let jumps = match bb.insts.last().and_then(|id| module.get_inst(*id).as_br()) {
+        Some(br) => br.cond == Cond::AL && br.target == succ,
+        None => bb.next == Some(succ),
+    };
+    let body = block_body(module, bb_id);
+    let predicable = body
+        .iter()
+        .all(|inst_id| module.get_inst(*inst_id).is_predicable());
+    (jumps && predicable && body.len() <= MAX_PREDICATED).then_some(succ)
+}
+
+/// 去掉末尾无条件跳转后的指令
+fn block_body(module: &AsmModule, bb_id: AsmValueId) -> Vec<AsmValueId> {
+    let mut insts = module.get_bb(bb_id).insts.clone();
+    if insts
+        .last()
+        .is_some_and(|inst_id| module.get_inst(*inst_id).is_br())
+    {
+        insts.pop();
+    }
+    insts
+}
+
+fn remove_block(module: &mut AsmModule, func_id: AsmValueId, bb_id: AsmValueId) {
+    let bb = module.get_bb(bb_id);
+    let (prev, next) = (bb.prev, bb.next);
+    if let Some(prev) = prev {
+        module.get_bb_mut(prev).next = next;
+    }
+    if let Some(next) = next {
+        module.get_bb_mut(next).prev = prev;
+    }
+    module.get_func_mut(func_id).bbs.retain(|id| *id != bb_id);
+}
+
+#[test]
+fn test_if_conversion() {
+    use crate::{arm_printer, mc_pass::reg_alloc};
+
+    let mut module = reg_alloc::build_asm(
+        "int getint();
+        int max(int a, int b) { int m = b; if (a > b) { m = a; } return m; }
+        int abs(int x) { int r = x; if (x < 0) { r = 0 - x; } return r; }
+        int sel(int c, int a, int b) { int r; if (c == 1) { r = a + b; } else { r = a - b; } return r; }",
+    );
+    reg_alloc::run(&mut module);
+    super::peephole::run(&mut module);
+    assert_eq!(run(&mut module), 3);
+    let asm = arm_printer::print(&mut module);
+
+    // 三个函数都不再有条件跳转，也没有多余的块
+    for func_id in module.funcs.clone() {
+        let func = module.get_func(func_id);
+        assert_eq!(func.bbs.len(), 1, "{}", func.name);
+    }
+    assert!(!asm.lines().any(|line| line.trim_start().starts_with('B')));
+    assert!(asm.contains("MOVGT\t") || asm.contains("MOVLE\t"));
+    assert!(asm.contains("SUBLT\t") || asm.contains("SUBGE\t"));
+    assert!(asm.contains("ADDEQ\t") || asm.contains("ADDNE\t"));
+}
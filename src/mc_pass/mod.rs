@@ -0,0 +1,5 @@
+pub mod frame_lowering;
+pub mod graph_coloring;
+pub mod if_conversion;
+pub mod peephole;
+pub mod reg_alloc;
@@ -0,0 +1,939 @@
+//! 窥孔优化，在寄存器分配之后、栈帧展开之前运行
+//!
+//! 每条规则只改写一个基本块内相邻的几条指令，寄存器之后是否还会被读取由块出口的活跃信息判断。
+//! 此时 ret 和尾调用上仍带有返回值、实参寄存器的 use，删改指令不会破坏调用约定
+use std::collections::HashSet;
+
+use log::debug;
+
+use crate::{mc::*, mc_inst::*};
+
+use super::reg_alloc::{inst_defs_uses, make_copy, Liveness, Reg};
+
+/// 改写规则：在一个基本块上原地改写，返回改写的次数
+pub type Rule = fn(&mut AsmModule, &Liveness, AsmValueId) -> usize;
+
+/// 依次应用的规则。乘 2 的幂先变为移位，才能继续折叠进 operand2，而不是合并为 mla
+pub const RULES: &[(&str, Rule)] = &[
+    ("redundant-mov", remove_redundant_mov),
+    ("store-load", forward_store_to_load),
+    ("mov-imm", combine_movw_movt),
+    ("fold-imm", fold_imm_operand),
+    ("mul-pow2", mul_pow2_to_shift),
+    ("fold-shift", fold_shift_operand),
+    ("mla", fuse_mul_add),
+    ("cmp-branch", branch_on_flags),
+    ("jump-thread", thread_jumps),
+    ("fall-through", remove_fall_through),
+];
+
+pub fn run(module: &mut AsmModule) -> usize {
+    run_rules(module, RULES)
+}
+
+/// 反复应用给定的规则直到没有变化，返回改写的总次数
+pub fn run_rules(module: &mut AsmModule, rules: &[(&str, Rule)]) -> usize {
+    let mut total = 0;
+    for func_id in module.funcs.clone() {
+        loop {
+            // 规则只删除定值或缩短活跃区间，一轮之内沿用同一份活跃信息是保守的
+            let liveness = Liveness::new(module, func_id);
+            let mut changed = 0;
+            for bb_id in module.get_func(func_id).bbs.clone() {
+                for (name, rule) in rules {
+                    let n = rule(module, &liveness, bb_id);
+                    if n > 0 {
+                        debug!("peephole {}: {} in {}", name, n, module.get_bb(bb_id).name);
+                    }
+                    changed += n;
+                }
+            }
+            remove_unreachable_blocks(module, func_id);
+            if changed == 0 {
+                break;
+            }
+            total += changed;
+        }
+    }
+    total
+}
+
+/// 删除 mov r, r 和 vmov s, s
+pub fn remove_redundant_mov(module: &mut AsmModule, _: &Liveness, bb_id: AsmValueId) -> usize {
+    let insts = module.get_bb(bb_id).insts.clone();
+    let kept: Vec<_> = insts
+        .iter()
+        .copied()
+        .filter(|inst_id| match module.get_inst(*inst_id) {
+            AsmInst::Mov(mov) => mov.ty != MovType::Reg || mov.get_defs() != mov.get_uses(),
+            AsmInst::VMov(vmov) => vmov.ty != VMovType::CPY || vmov.get_defs() != vmov.get_uses(),
+            _ => true,
+        })
+        .collect();
+    let n = insts.len() - kept.len();
+    module.get_bb_mut(bb_id).insts = kept;
+    n
+}
+
+/// str 之后读取同一地址的 ldr 改为寄存器拷贝，读入的就是被存的寄存器时直接删除
+pub fn forward_store_to_load(module: &mut AsmModule, _: &Liveness, bb_id: AsmValueId) -> usize {
+    let mut insts = module.get_bb(bb_id).insts.clone();
+    let mut n = 0;
+    let mut i = 0;
+    while i < insts.len() {
+        let (val, addr) = match module.get_inst(insts[i]) {
+            inst @ (AsmInst::STR(_) | AsmInst::VSTR(_)) if inst.cond() == Cond::AL => {
+                let uses = module.get_inst(insts[i]).get_uses();
+                (uses[0].clone(), uses[1].clone())
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        for j in i + 1..insts.len() {
+            let inst = module.get_inst(insts[j]);
+            let loaded = match inst {
+                AsmInst::LDR(_) | AsmInst::VLDR(_) if inst.get_uses()[0] == addr => {
+                    Some(inst.get_defs()[0].clone())
+                }
+                _ => None,
+            };
+            if loaded.is_some() && inst.cond() != Cond::AL {
+                break;
+            }
+            if let Some(to) = loaded {
+                if to == val {
+                    insts.remove(j);
+                } else {
+                    insts[j] = make_copy(module, to, val);
+                }
+                n += 1;
+                break;
+            }
+            // 其间不能写内存，也不能改写被存的值和地址
+            let defs = inst.get_defs();
+            if matches!(
+                inst,
+                AsmInst::STR(_) | AsmInst::VSTR(_) | AsmInst::Call(_) | AsmInst::Br(_)
+            ) || defs.contains(&val)
+                || defs.contains(&addr)
+            {
+                break;
+            }
+        }
+        i += 1;
+    }
+    module.get_bb_mut(bb_id).insts = insts;
+    n
+}
+
+/// movw + movt 装入的常量是合法的 Operand2 时改为一条 mov，取反后合法时改为 mvn
+pub fn combine_movw_movt(module: &mut AsmModule, _: &Liveness, bb_id: AsmValueId) -> usize {
+    let mut insts = module.get_bb(bb_id).insts.clone();
+    let mut n = 0;
+    let mut i = 0;
+    while i + 1 < insts.len() {
+        let (AsmInst::Mov(movw), AsmInst::Mov(movt)) =
+            (module.get_inst(insts[i]), module.get_inst(insts[i + 1]))
+        else {
+            i += 1;
+            continue;
+        };
+        let (Some(lo), Some(hi)) = (int_imm(&movw.get_uses()[0]), int_imm(&movt.get_uses()[0]))
+        else {
+            i += 1;
+            continue;
+        };
+        if movw.ty != MovType::Movw
+            || movt.ty != MovType::Movt
+            || movw.cond != Cond::AL
+            || movt.cond != Cond::AL
+            || movw.get_defs() != movt.get_defs()
+        {
+            i += 1;
+            continue;
+        }
+        let value = lo | (hi << 16);
+        let (ty, imm) = if is_operand2(value) {
+            (MovType::Reg, value)
+        } else if is_operand2(!value) {
+            (MovType::Mvn, !value)
+        } else {
+            i += 1;
+            continue;
+        };
+        let to = movw.get_defs()[0].clone();
+        let mov = MovInst::new(ty, to, IntImm::new(imm).into(), None);
+        module.set_inst(insts[i], mov.into());
+        insts.remove(i + 1);
+        n += 1;
+    }
+    module.get_bb_mut(bb_id).insts = insts;
+    n
+}
+
+/// mov r, #imm 之后的运算或比较以 r 为第二个操作数，且 r 不再使用时直接使用立即数
+pub fn fold_imm_operand(module: &mut AsmModule, liveness: &Liveness, bb_id: AsmValueId) -> usize {
+    let mut insts = module.get_bb(bb_id).insts.clone();
+    let mut n = 0;
+    let mut i = 0;
+    while i + 1 < insts.len() {
+        let Some((reg, value)) = mov_imm(module.get_inst(insts[i])) else {
+            i += 1;
+            continue;
+        };
+        let imm = Imm::Int(IntImm::new(value));
+        let mut next = module.get_inst(insts[i + 1]).clone();
+        let uses = next.get_uses();
+        let folded = match &next {
+            AsmInst::BinOp(bin) if bin.shift.is_none() && bin.is_imm_fit(&imm) => {
+                if uses[1] == reg && uses[0] != reg {
+                    Some(vec![uses[0].clone(), imm.into()])
+                } else if uses[0] == reg && uses[1] != reg && is_commutative(&bin.op) {
+                    Some(vec![uses[1].clone(), imm.into()])
+                } else {
+                    None
+                }
+            }
+            AsmInst::CMP(_) if uses[1] == reg && uses[0] != reg && Operand2::is_imm_fit(&imm) => {
+                Some(vec![uses[0].clone(), imm.into()])
+            }
+            _ => None,
+        };
+        let Some(folded) = folded else {
+            i += 1;
+            continue;
+        };
+        if !next.get_defs().contains(&reg)
+            && !is_dead_after(module, liveness, bb_id, &insts, i + 1, &reg)
+        {
+            i += 1;
+            continue;
+        }
+        next.set_uses(folded);
+        module.set_inst(insts[i + 1], next);
+        insts.remove(i);
+        n += 1;
+    }
+    module.get_bb_mut(bb_id).insts = insts;
+    n
+}
+
+/// mov c, #2^k; mul t, x, c 且 c 不再使用时改为 lsl t, x, #k
+pub fn mul_pow2_to_shift(module: &mut AsmModule, liveness: &Liveness, bb_id: AsmValueId) -> usize {
+    let mut insts = module.get_bb(bb_id).insts.clone();
+    let mut n = 0;
+    let mut i = 0;
+    while i + 1 < insts.len() {
+        let Some((reg, value)) = mov_imm(module.get_inst(insts[i])) else {
+            i += 1;
+            continue;
+        };
+        let AsmInst::BinOp(mul) = module.get_inst(insts[i + 1]) else {
+            i += 1;
+            continue;
+        };
+        let (to, uses) = (mul.get_defs()[0].clone(), mul.get_uses());
+        let other = if uses[1] == reg && uses[0] != reg {
+            uses[0].clone()
+        } else if uses[0] == reg && uses[1] != reg {
+            uses[1].clone()
+        } else {
+            i += 1;
+            continue;
+        };
+        if mul.op != BinaryOp::Mul
+            || mul.cond != Cond::AL
+            || value < 2
+            || !value.is_power_of_two()
+            || to != reg && !is_dead_after(module, liveness, bb_id, &insts, i + 1, &reg)
+        {
+            i += 1;
+            continue;
+        }
+        let shift = IntImm::new(value.trailing_zeros());
+        let lsl = BinOpInst::new(BinaryOp::Shl, to, other, shift.into());
+        module.set_inst(insts[i + 1], lsl.into());
+        insts.remove(i);
+        n += 1;
+    }
+    module.get_bb_mut(bb_id).insts = insts;
+    n
+}
+
+/// lsl/asr t, x, #n 之后的运算以 t 为第二个操作数，且 t 不再使用时把移位折叠进 operand2：
+/// add d, a, x, lsl #n
+pub fn fold_shift_operand(module: &mut AsmModule, liveness: &Liveness, bb_id: AsmValueId) -> usize {
+    let mut insts = module.get_bb(bb_id).insts.clone();
+    let mut n = 0;
+    let mut i = 0;
+    while i + 1 < insts.len() {
+        let (AsmInst::BinOp(shift), AsmInst::BinOp(bin)) =
+            (module.get_inst(insts[i]), module.get_inst(insts[i + 1]))
+        else {
+            i += 1;
+            continue;
+        };
+        let (tmp, x) = (shift.get_defs()[0].clone(), shift.get_uses()[0].clone());
+        let (Some(amount), AsmOperand::IntReg(_)) = (int_imm(&shift.get_uses()[1]), &x) else {
+            i += 1;
+            continue;
+        };
+        let uses = bin.get_uses();
+        let other = if uses[1] == tmp && uses[0] != tmp {
+            uses[0].clone()
+        } else if uses[0] == tmp && uses[1] != tmp && is_commutative(&bin.op) {
+            uses[1].clone()
+        } else {
+            i += 1;
+            continue;
+        };
+        // asr #0 在 operand2 中表示 asr #32
+        if !matches!(shift.op, BinaryOp::Shl | BinaryOp::Shr)
+            || amount == 0
+            || shift.cond != Cond::AL
+            || shift.shift.is_some()
+            || bin.shift.is_some()
+            || !matches!(
+                bin.op,
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor
+            )
+            || !matches!(other, AsmOperand::IntReg(_))
+            || bin.get_defs()[0] != tmp
+                && !is_dead_after(module, liveness, bb_id, &insts, i + 1, &tmp)
+        {
+            i += 1;
+            continue;
+        }
+        let mut folded = bin.clone();
+        folded.shift = Some((shift.op.clone(), amount));
+        folded.set_uses(vec![other, x]);
+        module.set_inst(insts[i + 1], folded.into());
+        insts.remove(i);
+        n += 1;
+    }
+    module.get_bb_mut(bb_id).insts = insts;
+    n
+}
+
+/// mul t, a, b 之后紧跟 add d, t, c 或 sub d, c, t，且 t 不再使用时合并为 mla/mls
+pub fn fuse_mul_add(module: &mut AsmModule, liveness: &Liveness, bb_id: AsmValueId) -> usize {
+    let mut insts = module.get_bb(bb_id).insts.clone();
+    let mut n = 0;
+    let mut i = 0;
+    while i + 1 < insts.len() {
+        let (AsmInst::BinOp(mul), AsmInst::BinOp(bin)) =
+            (module.get_inst(insts[i]), module.get_inst(insts[i + 1]))
+        else {
+            i += 1;
+            continue;
+        };
+        let (tmp, factors) = (mul.get_defs()[0].clone(), mul.get_uses());
+        let (to, uses) = (bin.get_defs()[0].clone(), bin.get_uses());
+        let addend = if uses[1] == tmp && uses[0] != tmp {
+            Some(uses[0].clone())
+        } else if uses[0] == tmp && uses[1] != tmp && bin.op == BinaryOp::Add {
+            Some(uses[1].clone())
+        } else {
+            None
+        };
+        let Some(addend) = addend else {
+            i += 1;
+            continue;
+        };
+        if mul.op != BinaryOp::Mul
+            || !matches!(bin.op, BinaryOp::Add | BinaryOp::Sub)
+            || mul.cond != Cond::AL
+            || bin.cond != Cond::AL
+            || mul.shift.is_some()
+            || bin.shift.is_some()
+            || !matches!(addend, AsmOperand::IntReg(_))
+            || to != tmp && !is_dead_after(module, liveness, bb_id, &insts, i + 1, &tmp)
+        {
+            i += 1;
+            continue;
+        }
+        let (rn, rm) = (factors[0].clone(), factors[1].clone());
+        let fused: AsmInst = if bin.op == BinaryOp::Add {
+            MLAInst::new(to, rn, rm, addend).into()
+        } else {
+            MLSInst::new(to, rn, rm, addend).into()
+        };
+        module.set_inst(insts[i + 1], fused);
+        insts.remove(i);
+        n += 1;
+    }
+    module.get_bb_mut(bb_id).insts = insts;
+    n
+}
+
+/// 比较结果物化为 0/1 后再与 0 比较并跳转时，直接按原比较的条件跳转：
+/// movw r, #0; movwCC r, #1; ...; cmp r, #0; bne L => ...; bCC L
+pub fn branch_on_flags(module: &mut AsmModule, liveness: &Liveness, bb_id: AsmValueId) -> usize {
+    let mut insts = module.get_bb(bb_id).insts.clone();
+    let mut n = 0;
+    let mut i = 0;
+    while i + 1 < insts.len() {
+        let Some((reg, cond)) = materialized_cond(module, insts[i], insts[i + 1]) else {
+            i += 1;
+            continue;
+        };
+        // 中间的指令不能改变标志位，也不能读写 r
+        let Some(reg_id) = Reg::from_operand(&reg) else {
+            i += 1;
+            continue;
+        };
+        let mut cmp = None;
+        for (j, inst_id) in insts.iter().enumerate().skip(i + 2) {
+            let inst = module.get_inst(*inst_id);
+            if let AsmInst::CMP(c) = inst {
+                if c.get_uses()[0] == reg && int_imm(&c.get_uses()[1]) == Some(0) {
+                    cmp = Some(j);
+                }
+                break;
+            }
+            let (defs, uses) = inst_defs_uses(inst);
+            if matches!(
+                inst,
+                AsmInst::FCMP(_) | AsmInst::VMRS(_) | AsmInst::Call(_) | AsmInst::Br(_)
+            ) || defs.contains(&reg_id)
+                || uses.contains(&reg_id)
+            {
+                break;
+            }
+        }
+        let Some(j) = cmp else {
+            i += 1;
+            continue;
+        };
+        let br_cond = match insts.get(j + 1).map(|inst_id| module.get_inst(*inst_id)) {
+            Some(AsmInst::Br(br)) if br.cond == Cond::NE => cond,
+            Some(AsmInst::Br(br)) if br.cond == Cond::EQ => cond.neg(),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        if !is_dead_after(module, liveness, bb_id, &insts, j + 1, &reg) {
+            i += 1;
+            continue;
+        }
+        module.get_inst_mut(insts[j + 1]).as_br_mut().unwrap().cond = br_cond;
+        insts.remove(j);
+        insts.drain(i..i + 2);
+        n += 1;
+    }
+    module.get_bb_mut(bb_id).insts = insts;
+    n
+}
+
+/// 跳往只含一条无条件跳转的块时，直接跳到最终的目标
+pub fn thread_jumps(module: &mut AsmModule, _: &Liveness, bb_id: AsmValueId) -> usize {
+    let mut n = 0;
+    for inst_id in module.get_bb(bb_id).insts.clone() {
+        let Some(br) = module.get_inst(inst_id).as_br() else {
+            continue;
+        };
+        let old = br.target;
+        let new = jump_target(module, old);
+        if new == old {
+            continue;
+        }
+        let label = module.get_bb(new).name.clone();
+        let br = module.get_inst_mut(inst_id).as_br_mut().unwrap();
+        br.target = new;
+        br.target_label = Some(label);
+        let bb = module.get_bb_mut(bb_id);
+        if let Some(succ) = bb.succs.iter_mut().find(|succ| **succ == old) {
+            *succ = new;
+        }
+        let old_preds = &mut module.get_bb_mut(old).preds;
+        if let Some(pos) = old_preds.iter().position(|pred| *pred == bb_id) {
+            old_preds.remove(pos);
+        }
+        module.get_bb_mut(new).preds.push(bb_id);
+        n += 1;
+    }
+    n
+}
+
+/// 块末尾跳往下一个块的无条件跳转可以删除；bCC next; b L 改为 b!CC L
+pub fn remove_fall_through(module: &mut AsmModule, _: &Liveness, bb_id: AsmValueId) -> usize {
+    let bb = module.get_bb(bb_id);
+    let Some(next) = bb.next else {
+        return 0;
+    };
+    let mut insts = bb.insts.clone();
+    let mut n = 0;
+    if let [.., cond_id, jump_id] = insts[..] {
+        if let (Some(cond_br), Some(jump)) = (
+            module.get_inst(cond_id).as_br(),
+            module.get_inst(jump_id).as_br(),
+        ) {
+            if cond_br.cond != Cond::AL && cond_br.target == next && jump.cond == Cond::AL {
+                let mut br = jump.clone();
+                br.cond = cond_br.cond.neg();
+                module.set_inst(cond_id, br.into());
+                insts.pop();
+                n += 1;
+            }
+        }
+    }
+    if let Some(jump_id) = insts.last() {
+        if let Some(jump) = module.get_inst(*jump_id).as_br() {
+            if jump.cond == Cond::AL && jump.target == next {
+                insts.pop();
+                n += 1;
+            }
+        }
+    }
+    module.get_bb_mut(bb_id).insts = insts;
+    n
+}
+
+/// 跳转线程化之后不再有前驱的块
+fn remove_unreachable_blocks(module: &mut AsmModule, func_id: AsmValueId) {
+    let bbs = module.get_func(func_id).bbs.clone();
+    for bb_id in bbs.iter().skip(1) {
+        let bb = module.get_bb(*bb_id);
+        if !bb.preds.is_empty() {
+            continue;
+        }
+        let (prev, next, succs) = (bb.prev, bb.next, bb.succs.clone());
+        for succ in succs {
+            let preds = &mut module.get_bb_mut(succ).preds;
+            if let Some(pos) = preds.iter().position(|pred| pred == bb_id) {
+                preds.remove(pos);
+            }
+        }
+        if let Some(prev) = prev {
+            module.get_bb_mut(prev).next = next;
+        }
+        if let Some(next) = next {
+            module.get_bb_mut(next).prev = prev;
+        }
+        module.get_func_mut(func_id).bbs.retain(|id| id != bb_id);
+    }
+}
+
+/// 沿着只含无条件跳转的块走到最终的目标，遇到环时停下
+fn jump_target(module: &AsmModule, mut bb_id: AsmValueId) -> AsmValueId {
+    let mut visited = HashSet::new();
+    while visited.insert(bb_id) {
+        match module.get_bb(bb_id).insts.as_slice() {
+            [inst_id] => match module.get_inst(*inst_id).as_br() {
+                Some(br) if br.cond == Cond::AL => bb_id = br.target,
+                _ => break,
+            },
+            _ => break,
+        }
+    }
+    bb_id
+}
+
+/// 寄存器在 insts 的第 pos 条指令之后不再被读取
+fn is_dead_after(
+    module: &AsmModule,
+    liveness: &Liveness,
+    bb_id: AsmValueId,
+    insts: &[AsmValueId],
+    pos: usize,
+    op: &AsmOperand,
+) -> bool {
+    // ip 等不参与活跃分析的寄存器保守地视为活跃
+    let Some(reg) = Reg::from_operand(op) else {
+        return false;
+    };
+    for inst_id in &insts[pos + 1..] {
+        let (defs, uses) = inst_defs_uses(module.get_inst(*inst_id));
+        if uses.contains(&reg) {
+            return false;
+        }
+        if defs.contains(&reg) {
+            return true;
+        }
+    }
+    !liveness.live_out[&bb_id].contains(&reg)
+}
+
+/// movw r, #0; movwCC r, #1 物化的比较结果
+fn materialized_cond(
+    module: &AsmModule,
+    zero_id: AsmValueId,
+    one_id: AsmValueId,
+) -> Option<(AsmOperand, Cond)> {
+    let (reg, zero) = mov_imm(module.get_inst(zero_id))?;
+    let one = module.get_inst(one_id).as_mov()?;
+    if zero != 0
+        || one.ty != MovType::Movw
+        || one.cond == Cond::AL
+        || one.get_defs()[0] != reg
+        || int_imm(&one.get_uses()[0]) != Some(1)
+    {
+        return None;
+    }
+    Some((reg, one.cond.clone()))
+}
+
+/// 无条件地把整数常量装入寄存器的 mov
+fn mov_imm(inst: &AsmInst) -> Option<(AsmOperand, u32)> {
+    let mov = inst.as_mov()?;
+    let value = int_imm(&mov.get_uses()[0])?;
+    match (&mov.ty, &mov.cond) {
+        (MovType::Reg | MovType::Movw, Cond::AL) => Some((mov.get_defs()[0].clone(), value)),
+        (MovType::Mvn, Cond::AL) => Some((mov.get_defs()[0].clone(), !value)),
+        _ => None,
+    }
+}
+
+fn int_imm(op: &AsmOperand) -> Option<u32> {
+    match op {
+        AsmOperand::Imm(Imm::Int(imm)) => Some(imm.value),
+        _ => None,
+    }
+}
+
+fn is_operand2(value: u32) -> bool {
+    Operand2::is_imm_fit(&Imm::Int(IntImm::new(value)))
+}
+
+fn is_commutative(op: &BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor
+    )
+}
+
+/// 测试用：顺序相连的 n 个空基本块组成的函数
+#[cfg(test)]
+fn build_func(module: &mut AsmModule, n: usize) -> (AsmValueId, Vec<AsmValueId>) {
+    let func = AsmFunction {
+        name: "f".to_string(),
+        entry: None,
+        bbs: vec![],
+        stack_state: StackState::default(),
+        callee_saved_regs: vec![],
+        callee_saved_vfp_regs: vec![],
+    };
+    let func_id = module.alloc_value(AsmValue::Function(func));
+    module.funcs.push(func_id);
+    let mut bbs: Vec<AsmValueId> = vec![];
+    for i in 0..n {
+        let bb = AsmBlock {
+            prev: bbs.last().copied(),
+            next: None,
+            name: format!(".LBB_f_{}", i),
+            preds: vec![],
+            succs: vec![],
+            insts: vec![],
+        };
+        let bb_id = module.alloc_value(AsmValue::Block(bb));
+        if let Some(prev) = bbs.last() {
+            module.get_bb_mut(*prev).next = Some(bb_id);
+        }
+        bbs.push(bb_id);
+    }
+    module.get_func_mut(func_id).bbs = bbs.clone();
+    (func_id, bbs)
+}
+
+#[cfg(test)]
+fn push_inst(module: &mut AsmModule, bb_id: AsmValueId, inst: impl Into<AsmInst>) {
+    let inst_id = module.alloc_value(AsmValue::Inst(inst.into()));
+    module.get_bb_mut(bb_id).insts.push(inst_id);
+}
+
+#[cfg(test)]
+fn add_edge(module: &mut AsmModule, from: AsmValueId, to: AsmValueId) {
+    module.get_bb_mut(from).succs.push(to);
+    module.get_bb_mut(to).preds.push(from);
+}
+
+#[cfg(test)]
+fn block_asm(module: &mut AsmModule, bb_id: AsmValueId) -> Vec<String> {
+    use crate::arm_printer::ToArm;
+
+    let insts = module.get_bb(bb_id).insts.clone();
+    insts
+        .iter()
+        .map(|inst_id| module.get_inst(*inst_id).clone().to_arm(module))
+        .collect()
+}
+
+#[cfg(test)]
+fn reg(i: i64) -> AsmOperand {
+    IntReg::from(i).into()
+}
+
+#[cfg(test)]
+fn imm(value: u32) -> AsmOperand {
+    IntImm::new(value).into()
+}
+
+#[cfg(test)]
+fn ret_r0(module: &mut AsmModule, func_id: AsmValueId, bb_id: AsmValueId) {
+    let mut ret = RetInst::new(func_id);
+    ret.set_uses(vec![reg(0)]);
+    push_inst(module, bb_id, ret);
+}
+
+#[test]
+fn test_peephole_mov_and_imm() {
+    let mut module = AsmModule::new();
+    let (func_id, bbs) = build_func(&mut module, 1);
+    let bb = bbs[0];
+    push_inst(
+        &mut module,
+        bb,
+        MovInst::new(MovType::Reg, reg(1), reg(1), None),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        MovInst::new(MovType::Movw, reg(2), imm(0), None),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        MovInst::new(MovType::Movt, reg(2), imm(0xff00), None),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Add, reg(0), reg(0), reg(2)),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        MovInst::new(MovType::Movw, reg(3), imm(0xfffe), None),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        MovInst::new(MovType::Movt, reg(3), imm(0xffff), None),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Xor, reg(0), reg(3), reg(0)),
+    );
+    // r3 之后仍被读取，不能折叠
+    push_inst(
+        &mut module,
+        bb,
+        MovInst::new(MovType::Movw, reg(3), imm(4), None),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Sub, reg(0), reg(0), reg(3)),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Add, reg(0), reg(0), reg(3)),
+    );
+    ret_r0(&mut module, func_id, bb);
+
+    assert_eq!(
+        run_rules(&mut module, &[("redundant-mov", remove_redundant_mov)]),
+        1
+    );
+    assert_eq!(run_rules(&mut module, &[("mov-imm", combine_movw_movt)]), 2);
+    let asm = block_asm(&mut module, bb);
+    assert_eq!(asm[0], "MOV\tr2,#0xff000000");
+    assert_eq!(asm[2], "MVN\tr3,#0x1");
+
+    assert_eq!(run_rules(&mut module, &[("fold-imm", fold_imm_operand)]), 1);
+    let asm = block_asm(&mut module, bb);
+    assert_eq!(asm[0], "ADD \tr0, r0, #0xff000000");
+    // 0xfffffffe 不是合法的 Operand2
+    assert_eq!(asm[1], "MVN\tr3,#0x1");
+    assert_eq!(asm[4], "SUB \tr0, r0, r3");
+}
+
+#[test]
+fn test_peephole_store_load() {
+    let mut module = AsmModule::new();
+    let (func_id, bbs) = build_func(&mut module, 1);
+    let bb = bbs[0];
+    let slot: AsmOperand = StackOperand {
+        ty: StackOperandType::Spill,
+        offset: 8,
+    }
+    .into();
+    push_inst(&mut module, bb, STRInst::new(reg(1), slot.clone()));
+    push_inst(&mut module, bb, LDRInst::new(reg(2), reg(4)));
+    push_inst(&mut module, bb, LDRInst::new(reg(0), slot.clone()));
+    push_inst(&mut module, bb, LDRInst::new(reg(1), slot.clone()));
+    // 其间改写了被存的值，不能转发
+    push_inst(&mut module, bb, STRInst::new(reg(0), slot.clone()));
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Add, reg(0), reg(0), imm(1)),
+    );
+    push_inst(&mut module, bb, LDRInst::new(reg(3), slot));
+    ret_r0(&mut module, func_id, bb);
+
+    // 第二条 ldr 读入的 r1 仍是被存的值，直接删除
+    assert_eq!(
+        run_rules(&mut module, &[("store-load", forward_store_to_load)]),
+        2
+    );
+    let asm = block_asm(&mut module, bb);
+    assert_eq!(asm[2], "MOV\tr0,r1");
+    assert!(asm[3].starts_with("STR\tr0, "));
+    assert!(asm[5].starts_with("LDR \tr3, "));
+}
+
+#[test]
+fn test_peephole_mla_and_shift() {
+    let mut module = AsmModule::new();
+    let (func_id, bbs) = build_func(&mut module, 1);
+    let bb = bbs[0];
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Mul, reg(2), reg(0), reg(1)),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Add, reg(3), reg(2), reg(4)),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Mul, reg(2), reg(0), reg(1)),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Sub, reg(2), reg(3), reg(2)),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        MovInst::new(MovType::Movw, reg(5), imm(4), None),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Mul, reg(5), reg(1), reg(5)),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Add, reg(0), reg(2), reg(5)),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Shr, reg(6), reg(3), imm(3)),
+    );
+    push_inst(
+        &mut module,
+        bb,
+        BinOpInst::new(BinaryOp::Or, reg(0), reg(6), reg(0)),
+    );
+    ret_r0(&mut module, func_id, bb);
+
+    // 乘 4 先变为移位，不会被合并为 mla
+    assert_eq!(
+        run_rules(&mut module, &[("mul-pow2", mul_pow2_to_shift)]),
+        1
+    );
+    assert_eq!(block_asm(&mut module, bb)[4], "LSL \tr5, r1, #0x2");
+    assert_eq!(run_rules(&mut module, &[("mla", fuse_mul_add)]), 2);
+    let asm = block_asm(&mut module, bb);
+    assert_eq!(asm[0], "MLA \tr3, r0, r1, r4");
+    assert_eq!(asm[1], "MLS \tr2, r0, r1, r3");
+
+    assert_eq!(
+        run_rules(&mut module, &[("fold-shift", fold_shift_operand)]),
+        2
+    );
+    let asm = block_asm(&mut module, bb);
+    assert_eq!(asm[2], "ADD \tr0, r2, r1, LSL #0x2");
+    assert_eq!(asm[3], "ORR \tr0, r0, r3, ASR #0x3");
+}
+
+#[test]
+fn test_peephole_branches() {
+    let mut module = AsmModule::new();
+    let (func_id, bbs) = build_func(&mut module, 4);
+    let label = |module: &AsmModule, i: usize| module.get_bb(bbs[i]).name.clone();
+    // bb0: 比较结果物化后再比较，跳往只含跳转的 bb3
+    push_inst(&mut module, bbs[0], CMPInst::new(reg(0), reg(1)));
+    push_inst(
+        &mut module,
+        bbs[0],
+        MovInst::new(MovType::Movw, reg(2), imm(0), None),
+    );
+    push_inst(
+        &mut module,
+        bbs[0],
+        MovInst::new(MovType::Movw, reg(2), imm(1), Some(Cond::LT)),
+    );
+    push_inst(&mut module, bbs[0], CMPInst::new(reg(2), imm(0)));
+    let (l1, l3) = (label(&module, 1), label(&module, 3));
+    push_inst(
+        &mut module,
+        bbs[0],
+        BrInst::new_with_label(Cond::NE, bbs[1], l1),
+    );
+    push_inst(
+        &mut module,
+        bbs[0],
+        BrInst::new_with_label(Cond::AL, bbs[3], l3),
+    );
+    add_edge(&mut module, bbs[0], bbs[1]);
+    add_edge(&mut module, bbs[0], bbs[3]);
+    // bb1: 跳往紧随其后的 bb2
+    push_inst(
+        &mut module,
+        bbs[1],
+        MovInst::new(MovType::Movw, reg(0), imm(1), None),
+    );
+    let l2 = label(&module, 2);
+    push_inst(
+        &mut module,
+        bbs[1],
+        BrInst::new_with_label(Cond::AL, bbs[2], l2.clone()),
+    );
+    add_edge(&mut module, bbs[1], bbs[2]);
+    ret_r0(&mut module, func_id, bbs[2]);
+    push_inst(
+        &mut module,
+        bbs[3],
+        BrInst::new_with_label(Cond::AL, bbs[2], l2),
+    );
+    add_edge(&mut module, bbs[3], bbs[2]);
+
+    assert_eq!(
+        run_rules(&mut module, &[("cmp-branch", branch_on_flags)]),
+        1
+    );
+    assert_eq!(
+        block_asm(&mut module, bbs[0]),
+        vec!["CMP\tr0,r1", "BLT\t.LBB_f_1", "B\t.LBB_f_3"]
+    );
+
+    // bb3 不再有前驱后被删除
+    assert_eq!(run_rules(&mut module, &[("jump-thread", thread_jumps)]), 1);
+    assert_eq!(module.get_func(func_id).bbs, vec![bbs[0], bbs[1], bbs[2]]);
+    assert_eq!(module.get_bb(bbs[0]).succs, vec![bbs[1], bbs[2]]);
+    assert_eq!(module.get_bb(bbs[2]).next, None);
+
+    assert_eq!(
+        run_rules(&mut module, &[("fall-through", remove_fall_through)]),
+        2
+    );
+    assert_eq!(
+        block_asm(&mut module, bbs[0]),
+        vec!["CMP\tr0,r1", "BGE\t.LBB_f_2"]
+    );
+    assert_eq!(block_asm(&mut module, bbs[1]), vec!["MOVW\tr0,#0x1"]);
+}
@@ -0,0 +1,702 @@
+//! 线性扫描寄存器分配
+//!
+//! 1. 将 Prologue/Call/Ret 上的寄存器约束展开为与物理寄存器之间的 mov
+//! 2. 活跃变量分析；虚拟寄存器取单个区间（不考虑空洞），物理寄存器保留精确区间
+//! 3. 按起点扫描分配，分配不下时溢出终点最远的区间，插入 load/store 后重新分配
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::AddAssign,
+};
+
+use log::debug;
+
+use crate::{mc::*, mc_inst::*};
+
+pub fn run(module: &mut AsmModule) -> RegAllocStats {
+    let mut stats = RegAllocStats::default();
+    for func_id in module.funcs.clone() {
+        let mut ra = LinearScan::new(module, func_id);
+        stats += ra.run();
+    }
+    stats
+}
+
+/// 寄存器分配的统计信息
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RegAllocStats {
+    /// 溢出到栈上的虚拟寄存器数
+    pub spilled: usize,
+    /// 两端分到同一寄存器而删除的拷贝数
+    pub coalesced: usize,
+}
+
+impl AddAssign for RegAllocStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.spilled += rhs.spilled;
+        self.coalesced += rhs.coalesced;
+    }
+}
+
+impl fmt::Display for RegAllocStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} spilled, {} moves coalesced",
+            self.spilled, self.coalesced
+        )
+    }
+}
+
+/// 参与分配的物理寄存器：r0-r10、s0-s31
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum PhysReg {
+    Int(RegType),
+    Vfp(i64),
+}
+
+impl PhysReg {
+    pub fn from_operand(op: &AsmOperand) -> Option<PhysReg> {
+        match op {
+            AsmOperand::IntReg(reg) if i64::from(reg.ty) <= 10 => Some(PhysReg::Int(reg.ty)),
+            AsmOperand::VfpReg(reg) => Some(PhysReg::Vfp(reg.index)),
+            _ => None,
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, PhysReg::Vfp(_))
+    }
+
+    pub fn is_callee_saved(&self) -> bool {
+        match self {
+            PhysReg::Int(ty) => ty.is_callee_saved(),
+            PhysReg::Vfp(index) => VfpReg::from(*index).is_callee_saved(),
+        }
+    }
+
+    // 按分配优先级排列，caller-saved 在前
+    pub fn allocatable(is_float: bool) -> Vec<PhysReg> {
+        if is_float {
+            (0..32).map(PhysReg::Vfp).collect()
+        } else {
+            (0..=10).map(|i| PhysReg::Int(RegType::from(i))).collect()
+        }
+    }
+
+    // 函数调用会破坏的寄存器，ip、lr 不参与分配
+    pub fn caller_saved() -> Vec<PhysReg> {
+        (0..4)
+            .map(|i| PhysReg::Int(RegType::from(i)))
+            .chain((0..16).map(PhysReg::Vfp))
+            .collect()
+    }
+}
+
+impl From<PhysReg> for AsmOperand {
+    fn from(reg: PhysReg) -> Self {
+        match reg {
+            PhysReg::Int(ty) => IntReg::new(ty).into(),
+            PhysReg::Vfp(index) => VfpReg::from(index).into(),
+        }
+    }
+}
+
+/// 活跃分析中的寄存器
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Reg {
+    Virt(VirtReg),
+    Phys(PhysReg),
+}
+
+impl Reg {
+    pub fn from_operand(op: &AsmOperand) -> Option<Reg> {
+        match op {
+            AsmOperand::VirtReg(vreg) => Some(Reg::Virt(*vreg)),
+            _ => PhysReg::from_operand(op).map(Reg::Phys),
+        }
+    }
+}
+
+/// 指令写入和读取的寄存器，包括调用破坏的寄存器等隐式操作数
+pub fn inst_defs_uses(inst: &AsmInst) -> (Vec<Reg>, Vec<Reg>) {
+    let mut defs: Vec<Reg> = inst
+        .get_defs()
+        .iter()
+        .filter_map(Reg::from_operand)
+        .collect();
+    let mut uses: Vec<Reg> = inst
+        .get_uses()
+        .iter()
+        .filter_map(Reg::from_operand)
+        .collect();
+    match inst {
+        AsmInst::Call(call) => {
+            defs.extend(PhysReg::caller_saved().into_iter().map(Reg::Phys));
+            // 可变参数提升为 double 时直接写入 r0-r3，调用上没有对应的 use
+            if let CallConv::BaseCallConv(cc) = &call.cc {
+                uses.extend((0..cc.ncrn.min(4)).map(|i| Reg::Phys(PhysReg::Int(RegType::from(i)))));
+            }
+        }
+        // 条件执行的指令不一定写入，原值仍然活跃
+        _ if inst.cond() != Cond::AL => uses.extend(defs.iter().copied()),
+        _ => (),
+    }
+    (defs, uses)
+}
+
+/// 按 bbs 顺序线性化后的活跃信息
+///
+/// 第 i 条指令在位置 2i 读取操作数，在 2i+1 写入结果
+pub struct Liveness {
+    pub bbs: Vec<AsmValueId>,
+    pub live_in: HashMap<AsmValueId, HashSet<Reg>>,
+    pub live_out: HashMap<AsmValueId, HashSet<Reg>>,
+}
+
+impl Liveness {
+    pub fn new(module: &AsmModule, func_id: AsmValueId) -> Liveness {
+        let bbs = module.get_func(func_id).bbs.clone();
+        let mut gen = HashMap::new();
+        let mut kill = HashMap::new();
+        for bb_id in &bbs {
+            let mut bb_gen = HashSet::new();
+            let mut bb_kill = HashSet::new();
+            for inst_id in &module.get_bb(*bb_id).insts {
+                let (defs, uses) = inst_defs_uses(module.get_inst(*inst_id));
+                for reg in uses {
+                    if !bb_kill.contains(&reg) {
+                        bb_gen.insert(reg);
+                    }
+                }
+                bb_kill.extend(defs);
+            }
+            gen.insert(*bb_id, bb_gen);
+            kill.insert(*bb_id, bb_kill);
+        }
+
+        let mut live_in: HashMap<AsmValueId, HashSet<Reg>> =
+            bbs.iter().map(|bb_id| (*bb_id, HashSet::new())).collect();
+        let mut live_out = live_in.clone();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for bb_id in bbs.iter().rev() {
+                let mut out = HashSet::new();
+                for succ in &module.get_bb(*bb_id).succs {
+                    out.extend(live_in[succ].iter().copied());
+                }
+                let mut in_: HashSet<Reg> = out.difference(&kill[bb_id]).copied().collect();
+                in_.extend(gen[bb_id].iter().copied());
+                if in_ != live_in[bb_id] {
+                    live_in.insert(*bb_id, in_);
+                    changed = true;
+                }
+                live_out.insert(*bb_id, out);
+            }
+        }
+
+        Liveness {
+            bbs,
+            live_in,
+            live_out,
+        }
+    }
+
+    /// 每个寄存器的活跃区间 [start, end]
+    pub fn live_ranges(&self, module: &AsmModule) -> HashMap<Reg, Vec<(usize, usize)>> {
+        let mut ranges: HashMap<Reg, Vec<(usize, usize)>> = HashMap::new();
+        let mut pos = 0;
+        for bb_id in &self.bbs {
+            let insts = &module.get_bb(*bb_id).insts;
+            if insts.is_empty() {
+                continue;
+            }
+            let start = 2 * pos;
+            let end = 2 * (pos + insts.len()) - 1;
+            // 尚未遇到定值的寄存器 -> 区间终点
+            let mut open: HashMap<Reg, usize> =
+                self.live_out[bb_id].iter().map(|reg| (*reg, end)).collect();
+            for (i, inst_id) in insts.iter().enumerate().rev() {
+                let p = 2 * (pos + i);
+                let (defs, uses) = inst_defs_uses(module.get_inst(*inst_id));
+                for reg in defs {
+                    let e = open.remove(&reg).unwrap_or(p + 1);
+                    ranges.entry(reg).or_default().push((p + 1, e));
+                }
+                for reg in uses {
+                    open.entry(reg).or_insert(p);
+                }
+            }
+            for (reg, e) in open {
+                ranges.entry(reg).or_default().push((start, e));
+            }
+            pos += insts.len();
+        }
+        ranges
+    }
+}
+
+/// 生成寄存器之间的拷贝，按两端的类型选择 mov 或 vmov
+pub fn make_copy(module: &mut AsmModule, to: AsmOperand, from: AsmOperand) -> AsmValueId {
+    let inst: AsmInst = match (to.is_float(), from.is_float()) {
+        (false, false) => MovInst::new(MovType::Reg, to, from, None).into(),
+        (true, true) => VMovInst::new(VMovType::CPY, to, from).into(),
+        (true, false) => VMovInst::new(VMovType::A2S, to, from).into(),
+        (false, true) => VMovInst::new(VMovType::S2A, to, from).into(),
+    };
+    module.alloc_value(AsmValue::Inst(inst))
+}
+
+/// 将 Prologue/Call/Ret 上的约束展开为 mov，指令本身改为直接使用物理寄存器
+///
+/// 返回虚拟寄存器到其约束寄存器的映射，可作为分配时的偏好
+pub fn lower_constraints(module: &mut AsmModule, func_id: AsmValueId) -> HashMap<VirtReg, PhysReg> {
+    let bbs = module.get_func(func_id).bbs.clone();
+    // 没有被使用的调用返回值不需要拷贝出来
+    let mut used = HashSet::new();
+    for bb_id in &bbs {
+        for inst_id in &module.get_bb(*bb_id).insts {
+            for op in module.get_inst(*inst_id).get_uses() {
+                if let AsmOperand::VirtReg(vreg) = op {
+                    used.insert(vreg);
+                }
+            }
+        }
+    }
+
+    let mut hints = HashMap::new();
+    for bb_id in bbs {
+        let mut new_insts = vec![];
+        for inst_id in module.get_bb(bb_id).insts.clone() {
+            let mut inst = module.get_inst(inst_id).clone();
+            let (in_constraints, out_constraints) = match &mut inst {
+                AsmInst::Prologue(inst) => take_constraints(inst),
+                AsmInst::Call(inst) => take_constraints(inst),
+                AsmInst::Ret(inst) => take_constraints(inst),
+                _ => {
+                    new_insts.push(inst_id);
+                    continue;
+                }
+            };
+
+            let mut uses = inst.get_uses();
+            for op in uses.iter_mut() {
+                if let AsmOperand::VirtReg(vreg) = *op {
+                    if let Some(loc) = in_constraints.get(&vreg) {
+                        new_insts.push(make_copy(module, loc.clone(), op.clone()));
+                        if let Some(reg) = PhysReg::from_operand(loc) {
+                            hints.insert(vreg, reg);
+                        }
+                        *op = loc.clone();
+                    }
+                }
+            }
+            inst.set_uses(uses);
+
+            let mut after = vec![];
+            let mut defs = inst.get_defs();
+            for op in defs.iter_mut() {
+                if let AsmOperand::VirtReg(vreg) = *op {
+                    if let Some(loc) = out_constraints.get(&vreg) {
+                        if used.contains(&vreg) {
+                            after.push(make_copy(module, op.clone(), loc.clone()));
+                        }
+                        if let Some(reg) = PhysReg::from_operand(loc) {
+                            hints.insert(vreg, reg);
+                        }
+                        *op = loc.clone();
+                    }
+                }
+            }
+            inst.set_defs(defs);
+
+            module.set_inst(inst_id, inst);
+            new_insts.push(inst_id);
+            new_insts.extend(after);
+        }
+        module.get_bb_mut(bb_id).insts = new_insts;
+    }
+    hints
+}
+
+fn take_constraints(inst: &mut impl ConstraintsTrait) -> (RegConstraintMap, RegConstraintMap) {
+    (
+        std::mem::take(inst.get_in_constraints_mut()),
+        std::mem::take(inst.get_out_constraints_mut()),
+    )
+}
+
+/// 函数中尚未使用的最小虚拟寄存器编号
+pub fn next_vreg_index(module: &AsmModule, func_id: AsmValueId) -> i32 {
+    let mut next = 0;
+    for bb_id in &module.get_func(func_id).bbs {
+        for inst_id in &module.get_bb(*bb_id).insts {
+            let inst = module.get_inst(*inst_id);
+            for op in inst.get_defs().iter().chain(inst.get_uses().iter()) {
+                if let AsmOperand::VirtReg(vreg) = op {
+                    next = next.max(vreg.index + 1);
+                }
+            }
+        }
+    }
+    next
+}
+
+/// 为 vreg 分配溢出槽，在每次读取前 load、写入后 store
+///
+/// 每条相关指令使用一个新的临时寄存器，返回这些临时寄存器
+pub fn insert_spill_code(
+    module: &mut AsmModule,
+    func_id: AsmValueId,
+    vreg: VirtReg,
+    next_vreg: &mut i32,
+) -> Vec<VirtReg> {
+    let offset = module.get_func_mut(func_id).stack_state.alloc_spill(4);
+    let slot = StackOperand {
+        ty: StackOperandType::Spill,
+        offset,
+    };
+    let target = AsmOperand::VirtReg(vreg);
+    let mut temps = vec![];
+    for bb_id in module.get_func(func_id).bbs.clone() {
+        let mut new_insts = vec![];
+        for inst_id in module.get_bb(bb_id).insts.clone() {
+            let mut inst = module.get_inst(inst_id).clone();
+            let mut uses = inst.get_uses();
+            let mut defs = inst.get_defs();
+            let cond_def = matches!(&inst, AsmInst::Mov(mov) if mov.cond != Cond::AL);
+            let is_def = defs.contains(&target);
+            let is_use = uses.contains(&target) || (cond_def && is_def);
+            if !is_def && !is_use {
+                new_insts.push(inst_id);
+                continue;
+            }
+
+            let tmp = VirtReg::new(*next_vreg, vreg.is_float);
+            *next_vreg += 1;
+            temps.push(tmp);
+            for op in uses.iter_mut().chain(defs.iter_mut()) {
+                if *op == target {
+                    *op = tmp.into();
+                }
+            }
+            inst.set_uses(uses);
+            inst.set_defs(defs);
+            module.set_inst(inst_id, inst);
+
+            if is_use {
+                new_insts.extend(spill_access(module, tmp.into(), &slot, true));
+            }
+            new_insts.push(inst_id);
+            if is_def {
+                new_insts.extend(spill_access(module, tmp.into(), &slot, false));
+            }
+        }
+        module.get_bb_mut(bb_id).insts = new_insts;
+    }
+    temps
+}
+
+// 偏移超出立即数范围时借用 ip 计算地址
+fn spill_access(
+    module: &mut AsmModule,
+    reg: AsmOperand,
+    slot: &StackOperand,
+    is_load: bool,
+) -> Vec<AsmValueId> {
+    let mut ret = vec![];
+    let is_float = reg.is_float();
+    let fit = if is_float {
+        VLDRInst::is_imm_fit(slot)
+    } else {
+        LDRInst::is_imm_fit(slot)
+    };
+    let addr: AsmOperand = if fit {
+        slot.clone().into()
+    } else {
+        let ip = AsmOperand::IntReg(IntReg::new(RegType::Ip));
+        ret.extend(module.load_imm(ip.clone(), &Imm::Int(IntImm::from(slot.offset as i32))));
+        let sub = BinOpInst::new(
+            BinaryOp::Sub,
+            ip.clone(),
+            IntReg::new(RegType::Fp).into(),
+            ip.clone(),
+        );
+        ret.push(module.alloc_value(AsmValue::Inst(sub.into())));
+        ip
+    };
+    let inst: AsmInst = match (is_load, is_float) {
+        (true, false) => LDRInst::new(reg, addr).into(),
+        (true, true) => VLDRInst::new(reg, addr).into(),
+        (false, false) => STRInst::new(reg, addr).into(),
+        (false, true) => VSTRInst::new(reg, addr).into(),
+    };
+    ret.push(module.alloc_value(AsmValue::Inst(inst)));
+    ret
+}
+
+/// 寄存器之间的无条件拷贝，返回 (目标, 源)
+pub fn as_copy(inst: &AsmInst) -> Option<(Reg, Reg)> {
+    let is_copy = match inst {
+        AsmInst::Mov(mov) => mov.ty == MovType::Reg && mov.cond == Cond::AL,
+        AsmInst::VMov(vmov) => vmov.ty == VMovType::CPY && vmov.cond == Cond::AL,
+        _ => false,
+    };
+    if !is_copy {
+        return None;
+    }
+    let to = Reg::from_operand(&inst.get_defs()[0])?;
+    let from = Reg::from_operand(&inst.get_uses()[0])?;
+    Some((to, from))
+}
+
+/// 删除分配后两端相同的拷贝，返回删除的条数
+pub fn remove_identity_moves(module: &mut AsmModule, func_id: AsmValueId) -> usize {
+    let mut removed = 0;
+    for bb_id in module.get_func(func_id).bbs.clone() {
+        let mut insts = module.get_bb(bb_id).insts.clone();
+        insts.retain(|inst_id| {
+            let is_identity =
+                as_copy(module.get_inst(*inst_id)).is_some_and(|(to, from)| to == from);
+            removed += is_identity as usize;
+            !is_identity
+        });
+        module.get_bb_mut(bb_id).insts = insts;
+    }
+    removed
+}
+
+/// 按分配结果改写虚拟寄存器，并记录函数用到的 callee-saved 寄存器
+pub fn assign_regs(
+    module: &mut AsmModule,
+    func_id: AsmValueId,
+    assignment: &HashMap<VirtReg, PhysReg>,
+) {
+    let mut used = HashSet::new();
+    for bb_id in module.get_func(func_id).bbs.clone() {
+        for inst_id in module.get_bb(bb_id).insts.clone() {
+            let mut inst = module.get_inst(inst_id).clone();
+            let mut defs = inst.get_defs();
+            let mut uses = inst.get_uses();
+            for op in defs.iter_mut().chain(uses.iter_mut()) {
+                if let AsmOperand::VirtReg(vreg) = op {
+                    *op = assignment
+                        .get(vreg)
+                        .copied()
+                        .unwrap_or_else(|| panic!("unallocated register vr{}", vreg.index))
+                        .into();
+                }
+                if let Some(reg) = PhysReg::from_operand(op) {
+                    used.insert(reg);
+                }
+            }
+            if !defs.is_empty() || !uses.is_empty() {
+                inst.set_defs(defs);
+                inst.set_uses(uses);
+                module.set_inst(inst_id, inst);
+            }
+        }
+    }
+
+    let func = module.get_func_mut(func_id);
+    func.callee_saved_regs.clear();
+    func.callee_saved_vfp_regs.clear();
+    for reg in used.into_iter().filter(PhysReg::is_callee_saved) {
+        match reg {
+            PhysReg::Int(ty) => func.callee_saved_regs.push(ty),
+            PhysReg::Vfp(index) => func.callee_saved_vfp_regs.push(index),
+        }
+    }
+    func.callee_saved_regs.sort_by_key(|ty| i64::from(*ty));
+    func.callee_saved_vfp_regs.sort();
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    vreg: VirtReg,
+    start: usize,
+    end: usize,
+}
+
+pub struct LinearScan<'a> {
+    module: &'a mut AsmModule,
+    func_id: AsmValueId,
+    next_vreg: i32,
+    // 溢出产生的临时寄存器区间极短，不再溢出
+    no_spill: HashSet<VirtReg>,
+    hints: HashMap<VirtReg, PhysReg>,
+}
+
+impl LinearScan<'_> {
+    pub fn new(module: &mut AsmModule, func_id: AsmValueId) -> LinearScan<'_> {
+        LinearScan {
+            module,
+            func_id,
+            next_vreg: 0,
+            no_spill: HashSet::new(),
+            hints: HashMap::new(),
+        }
+    }
+
+    pub fn run(&mut self) -> RegAllocStats {
+        self.next_vreg = next_vreg_index(self.module, self.func_id);
+        self.hints = lower_constraints(self.module, self.func_id);
+        let mut n_spilled = 0;
+        let assignment = loop {
+            let liveness = Liveness::new(self.module, self.func_id);
+            let ranges = liveness.live_ranges(self.module);
+            match self.allocate(&ranges) {
+                Ok(assignment) => break assignment,
+                Err(spilled) => {
+                    n_spilled += spilled.len();
+                    for vreg in spilled {
+                        let temps =
+                            insert_spill_code(self.module, self.func_id, vreg, &mut self.next_vreg);
+                        self.no_spill.extend(temps);
+                    }
+                }
+            }
+        };
+        assign_regs(self.module, self.func_id, &assignment);
+        let stats = RegAllocStats {
+            spilled: n_spilled,
+            coalesced: remove_identity_moves(self.module, self.func_id),
+        };
+        debug!(
+            "linear scan: {}: {}",
+            self.module.get_func(self.func_id).name,
+            stats
+        );
+        stats
+    }
+
+    fn allocate(
+        &self,
+        ranges: &HashMap<Reg, Vec<(usize, usize)>>,
+    ) -> Result<HashMap<VirtReg, PhysReg>, Vec<VirtReg>> {
+        let mut fixed: HashMap<PhysReg, Vec<(usize, usize)>> = HashMap::new();
+        let mut intervals = vec![];
+        for (reg, rs) in ranges {
+            match reg {
+                Reg::Phys(phys) => fixed.entry(*phys).or_default().extend(rs),
+                Reg::Virt(vreg) => intervals.push(Interval {
+                    vreg: *vreg,
+                    start: rs.iter().map(|r| r.0).min().unwrap(),
+                    end: rs.iter().map(|r| r.1).max().unwrap(),
+                }),
+            }
+        }
+        intervals.sort_by_key(|it| (it.start, it.vreg.index));
+        // 与物理寄存器的固定区间冲突
+        let conflicts = |reg: PhysReg, it: &Interval| {
+            fixed
+                .get(&reg)
+                .is_some_and(|rs| rs.iter().any(|&(s, e)| s <= it.end && it.start <= e))
+        };
+
+        let mut assignment = HashMap::new();
+        let mut active: Vec<Interval> = vec![];
+        let mut spilled = vec![];
+        for cur in intervals {
+            active.retain(|it| it.end >= cur.start);
+            let busy: HashSet<PhysReg> = active.iter().map(|it| assignment[&it.vreg]).collect();
+            let free: Vec<PhysReg> = PhysReg::allocatable(cur.vreg.is_float)
+                .into_iter()
+                .filter(|reg| !busy.contains(reg) && !conflicts(*reg, &cur))
+                .collect();
+            let hint = self.hints.get(&cur.vreg).filter(|reg| free.contains(reg));
+            if let Some(reg) = hint.or(free.first()) {
+                assignment.insert(cur.vreg, *reg);
+                active.push(cur);
+                continue;
+            }
+
+            // 没有空闲寄存器，从活跃区间中挑选终点最远的溢出
+            let can_spill_cur = !self.no_spill.contains(&cur.vreg);
+            let victim = active
+                .iter()
+                .enumerate()
+                .filter(|(_, it)| {
+                    it.vreg.is_float == cur.vreg.is_float
+                        && !self.no_spill.contains(&it.vreg)
+                        && !conflicts(assignment[&it.vreg], &cur)
+                })
+                .max_by_key(|(_, it)| it.end)
+                .map(|(i, _)| i);
+            match victim {
+                Some(i) if !can_spill_cur || active[i].end > cur.end => {
+                    let victim = active.swap_remove(i);
+                    let reg = assignment.remove(&victim.vreg).unwrap();
+                    assignment.insert(cur.vreg, reg);
+                    active.push(cur);
+                    spilled.push(victim.vreg);
+                }
+                _ => {
+                    assert!(can_spill_cur, "unable to allocate vr{}", cur.vreg.index);
+                    spilled.push(cur.vreg);
+                }
+            }
+        }
+        if spilled.is_empty() {
+            Ok(assignment)
+        } else {
+            Err(spilled)
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn build_asm(src: &str) -> AsmModule {
+    use crate::{ir_pass::build_ir, mc_builder};
+
+    mc_builder::build(&mut build_ir(src))
+}
+
+#[cfg(test)]
+pub(crate) fn has_virt_reg(module: &AsmModule, func_id: AsmValueId) -> bool {
+    module.get_func(func_id).bbs.iter().any(|bb_id| {
+        module.get_bb(*bb_id).insts.iter().any(|inst_id| {
+            let inst = module.get_inst(*inst_id);
+            inst.get_defs()
+                .iter()
+                .chain(inst.get_uses().iter())
+                .any(|op| op.as_virt_reg().is_some())
+        })
+    })
+}
+
+#[test]
+fn test_linear_scan_across_call() {
+    let mut module = build_asm(
+        "int getint();
+        int f(int a, int b) { return a + b; }
+        int main() { int x = getint(); return f(x, 2) + x; }",
+    );
+    run(&mut module);
+    for func_id in module.funcs.clone() {
+        assert!(!has_virt_reg(&module, func_id));
+    }
+    // x 跨过调用，只能放在 callee-saved 寄存器里
+    let main = module.get_func(module.funcs[1]);
+    assert_eq!(main.name, "main");
+    assert!(!main.callee_saved_regs.is_empty());
+    assert_eq!(main.stack_state.spill_size, 0);
+}
+
+#[test]
+fn test_linear_scan_spill() {
+    let n = 12;
+    let decls: String = (0..n).map(|i| format!("int a{} = getint();", i)).collect();
+    let sum: Vec<String> = (0..n).map(|i| format!("a{}", i)).collect();
+    let mut module = build_asm(&format!(
+        "int getint(); int main() {{ {} return {}; }}",
+        decls,
+        sum.join(" + ")
+    ));
+    run(&mut module);
+    let main = module.funcs[0];
+    assert!(!has_virt_reg(&module, main));
+    assert!(module.get_func(main).stack_state.spill_size > 0);
+    assert_eq!(module.get_func(main).callee_saved_regs.len(), 7);
+}
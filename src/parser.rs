@@ -6,7 +6,7 @@ use pest::{
     Parser,
 };
 
-use crate::{ast::*};
+use crate::{ast::*, diagnostic::Span};
 type ParseResult<T> = Result<T, Box<ParseError<Rule>>>;
 
 trait IntoParseResult<T> {
@@ -111,6 +111,20 @@ pub fn parse(src: &str) -> ParseResult<TransUnit> {
     Ok(tu)
 }
 
+/// 以 `行:列 规则 文本` 的形式输出词法单元，每行一个
+pub fn dump_tokens(src: &str) -> ParseResult<String> {
+    let pairs = SysYParser::parse(Rule::grammar, src)?;
+    let mut out = String::new();
+    for pair in pairs.flatten() {
+        if pair.clone().into_inner().next().is_some() || pair.as_rule() == Rule::EOI {
+            continue;
+        }
+        let (line, col) = pair.as_span().start_pos().line_col();
+        out += &format!("{}:{}\t{:?}\t{:?}\n", line, col, pair.as_rule(), pair.as_str());
+    }
+    Ok(out)
+}
+
 // grammar = { trans_unit ~ EOI }
 pub fn parse_grammar(pair: Pair<Rule>) -> ParseResult<TransUnit> {
     _debug_rule("parse_grammar", &pair);
@@ -182,6 +196,7 @@ pub fn parse_normal_var_decls(pair: Pair<Rule>) -> ParseResult<Vec<VarDecl>> {
 // var_def = { ID ~ ("[" ~ const_expr ~ "]")* ~ "=" ~ init_val | ID ~ ("[" ~ const_expr ~ "]")* }
 pub fn parse_var_def(pair: Pair<Rule>, type_: &Type, is_const: bool) -> ParseResult<VarDecl> {
     _debug_rule("parse_var_def", &pair);
+    let span = pair.as_span().into();
     let mut inner = pair.into_inner();
     let name = inner.next().unwrap().as_str().to_owned();
     let mut init = None;
@@ -211,6 +226,7 @@ pub fn parse_var_def(pair: Pair<Rule>, type_: &Type, is_const: bool) -> ParseRes
         is_const,
         init,
         sema_ref: None,
+        span,
     })
 }
 
@@ -256,7 +272,9 @@ pub fn parse_func_decl(pair: Pair<Rule>) -> ParseResult<FuncDecl> {
     _debug_rule("parse_func_decl", &pair);
     let mut inner = pair.into_inner();
     let ret_ty = parse_func_type(inner.next().unwrap())?;
-    let name = inner.next().unwrap().as_str().to_owned();
+    let id = inner.next().unwrap();
+    let name = id.as_str().to_owned();
+    let span = id.as_span().into();
     let mut params = Vec::new();
     let mut block = None;
 
@@ -274,6 +292,7 @@ pub fn parse_func_decl(pair: Pair<Rule>) -> ParseResult<FuncDecl> {
         ret_ty,
         body: block,
         sema_ref: None,
+        span,
     })
 }
 
@@ -311,10 +330,30 @@ pub fn parse_func_params(pair: Pair<Rule>) -> ParseResult<Vec<Param>> {
 // func_param = { basic_type ~ ID ~ ("[" ~ "]" ~ ("[" ~ const_expr ~ "]")*)? }
 pub fn parse_func_param(pair: Pair<Rule>) -> ParseResult<Param> {
     _debug_rule("parse_func_param", &pair);
+    // "[" 和 "]" 不产生 pair，只能从文本判断是否为数组参数
+    let is_array = pair.as_str().contains('[');
     let mut inner = pair.into_inner();
-    let type_ = parse_basic_type(inner.next().unwrap())?;
-    let name = inner.next().unwrap().as_str().to_owned();
-    Ok(Param::new(name, type_))
+    let mut type_ = parse_basic_type(inner.next().unwrap())?;
+    let id = inner.next().unwrap();
+    if is_array {
+        let const_exprs = inner
+            .map(parse_const_expr)
+            .collect::<ParseResult<Vec<_>>>()?;
+        for const_expr in const_exprs.into_iter().rev() {
+            type_ = Type::Array(ArrayType::Constant(ConstantArrayType {
+                element_type: Box::new(type_),
+                size: 0,
+                size_info: Some(const_expr),
+                dims: None,
+            }));
+        }
+        // 第一维省略长度
+        type_ = Type::Array(ArrayType::Incomplete(IncompleteArrayType {
+            element_type: Box::new(type_),
+            size_info: None,
+        }));
+    }
+    Ok(Param::new(id.as_str().to_owned(), type_, id.as_span().into()))
 }
 
 // block = { "{" ~ (block_item)* ~ "}" }
@@ -374,16 +413,16 @@ pub fn parse_stmt(pair: Pair<Rule>) -> ParseResult<Box<Stmt>> {
             Ok(Box::new(Stmt::For(for_stmt)))
         }
         Rule::break_stmt => {
-            parse_break_stmt(inner)?;
-            Ok(Box::new(Stmt::Break))
+            let break_stmt = parse_break_stmt(inner)?;
+            Ok(Box::new(Stmt::Break(break_stmt)))
         }
         Rule::do_while_stmt => {
             let do_while_stmt = parse_do_while_stmt(inner)?;
             Ok(Box::new(Stmt::DoWhile(do_while_stmt)))
         }
         Rule::continue_stmt => {
-            parse_continue_stmt(inner)?;
-            Ok(Box::new(Stmt::Continue))
+            let continue_stmt = parse_continue_stmt(inner)?;
+            Ok(Box::new(Stmt::Continue(continue_stmt)))
         }
         Rule::return_stmt => {
             let return_stmt = parse_return_stmt(inner)?;
@@ -473,10 +512,12 @@ pub fn parse_for_stmt(pair: Pair<Rule>) -> ParseResult<ForStmt> {
 }
 
 // break_stmt = { KW_BREAK ~ ";" }
-pub fn parse_break_stmt(pair: Pair<Rule>) -> ParseResult<()> {
+pub fn parse_break_stmt(pair: Pair<Rule>) -> ParseResult<BreakStmt> {
     _debug_rule("parse_break_stmt", &pair);
-    pair.into_inner().next(); // consume KW_BREAK
-    Ok(())
+    Ok(BreakStmt {
+        target: None,
+        span: pair.as_span().into(),
+    })
 }
 
 // do_while_stmt = { KW_DO ~ stmt ~ KW_WHILE ~ "(" ~ cond ~ ")" ~ ";" }
@@ -490,15 +531,18 @@ pub fn parse_do_while_stmt(pair: Pair<Rule>) -> ParseResult<DoWhileStmt> {
 }
 
 // continue_stmt = { KW_CONTINUE ~ ";" }
-pub fn parse_continue_stmt(pair: Pair<Rule>) -> ParseResult<()> {
+pub fn parse_continue_stmt(pair: Pair<Rule>) -> ParseResult<ContinueStmt> {
     _debug_rule("parse_continue_stmt", &pair);
-    pair.into_inner().next(); // consume KW_CONTINUE
-    Ok(())
+    Ok(ContinueStmt {
+        target: None,
+        span: pair.as_span().into(),
+    })
 }
 
 // return_stmt = { KW_RETURN ~ (expr)? ~ ";" }
 pub fn parse_return_stmt(pair: Pair<Rule>) -> ParseResult<ReturnStmt> {
     _debug_rule("parse_return_stmt", &pair);
+    let span = pair.as_span().into();
     let inner = pair.into_inner();
     // skip KW_RETURN
     let expr = inner
@@ -506,42 +550,55 @@ pub fn parse_return_stmt(pair: Pair<Rule>) -> ParseResult<ReturnStmt> {
         .next()
         .map(parse_expr)
         .transpose()?;
-    Ok(ReturnStmt { expr })
+    Ok(ReturnStmt { expr, span })
 }
 
 // expr = { prefix* ~ primary_expr ~ postfix* ~ (infix ~ prefix* ~ primary_expr ~ postfix* )* }
 pub fn parse_expr(pair: Pair<Rule>) -> ParseResult<Box<Expr>> {
     _debug_rule("parse_expr", &pair);
     let inner = pair.into_inner();
-    let expr = PRATT_PARSER_EXPR
-        .map_primary(|x| Expr::Primary(parse_primary_expr(x).unwrap()))
-        .map_infix(|lhs, op, rhs| {
-            Expr::Infix(InfixExpr {
+    // 每个子表达式同时带上其源码区间
+    let (expr, _) = PRATT_PARSER_EXPR
+        .map_primary(|x| {
+            let span = Span::from(x.as_span());
+            (Expr::Primary(parse_primary_expr(x).unwrap()), span)
+        })
+        .map_infix(|(lhs, lhs_span), op, (rhs, rhs_span)| {
+            let span = lhs_span.to(rhs_span);
+            let expr = Expr::Infix(InfixExpr {
                 lhs: Box::new(lhs),
                 op: parse_infix_op(op).unwrap(),
                 rhs: Box::new(rhs),
 
                 infer_ty: None,
                 infer_val: None,
-            })
+                span,
+            });
+            (expr, span)
         })
-        .map_prefix(|op, rhs| {
-            Expr::Prefix(PrefixExpr {
+        .map_prefix(|op, (rhs, rhs_span)| {
+            let span = Span::from(op.as_span()).to(rhs_span);
+            let expr = Expr::Prefix(PrefixExpr {
                 op: parse_prefix_op(op).unwrap(),
                 rhs: Box::new(rhs),
 
                 infer_ty: None,
                 infer_val: None,
-            })
+                span,
+            });
+            (expr, span)
         })
-        .map_postfix(|lhs, op| {
-            Expr::Postfix(PostfixExpr {
+        .map_postfix(|(lhs, lhs_span), op| {
+            let span = lhs_span.to(op.as_span().into());
+            let expr = Expr::Postfix(PostfixExpr {
                 lhs: Box::new(lhs),
                 op: parse_postfix_op(op).unwrap(),
 
                 infer_ty: None,
                 infer_val: None,
-            })
+                span,
+            });
+            (expr, span)
         })
         .parse(inner.into_iter());
     Ok(Box::new(expr))
@@ -599,6 +656,7 @@ pub fn parse_literal_expr(pair: Pair<Rule>) -> ParseResult<Literal> {
 // call_expr = { ID ~ "(" ~ (func_args)? ~ ")" }
 pub fn parse_call_expr(pair: Pair<Rule>) -> ParseResult<CallExpr> {
     _debug_rule("parse_call_expr", &pair);
+    let span = pair.as_span().into();
     let mut inner = pair.into_inner();
     let id = inner.next().unwrap().as_str().to_string();
     let args = inner.next().map(parse_func_args).transpose()?;
@@ -609,6 +667,7 @@ pub fn parse_call_expr(pair: Pair<Rule>) -> ParseResult<CallExpr> {
 
         infer_ty: None,
         infer_val: None,
+        span,
     })
 }
 
@@ -896,7 +955,11 @@ pub fn parse_const_expr(pair: Pair<Rule>) -> ParseResult<Box<Expr>> {
 // id = @{ ("_" | "$" | alpha | unicode) ~ ("_" | "$" | alpha_num | unicode)* }
 pub fn parse_id(pair: Pair<Rule>) -> ParseResult<IdentExpr> {
     let id = pair.as_str().to_string();
-    Ok(IdentExpr { id, sema_ref: None })
+    Ok(IdentExpr {
+        id,
+        sema_ref: None,
+        span: pair.as_span().into(),
+    })
 }
 
 // string = ${ quote ~ inner_str ~ quote }
@@ -987,3 +1050,13 @@ pub fn parse_unicode_escape(escape_content: &str, pair: Pair<Rule>) -> ParseResu
     })?;
     Ok(unicode_char)
 }
+
+#[test]
+fn test_dump_tokens() {
+    let r = dump_tokens("int main() {\n  return 0x1;\n}").unwrap();
+    let lines: Vec<_> = r.lines().collect();
+    assert_eq!(lines[0], "1:1\tKW_INT\t\"int\"");
+    assert_eq!(lines[1], "1:5\tid\t\"main\"");
+    assert_eq!(lines[2], "2:3\tKW_RETURN\t\"return\"");
+    assert!(lines[3].starts_with("2:10\t"));
+}
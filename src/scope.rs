@@ -1,7 +1,7 @@
 use id_arena::{Arena, Id};
 use std::collections::HashMap;
 
-use crate::symbol::Symbol;
+use crate::{diagnostic::Diagnostic, symbol::Symbol};
 
 pub struct Scope {
     symbols: HashMap<String, SymbolId>,
@@ -18,6 +18,8 @@ pub struct SymbolTable {
     pub symbols: SymbolArena,
     pub scopes: ScopeArena,
     current_scope: ScopeId,
+    // 语义分析过程中收集的诊断信息
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Scope {
@@ -57,6 +59,7 @@ impl SymbolTable {
             symbols: SymbolArena::new(),
             scopes: scope_arena,
             current_scope: root_scope,
+            diagnostics: Vec::new(),
         }
     }
 
@@ -94,9 +97,26 @@ impl SymbolTable {
         Some(self.symbols[symbol_id].clone())
     }
 
+    // 记录一条诊断信息
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    // 取出已收集的诊断信息
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
     // 打印符号表，并返回字符串
     pub fn print_table(&self) -> String {
-        self.print_scope(self.current_scope, 0)
+        self.print_scope(self.current_scope, 0, &[])
+    }
+
+    /// 打印符号表，跳过 hidden 中的全局函数及其作用域
+    ///
+    /// hidden 中的函数必须是最先声明的函数，它们的作用域也就是最前面的几个子作用域
+    pub fn print_table_without(&self, hidden: &[SymbolId]) -> String {
+        self.print_scope(self.current_scope, 0, hidden)
     }
 
     pub fn scope_id(&self) -> ScopeId {
@@ -104,15 +124,22 @@ impl SymbolTable {
     }
 
     // 打印作用域，并返回字符串
-    fn print_scope(&self, scope_id: ScopeId, level: usize) -> String {
+    fn print_scope(&self, scope_id: ScopeId, level: usize, hidden: &[SymbolId]) -> String {
         let mut result = String::new();
         let scope = &self.scopes[scope_id];
-        for (name, symbol_id) in &scope.symbols {
+        // 按声明顺序输出，保证结果稳定
+        let mut symbols: Vec<_> = scope
+            .symbols
+            .iter()
+            .filter(|(_, symbol_id)| !hidden.contains(symbol_id))
+            .collect();
+        symbols.sort_by_key(|(_, symbol_id)| symbol_id.index());
+        for (name, symbol_id) in symbols {
             let symbol = &self.symbols[*symbol_id];
             result += &format!("{:indent$}{}: {:?}\n", "", name, symbol, indent = level * 4);
         }
-        for child_id in &scope.children {
-            result += &self.print_scope(*child_id, level + 1);
+        for child_id in scope.children.iter().skip(hidden.len()) {
+            result += &self.print_scope(*child_id, level + 1, &[]);
         }
         result
     }
@@ -1,6 +1,6 @@
 use std::collections::VecDeque;
 
-use crate::{ast::*, infer_eval::InferEvaluator, scope::*, symbol::*};
+use crate::{ast::*, diagnostic::Diagnostic, infer_eval::InferEvaluator, scope::*, symbol::*};
 use log::{debug, trace};
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct SemaRef {
@@ -44,6 +44,9 @@ impl ToSemaTrait for FuncDecl {
 
         // 将函数参数添加到符号表中
         for param in &mut self.params {
+            if let Type::Array(at) = &mut param.type_ {
+                eval_array_type(at, symbol_table);
+            }
             let symbol = Symbol::Var(VarSymbol::new(param.clone().into()));
             let symbol_id = symbol_table.insert_symbol(param.name.clone(), symbol);
             param.sema_ref = Some(SemaRef::new(symbol_id, symbol_table.scope_id()));
@@ -73,7 +76,12 @@ fn eval_array_type(at: &mut ArrayType, symbol_table: &mut SymbolTable) {
                 eval_array_type(at, symbol_table);
             }
         }
-        _ => (),
+        // 数组参数的第一维长度省略，只计算其余各维
+        ArrayType::Incomplete(incomplete_at) => {
+            if let Type::Array(at) = incomplete_at.element_type.as_mut() {
+                eval_array_type(at, symbol_table);
+            }
+        }
     }
 }
 
@@ -153,12 +161,19 @@ impl ToSemaTrait for Stmt {
             Stmt::IfElse(if_else_stmt) => if_else_stmt.to_sema(symbol_table),
             Stmt::While(while_stmt) => while_stmt.to_sema(symbol_table),
             Stmt::For(for_stmt) => for_stmt.to_sema(symbol_table),
-            Stmt::Break => {}
+            Stmt::Break(_) => {}
             Stmt::DoWhile(do_while_stmt) => do_while_stmt.to_sema(symbol_table),
-            Stmt::Continue => {}
+            Stmt::Continue(_) => {}
             Stmt::Return(return_stmt) => {
                 if let Some(expr) = &mut return_stmt.expr {
                     expr.to_sema(symbol_table);
+                    // 函数只能返回 int、float 或 void
+                    if let Some(Type::Array(_)) = expr.infer_type(symbol_table) {
+                        symbol_table.report(
+                            Diagnostic::error("cannot return an array")
+                                .with_primary(return_stmt.span, "returns an array"),
+                        );
+                    }
                 }
             }
         }
@@ -180,6 +195,19 @@ impl ToSemaTrait for InfixExpr {
     fn to_sema(&mut self, symbol_table: &mut SymbolTable) {
         self.lhs.to_sema(symbol_table);
         self.rhs.to_sema(symbol_table);
+        if self.op == InfixOp::Mod {
+            let is_float = |expr: &Expr| {
+                expr.infer_type(symbol_table)
+                    .is_some_and(|ty| ty.is_float())
+            };
+            if is_float(&self.lhs) || is_float(&self.rhs) {
+                symbol_table.report(
+                    Diagnostic::error("cannot apply `%` to float operands")
+                        .with_primary(self.span, "float operand"),
+                );
+                return;
+            }
+        }
         self.infer_ty = self.infer_type(symbol_table);
     }
 }
@@ -200,6 +228,17 @@ impl ToSemaTrait for PostfixExpr {
             PostfixOp::DotAccess(dot_access) => dot_access.to_sema(symbol_table),
             PostfixOp::IndexAccess(index_access) => index_access.to_sema(symbol_table),
         }
+        if let PostfixOp::IndexAccess(_) = &self.op {
+            if let Some(lhs_ty) = self.lhs.infer_type(symbol_table) {
+                if !matches!(lhs_ty, Type::Array(_) | Type::Pointer(_)) {
+                    symbol_table.report(
+                        Diagnostic::error("index access on non-array type")
+                            .with_primary(self.span, "cannot be indexed"),
+                    );
+                    return;
+                }
+            }
+        }
         self.infer_ty = self.infer_type(symbol_table);
     }
 }
@@ -240,15 +279,50 @@ impl ToSemaTrait for CallExpr {
         for arg in &mut self.args {
             arg.to_sema(symbol_table);
         }
+        let func = match symbol_table.resolve_symbol(&self.id) {
+            Some(Symbol::Func(func_sym)) => func_sym.func,
+            Some(_) => {
+                symbol_table.report(
+                    Diagnostic::error(format!("`{}` is not a function", self.id))
+                        .with_primary(self.span, "not a function"),
+                );
+                return;
+            }
+            None => {
+                symbol_table.report(
+                    Diagnostic::error(format!("undefined function `{}`", self.id))
+                        .with_primary(self.span, "not found in this scope"),
+                );
+                return;
+            }
+        };
+        if func.params.len() != self.args.len() {
+            let plural = |n: usize| if n == 1 { "" } else { "s" };
+            symbol_table.report(
+                Diagnostic::error(format!(
+                    "function `{}` takes {} argument{} but {} {} supplied",
+                    self.id,
+                    func.params.len(),
+                    plural(func.params.len()),
+                    self.args.len(),
+                    if self.args.len() == 1 { "was" } else { "were" }
+                ))
+                .with_primary(self.span, "wrong number of arguments"),
+            );
+        }
     }
 }
 
 impl ToSemaTrait for IdentExpr {
     fn to_sema(&mut self, symbol_table: &mut SymbolTable) {
         trace!("IdentExpr to_sema: {:?}", self);
-        let symbol = symbol_table.lookup_symbol(&mut self.id);
+        let symbol = symbol_table.lookup_symbol(&self.id);
         if symbol.is_none() {
-            panic!("Undefined identifier: {}", self.id);
+            symbol_table.report(
+                Diagnostic::error(format!("undefined identifier `{}`", self.id))
+                    .with_primary(self.span, "not found in this scope"),
+            );
+            return;
         }
         self.sema_ref = Some(SemaRef::new(symbol.unwrap(), symbol_table.scope_id()));
     }
@@ -331,3 +405,70 @@ impl ToSemaTrait for ArrayInitVal {
         }
     }
 }
+
+#[test]
+fn test_call_diagnostics() {
+    let mut ast = crate::parser::parse(
+        "int f(int x) { return x; }
+        int main() {
+            int a[2];
+            f(1, 2);
+            g();
+            if (a[0]) { return a; }
+            return f(1);
+        }",
+    )
+    .unwrap();
+    let mut syms = SymbolTable::new();
+    ast.to_sema(&mut syms);
+    let messages = syms
+        .take_diagnostics()
+        .into_iter()
+        .map(|diag| diag.message)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "function `f` takes 1 argument but 2 were supplied",
+            "undefined function `g`",
+            "cannot return an array",
+        ]
+    );
+}
+
+#[test]
+fn test_array_params() {
+    let mut ast = crate::parser::parse(
+        "int f(int a[], int n) { return a[n]; }
+        int g(int b[][3]) { return b[1][2] + f(b[0], 3); }
+        int main() { int x[2][3]; return g(x); }",
+    )
+    .unwrap();
+    let mut syms = SymbolTable::new();
+    ast.to_sema(&mut syms);
+    assert!(syms.take_diagnostics().is_empty());
+    // 省略的第一维之外，其余各维的长度已经求出
+    let Type::Array(ArrayType::Incomplete(at)) = &ast.func_decls[1].params[0].type_ else {
+        panic!("{:?}", ast.func_decls[1].params[0].type_);
+    };
+    let Type::Array(ArrayType::Constant(row)) = at.element_type.as_ref() else {
+        panic!("{:?}", at.element_type);
+    };
+    assert_eq!(row.size, 3);
+}
+
+#[test]
+fn test_float_mod() {
+    let mut ast = crate::parser::parse(
+        "int main() { float a = 5.0; int b = 7 % 3; b = b % 2.0; return a % b; }",
+    )
+    .unwrap();
+    let mut syms = SymbolTable::new();
+    ast.to_sema(&mut syms);
+    // 两侧都是整数时才能取模
+    let diags = syms.take_diagnostics();
+    assert_eq!(diags.len(), 2);
+    assert!(diags
+        .iter()
+        .all(|diag| diag.message == "cannot apply `%` to float operands"));
+}